@@ -0,0 +1,34 @@
+// Copyright (c) 2020-2025 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Build script exposing the build information reported by the `version` subcommand.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let target = env::var("TARGET").unwrap_or_default();
+    println!("cargo:rustc-env=BDK_CLI_TARGET={target}");
+
+    // The resolved bdk_wallet version is only known from the lock file, which isn't available
+    // when building from a packaged crate.
+    let lock_file =
+        Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap_or_default()).join("Cargo.lock");
+    let bdk_wallet_version = fs::read_to_string(&lock_file)
+        .ok()
+        .and_then(|lock| {
+            let mut lines = lock.lines();
+            lines.find(|line| *line == "name = \"bdk_wallet\"")?;
+            let version = lines.next()?.strip_prefix("version = \"")?;
+            Some(version.trim_end_matches('"').to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=BDK_CLI_BDK_WALLET_VERSION={bdk_wallet_version}");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2020-2025 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Encrypted wallet backups
+//!
+//! A backup file is a JSON envelope holding the wallet payload encrypted with ChaCha20-Poly1305,
+//! using a key derived from the passphrase with Argon2id. The envelope and the payload both carry
+//! a version, so the format can be migrated later.
+//!
+//! Besides the descriptors and the birthday, the payload holds the bdk-cli state files of the
+//! wallet: its frozen UTXOs, transaction annotations, fee bump replacements and address indexes.
+
+use crate::error::BDKCliError as Error;
+use crate::utils::{
+    ANNOTATIONS_FILE, CHANGE_INDEX_FILE, DESCRIPTOR_INDEX_FILE, FROZEN_UTXOS_FILE,
+    REPLACEMENTS_FILE, load_birthday, load_state_file,
+};
+
+use argon2::Argon2;
+use bdk_wallet::bitcoin::base64::Engine;
+use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+use bdk_wallet::bitcoin::hex::DisplayHex;
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+use bdk_wallet::bitcoin::hex::FromHex;
+use bdk_wallet::chain::ChainPosition;
+use bdk_wallet::{KeychainKind, Wallet};
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+use chacha20poly1305::Nonce;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit};
+use serde_json::{Map, Value, json};
+use std::path::Path;
+
+/// Current version of the backup envelope and payload.
+pub(crate) const BACKUP_VERSION: u64 = 1;
+
+const SALT_LEN: usize = 16;
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+const NONCE_LEN: usize = 12;
+
+/// The state files of the wallet data directory carried by the backups.
+pub(crate) const BACKUP_STATE_FILES: [&str; 5] = [
+    FROZEN_UTXOS_FILE,
+    ANNOTATIONS_FILE,
+    REPLACEMENTS_FILE,
+    DESCRIPTOR_INDEX_FILE,
+    CHANGE_INDEX_FILE,
+];
+
+/// Build the backup payload of a wallet, with its private descriptors when it has the keys and
+/// the state files of its data directory.
+///
+/// The birthday is the height of the first confirmed wallet transaction, to limit rescans, or the
+/// birthday of the backup the wallet was restored from when it has no confirmed transaction yet.
+pub(crate) fn wallet_backup_payload(wallet: &Wallet, wallet_dir: &Path) -> Result<Value, Error> {
+    let descriptor = |keychain: KeychainKind| {
+        let keymap = wallet.get_signers(keychain).as_key_map(wallet.secp_ctx());
+        wallet
+            .public_descriptor(keychain)
+            .to_string_with_secret(&keymap)
+    };
+    let internal_descriptor = wallet
+        .keychains()
+        .any(|(keychain, _)| keychain == KeychainKind::Internal)
+        .then(|| descriptor(KeychainKind::Internal));
+    let birthday = wallet
+        .transactions()
+        .filter_map(|tx| match tx.chain_position {
+            ChainPosition::Confirmed { anchor, .. } => Some(anchor.block_id.height),
+            ChainPosition::Unconfirmed { .. } => None,
+        })
+        .min();
+    let birthday = match birthday {
+        Some(birthday) => Some(birthday),
+        None => load_birthday(wallet_dir)?,
+    };
+    let mut state_files = Map::new();
+    for name in BACKUP_STATE_FILES {
+        let path = wallet_dir.join(name);
+        if path.exists() {
+            state_files.insert(name.to_string(), load_state_file::<Value>(&path)?);
+        }
+    }
+
+    Ok(json!({
+        "version": BACKUP_VERSION,
+        "network": wallet.network(),
+        "external_descriptor": descriptor(KeychainKind::External),
+        "internal_descriptor": internal_descriptor,
+        "birthday": birthday,
+        "state_files": state_files,
+    }))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, Error> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::Generic(format!("Backup key derivation failed: {e}")))?;
+    Ok(key)
+}
+
+/// Encrypt a backup payload with the passphrase, returning the backup file content.
+pub(crate) fn encrypt_backup(payload: &Value, passphrase: &str) -> Result<String, Error> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher
+        .encrypt(&nonce, serde_json::to_vec(payload)?.as_slice())
+        .map_err(|_| Error::Generic("Backup encryption failed.".to_string()))?;
+
+    Ok(serde_json::to_string_pretty(&json!({
+        "version": BACKUP_VERSION,
+        "kdf": "argon2id",
+        "cipher": "chacha20poly1305",
+        "salt": salt.to_lower_hex_string(),
+        "nonce": nonce.to_lower_hex_string(),
+        "ciphertext": BASE64_STANDARD.encode(ciphertext),
+    }))?)
+}
+
+/// Decrypt a backup file content with the passphrase, returning its payload.
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+pub(crate) fn decrypt_backup(backup: &str, passphrase: &str) -> Result<Value, Error> {
+    let envelope: Value = serde_json::from_str(backup)
+        .map_err(|_| Error::Generic("Not a bdk-cli backup file.".to_string()))?;
+    match envelope["version"].as_u64() {
+        Some(BACKUP_VERSION) => {}
+        Some(version) => {
+            return Err(Error::Generic(format!(
+                "Unsupported backup version {version}."
+            )));
+        }
+        None => return Err(Error::Generic("Not a bdk-cli backup file.".to_string())),
+    }
+
+    let field = |name: &str| {
+        envelope[name]
+            .as_str()
+            .ok_or_else(|| Error::Generic(format!("The backup has no {name}.")))
+    };
+    let salt = Vec::<u8>::from_hex(field("salt")?)?;
+    let nonce = Vec::<u8>::from_hex(field("nonce")?)?;
+    if nonce.len() != NONCE_LEN {
+        return Err(Error::Generic("The backup nonce is invalid.".to_string()));
+    }
+    let ciphertext = BASE64_STANDARD.decode(field("ciphertext")?)?;
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let payload = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| Error::Generic("Wrong passphrase or corrupted backup.".to_string()))?;
+    Ok(serde_json::from_slice(&payload)?)
+}
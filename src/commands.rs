@@ -13,15 +13,22 @@
 //! All subcommands are defined in the below enums.
 
 #![allow(clippy::large_enum_variant)]
+use bdk_wallet::KeychainKind;
 use bdk_wallet::bitcoin::{
-    Address, Network, OutPoint, ScriptBuf,
+    Address, BlockHash, Network, OutPoint, ScriptBuf, TapLeafHash, Txid,
     bip32::{DerivationPath, Xpriv},
 };
 use clap::{Args, Parser, Subcommand, ValueEnum, value_parser};
+use clap_complete::Shell;
+use std::ops::RangeInclusive;
 
 #[cfg(any(feature = "electrum", feature = "esplora", feature = "rpc"))]
 use crate::utils::parse_proxy_auth;
-use crate::utils::{parse_address, parse_outpoint, parse_recipient};
+use crate::utils::{
+    parse_account_range, parse_address, parse_currency, parse_descriptor_recipient, parse_fee_rate,
+    parse_fiat_amount, parse_network, parse_outpoint, parse_recipient, parse_script_recipient,
+    parse_signet_challenge, parse_test_seed, parse_tx_bound,
+};
 
 /// The BDK Command Line Wallet App
 ///
@@ -37,22 +44,68 @@ use crate::utils::{parse_address, parse_outpoint, parse_recipient};
 #[derive(PartialEq, Clone, Debug, Parser)]
 #[command(version, about, long_about = None)]
 pub struct CliOpts {
-    /// Sets the network.
+    /// Sets the network, one of bitcoin (main), testnet (test), testnet4, signet (sig) or regtest (reg).
     #[arg(
-        env = "NETWORK",
+        env = "BDK_NETWORK",
         short = 'n',
         long = "network",
         default_value = "testnet",
-        value_parser = value_parser!(Network)
+        value_parser = parse_network
     )]
     pub network: Network,
     /// Sets the wallet data directory.
     /// Default value : ~/.bdk-bitcoin
     #[arg(env = "DATADIR", short = 'd', long = "datadir")]
     pub datadir: Option<std::path::PathBuf>,
+    /// Sets the challenge script of a custom signet, in hex. Requires the signet network.
+    #[arg(env = "SIGNET_CHALLENGE", long, value_parser = parse_signet_challenge)]
+    pub signet_challenge: Option<ScriptBuf>,
+    /// Uses Electrum and Esplora servers on the custom signet of `--signet-challenge`, although they can't report their challenge to verify it.
+    #[cfg(any(
+        feature = "electrum",
+        feature = "esplora",
+        feature = "rpc",
+        feature = "cbf"
+    ))]
+    #[arg(
+        env = "UNVERIFIED_SIGNET",
+        long = "unverified-signet",
+        requires = "signet_challenge"
+    )]
+    pub unverified_signet: bool,
+    /// Uses this hex encoded BIP39 entropy, of up to 32 bytes, for the generated mnemonics instead of random entropy, to get reproducible keys in tests. Refused on mainnet.
+    #[arg(env = "TEST_SEED", long = "test-seed", value_parser = parse_test_seed)]
+    pub test_seed: Option<[u8; 32]>,
+    /// Sets a timeout, in seconds, after which the commands connecting to a blockchain client fail. There is no timeout by default.
+    #[cfg(any(
+        feature = "electrum",
+        feature = "esplora",
+        feature = "rpc",
+        feature = "cbf"
+    ))]
+    #[arg(env = "TIMEOUT", long = "timeout")]
+    pub timeout: Option<u64>,
     /// Output results in pretty format (instead of JSON).
     #[arg(long = "pretty", global = true)]
     pub pretty: bool,
+    /// Wraps the JSON output of successful commands in an object also holding the network, the command and a unix timestamp.
+    #[arg(long = "envelope", global = true, conflicts_with = "pretty")]
+    pub envelope: bool,
+    /// Runs the wallet commands without saving the wallet changes and state files or broadcasting transactions, to preview their result.
+    #[arg(long = "dry-run", global = true)]
+    pub dry_run: bool,
+    /// Sets the denomination of the amounts in the outputs, one of sat, bit, mbtc or btc.
+    #[arg(env = "DENOM", long = "denom", global = true, value_enum, default_value_t = Denom::Sat)]
+    pub denom: Denom,
+    /// Only logs errors, whatever `RUST_LOG` is set to, and leaves out the progress messages of the scans and syncs, so that only the result is printed.
+    #[arg(long = "quiet", global = true)]
+    pub quiet: bool,
+    /// Sets the format of the log records.
+    #[arg(env = "LOG_FORMAT", long = "log-format", global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Colors the `--pretty` output and the log records: `auto` only colors them on a terminal and when `NO_COLOR` isn't set. JSON output is never colored.
+    #[arg(long = "color", global = true, value_enum, default_value_t = ColorMode::Auto)]
+    pub color: ColorMode,
     /// Top level cli sub-commands.
     #[command(subcommand)]
     pub subcommand: CliSubCommand,
@@ -85,6 +138,16 @@ pub enum CliSubCommand {
         #[clap(subcommand)]
         subcommand: KeySubCommand,
     },
+    /// Elements/Liquid operations.
+    ///
+    /// Parses Elements descriptors and addresses of the Liquid chain matching the network: liquid
+    /// for bitcoin, elementsregtest for regtest and liquidtestnet otherwise. Elements wallets are
+    /// not supported.
+    #[cfg(feature = "elements")]
+    Elements {
+        #[clap(subcommand)]
+        subcommand: ElementsSubCommand,
+    },
     /// Compile a miniscript policy to an output descriptor.
     #[cfg(feature = "compiler")]
     #[clap(long_about = "Miniscript policy compiler")]
@@ -121,7 +184,140 @@ pub enum CliSubCommand {
         desc_type: String,
         /// Optional key: xprv, xpub, or mnemonic phrase
         key: Option<String>,
+        /// Optional different key for the change descriptor, instead of deriving it from `key`: xprv, xpub, or mnemonic phrase.
+        #[arg(long = "change_key", requires = "key")]
+        change_key: Option<String>,
+        /// Sets the hardened account derivation path (eg. "m/84h/1h/5h"), instead of the first BIP44/49/84/86 account.
+        #[arg(long = "account_path")]
+        account_path: Option<DerivationPath>,
+        /// Fetch the account xpub from the connected hardware wallet instead of using a key.
+        #[cfg(feature = "hwi")]
+        #[arg(long = "hwi", conflicts_with = "key")]
+        hwi: bool,
+    },
+    /// Recreate a wallet from an encrypted backup written by `wallet backup`.
+    #[cfg(any(feature = "sqlite", feature = "redb"))]
+    Restore {
+        /// Selects the wallet to create.
+        #[arg(env = "WALLET_NAME", short = 'w', long = "wallet")]
+        wallet: Option<String>,
+        /// Selects the database of the created wallet.
+        #[arg(env = "DATABASE_TYPE", short = 'd', long, value_enum, required = true)]
+        database_type: DatabaseType,
+        /// Sets the backup file to restore, the passphrase is asked for.
+        #[arg(env = "BACKUP_FILE", long = "file")]
+        file: std::path::PathBuf,
+    },
+    /// Sum the balances of all the SQLite wallets of the data directory, per network.
+    ///
+    /// Each wallet is opened read-only. The balances of wallets on different networks are never
+    /// added together.
+    #[cfg(feature = "sqlite")]
+    TotalBalance,
+    /// Generate an inheritance vault descriptor.
+    ///
+    /// The primary key can spend at any time, the recovery key only once the funds have not moved
+    /// for the given number of blocks: `wsh(or_d(pk(primary),and_v(v:pk(recovery),older(blocks))))`.
+    GenerateVault {
+        /// Sets the key that can always spend.
+        #[arg(env = "PRIMARY_KEY", long = "primary")]
+        primary: String,
+        /// Sets the key that can spend after the relative timelock.
+        #[arg(env = "RECOVERY_KEY", long = "recovery")]
+        recovery: String,
+        /// Sets the relative timelock of the recovery path, in blocks.
+        #[arg(env = "BLOCKS", long = "blocks", value_parser = value_parser!(u16).range(1..))]
+        blocks: u16,
+    },
+    /// Normalize a descriptor to its canonical form, to compare descriptors emitted by different tools.
+    ///
+    /// Private keys are replaced by their public keys and the keys of `sortedmulti` are sorted.
+    Normalize {
+        /// Sets the descriptor to normalize.
+        #[arg(env = "DESCRIPTOR", long = "descriptor")]
+        descriptor: String,
+    },
+    /// Inspect a descriptor: its script type, miniscripts and their properties, spending policy and maximum satisfaction weight.
+    InspectDescriptor {
+        /// Sets the descriptor to inspect.
+        #[arg(env = "DESCRIPTOR", long = "descriptor")]
+        descriptor: String,
+    },
+    /// Describe a descriptor like Bitcoin Core's `getdescriptorinfo`.
+    ///
+    /// Outputs the public descriptor with its checksum, the checksum of the descriptor as given, its
+    /// canonical form and whether it has a wildcard, is multipath or has private keys. The
+    /// descriptor can be given with or without its checksum.
+    GetDescriptorInfo {
+        /// Sets the descriptor to describe.
+        #[arg(env = "DESCRIPTOR", long = "descriptor")]
+        descriptor: String,
+    },
+    /// Derive test vectors from a descriptor, to check that other wallets derive the same scripts.
+    ///
+    /// Outputs the script pubkey, the address and, for private descriptors, the WIF private keys of
+    /// each index.
+    TestVectors {
+        /// Sets the descriptor to derive.
+        #[arg(env = "DESCRIPTOR", long = "descriptor")]
+        descriptor: String,
+        /// Sets the number of indexes to derive, starting from 0.
+        #[arg(long = "count", default_value_t = 10)]
+        count: u32,
+    },
+    /// Check that a descriptor derives an expected address, as shown by another wallet, at an index.
+    VerifyAddress {
+        /// Sets the descriptor to derive.
+        #[arg(env = "DESCRIPTOR", long = "descriptor")]
+        descriptor: String,
+        /// Sets the derivation index.
+        #[arg(long = "index", default_value_t = 0)]
+        index: u32,
+        /// Sets the address the descriptor should derive.
+        #[arg(env = "ADDRESS", long = "expect")]
+        expect: String,
+    },
+    /// Compute the Electrum scripthash of addresses, to query an Electrum server manually.
+    ///
+    /// The scripthash is the SHA256 of the script pubkey of the address, in reversed byte order.
+    Scripthash {
+        /// Sets the addresses to hash.
+        #[arg(env = "ADDRESS", required = true)]
+        addresses: Vec<String>,
     },
+    /// Decode a raw transaction.
+    DecodeRawTransaction {
+        /// Sets the raw transaction to decode, in hex.
+        #[arg(env = "RAW_TX")]
+        tx: String,
+    },
+    /// Create an unsigned raw transaction from explicit inputs and outputs, without a wallet.
+    CreateRawTransaction {
+        /// Adds an input to the transaction.
+        #[arg(env = "TXID:VOUT", long = "input", required = true, value_parser = parse_outpoint)]
+        inputs: Vec<OutPoint>,
+        /// Adds an output to the transaction.
+        #[arg(env = "ADDRESS:SAT", long = "output", required = true, value_parser = parse_recipient)]
+        outputs: Vec<(ScriptBuf, u64)>,
+    },
+    /// Generate a shell completion script.
+    ///
+    /// The script is written to stdout, e.g. for bash:
+    /// `bdk-cli completions bash > /usr/share/bash-completion/completions/bdk-cli`.
+    Completions {
+        /// Shell to generate the completion script for.
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Dump the command line interface definition as JSON.
+    ///
+    /// Lists every subcommand with its options, value types and help, in definition order, so the
+    /// output can be used to generate clients.
+    Schema,
+    /// Print the build information: crate and bdk_wallet versions, enabled features and target.
+    ///
+    /// Include it in bug reports.
+    Version,
 }
 /// Wallet operation subcommands.
 #[derive(Debug, Subcommand, Clone, PartialEq)]
@@ -166,6 +362,152 @@ pub enum ClientType {
     Cbf,
 }
 
+/// Format of the log records.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per record
+    Json,
+}
+
+/// When to color the human-readable output.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Only on a terminal, when `NO_COLOR` isn't set
+    Auto,
+    /// Always, even when piped or with `NO_COLOR` set
+    Always,
+    /// Never
+    Never,
+}
+
+/// Denomination of the reported amounts.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum Denom {
+    /// Satoshis
+    Sat,
+    /// Bits, 100 sats
+    Bit,
+    /// Millibitcoins, 100,000 sats
+    Mbtc,
+    /// Bitcoins, 100,000,000 sats
+    Btc,
+}
+
+impl Denom {
+    /// Name of the denomination, as used on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Denom::Sat => "sat",
+            Denom::Bit => "bit",
+            Denom::Mbtc => "mbtc",
+            Denom::Btc => "btc",
+        }
+    }
+}
+
+/// Wallet keychain selection.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum Keychain {
+    /// External (receive) keychain
+    External,
+    /// Internal (change) keychain
+    Internal,
+}
+
+/// Encoding of an address, fixed by the type of the descriptor it is derived from.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum AddressEncoding {
+    /// Base58 P2PKH, of `pkh` descriptors
+    Legacy,
+    /// Base58 P2SH, of `sh` descriptors, including `sh(wpkh)` and `sh(wsh)`
+    P2sh,
+    /// Bech32 segwit v0, of `wpkh` and `wsh` descriptors
+    Bech32,
+    /// Bech32m segwit v1, of `tr` descriptors
+    Bech32m,
+}
+
+impl AddressEncoding {
+    /// Name of the encoding, as used on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AddressEncoding::Legacy => "legacy",
+            AddressEncoding::P2sh => "p2sh",
+            AddressEncoding::Bech32 => "bech32",
+            AddressEncoding::Bech32m => "bech32m",
+        }
+    }
+}
+
+impl From<Keychain> for KeychainKind {
+    fn from(keychain: Keychain) -> Self {
+        match keychain {
+            Keychain::External => KeychainKind::External,
+            Keychain::Internal => KeychainKind::Internal,
+        }
+    }
+}
+
+/// Accounting category of a transaction.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum TxCategory {
+    /// Payment received
+    Income,
+    /// Payment made
+    Expense,
+    /// Move of funds between accounts of the same owner
+    Transfer,
+}
+
+impl TxCategory {
+    /// Name of the category, as used on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TxCategory::Income => "income",
+            TxCategory::Expense => "expense",
+            TxCategory::Transfer => "transfer",
+        }
+    }
+}
+
+/// Field to sort wallet transactions by.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum TxSort {
+    /// Confirmation height
+    Height,
+    /// Confirmation time, or last seen time for unconfirmed transactions
+    Time,
+}
+
+/// Bound of a transaction range, a block height or a UTC date.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TxBound {
+    /// Block height
+    Height(u32),
+    /// Unix timestamp of the start of the day
+    Date(u64),
+}
+
+/// Fee rate of a new transaction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxFeeRate {
+    /// Fee rate in sat/vbyte
+    SatPerVb(f32),
+    /// Fee rate taken from the cached fee estimates
+    Auto,
+}
+
+/// Sort order of a listing.
+#[derive(Clone, Copy, ValueEnum, Debug, Eq, PartialEq)]
+pub enum SortOrder {
+    /// Ascending
+    Asc,
+    /// Descending
+    Desc,
+}
+
 /// Config options wallet operations can take.
 #[derive(Debug, Args, Clone, PartialEq, Eq)]
 pub struct WalletOpts {
@@ -181,6 +523,34 @@ pub struct WalletOpts {
     /// Sets the descriptor to use for internal/change addresses.
     #[arg(env = "INT_DESCRIPTOR", short = 'i', long)]
     pub int_descriptor: Option<String>,
+    /// Sets both descriptors from a template, one of `bip44(<key>)`, `bip49(<key>)`, `bip84(<key>)` or `bip86(<key>)`.
+    #[arg(
+        env = "DESCRIPTOR_TEMPLATE",
+        long,
+        conflicts_with_all = ["ext_descriptor", "int_descriptor"]
+    )]
+    pub template: Option<String>,
+    /// Sets watch-only descriptors for an account xpub, optionally with its key origin (`[fingerprint/path]xpub`), the first BIP account path of the type is assumed without it.
+    #[arg(
+        env = "XPUB",
+        long,
+        conflicts_with_all = ["ext_descriptor", "int_descriptor", "template"]
+    )]
+    pub xpub: Option<String>,
+    /// Selects the script type of the `--xpub` descriptors.
+    #[arg(
+        env = "XPUB_TYPE",
+        long,
+        value_parser = ["pkh", "wpkh", "sh", "wsh", "tr"],
+        default_value = "wpkh"
+    )]
+    pub xpub_type: String,
+    /// Fails the wallet creation, instead of warning, when the external and internal descriptors don't use the same keys on different branches.
+    #[arg(env = "STRICT", long)]
+    pub strict: bool,
+    /// Silences the warnings about addresses of the wallet that were already used.
+    #[arg(env = "NO_REUSE_WARNINGS", long)]
+    pub no_reuse_warnings: bool,
     #[cfg(any(
         feature = "electrum",
         feature = "esplora",
@@ -189,10 +559,27 @@ pub struct WalletOpts {
     ))]
     #[arg(env = "CLIENT_TYPE", short = 'c', long, value_enum, required = true)]
     pub client_type: ClientType,
+    /// Syncs the wallet with the blockchain client before the commands reporting its balance, UTXOs or transactions, in the REPL too.
+    #[cfg(any(
+        feature = "electrum",
+        feature = "esplora",
+        feature = "rpc",
+        feature = "cbf"
+    ))]
+    #[arg(env = "SYNC_FIRST", long)]
+    pub sync_first: bool,
     #[cfg(any(feature = "sqlite", feature = "redb"))]
     #[arg(env = "DATABASE_TYPE", short = 'd', long, value_enum, required = true)]
     pub database_type: DatabaseType,
-    /// Sets the server url.
+    /// Opens the wallet database without saving any change, so other read-only processes can use the wallet at the same time. Like with `--dry-run`, nothing is saved or broadcast.
+    #[cfg(any(feature = "sqlite", feature = "redb"))]
+    #[arg(env = "READ_ONLY", long)]
+    pub read_only: bool,
+    /// Runs the command with the given descriptors instead of the stored ones, on the blocks and transactions of the stored wallet, in a transient session: like with `--dry-run`, nothing is saved or broadcast.
+    #[cfg(any(feature = "sqlite", feature = "redb"))]
+    #[arg(env = "DESCRIPTOR_OVERRIDE", long)]
+    pub descriptor_override: bool,
+    /// Sets the server url, or `unix://<path>` for an Electrum server on a local Unix socket.
     #[cfg(any(feature = "electrum", feature = "esplora", feature = "rpc"))]
     #[arg(env = "SERVER_URL", short = 'u', long, required = true)]
     pub url: String,
@@ -200,6 +587,10 @@ pub struct WalletOpts {
     #[cfg(feature = "electrum")]
     #[arg(env = "ELECTRUM_BATCH_SIZE", short = 'b', long, default_value = "10")]
     pub batch_size: usize,
+    /// Uses the Electrum server at `--url` as a seed to discover its peers, and connects to the first one that responds.
+    #[cfg(feature = "electrum")]
+    #[arg(env = "ELECTRUM_DISCOVER", long)]
+    pub electrum_discover: bool,
     /// Esplora parallel requests.
     #[cfg(feature = "esplora")]
     #[arg(
@@ -209,6 +600,22 @@ pub struct WalletOpts {
         default_value = "5"
     )]
     pub parallel_requests: usize,
+    /// Sets how many times an Esplora request is retried when the server answers 429, 500 or 503.
+    #[cfg(feature = "esplora")]
+    #[arg(env = "ESPLORA_MAX_RETRIES", long, default_value = "6")]
+    pub esplora_max_retries: usize,
+    /// Sets the delay before the first Esplora retry, in milliseconds, doubled after each retry.
+    #[cfg(feature = "esplora")]
+    #[arg(env = "ESPLORA_BACKOFF_MS", long, default_value = "256")]
+    pub esplora_backoff_ms: u64,
+    /// Sets the client name announced to Electrum servers and sent as the Esplora `User-Agent` header.
+    #[cfg(any(feature = "electrum", feature = "esplora"))]
+    #[arg(
+        env = "USER_AGENT",
+        long,
+        default_value = concat!("bdk-cli/", env!("CARGO_PKG_VERSION"))
+    )]
+    pub user_agent: String,
     #[cfg(feature = "rpc")]
     /// Sets the rpc basic authentication.
     #[arg(
@@ -223,6 +630,10 @@ pub struct WalletOpts {
     /// Sets an optional cookie authentication.
     #[arg(env = "COOKIE")]
     pub cookie: Option<String>,
+    /// Targets this wallet of the Bitcoin Core node, when it has several loaded wallets.
+    #[cfg(feature = "rpc")]
+    #[arg(env = "RPC_WALLET", long)]
+    pub rpc_wallet: Option<String>,
     #[cfg(feature = "cbf")]
     #[clap(flatten)]
     pub compactfilter_opts: CompactFilterOpts,
@@ -268,22 +679,156 @@ pub struct CompactFilterOpts {
 #[command(rename_all = "snake")]
 pub enum OfflineWalletSubCommand {
     /// Get a new external address.
-    NewAddress,
+    NewAddress {
+        /// Returns the bare address, for scripts.
+        #[arg(long = "raw")]
+        raw: bool,
+        /// Returns the lowest-index revealed address that never received funds, revealing a
+        /// new one only when all revealed addresses are used, like `unused_address`.
+        #[arg(long = "reuse_unused")]
+        reuse_unused: bool,
+        /// Checks that the address has this encoding, and fails without revealing it otherwise.
+        ///
+        /// Every descriptor type has a single encoding: `pkh` addresses are legacy ones, `sh`,
+        /// `sh(wpkh)` and `sh(wsh)` addresses are p2sh ones, `wpkh` and `wsh` addresses are
+        /// bech32 ones and `tr` addresses are bech32m ones. Another encoding would pay to a
+        /// different script, that the wallet doesn't watch.
+        #[arg(long = "type", value_enum)]
+        address_type: Option<AddressEncoding>,
+    },
     /// Get the first unused external address.
+    ///
+    /// This is an alias of `new_address --reuse_unused`.
     UnusedAddress,
+    /// Lists the next external addresses from the first unused one, without revealing them.
+    ///
+    /// Unlike `new_address`, the derivation index of the wallet doesn't move, so the addresses can
+    /// be handed out before deciding to reveal them. Used addresses are listed too, flagged as such.
+    NextAddresses {
+        /// Sets the number of addresses to list.
+        #[arg(long = "count", default_value_t = 10)]
+        count: u32,
+    },
+    /// Get a new internal (change) address.
+    ///
+    /// The address is not used as change by later `create_tx` calls.
+    NewChangeAddress,
+    /// Checks which addresses of a file, one per line, belong to the wallet, without revealing any address.
+    CheckAddresses {
+        /// Sets the file listing the addresses.
+        #[arg(env = "ADDRESSES_FILE", long = "file")]
+        file: std::path::PathBuf,
+        /// Derives the addresses up to this many past the last revealed index of each keychain.
+        #[arg(long = "gap", default_value_t = 20)]
+        gap: u32,
+    },
+    /// Skips the addresses of a keychain up to an index, so the next revealed address is at this index.
+    ///
+    /// The skipped internal addresses are never used as change by `create_tx`, while
+    /// `unused_address` still returns the skipped external addresses that never received funds.
+    SkipIndex {
+        /// Sets the keychain to skip the addresses of.
+        #[arg(long = "keychain", value_enum)]
+        keychain: Keychain,
+        /// Sets the index of the next revealed address, past the current one.
+        #[arg(long = "to")]
+        to: u32,
+    },
     /// Lists the available spendable UTXOs.
-    Unspent,
+    Unspent {
+        /// Returns the UTXOs as CSV.
+        #[arg(long = "csv")]
+        csv: bool,
+        /// Returns the CSV values in BTC, like `--denom btc`.
+        #[arg(long = "btc", requires = "csv")]
+        btc: bool,
+        /// Groups the UTXOs by the address owning them, with the total of each address.
+        #[arg(long = "group_by_address", conflicts_with = "csv")]
+        group_by_address: bool,
+    },
+    /// Freezes a UTXO, so that it's never selected automatically to fund a transaction.
+    FreezeUtxo {
+        /// The UTXO to freeze, as TXID:VOUT.
+        #[arg(value_parser = parse_outpoint)]
+        utxo: OutPoint,
+    },
+    /// Unfreezes a UTXO frozen with `freeze_utxo`.
+    UnfreezeUtxo {
+        /// The UTXO to unfreeze, as TXID:VOUT.
+        #[arg(value_parser = parse_outpoint)]
+        utxo: OutPoint,
+    },
+    /// Lists the frozen UTXOs.
+    ListFrozen,
+    /// Returns a statistical summary of the available UTXOs.
+    UtxoStats,
     /// Lists all the incoming and outgoing transactions of the wallet.
-    Transactions,
+    ///
+    /// Unconfirmed transactions are sorted after the confirmed ones.
+    Transactions {
+        /// Sets the maximum number of transactions to return.
+        #[arg(long = "limit")]
+        limit: Option<usize>,
+        /// Sets the number of transactions to skip, after sorting.
+        #[arg(long = "offset", default_value = "0")]
+        offset: usize,
+        /// Sets the field to sort the transactions by.
+        #[arg(long = "sort", value_enum, default_value = "height")]
+        sort: TxSort,
+        /// Sets the sort order.
+        #[arg(long = "order", value_enum, default_value = "asc")]
+        order: SortOrder,
+        /// Only lists transactions confirmed at or after the given height or `YYYY-MM-DD` date.
+        #[arg(long = "since", value_parser = parse_tx_bound)]
+        since: Option<TxBound>,
+        /// Only lists transactions confirmed at or before the given height or `YYYY-MM-DD` date.
+        /// Unconfirmed transactions are excluded when set.
+        #[arg(long = "until", value_parser = parse_tx_bound)]
+        until: Option<TxBound>,
+        /// Returns the transactions as CSV.
+        #[arg(long = "csv")]
+        csv: bool,
+        /// Returns the CSV values in BTC, like `--denom btc`.
+        #[arg(long = "btc", requires = "csv")]
+        btc: bool,
+    },
+    /// Annotates a transaction with an accounting category and its fiat value at the time, listed by `transactions`.
+    ///
+    /// The annotations are stored in the wallet data directory. Fields that are not given keep
+    /// their previous value.
+    Annotate {
+        /// Sets the transaction to annotate.
+        #[arg(env = "TXID", long = "txid")]
+        txid: Txid,
+        /// Sets the accounting category of the transaction.
+        #[arg(long = "category", value_enum, required_unless_present = "fiat")]
+        category: Option<TxCategory>,
+        /// Sets the fiat value of the transaction at the time it was made, such as `12.50`.
+        #[arg(long = "fiat", value_parser = parse_fiat_amount, requires = "currency")]
+        fiat: Option<String>,
+        /// Sets the currency of the fiat value, such as `USD`.
+        #[arg(long = "currency", value_parser = parse_currency, requires = "fiat")]
+        currency: Option<String>,
+    },
     /// Returns the current wallet balance.
-    Balance,
-    /// Creates a new unsigned transaction.
+    Balance {
+        /// Returns the bare confirmed balance, in the `--denom` denomination, for scripts.
+        #[arg(long = "raw")]
+        raw: bool,
+    },
+    /// Creates a new unsigned transaction, or a signed one with `--no_broadcast`.
     CreateTx {
         /// Adds a recipient to the transaction.
         // Clap Doesn't support complex vector parsing https://github.com/clap-rs/clap/issues/1704.
         // Address and amount parsing is done at run time in handler function.
-        #[arg(env = "ADDRESS:SAT", long = "to", required = true, value_parser = parse_recipient)]
+        #[arg(env = "ADDRESS:SAT", long = "to", required_unless_present_any = ["to_descriptor", "to_script"], value_parser = parse_recipient)]
         recipients: Vec<(ScriptBuf, u64)>,
+        /// Adds a recipient paying to the next address of another descriptor. The derivation index used for the descriptor is tracked in the wallet data directory.
+        #[arg(env = "DESCRIPTOR:SAT", long = "to_descriptor", value_parser = parse_descriptor_recipient)]
+        to_descriptor: Option<(String, u64)>,
+        /// Adds a recipient paying to a raw script pubkey, in hex, for instance to test custom scripts on regtest.
+        #[arg(env = "SCRIPT:SAT", long = "to_script", value_parser = parse_script_recipient)]
+        to_script: Vec<(ScriptBuf, u64)>,
         /// Sends all the funds (or all the selected utxos). Requires only one recipient with value 0.
         #[arg(long = "send_all", short = 'a')]
         send_all: bool,
@@ -299,9 +844,68 @@ pub enum OfflineWalletSubCommand {
         /// Marks a utxo as unspendable.
         #[arg(env = "CANT_SPEND_TXID:VOUT", long = "unspendable", value_parser = parse_outpoint)]
         unspendable: Option<Vec<OutPoint>>,
-        /// Fee rate to use in sat/vbyte.
-        #[arg(env = "SATS_VBYTE", short = 'f', long = "fee_rate")]
-        fee_rate: Option<f32>,
+        /// Sends the change to this address instead of the next internal address.
+        #[arg(
+            env = "CHANGE_ADDRESS",
+            long = "change_address",
+            value_parser = parse_address,
+            conflicts_with_all = ["change_keychain", "send_all"]
+        )]
+        change_address: Option<Address>,
+        /// Selects the keychain the change address is derived from.
+        #[arg(
+            env = "CHANGE_KEYCHAIN",
+            long = "change_keychain",
+            value_enum,
+            conflicts_with = "send_all"
+        )]
+        change_keychain: Option<Keychain>,
+        /// Allows the change address to be an address not owned by the wallet.
+        #[arg(long = "allow_external_change", requires = "change_address")]
+        allow_external_change: bool,
+        /// Adds a pay-to-anchor (P2A) output, so the transaction can be fee bumped with CPFP by anyone.
+        #[arg(long = "anchor")]
+        anchor: bool,
+        /// Sorts the inputs and outputs as described in BIP69, instead of shuffling them.
+        #[arg(long = "bip69", conflicts_with = "preserve_order")]
+        bip69: bool,
+        /// Keeps the outputs in the order of the recipients, and the `--utxos` inputs first in the given order, instead of shuffling them.
+        #[arg(long = "preserve_order")]
+        preserve_order: bool,
+        /// Allows spending coinbase outputs with less than 100 confirmations. The transaction is only valid once they mature, this is mostly useful in tests.
+        #[arg(long = "allow_immature")]
+        allow_immature: bool,
+        /// Splits the change into this many roughly equal outputs, each paying to a fresh change address.
+        #[arg(long = "split_change", value_parser = value_parser!(u32).range(1..), conflicts_with = "send_all")]
+        split_change: Option<u32>,
+        /// Also spends every UTXO below this value, in sats, that is worth more than the fee to spend it.
+        #[arg(long = "sweep_small", conflicts_with = "send_all")]
+        sweep_small: Option<u64>,
+        /// Deducts the fee from the recipients at these indices, in proportion to their amounts, counting the `--to` recipients in order and then `--to_descriptor`.
+        #[arg(
+            long = "subtract_fee_from",
+            value_delimiter = ',',
+            conflicts_with_all = ["send_all", "split_change"]
+        )]
+        subtract_fee_from: Option<Vec<usize>>,
+        /// Only selects inputs paying the recipients and the fee without a change output, with branch and bound, and fails if there are none. The excess, less than the cost of a change output, goes to the fee, `exact_match` tells whether there was none.
+        #[arg(
+            long = "no_change",
+            conflicts_with_all = ["send_all", "split_change", "subtract_fee_from", "change_address", "change_keychain"]
+        )]
+        no_change: bool,
+        /// Leaves out a change output worth less than this many sats, adding its amount to the fee instead.
+        #[arg(long = "min_change", conflicts_with_all = ["send_all", "no_change"])]
+        min_change: Option<u64>,
+        /// Signs and finalizes the transaction with the wallet keys, returning its raw hex along with the signed PSBT to hand it to another service for broadcasting.
+        #[arg(long = "no_broadcast")]
+        no_broadcast: bool,
+        /// Reports, for each input, the worst-case satisfaction weight the fee is computed with next to the weight of the cheapest path the wallet keys and the chosen policy paths can satisfy, with the vsize and fee rate of both.
+        #[arg(long = "estimate_satisfaction")]
+        estimate_satisfaction: bool,
+        /// Fee rate to use in sat/vbyte, or `auto` to use the fee estimates of the blockchain server, or the ones cached by `fee_estimates` when the server can't be reached.
+        #[arg(env = "SATS_VBYTE", short = 'f', long = "fee_rate", value_parser = parse_fee_rate)]
+        fee_rate: Option<TxFeeRate>,
         /// Selects which policy should be used to satisfy the external descriptor.
         #[arg(env = "EXT_POLICY", long = "external_policy")]
         external_policy: Option<String>,
@@ -325,6 +929,21 @@ pub enum OfflineWalletSubCommand {
         )]
         add_data: Option<String>, //base 64 econding
     },
+    /// Compares the transactions paying the recipients at several fee rates, without signing or saving them.
+    SimulateSend {
+        /// Adds a recipient to the transaction.
+        #[arg(env = "ADDRESS:SAT", long = "to", required = true, value_parser = parse_recipient)]
+        recipients: Vec<(ScriptBuf, u64)>,
+        /// Fee rates to compare, in sat/vbyte.
+        #[arg(
+            env = "SATS_VBYTE",
+            long = "fee_rates",
+            value_delimiter = ',',
+            required = true,
+            value_parser = value_parser!(u64).range(1..)
+        )]
+        fee_rates: Vec<u64>,
+    },
     /// Bumps the fees of an RBF transaction.
     BumpFee {
         /// TXID of the transaction to update.
@@ -350,12 +969,109 @@ pub enum OfflineWalletSubCommand {
             default_value = "1.0"
         )]
         fee_rate: f32,
+        /// Targets confirmation within this many blocks instead of `--fee_rate`, using the fee estimate of the blockchain server for the target, or the one cached by `fee_estimates` when the server can't be reached.
+        ///
+        /// Fails when the estimate isn't above the fee rate of the original transaction, as a
+        /// replacement must pay a higher fee rate.
+        #[arg(long = "target", conflicts_with = "fee_rate", value_parser = value_parser!(u16).range(1..))]
+        target: Option<u16>,
+    },
+    /// Clears the chain data and the transactions of the wallet database, so the next sync fetches them again.
+    ///
+    /// The descriptors and revealed address indices are kept, unlike recreating the wallet from
+    /// its descriptors, so every address given out so far is still synced.
+    ResetState {
+        /// Confirms the wallet state should be cleared.
+        #[arg(long = "confirm")]
+        confirm: bool,
+    },
+    /// Lists the chain of fee bumps of a transaction, recorded each time `bump_fee` creates a replacement of a wallet transaction and when `broadcast` sends it, and which version is live.
+    ReplacementHistory {
+        /// Selects any transaction of the chain.
+        #[arg(env = "TXID")]
+        txid: Txid,
+    },
+    /// Reports whether a wallet transaction signals BIP125 replaceability, directly or through its unconfirmed ancestors.
+    ///
+    /// Check it before `bump_fee`: only the unconfirmed transactions signaling RBF, or
+    /// descending from one, are replaceable under BIP125. A transaction only replaceable through
+    /// an ancestor reports `replaceable` as `"inherited"`.
+    RbfStatus {
+        /// The transaction to inspect.
+        #[arg(env = "TXID")]
+        txid: Txid,
+    },
+    /// Inserts a known transaction in the wallet without syncing, to reconcile it with an external source.
+    InsertTx {
+        /// The raw transaction, hex encoded.
+        #[arg(env = "RAW_TX", long = "raw")]
+        raw: String,
+        /// Height of the block confirming the transaction, it stays unconfirmed otherwise.
+        #[arg(env = "HEIGHT", long = "height")]
+        height: Option<u32>,
+        /// Hash of the confirming block, needed when the wallet doesn't know the block at `--height`.
+        #[arg(env = "BLOCK_HASH", long = "block_hash", requires = "height")]
+        block_hash: Option<BlockHash>,
+        /// Unix timestamp of the confirming block.
+        #[arg(
+            env = "BLOCK_TIME",
+            long = "block_time",
+            requires = "height",
+            default_value_t = 0
+        )]
+        block_time: u64,
     },
     /// Returns the available spending policies for the descriptor.
     Policies,
     /// Returns the public version of the wallet's descriptor(s).
     PublicDescriptor,
+    /// Returns the private key of an owned address in WIF format.
+    ///
+    /// Anyone with the private key can spend the funds sent to the address.
+    DumpPrivKey {
+        /// Sets the address to dump the private key of.
+        #[arg(env = "ADDRESS", value_parser = parse_address)]
+        address: Address,
+        /// Confirms that the private key should be exposed.
+        #[arg(long = "confirm_danger")]
+        confirm_danger: bool,
+    },
+    /// Writes the transactions, anchors and block checkpoints cached by the wallet to a JSON file, to move its chain state to another machine with `import_graph`.
+    ExportGraph {
+        /// Sets the file to write.
+        #[arg(env = "GRAPH_FILE", long = "out")]
+        out: std::path::PathBuf,
+    },
+    /// Adds the chain state written by `export_graph` to the wallet, without syncing.
+    ///
+    /// The graph must be exported from a wallet with the same descriptors and network.
+    ImportGraph {
+        /// Sets the file to read.
+        #[arg(env = "GRAPH_FILE", long = "file")]
+        file: std::path::PathBuf,
+    },
+    /// Merges a serialized BDK wallet changeset into the wallet, without syncing: its chain, transactions and revealed address indexes.
+    ///
+    /// The network and descriptors of the changeset, when it has them, must be the wallet's.
+    ImportChangeset {
+        /// Sets the JSON file of the changeset.
+        #[arg(env = "CHANGESET_FILE", long = "file")]
+        file: std::path::PathBuf,
+    },
+    /// Writes an encrypted backup of the wallet, private keys and state files included, to restore it with `restore`.
+    ///
+    /// The passphrase is asked for twice on a terminal, or read from the first line of the standard input.
+    Backup {
+        /// Sets the backup file to write.
+        #[arg(env = "BACKUP_FILE", long = "out")]
+        out: std::path::PathBuf,
+        /// Overwrites the backup file if it already exists.
+        #[arg(long = "force")]
+        force: bool,
+    },
     /// Signs and tries to finalize a PSBT.
+    ///
+    /// The output lists the key origins each input is signed with, to check the signed derivation paths.
     Sign {
         /// Sets the PSBT to sign.
         #[arg(env = "BASE64_PSBT")]
@@ -366,6 +1082,27 @@ pub enum OfflineWalletSubCommand {
         /// Whether the signer should trust the witness_utxo, if the non_witness_utxo hasn’t been provided.
         #[arg(env = "WITNESS", long = "trust_witness_utxo")]
         trust_witness_utxo: Option<bool>,
+        /// Signs with the given xprv instead of the wallet keys, the xprv is never stored.
+        #[arg(env = "XPRV", long = "xprv")]
+        xprv: Option<Xpriv>,
+        /// Also signs with the keys of this private descriptor of another wallet, the keys are never stored. This option can be repeated to sign for several wallets at once.
+        #[arg(env = "SIGN_DESCRIPTOR", long = "also_descriptor")]
+        also_descriptors: Vec<String>,
+        /// Only signs the taproot script path spends of the given leaf hashes. This option can be repeated multiple times, all the leaves are signed by default.
+        #[arg(long = "tap_leaf")]
+        tap_leaves: Vec<TapLeafHash>,
+        /// Skips the taproot key path signatures, so that only script path spends are signed.
+        #[arg(long = "no_tap_internal_key")]
+        no_tap_internal_key: bool,
+        /// Whether the ECDSA signatures are ground until their R value is low, saving a byte each, as BDK does by default. The RFC6979 nonce is used as is otherwise.
+        #[arg(env = "GRIND_R", long = "grind_r")]
+        grind_r: Option<bool>,
+        /// Refuses to sign if the fee rate of the PSBT, in sat/vbyte, is above this value.
+        #[arg(env = "MAX_FEE_RATE", long = "max_fee_rate")]
+        max_fee_rate: Option<f64>,
+        /// Signs even if the fee rate of the PSBT is above `--max_fee_rate`.
+        #[arg(long = "force")]
+        force: bool,
     },
     /// Extracts a raw transaction from a PSBT.
     ExtractPsbt {
@@ -385,7 +1122,15 @@ pub enum OfflineWalletSubCommand {
         #[arg(env = "WITNESS", long = "trust_witness_utxo")]
         trust_witness_utxo: Option<bool>,
     },
+    /// Reports how many of the required signatures each input of a PSBT has, which cosigners signed it and the key origins the wallet signs it with.
+    PsbtStatus {
+        /// Sets the PSBT to inspect.
+        #[arg(env = "BASE64_PSBT")]
+        psbt: String,
+    },
     /// Combines multiple PSBTs into one.
+    ///
+    /// The result doesn't depend on the order of the PSBTs. Duplicate signatures are merged, and two different signatures of the same key for an input are an error.
     CombinePsbt {
         /// Add one PSBT to combine. This option can be repeated multiple times, one for each PSBT.
         #[arg(env = "BASE64_PSBT", required = true)]
@@ -394,7 +1139,7 @@ pub enum OfflineWalletSubCommand {
 }
 
 /// Wallet subcommands that needs a blockchain backend.
-#[derive(Debug, Subcommand, Clone, PartialEq, Eq)]
+#[derive(Debug, Subcommand, Clone, PartialEq)]
 #[command(rename_all = "snake")]
 #[cfg(any(
     feature = "electrum",
@@ -410,7 +1155,35 @@ pub enum OnlineWalletSubCommand {
         stop_gap: usize,
     },
     /// Syncs with the chosen blockchain server.
-    Sync,
+    Sync {
+        /// Syncs the blocks from this height again, even if the wallet already synced past it. Requires the rpc client.
+        #[arg(long = "from_height")]
+        from_height: Option<u32>,
+        /// Stops the sync after this height instead of at the tip. Requires the rpc client.
+        #[arg(long = "to_height")]
+        to_height: Option<u32>,
+    },
+    /// Broadcasts the unconfirmed wallet transactions again, for instance after they were dropped from mempools.
+    Rebroadcast {
+        /// Only rebroadcasts the transaction with this TXID.
+        #[arg(env = "TXID", long = "txid")]
+        txid: Option<Txid>,
+    },
+    /// Fetches fee estimates from the chosen blockchain server and caches them in the wallet data directory for `create_tx --fee_rate auto`.
+    FeeEstimates {
+        /// Returns the number of blocks a transaction paying this fee rate, in sat/vbyte, is expected to take to confirm, from the mempool of the blockchain server, instead of caching fee estimates.
+        #[arg(long = "when")]
+        when: Option<f64>,
+    },
+    /// Fetches any transaction from the chosen blockchain server and returns its raw hex.
+    GetRawTx {
+        /// TXID of the transaction to fetch.
+        #[arg(env = "TXID")]
+        txid: Txid,
+        /// Returns the decoded transaction alongside its hex.
+        #[arg(long = "verbose")]
+        verbose: bool,
+    },
     /// Broadcasts a transaction to the network. Takes either a raw transaction or a PSBT to extract.
     Broadcast {
         /// Sets the PSBT to sign.
@@ -493,6 +1266,26 @@ pub enum KeySubCommand {
         #[arg(env = "PASSWORD", short = 'p', long = "password")]
         password: Option<String>,
     },
+    /// Verifies a written down mnemonic: its word count, its words and its checksum, and optionally its first receive address.
+    VerifyMnemonic {
+        /// Seed mnemonic words, must be quoted (eg. "word1 word2 ...").
+        #[arg(env = "MNEMONIC", short = 'm', long = "mnemonic")]
+        mnemonic: String,
+        /// Seed password.
+        #[arg(env = "PASSWORD", short = 'p', long = "password")]
+        password: Option<String>,
+        /// Expected first receive address of the first account, compared to the derived one.
+        #[arg(env = "ADDRESS", long = "address")]
+        address: Option<String>,
+        /// Sets the BIP standard, and so the script type, of the first address.
+        #[arg(
+            long = "type",
+            short = 't',
+            value_parser = ["bip44", "bip49", "bip84", "bip86"],
+            default_value = "bip84"
+        )]
+        bip: String,
+    },
     /// Derive a child key pair from a master extended key and a derivation path string (eg. "m/84'/1'/0'/0" or "m/84h/1h/0h/0").
     Derive {
         /// Extended private key to derive from.
@@ -502,6 +1295,45 @@ pub enum KeySubCommand {
         #[arg(env = "PATH", short = 'p', long = "path")]
         path: DerivationPath,
     },
+    /// Derive the descriptors of several BIP44/49/84/86 accounts of a master extended key, to create one wallet per account.
+    DeriveAccounts {
+        /// Extended private key to derive from.
+        #[arg(env = "XPRV", short = 'x', long = "xprv")]
+        xprv: Xpriv,
+        /// Sets the account indexes to derive, a single index or an inclusive range (eg. "0-3").
+        #[arg(long = "accounts", default_value = "0", value_parser = parse_account_range)]
+        accounts: RangeInclusive<u32>,
+        /// Sets the BIP standard, and so the script type, of the descriptors.
+        #[arg(
+            long = "type",
+            short = 't',
+            value_parser = ["bip44", "bip49", "bip84", "bip86"],
+            default_value = "bip84"
+        )]
+        bip: String,
+    },
+}
+
+/// Subcommands for Elements/Liquid operations.
+#[cfg(feature = "elements")]
+#[derive(Debug, Subcommand, Clone, PartialEq, Eq)]
+#[command(rename_all = "snake")]
+pub enum ElementsSubCommand {
+    /// Validates an Elements address, and returns its blinding key if it's confidential.
+    Address {
+        /// Sets the address to validate.
+        #[arg(env = "ADDRESS")]
+        address: String,
+    },
+    /// Parses an Elements descriptor, `el` prefixed or wrapped in `ct(<blinding key>,...)`, and returns its address at an index.
+    Descriptor {
+        /// Sets the descriptor to parse.
+        #[arg(env = "DESCRIPTOR")]
+        descriptor: String,
+        /// Sets the derivation index of the address.
+        #[arg(long = "index", default_value_t = 0)]
+        index: u32,
+    },
 }
 
 /// Subcommands available in REPL mode.
@@ -531,7 +1363,24 @@ pub enum ReplSubCommand {
         desc_type: String,
         /// Optional key: xprv, xpub, or mnemonic phrase
         key: Option<String>,
+        /// Optional different key for the change descriptor, instead of deriving it from `key`: xprv, xpub, or mnemonic phrase.
+        #[arg(long = "change_key", requires = "key")]
+        change_key: Option<String>,
+        /// Sets the hardened account derivation path (eg. "m/84h/1h/5h"), instead of the first BIP44/49/84/86 account.
+        #[arg(long = "account_path")]
+        account_path: Option<DerivationPath>,
+    },
+    /// Generate a shell completion script for the `bdk-cli` command line, as `bdk-cli completions` does.
+    Completions {
+        /// Shell to generate the completion script for.
+        #[arg(value_enum)]
+        shell: Shell,
     },
+    /// Stage the wallet changes of the following commands in memory until `commit`, instead of
+    /// persisting them after each command.
+    Begin,
+    /// Persist the wallet changes staged since `begin` in a single database write.
+    Commit,
     /// Exit REPL loop.
     Exit,
 }
@@ -96,6 +96,10 @@ pub enum BDKCliError {
     #[error("RPC error: {0}")]
     BitcoinCoreRpcError(#[from] bdk_bitcoind_rpc::bitcoincore_rpc::Error),
 
+    #[cfg(feature = "hwi")]
+    #[error("HWI error: {0:?}")]
+    HwiError(#[from] hwi::error::Error),
+
     #[cfg(feature = "cbf")]
     #[error("BDK-Kyoto builder error: {0}")]
     KyotoBuilderError(#[from] bdk_kyoto::builder::BuilderError),
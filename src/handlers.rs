@@ -9,47 +9,59 @@
 //! Command Handlers
 //!
 //! This module describes all the command handling logic used by bdk-cli.
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+use crate::backup::{BACKUP_STATE_FILES, decrypt_backup};
+use crate::backup::{BACKUP_VERSION, encrypt_backup, wallet_backup_payload};
 use crate::commands::OfflineWalletSubCommand::*;
 use crate::commands::*;
 use crate::error::BDKCliError as Error;
 #[cfg(any(feature = "sqlite", feature = "redb"))]
-use crate::persister::Persister;
+use crate::persister::{Persister, WalletLock};
 use crate::utils::*;
 #[cfg(feature = "redb")]
 use bdk_redb::Store as RedbStore;
 use bdk_wallet::bip39::{Language, Mnemonic};
 use bdk_wallet::bitcoin::base64::Engine;
 use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+use bdk_wallet::bitcoin::hashes::{Hash, sha256};
 use bdk_wallet::bitcoin::{
-    Address, Amount, FeeRate, Network, Psbt, Sequence, Txid,
+    Address, Amount, FeeRate, Network, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Txid,
+    absolute::LockTime,
     bip32::{DerivationPath, KeySource},
-    consensus::encode::serialize_hex,
+    consensus::encode::{deserialize, serialize_hex},
+    hex::FromHex,
     script::PushBytesBuf,
     secp256k1::Secp256k1,
+    transaction::Version,
 };
 use bdk_wallet::chain::ChainPosition;
 use bdk_wallet::descriptor::Segwitv0;
+use bdk_wallet::error::CreateTxError;
 use bdk_wallet::keys::{
-    DerivableKey, DescriptorKey, DescriptorKey::Secret, ExtendedKey, GeneratableKey, GeneratedKey,
-    bip39::WordCount,
+    DerivableKey, DescriptorKey, DescriptorKey::Secret, ExtendedKey, GeneratedKey, bip39::WordCount,
 };
 use bdk_wallet::miniscript::miniscript;
 #[cfg(feature = "sqlite")]
-use bdk_wallet::rusqlite::Connection;
-use bdk_wallet::{KeychainKind, SignOptions, Wallet};
+use bdk_wallet::rusqlite::{Connection, OpenFlags};
+use bdk_wallet::signer::TapLeavesOptions;
+use bdk_wallet::{KeychainKind, LocalOutput, SignOptions, TxOrdering, Wallet};
 #[cfg(feature = "compiler")]
 use bdk_wallet::{
     bitcoin::XOnlyPublicKey,
     descriptor::{Descriptor, Legacy, Miniscript},
     miniscript::{Tap, descriptor::TapTree, policy::Concrete},
 };
-use cli_table::{Cell, CellStruct, Style, Table, format::Justify};
-use serde_json::json;
+use clap::CommandFactory;
+use cli_table::{Cell, CellStruct, Color, Style, Table, format::Justify};
+use serde_json::{Value, json};
 #[cfg(feature = "cbf")]
 use {crate::utils::BlockchainClient::KyotoClient, bdk_kyoto::LightClient, tokio::select};
 
 #[cfg(feature = "electrum")]
 use crate::utils::BlockchainClient::Electrum;
+#[cfg(all(feature = "electrum", unix))]
+use crate::utils::BlockchainClient::ElectrumUnix;
 use std::collections::BTreeMap;
 #[cfg(any(feature = "electrum", feature = "esplora"))]
 use std::collections::HashSet;
@@ -75,7 +87,7 @@ use std::sync::Arc;
 use {
     crate::commands::OnlineWalletSubCommand::*,
     crate::payjoin::{PayjoinManager, ohttp::RelayManager},
-    bdk_wallet::bitcoin::{Transaction, consensus::Decodable, hex::FromHex},
+    bdk_wallet::bitcoin::consensus::Decodable,
     std::sync::Mutex,
 };
 #[cfg(feature = "esplora")]
@@ -93,19 +105,55 @@ const NUMS_UNSPENDABLE_KEY_HEX: &str =
 
 /// Execute an offline wallet sub-command
 ///
-/// Offline wallet sub-commands are described in [`OfflineWalletSubCommand`].
+/// Offline wallet sub-commands are described in [`OfflineWalletSubCommand`]. The fee rates
+/// estimated by confirmation target come from `fee_estimates` when they were fetched from the
+/// blockchain server, from the cached estimates otherwise.
 pub fn handle_offline_wallet_subcommand(
     wallet: &mut Wallet,
     wallet_opts: &WalletOpts,
     cli_opts: &CliOpts,
     offline_subcommand: OfflineWalletSubCommand,
+    fee_estimates: Option<&BTreeMap<u16, f64>>,
 ) -> Result<String, Error> {
     match offline_subcommand {
-        NewAddress => {
-            let addr = wallet.reveal_next_address(KeychainKind::External);
-            if cli_opts.pretty {
+        NewAddress {
+            raw,
+            reuse_unused,
+            address_type,
+        } => {
+            if let Some(address_type) = address_type {
+                let encoding =
+                    descriptor_address_encoding(wallet.public_descriptor(KeychainKind::External));
+                if encoding != Some(address_type) {
+                    return Err(Error::Generic(format!(
+                        "The wallet descriptor only has {} addresses, it can't give a {} one: another encoding pays to a different script, that the wallet doesn't watch.",
+                        encoding.map_or("no", |encoding| encoding.name()),
+                        address_type.name()
+                    )));
+                }
+            }
+            let addr = if reuse_unused {
+                wallet.next_unused_address(KeychainKind::External)
+            } else {
+                wallet.reveal_next_address(KeychainKind::External)
+            };
+            if !wallet_opts.no_reuse_warnings && is_used_script(wallet, &addr.script_pubkey()) {
+                log::warn!(
+                    "Address {} already received funds, reusing it harms the privacy of the wallet.",
+                    addr.address
+                );
+            }
+            if raw {
+                Ok(addr.address.to_string())
+            } else if cli_opts.pretty {
                 let table = vec![
-                    vec!["Address".cell().bold(true), addr.address.to_string().cell()],
+                    vec![
+                        "Address".cell().bold(true),
+                        addr.address
+                            .to_string()
+                            .cell()
+                            .foreground_color(Some(Color::Cyan)),
+                    ],
                     vec![
                         "Index".cell().bold(true),
                         addr.index.to_string().cell().justify(Justify::Right),
@@ -126,12 +174,104 @@ pub fn handle_offline_wallet_subcommand(
                 }))?)
             }
         }
-        UnusedAddress => {
-            let addr = wallet.next_unused_address(KeychainKind::External);
+        // The same address as `new_address --reuse_unused`, through the same code.
+        UnusedAddress => handle_offline_wallet_subcommand(
+            wallet,
+            wallet_opts,
+            cli_opts,
+            NewAddress {
+                raw: false,
+                reuse_unused: true,
+                address_type: None,
+            },
+            fee_estimates,
+        ),
+        NextAddresses { count } => {
+            let next_index = wallet
+                .derivation_index(KeychainKind::External)
+                .map_or(0, |index| index + 1);
+            let first_unused = wallet
+                .list_unused_addresses(KeychainKind::External)
+                .next()
+                .map_or(next_index, |addr| addr.index);
+            let addresses = (first_unused..first_unused.saturating_add(count))
+                .map(|index| {
+                    let addr = wallet.peek_address(KeychainKind::External, index);
+                    let used = is_used_script(wallet, &addr.script_pubkey());
+                    (addr, index < next_index, used)
+                })
+                .collect::<Vec<_>>();
+
+            if cli_opts.pretty {
+                let table = addresses
+                    .iter()
+                    .map(|(addr, revealed, used)| {
+                        vec![
+                            addr.index.to_string().cell().justify(Justify::Right),
+                            addr.address
+                                .to_string()
+                                .cell()
+                                .foreground_color(Some(Color::Cyan)),
+                            revealed.cell().justify(Justify::Center),
+                            used.cell().justify(Justify::Center),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+                    .table()
+                    .title(vec![
+                        "Index".cell().bold(true),
+                        "Address".cell().bold(true),
+                        "Revealed".cell().bold(true),
+                        "Used".cell().bold(true),
+                    ])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                let addresses = addresses
+                    .iter()
+                    .map(|(addr, revealed, used)| {
+                        json!({
+                            "index": addr.index,
+                            "address": addr.address,
+                            "revealed": revealed,
+                            "used": used,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                Ok(serde_json::to_string_pretty(&json!({
+                    "next_index": next_index,
+                    "addresses": addresses,
+                }))?)
+            }
+        }
+        NewChangeAddress => {
+            if !wallet
+                .keychains()
+                .any(|(keychain, _)| keychain == KeychainKind::Internal)
+            {
+                return Err(Error::Generic(
+                    "The wallet has no internal keychain to derive the change address from."
+                        .to_string(),
+                ));
+            }
+            let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+            let state_file =
+                prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?.join(CHANGE_INDEX_FILE);
+            let addr = wallet.reveal_next_address(KeychainKind::Internal);
+            if !cli_opts.dry_run {
+                store_state_file(&state_file, &Some(addr.index))?;
+            }
 
             if cli_opts.pretty {
                 let table = vec![
-                    vec!["Address".cell().bold(true), addr.address.to_string().cell()],
+                    vec![
+                        "Address".cell().bold(true),
+                        addr.address
+                            .to_string()
+                            .cell()
+                            .foreground_color(Some(Color::Cyan)),
+                    ],
                     vec![
                         "Index".cell().bold(true),
                         addr.index.to_string().cell().justify(Justify::Right),
@@ -141,20 +281,116 @@ pub fn handle_offline_wallet_subcommand(
                 .display()
                 .map_err(|e| Error::Generic(e.to_string()))?;
                 Ok(format!("{table}"))
-            } else if wallet_opts.verbose {
-                Ok(serde_json::to_string_pretty(&json!({
-                    "address": addr.address,
-                    "index": addr.index
-                }))?)
             } else {
                 Ok(serde_json::to_string_pretty(&json!({
                     "address": addr.address,
+                    "index": addr.index
                 }))?)
             }
         }
-        Unspent => {
+        Unspent {
+            csv,
+            btc,
+            group_by_address,
+        } => {
+            let denom = if btc { Denom::Btc } else { cli_opts.denom };
             let utxos = wallet.list_unspent().collect::<Vec<_>>();
-            if cli_opts.pretty {
+            if group_by_address {
+                // Scripts without an address form, like bare multisig, are keyed by their hex.
+                let mut groups: BTreeMap<String, Vec<&LocalOutput>> = BTreeMap::new();
+                for utxo in &utxos {
+                    let address = Address::from_script(&utxo.txout.script_pubkey, cli_opts.network)
+                        .map(|address| address.to_string())
+                        .unwrap_or_else(|_| utxo.txout.script_pubkey.to_hex_string());
+                    groups.entry(address).or_default().push(utxo);
+                }
+                let total = |utxos: &[&LocalOutput]| {
+                    utxos
+                        .iter()
+                        .map(|utxo| utxo.txout.value.to_sat())
+                        .sum::<u64>()
+                };
+                if cli_opts.pretty {
+                    let table = groups
+                        .iter()
+                        .map(|(address, utxos)| {
+                            vec![
+                                address.cell(),
+                                utxos.len().cell().justify(Justify::Right),
+                                format_amount(total(utxos), denom)
+                                    .cell()
+                                    .justify(Justify::Right),
+                            ]
+                        })
+                        .collect::<Vec<_>>()
+                        .table()
+                        .title(vec![
+                            "Address".cell().bold(true),
+                            "UTXOs".cell().bold(true),
+                            format!("Total ({})", denom.name()).cell().bold(true),
+                        ])
+                        .display()
+                        .map_err(|e| Error::Generic(e.to_string()))?;
+                    Ok(format!("{table}"))
+                } else {
+                    let mut json_groups = serde_json::Map::new();
+                    for (address, utxos) in groups {
+                        let mut json_utxos = serde_json::to_value(&utxos)?;
+                        for (json_utxo, utxo) in
+                            json_utxos.as_array_mut().into_iter().flatten().zip(&utxos)
+                        {
+                            json_utxo["txout"]["value"] =
+                                amount_value(utxo.txout.value.to_sat(), denom);
+                        }
+                        json_groups.insert(
+                            address,
+                            json!({
+                                "total": amount_value(total(&utxos), denom),
+                                "utxos": json_utxos,
+                            }),
+                        );
+                    }
+                    Ok(serde_json::to_string_pretty(&json_groups)?)
+                }
+            } else if csv {
+                let rows: Vec<Vec<String>> = utxos
+                    .iter()
+                    .map(|utxo| {
+                        let (height, block_hash) = match &utxo.chain_position {
+                            ChainPosition::Confirmed { anchor, .. } => (
+                                anchor.block_id.height.to_string(),
+                                anchor.block_id.hash.to_string(),
+                            ),
+                            ChainPosition::Unconfirmed { .. } => (String::new(), String::new()),
+                        };
+                        vec![
+                            utxo.outpoint.to_string(),
+                            format_amount(utxo.txout.value.to_sat(), denom),
+                            Address::from_script(&utxo.txout.script_pubkey, cli_opts.network)
+                                .map(|address| address.to_string())
+                                .unwrap_or_default(),
+                            utxo.keychain.to_string(),
+                            utxo.derivation_index.to_string(),
+                            utxo.is_spent.to_string(),
+                            height,
+                            block_hash,
+                        ]
+                    })
+                    .collect();
+                Ok(to_csv(
+                    &[
+                        "outpoint",
+                        "value",
+                        "address",
+                        "keychain",
+                        "derivation_index",
+                        "is_spent",
+                        "height",
+                        "block_hash",
+                    ],
+                    &rows,
+                ))
+            } else if cli_opts.pretty {
                 let mut rows: Vec<Vec<CellStruct>> = vec![];
                 for utxo in &utxos {
                     let height = utxo
@@ -170,10 +406,7 @@ pub fn handle_offline_wallet_subcommand(
 
                     rows.push(vec![
                         shorten(utxo.outpoint, 8, 10).cell(),
-                        utxo.txout
-                            .value
-                            .to_sat()
-                            .to_string()
+                        format_amount(utxo.txout.value.to_sat(), denom)
                             .cell()
                             .justify(Justify::Right),
                         Address::from_script(&utxo.txout.script_pubkey, cli_opts.network)
@@ -190,7 +423,7 @@ pub fn handle_offline_wallet_subcommand(
                     .table()
                     .title(vec![
                         "Outpoint".cell().bold(true),
-                        "Output (sat)".cell().bold(true),
+                        format!("Output ({})", denom.name()).cell().bold(true),
                         "Output Address".cell().bold(true),
                         "Keychain".cell().bold(true),
                         "Is Spent".cell().bold(true),
@@ -202,13 +435,208 @@ pub fn handle_offline_wallet_subcommand(
                     .map_err(|e| Error::Generic(e.to_string()))?;
                 Ok(format!("{table}"))
             } else {
-                Ok(serde_json::to_string_pretty(&utxos)?)
+                let mut json_utxos = serde_json::to_value(&utxos)?;
+                for (json_utxo, utxo) in json_utxos.as_array_mut().into_iter().flatten().zip(&utxos)
+                {
+                    json_utxo["txout"]["value"] = amount_value(utxo.txout.value.to_sat(), denom);
+                }
+                Ok(serde_json::to_string_pretty(&json_utxos)?)
             }
         }
-        Transactions => {
-            let transactions = wallet.transactions();
-
+        FreezeUtxo { utxo } => {
+            if wallet.get_utxo(utxo).is_none() {
+                return Err(Error::Generic(format!(
+                    "UTXO {utxo} is not an unspent output of the wallet."
+                )));
+            }
+            let wallet_dir = prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?;
+            let mut frozen = load_frozen_utxos(&wallet_dir)?;
+            frozen.insert(utxo);
+            if !cli_opts.dry_run {
+                store_frozen_utxos(&wallet_dir, &frozen)?;
+            }
+            Ok(serde_json::to_string_pretty(
+                &json!({"utxo": utxo, "frozen": true}),
+            )?)
+        }
+        UnfreezeUtxo { utxo } => {
+            let wallet_dir = prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?;
+            let mut frozen = load_frozen_utxos(&wallet_dir)?;
+            if !frozen.remove(&utxo) {
+                return Err(Error::Generic(format!("UTXO {utxo} is not frozen.")));
+            }
+            if !cli_opts.dry_run {
+                store_frozen_utxos(&wallet_dir, &frozen)?;
+            }
+            Ok(serde_json::to_string_pretty(
+                &json!({"utxo": utxo, "frozen": false}),
+            )?)
+        }
+        ListFrozen => {
+            let wallet_dir = prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?;
+            let frozen: Vec<_> = load_frozen_utxos(&wallet_dir)?
+                .into_iter()
+                .map(|utxo| (utxo, wallet.get_utxo(utxo)))
+                .collect();
+            if cli_opts.pretty {
+                let table = frozen
+                    .iter()
+                    .map(|(utxo, local_output)| {
+                        vec![
+                            utxo.to_string().cell(),
+                            local_output
+                                .as_ref()
+                                .map(|output| output.txout.value.to_sat().to_string())
+                                .unwrap_or_else(|| "spent".to_string())
+                                .cell()
+                                .justify(Justify::Right),
+                        ]
+                    })
+                    .table()
+                    .title(vec![
+                        "Outpoint".cell().bold(true),
+                        "Value".cell().bold(true),
+                    ])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                let frozen: Vec<_> = frozen
+                    .iter()
+                    .map(|(utxo, local_output)| {
+                        json!({
+                            "outpoint": utxo,
+                            "value": local_output
+                                .as_ref()
+                                .map(|output| amount_value(output.txout.value.to_sat(), cli_opts.denom)),
+                            "is_spent": local_output.is_none(),
+                        })
+                    })
+                    .collect();
+                Ok(serde_json::to_string_pretty(&json!({"frozen": frozen}))?)
+            }
+        }
+        UtxoStats => {
+            let utxos = wallet.list_unspent().collect::<Vec<_>>();
+            let stats = utxo_stats(&utxos, wallet.latest_checkpoint().height());
             if cli_opts.pretty {
+                let mut rows: Vec<Vec<CellStruct>> = vec![];
+                for (title, key) in [
+                    ("Count", "count"),
+                    ("Total (sat)", "total"),
+                    ("Min (sat)", "min"),
+                    ("Max (sat)", "max"),
+                    ("Median (sat)", "median"),
+                ] {
+                    rows.push(vec![
+                        title.cell().bold(true),
+                        stats[key].to_string().cell().justify(Justify::Right),
+                    ]);
+                }
+                for (title, key) in [("Value (sat)", "histogram"), ("Depth", "depth_histogram")] {
+                    for bucket in stats[key].as_array().into_iter().flatten() {
+                        rows.push(vec![
+                            format!("{title} {}", bucket["bucket"].as_str().unwrap_or("N/A"))
+                                .cell()
+                                .bold(true),
+                            format!("{} utxos, {} sat", bucket["count"], bucket["total"])
+                                .cell()
+                                .justify(Justify::Right),
+                        ]);
+                    }
+                }
+                let table = rows
+                    .table()
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(&stats)?)
+            }
+        }
+        Transactions {
+            limit,
+            offset,
+            sort,
+            order,
+            since,
+            until,
+            csv,
+            btc,
+        } => {
+            let denom = if btc { Denom::Btc } else { cli_opts.denom };
+            let mut transactions: Vec<_> = wallet
+                .transactions()
+                .filter(|tx| tx_in_range(&tx.chain_position, since, until))
+                .collect();
+            transactions.sort_by_key(|tx| (tx_sort_key(&tx.chain_position, sort), tx.tx_node.txid));
+            if order == SortOrder::Desc {
+                transactions.reverse();
+            }
+            let total = transactions.len();
+            let transactions = transactions
+                .into_iter()
+                .skip(offset)
+                .take(limit.unwrap_or(usize::MAX));
+            let annotations = load_annotations(&prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?)?;
+            let annotation = |txid: &Txid, field: &str| {
+                annotations
+                    .get(txid)
+                    .and_then(|annotation| annotation[field].as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+
+            if csv {
+                let rows: Vec<Vec<String>> = transactions
+                    .map(|tx| {
+                        let (height, time) = match &tx.chain_position {
+                            ChainPosition::Confirmed { anchor, .. } => (
+                                anchor.block_id.height.to_string(),
+                                anchor.confirmation_time.to_string(),
+                            ),
+                            ChainPosition::Unconfirmed { .. } => (String::new(), String::new()),
+                        };
+                        let (sent, received) = wallet.sent_and_received(&tx.tx_node);
+                        let fee = wallet
+                            .calculate_fee(&tx.tx_node)
+                            .map(|fee| format_amount(fee.to_sat(), denom))
+                            .unwrap_or_default();
+                        vec![
+                            tx.tx_node.txid.to_string(),
+                            height,
+                            time,
+                            format_amount(sent.to_sat(), denom),
+                            format_amount(received.to_sat(), denom),
+                            fee,
+                            tx.tx_node.version.to_string(),
+                            tx.tx_node.is_explicitly_rbf().to_string(),
+                            annotation(&tx.tx_node.txid, "category"),
+                            annotation(&tx.tx_node.txid, "fiat"),
+                            annotation(&tx.tx_node.txid, "currency"),
+                        ]
+                    })
+                    .collect();
+                Ok(to_csv(
+                    &[
+                        "txid", "height", "time", "sent", "received", "fee", "version", "is_rbf",
+                        "category", "fiat", "currency",
+                    ],
+                    &rows,
+                ))
+            } else if cli_opts.pretty {
                 let txns = transactions
                     .map(|tx| {
                         let total_value = tx
@@ -224,18 +652,25 @@ pub fn handle_offline_wallet_subcommand(
                             tx.tx_node.input.len(),
                             tx.tx_node.output.len(),
                             total_value,
+                            annotation(&tx.tx_node.txid, "category"),
                         )
                     })
                     .collect::<Vec<_>>();
+                let shown = txns.len();
                 let mut rows: Vec<Vec<CellStruct>> = vec![];
-                for (txid, version, is_rbf, input_count, output_count, total_value) in txns {
+                for (txid, version, is_rbf, input_count, output_count, total_value, category) in
+                    txns
+                {
                     rows.push(vec![
                         txid.cell(),
                         version.to_string().cell().justify(Justify::Right),
                         is_rbf.to_string().cell().justify(Justify::Center),
                         input_count.to_string().cell().justify(Justify::Right),
                         output_count.to_string().cell().justify(Justify::Right),
-                        total_value.to_string().cell().justify(Justify::Right),
+                        format_amount(total_value, denom)
+                            .cell()
+                            .justify(Justify::Right),
+                        category.cell(),
                     ]);
                 }
                 let table = rows
@@ -246,135 +681,416 @@ pub fn handle_offline_wallet_subcommand(
                         "Is RBF".cell().bold(true),
                         "Input Count".cell().bold(true),
                         "Output Count".cell().bold(true),
-                        "Total Value (sat)".cell().bold(true),
+                        format!("Total Value ({})", denom.name()).cell().bold(true),
+                        "Category".cell().bold(true),
                     ])
                     .display()
                     .map_err(|e| Error::Generic(e.to_string()))?;
-                Ok(format!("{table}"))
+                let range = match shown {
+                    0 => "0".to_string(),
+                    _ => format!("{}-{}", offset + 1, offset + shown),
+                };
+                Ok(format!("{table}\nShowing {range} of {total} transactions"))
             } else {
                 let txns: Vec<_> = transactions
                     .map(|tx| {
-                        json!({
+                        let outputs: Vec<_> = tx
+                            .tx_node
+                            .output
+                            .iter()
+                            .map(|output| {
+                                json!({
+                                    "value": amount_value(output.value.to_sat(), denom),
+                                    "script_pubkey": output.script_pubkey,
+                                })
+                            })
+                            .collect();
+                        let mut tx_json = json!({
                             "txid": tx.tx_node.txid,
                             "is_coinbase": tx.tx_node.is_coinbase(),
                             "wtxid": tx.tx_node.compute_wtxid(),
                             "version": tx.tx_node.version,
                             "is_rbf": tx.tx_node.is_explicitly_rbf(),
                             "inputs": tx.tx_node.input,
-                            "outputs": tx.tx_node.output,
-                        })
+                            "outputs": outputs,
+                        });
+                        if let Some(annotation) = annotations.get(&tx.tx_node.txid) {
+                            tx_json["annotation"] = annotation.clone();
+                        }
+                        tx_json
                     })
                     .collect();
-                Ok(serde_json::to_string_pretty(&txns)?)
+                Ok(serde_json::to_string_pretty(&json!({
+                    "total": total,
+                    "offset": offset,
+                    "limit": limit,
+                    "transactions": txns,
+                }))?)
             }
         }
-        Balance => {
+        Annotate {
+            txid,
+            category,
+            fiat,
+            currency,
+        } => {
+            if wallet.get_tx(txid).is_none() {
+                return Err(Error::Generic(format!(
+                    "Transaction {txid} is not in the wallet."
+                )));
+            }
+            let wallet_dir = prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?;
+            let mut annotations = load_annotations(&wallet_dir)?;
+            let annotation = annotations.entry(txid).or_insert_with(|| json!({}));
+            if let Some(category) = category {
+                annotation["category"] = json!(category.name());
+            }
+            if let (Some(fiat), Some(currency)) = (fiat, currency) {
+                annotation["fiat"] = json!(fiat);
+                annotation["currency"] = json!(currency);
+            }
+            let annotation = annotation.clone();
+            if !cli_opts.dry_run {
+                store_annotations(&wallet_dir, &annotations)?;
+            }
+            Ok(serde_json::to_string_pretty(
+                &json!({"txid": txid, "annotation": annotation}),
+            )?)
+        }
+        Balance { raw } => {
             let balance = wallet.balance();
-            if cli_opts.pretty {
+            let denom = cli_opts.denom;
+            if raw {
+                Ok(format_amount(balance.confirmed.to_sat(), denom))
+            } else if cli_opts.pretty {
+                let amount_cell = |amount: Amount| {
+                    format_amount(amount.to_sat(), denom)
+                        .cell()
+                        .justify(Justify::Right)
+                };
                 let table = vec![
-                    vec!["Type".cell().bold(true), "Amount (sat)".cell().bold(true)],
-                    vec![
-                        "Total".cell(),
-                        balance
-                            .total()
-                            .to_sat()
-                            .to_string()
-                            .cell()
-                            .justify(Justify::Right),
-                    ],
                     vec![
-                        "Confirmed".cell(),
-                        balance
-                            .confirmed
-                            .to_sat()
-                            .to_string()
-                            .cell()
-                            .justify(Justify::Right),
+                        "Type".cell().bold(true),
+                        format!("Amount ({})", denom.name()).cell().bold(true),
                     ],
                     vec![
-                        "Unconfirmed".cell(),
-                        balance
-                            .immature
-                            .to_sat()
-                            .to_string()
-                            .cell()
-                            .justify(Justify::Right),
+                        "Total".cell(),
+                        amount_cell(balance.total())
+                            .bold(true)
+                            .foreground_color(Some(Color::Green)),
                     ],
+                    vec!["Confirmed".cell(), amount_cell(balance.confirmed)],
+                    vec!["Unconfirmed".cell(), amount_cell(balance.immature)],
                     vec![
                         "Trusted Pending".cell(),
-                        balance
-                            .trusted_pending
-                            .to_sat()
-                            .cell()
-                            .justify(Justify::Right),
+                        amount_cell(balance.trusted_pending),
                     ],
                     vec![
                         "Untrusted Pending".cell(),
-                        balance
-                            .untrusted_pending
-                            .to_sat()
-                            .cell()
-                            .justify(Justify::Right),
+                        amount_cell(balance.untrusted_pending),
                     ],
                 ]
                 .table()
                 .display()
                 .map_err(|e| Error::Generic(e.to_string()))?;
                 Ok(format!("{table}"))
-            } else {
+            } else if denom == Denom::Sat {
                 Ok(serde_json::to_string_pretty(
                     &json!({"satoshi": wallet.balance()}),
                 )?)
+            } else {
+                Ok(serde_json::to_string_pretty(&json!({
+                    denom.name(): {
+                        "immature": format_amount(balance.immature.to_sat(), denom),
+                        "trusted_pending": format_amount(balance.trusted_pending.to_sat(), denom),
+                        "untrusted_pending": format_amount(balance.untrusted_pending.to_sat(), denom),
+                        "confirmed": format_amount(balance.confirmed.to_sat(), denom),
+                    }
+                }))?)
             }
         }
 
         CreateTx {
-            recipients,
+            mut recipients,
+            to_descriptor,
+            to_script,
             send_all,
             enable_rbf,
             offline_signer,
             utxos,
             unspendable,
+            change_address,
+            change_keychain,
+            allow_external_change,
+            anchor,
+            bip69,
+            preserve_order,
+            allow_immature,
+            split_change,
+            sweep_small,
+            subtract_fee_from,
+            no_change,
+            min_change,
+            no_broadcast,
+            estimate_satisfaction,
             fee_rate,
             external_policy,
             internal_policy,
             add_data,
             add_string,
         } => {
-            let mut tx_builder = wallet.build_tx();
+            let mut descriptor_indexes = None;
+            if let Some((descriptor, amount)) = to_descriptor {
+                let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+                let state_file = prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?
+                    .join(DESCRIPTOR_INDEX_FILE);
+                let mut indexes: BTreeMap<String, u32> = load_state_file(&state_file)?;
+                let (address, index) =
+                    next_descriptor_address(&descriptor, &mut indexes, cli_opts.network)?;
+                log::info!("Paying to descriptor address {address} at index {index}");
+                recipients.push((address.script_pubkey(), amount));
+                descriptor_indexes = Some((state_file, indexes));
+            }
+            for (script, _) in to_script.iter().filter(|(s, _)| !is_standard_script(s)) {
+                log::warn!(
+                    "The script {} isn't a standard output type, nodes won't relay the transaction by default.",
+                    script.to_hex_string()
+                );
+            }
+            recipients.extend(to_script);
+
+            let mut subtract_fee_from = subtract_fee_from.unwrap_or_default();
+            subtract_fee_from.sort_unstable();
+            subtract_fee_from.dedup();
+            if let Some(index) = subtract_fee_from
+                .iter()
+                .find(|index| **index >= recipients.len())
+            {
+                return Err(Error::Generic(format!(
+                    "Recipient index {index} of --subtract_fee_from is out of range, the first recipient has index 0."
+                )));
+            }
+
+            if !wallet_opts.no_reuse_warnings {
+                for (script, _) in recipients.iter().filter(|(s, _)| is_used_script(wallet, s)) {
+                    let address = Address::from_script(script, cli_opts.network)
+                        .map(|address| address.to_string())
+                        .unwrap_or_else(|_| script.to_hex_string());
+                    log::warn!(
+                        "Recipient {address} is an already used address of this wallet, reusing it harms its privacy."
+                    );
+                }
+            }
+
+            let change_script = match (change_address, change_keychain) {
+                (Some(address), _) => {
+                    let script = address.script_pubkey();
+                    if !allow_external_change && !wallet.is_mine(script.clone()) {
+                        return Err(Error::Generic(format!(
+                            "Change address {address} is not owned by the wallet, use --allow_external_change to send the change to it."
+                        )));
+                    }
+                    Some(script)
+                }
+                (None, Some(keychain)) => {
+                    let keychain = KeychainKind::from(keychain);
+                    if !wallet.keychains().any(|(k, _)| k == keychain) {
+                        return Err(Error::Generic(format!(
+                            "The wallet has no {keychain:?} keychain to derive the change address from."
+                        )));
+                    }
+                    // Reveal a fresh address, the change output isn't seen by the wallet until the
+                    // transaction is broadcast and synced.
+                    Some(wallet.reveal_next_address(keychain).script_pubkey())
+                }
+                (None, None) if !send_all => {
+                    // Don't send the change to an address given out by `new_change_address`,
+                    // the wallet would otherwise pick the first unused one.
+                    let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+                    let state_file = prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?
+                        .join(CHANGE_INDEX_FILE);
+                    let given_out: Option<u32> = load_state_file(&state_file)?;
+                    let unused: Vec<_> = wallet
+                        .list_unused_addresses(KeychainKind::Internal)
+                        .collect();
+                    given_out
+                        .filter(|given_out| unused.iter().any(|addr| addr.index <= *given_out))
+                        .map(
+                            |given_out| match unused.iter().find(|addr| addr.index > given_out) {
+                                Some(addr) => addr.script_pubkey(),
+                                None => wallet
+                                    .reveal_next_address(KeychainKind::Internal)
+                                    .script_pubkey(),
+                            },
+                        )
+                }
+                (None, None) => None,
+            };
+
+            let immature_utxos = immature_coinbase_utxos(wallet);
+            if !allow_immature {
+                if let Some(utxo) = immature_utxos
+                    .iter()
+                    .find(|utxo| utxos.iter().flatten().any(|op| *op == utxo.outpoint))
+                {
+                    return Err(Error::Generic(format!(
+                        "UTXO {} is an immature coinbase output, use --allow_immature to spend it anyway.",
+                        utxo.outpoint
+                    )));
+                }
+            }
+            let tip = wallet.latest_checkpoint().height();
+            let frozen_utxos = load_frozen_utxos(&prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?)?;
+
+            let fee_rate = match fee_rate {
+                Some(TxFeeRate::SatPerVb(fee_rate)) => FeeRate::from_sat_per_vb(fee_rate as u64),
+                Some(TxFeeRate::Auto) => {
+                    let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+                    let fee_rate = estimated_fee_rate(
+                        &prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?,
+                        AUTO_FEE_TARGET,
+                        fee_estimates,
+                    )?;
+                    FeeRate::from_sat_per_vb(fee_rate as u64)
+                }
+                None => None,
+            };
+            let policies = [
+                external_policy.map(|p| (p, KeychainKind::External)),
+                internal_policy.map(|p| (p, KeychainKind::Internal)),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|(policy, keychain)| {
+                Ok((
+                    serde_json::from_str::<BTreeMap<String, Vec<usize>>>(&policy)?,
+                    keychain,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+            let tx_ordering = if bip69 {
+                bip69_ordering()
+            } else if preserve_order {
+                TxOrdering::Untouched
+            } else {
+                TxOrdering::Shuffle
+            };
+
+            let mut small_utxos = Vec::new();
+            if let Some(threshold) = sweep_small {
+                let sweep_fee_rate = fee_rate.unwrap_or(FeeRate::BROADCAST_MIN);
+                for utxo in wallet.list_unspent() {
+                    let excluded = utxos.iter().flatten().any(|op| *op == utxo.outpoint)
+                        || unspendable.iter().flatten().any(|op| *op == utxo.outpoint)
+                        || frozen_utxos.contains(&utxo.outpoint)
+                        || immature_utxos
+                            .iter()
+                            .any(|immature| immature.outpoint == utxo.outpoint);
+                    if excluded || utxo.txout.value.to_sat() >= threshold {
+                        continue;
+                    }
+                    // Only sweep the UTXOs worth more than the fee of their input.
+                    let satisfaction_weight = wallet
+                        .public_descriptor(utxo.keychain)
+                        .max_weight_to_satisfy()
+                        .map_err(|e| Error::Generic(e.to_string()))?;
+                    let input_fee = sweep_fee_rate
+                        .fee_wu(TxIn::default().segwit_weight() + satisfaction_weight)
+                        .unwrap_or(Amount::MAX_MONEY);
+                    if utxo.txout.value > input_fee {
+                        small_utxos.push(utxo.outpoint);
+                    }
+                }
+            }
+
+            let coin_selection = TxCoinSelection {
+                no_change,
+                min_change: min_change.map(Amount::from_sat),
+            };
+            let mut tx_builder = wallet.build_tx().coin_selection(coin_selection);
+
+            if allow_immature {
+                // Coin selection treats every coinbase output as mature at this height, the
+                // locktime stays at the tip for fee sniping protection.
+                tx_builder
+                    .current_height(tip + COINBASE_MATURITY)
+                    .nlocktime(
+                        LockTime::from_height(tip).map_err(|e| Error::Generic(e.to_string()))?,
+                    );
+            }
 
             if send_all {
                 tx_builder.drain_wallet().drain_to(recipients[0].0.clone());
             } else {
                 let recipients = recipients
-                    .into_iter()
-                    .map(|(script, amount)| (script, Amount::from_sat(amount)))
+                    .iter()
+                    .map(|(script, amount)| (script.clone(), Amount::from_sat(*amount)))
                     .collect();
                 tx_builder.set_recipients(recipients);
             }
 
+            if let Some(change_script) = &change_script {
+                tx_builder.drain_to(change_script.clone());
+            }
+
+            if anchor {
+                log::warn!(
+                    "P2A outputs are only relayed by recent nodes, the transaction may not propagate widely."
+                );
+                let anchor_script = ScriptBuf::new_p2a();
+                let anchor_value = anchor_script.minimal_non_dust();
+                tx_builder.add_recipient(anchor_script, anchor_value);
+            }
+
             if !enable_rbf {
                 tx_builder.set_exact_sequence(Sequence::MAX);
             }
 
+            tx_builder.ordering(tx_ordering.clone());
+            let ordering = if bip69 {
+                "bip69"
+            } else if preserve_order {
+                "untouched"
+            } else {
+                "shuffle"
+            };
+
             if offline_signer {
                 tx_builder.include_output_redeem_witness_script();
             }
 
             if let Some(fee_rate) = fee_rate {
-                if let Some(fee_rate) = FeeRate::from_sat_per_vb(fee_rate as u64) {
-                    tx_builder.fee_rate(fee_rate);
-                }
+                tx_builder.fee_rate(fee_rate);
             }
 
-            if let Some(utxos) = utxos {
+            if let Some(utxos) = &utxos {
                 tx_builder.add_utxos(&utxos[..]).unwrap();
             }
 
+            if !small_utxos.is_empty() {
+                tx_builder
+                    .add_utxos(&small_utxos)
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+            }
+
             if let Some(unspendable) = unspendable {
                 tx_builder.unspendable(unspendable);
             }
 
+            // Frozen UTXOs can still be spent by selecting them explicitly.
+            for frozen in frozen_utxos
+                .iter()
+                .filter(|frozen| !utxos.iter().flatten().any(|utxo| utxo == *frozen))
+            {
+                tx_builder.add_unspendable(*frozen);
+            }
+
             if let Some(base64_data) = add_data {
                 let op_return_data = BASE64_STANDARD.decode(base64_data).unwrap();
                 tx_builder.add_data(&PushBytesBuf::try_from(op_return_data).unwrap());
@@ -383,55 +1099,455 @@ pub fn handle_offline_wallet_subcommand(
                 tx_builder.add_data(&data);
             }
 
-            let policies = vec![
-                external_policy.map(|p| (p, KeychainKind::External)),
-                internal_policy.map(|p| (p, KeychainKind::Internal)),
-            ];
-
-            for (policy, keychain) in policies.into_iter().flatten() {
-                let policy = serde_json::from_str::<BTreeMap<String, Vec<usize>>>(&policy)?;
-                tx_builder.policy_path(policy, keychain);
+            for (policy, keychain) in &policies {
+                tx_builder.policy_path(policy.clone(), *keychain);
             }
 
-            let psbt = tx_builder.finish()?;
+            let mut psbt = match tx_builder.finish() {
+                Err(CreateTxError::CoinSelection(e)) if no_change && e.available >= e.needed => {
+                    return Err(Error::Generic(
+                        "No selection of inputs pays the recipients and the fee without change, within the cost of a change output.".to_string(),
+                    ));
+                }
+                Err(CreateTxError::CoinSelection(e))
+                    if !allow_immature && !immature_utxos.is_empty() =>
+                {
+                    let immature: Amount = immature_utxos.iter().map(|utxo| utxo.txout.value).sum();
+                    return Err(Error::Generic(format!(
+                        "{e}, {immature} more are in immature coinbase outputs that need {COINBASE_MATURITY} confirmations, use --allow_immature to spend them anyway."
+                    )));
+                }
+                result => result?,
+            };
 
-            let psbt_base64 = BASE64_STANDARD.encode(psbt.serialize());
+            let rebuild_params = RebuildParams {
+                coin_selection,
+                current_height: allow_immature.then_some(tip + COINBASE_MATURITY),
+                change_script: change_script.as_ref(),
+                recipients: &recipients,
+                offline_signer,
+                fee_rate,
+                policies: &policies,
+                ordering: tx_ordering,
+            };
 
-            if wallet_opts.verbose {
-                Ok(serde_json::to_string_pretty(
-                    &json!({"psbt": psbt_base64, "details": psbt}),
-                )?)
-            } else {
-                Ok(serde_json::to_string_pretty(
-                    &json!({"psbt": psbt_base64 }),
-                )?)
-            }
-        }
-        BumpFee {
-            txid,
-            shrink_address,
-            offline_signer,
-            utxos,
-            unspendable,
-            fee_rate,
-        } => {
-            let txid = Txid::from_str(txid.as_str())?;
+            let mut recipient_amounts = None;
+            if !subtract_fee_from.is_empty() {
+                // Spend the same inputs again with the fee taken out of the selected recipients,
+                // until the fee of the new transaction matches the subtracted amount.
 
-            let mut tx_builder = wallet.build_fee_bump(txid)?;
-            let fee_rate =
-                FeeRate::from_sat_per_vb(fee_rate as u64).unwrap_or(FeeRate::BROADCAST_MIN);
-            tx_builder.fee_rate(fee_rate);
+                // Match every recipient to an output of the first transaction, the change output
+                // is dropped and any other output (anchor, OP_RETURN) is kept as is.
+                let mut matched: Vec<usize> = Vec::with_capacity(recipients.len());
+                for (script, amount) in &recipients {
+                    let vout = psbt
+                        .unsigned_tx
+                        .output
+                        .iter()
+                        .enumerate()
+                        .position(|(vout, output)| {
+                            output.script_pubkey == *script
+                                && output.value.to_sat() == *amount
+                                && !matched.contains(&vout)
+                        })
+                        .ok_or_else(|| {
+                            Error::Generic("Recipient output not found in the transaction.".into())
+                        })?;
+                    matched.push(vout);
+                }
+                let change_vout =
+                    draft_change_vout(wallet, &rebuild_params, &psbt.unsigned_tx, &matched);
+                let outputs: Vec<(ScriptBuf, Amount)> = psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .map(|output| (output.script_pubkey.clone(), output.value))
+                    .collect();
 
-            if let Some(address) = shrink_address {
-                let script_pubkey = address.script_pubkey();
-                tx_builder.drain_to(script_pubkey);
+                // Each recipient pays a share of the fee proportional to its amount, the last one
+                // also pays the rounding remainder.
+                let total: u128 = subtract_fee_from
+                    .iter()
+                    .map(|index| u128::from(recipients[*index].1))
+                    .sum();
+                let mut subtracted = psbt.fee()?;
+                for _ in 0..3 {
+                    let mut reduced = outputs.clone();
+                    let mut remaining = subtracted.to_sat();
+                    for (n, index) in subtract_fee_from.iter().enumerate() {
+                        let (script, value) = &mut reduced[matched[*index]];
+                        let deduction = if n == subtract_fee_from.len() - 1 {
+                            remaining
+                        } else {
+                            (u128::from(subtracted.to_sat()) * u128::from(recipients[*index].1)
+                                / total) as u64
+                        };
+                        remaining -= deduction;
+                        let deduction = Amount::from_sat(deduction);
+                        match value.checked_sub(deduction) {
+                            Some(rest) if rest >= script.minimal_non_dust() => *value = rest,
+                            _ => {
+                                return Err(Error::Generic(format!(
+                                    "Recipient {index} would be below the dust limit after subtracting its {deduction} share of the fee."
+                                )));
+                            }
+                        }
+                    }
+
+                    let new_psbt = rebuild_tx(
+                        wallet,
+                        &rebuild_params,
+                        &psbt,
+                        reduced
+                            .iter()
+                            .enumerate()
+                            .filter(|(vout, _)| Some(*vout) != change_vout)
+                            .map(|(_, output)| output.clone())
+                            .collect(),
+                        change_script.as_ref(),
+                    )?;
+                    let fee = new_psbt.fee()?;
+                    let done = fee <= subtracted;
+                    subtracted = fee;
+                    psbt = new_psbt;
+                    if done {
+                        recipient_amounts = Some(
+                            matched
+                                .iter()
+                                .enumerate()
+                                .map(|(index, position)| {
+                                    let (script, value) = &reduced[*position];
+                                    json!({
+                                        "index": index,
+                                        "address": Address::from_script(script, cli_opts.network)
+                                            .map(|address| address.to_string())
+                                            .ok(),
+                                        "amount": amount_value(value.to_sat(), cli_opts.denom),
+                                    })
+                                })
+                                .collect::<Vec<_>>(),
+                        );
+                        break;
+                    }
+                }
+                if recipient_amounts.is_none() {
+                    return Err(Error::Generic(
+                        "Could not find a fee to subtract from the recipients.".to_string(),
+                    ));
+                }
             }
 
-            if offline_signer {
+            let mut change_outputs = None;
+            if let Some(split_change) = split_change {
+                // Spend the same inputs again, with the change of the first transaction divided
+                // between fresh change addresses and the original change output.
+                let change_index =
+                    draft_change_vout(wallet, &rebuild_params, &psbt.unsigned_tx, &[]).ok_or_else(
+                        || {
+                            Error::Generic(
+                                "The transaction has no change output to split.".to_string(),
+                            )
+                        },
+                    )?;
+                let change = psbt.unsigned_tx.output[change_index].clone();
+                let extra_fee = fee_rate
+                    .unwrap_or(FeeRate::BROADCAST_MIN)
+                    .fee_vb(change.size() as u64 * (split_change as u64 - 1))
+                    .unwrap_or(Amount::MAX_MONEY);
+                let share = change
+                    .value
+                    .checked_sub(extra_fee)
+                    .map(|value| value / split_change as u64)
+                    .unwrap_or(Amount::ZERO);
+                if share < change.script_pubkey.minimal_non_dust() {
+                    return Err(Error::Generic(format!(
+                        "The change of {} is too small to split into {split_change} outputs above the dust limit.",
+                        change.value
+                    )));
+                }
+
+                let mut outputs: Vec<(ScriptBuf, Amount)> = psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != change_index)
+                    .map(|(_, output)| (output.script_pubkey.clone(), output.value))
+                    .collect();
+                let mut split_scripts: Vec<ScriptBuf> = (1..split_change)
+                    .map(|_| {
+                        wallet
+                            .reveal_next_address(KeychainKind::Internal)
+                            .script_pubkey()
+                    })
+                    .collect();
+                outputs.extend(split_scripts.iter().map(|script| (script.clone(), share)));
+                split_scripts.push(change.script_pubkey.clone());
+                psbt = rebuild_tx(
+                    wallet,
+                    &rebuild_params,
+                    &psbt,
+                    outputs,
+                    Some(&change.script_pubkey),
+                )?;
+
+                let outputs: Vec<Value> = psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, output)| split_scripts.contains(&output.script_pubkey))
+                    .map(|(vout, output)| {
+                        json!({
+                            "vout": vout,
+                            "address": Address::from_script(&output.script_pubkey, cli_opts.network)
+                                .map(|address| address.to_string())
+                                .ok(),
+                            "amount": amount_value(output.value.to_sat(), cli_opts.denom),
+                        })
+                    })
+                    .collect();
+                if outputs.len() != split_change as usize {
+                    return Err(Error::Generic(format!(
+                        "The change of {} is too small to split into {split_change} outputs above the dust limit.",
+                        change.value
+                    )));
+                }
+                change_outputs = Some(outputs);
+            }
+
+            let satisfaction = if estimate_satisfaction {
+                Some(satisfaction_estimate(wallet, &psbt)?)
+            } else {
+                None
+            };
+
+            let raw_tx = if no_broadcast {
+                if !wallet.sign(&mut psbt, SignOptions::default())? {
+                    return Err(Error::Generic(
+                        "The wallet keys can't finalize the transaction alone, create it without --no_broadcast and collect the other signatures with sign.".to_string(),
+                    ));
+                }
+                Some(psbt.clone().extract_tx()?)
+            } else {
+                None
+            };
+
+            if let Some((state_file, indexes)) = descriptor_indexes.filter(|_| !cli_opts.dry_run) {
+                store_state_file(&state_file, &indexes)?;
+            }
+
+            let psbt_base64 = BASE64_STANDARD.encode(psbt.serialize());
+
+            let mut result = if wallet_opts.verbose {
+                json!({"psbt": psbt_base64, "ordering": ordering, "details": psbt})
+            } else {
+                json!({"psbt": psbt_base64, "ordering": ordering })
+            };
+            if let Some(raw_tx) = raw_tx {
+                result["raw_tx"] = json!(serialize_hex(&raw_tx));
+                result["txid"] = json!(raw_tx.compute_txid());
+            }
+            if let Some(satisfaction) = satisfaction {
+                result["satisfaction"] = satisfaction;
+            }
+            if let Some(change_outputs) = change_outputs {
+                result["change_outputs"] = json!(change_outputs);
+            }
+            if let Some(recipient_amounts) = recipient_amounts {
+                result["recipients"] = json!(recipient_amounts);
+            }
+            if sweep_small.is_some() {
+                result["swept_small_utxos"] = json!(small_utxos.len());
+            }
+            if let Some(min_change) = min_change {
+                let change = psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .filter(|output| {
+                        is_change_output(wallet, change_script.as_ref(), &recipients, output)
+                    })
+                    .map(|output| output.value)
+                    .sum::<Amount>();
+                let absorbed = if change == Amount::ZERO {
+                    psbt_fee_excess(wallet, &psbt, fee_rate)?
+                } else {
+                    Amount::ZERO
+                };
+                result["change"] = json!({
+                    "min_change": amount_value(min_change, cli_opts.denom),
+                    "created": change > Amount::ZERO,
+                    "amount": amount_value(change.to_sat(), cli_opts.denom),
+                    "absorbed_into_fee": amount_value(absorbed.to_sat(), cli_opts.denom),
+                });
+            }
+            if no_change {
+                let excess = psbt_fee_excess(wallet, &psbt, fee_rate)?;
+                result["exact_match"] = json!(excess == Amount::ZERO);
+                result["excess"] = amount_value(excess.to_sat(), cli_opts.denom);
+            }
+            if anchor {
+                let anchor_script = ScriptBuf::new_p2a();
+                result["anchor_vout"] = json!(
+                    psbt.unsigned_tx
+                        .output
+                        .iter()
+                        .position(|output| output.script_pubkey == anchor_script)
+                );
+            }
+            Ok(serde_json::to_string_pretty(&result)?)
+        }
+        SimulateSend {
+            recipients,
+            fee_rates,
+        } => {
+            let frozen_utxos = load_frozen_utxos(&prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?)?;
+            // Building a transaction reveals and reserves its change address, restore what was
+            // staged before so the simulation leaves nothing to persist.
+            let staged = wallet.staged().cloned().unwrap_or_default();
+
+            let mut simulations = Vec::with_capacity(fee_rates.len());
+            for fee_rate in fee_rates {
+                let mut tx_builder = wallet.build_tx();
+                tx_builder
+                    .set_recipients(
+                        recipients
+                            .iter()
+                            .map(|(script, amount)| (script.clone(), Amount::from_sat(*amount)))
+                            .collect(),
+                    )
+                    .fee_rate(FeeRate::from_sat_per_vb(fee_rate).unwrap_or(FeeRate::BROADCAST_MIN));
+                for frozen in &frozen_utxos {
+                    tx_builder.add_unspendable(*frozen);
+                }
+                let psbt = match tx_builder.finish() {
+                    Ok(psbt) => psbt,
+                    Err(e) => {
+                        simulations.push(json!({ "fee_rate": fee_rate, "error": e.to_string() }));
+                        continue;
+                    }
+                };
+                wallet.cancel_tx(&psbt.unsigned_tx);
+
+                let change: u64 = psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .filter(|output| {
+                        wallet.is_mine(output.script_pubkey.clone())
+                            && !recipients.iter().any(|(script, amount)| {
+                                *script == output.script_pubkey && *amount == output.value.to_sat()
+                            })
+                    })
+                    .map(|output| output.value.to_sat())
+                    .sum();
+                simulations.push(json!({
+                    "fee_rate": fee_rate,
+                    "fee": amount_value(psbt.fee()?.to_sat(), cli_opts.denom),
+                    "vsize": psbt_signed_weight(wallet, &psbt)?.to_vbytes_ceil(),
+                    "change": amount_value(change, cli_opts.denom),
+                    "inputs": psbt.unsigned_tx.input.len(),
+                }));
+            }
+            if let Some(changeset) = wallet.staged_mut() {
+                *changeset = staged;
+            }
+
+            if cli_opts.pretty {
+                let table = simulations
+                    .iter()
+                    .map(|simulation| {
+                        let cell = |key: &str| match &simulation[key] {
+                            Value::Null => "-".to_string(),
+                            Value::String(s) => s.clone(),
+                            value => value.to_string(),
+                        };
+                        vec![
+                            cell("fee_rate").cell(),
+                            cell("fee").cell().justify(Justify::Right),
+                            cell("vsize").cell().justify(Justify::Right),
+                            cell("change").cell().justify(Justify::Right),
+                            cell("inputs").cell().justify(Justify::Right),
+                            cell("error").cell(),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+                    .table()
+                    .title(vec![
+                        "Fee rate".cell().bold(true),
+                        format!("Fee ({})", cli_opts.denom.name()).cell().bold(true),
+                        "Vsize".cell().bold(true),
+                        format!("Change ({})", cli_opts.denom.name())
+                            .cell()
+                            .bold(true),
+                        "Inputs".cell().bold(true),
+                        "Error".cell().bold(true),
+                    ])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(
+                    &json!({ "simulations": simulations }),
+                )?)
+            }
+        }
+        BumpFee {
+            txid,
+            shrink_address,
+            offline_signer,
+            utxos,
+            unspendable,
+            fee_rate,
+            target,
+        } => {
+            let txid = Txid::from_str(txid.as_str())?;
+            let wallet_dir = prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?;
+            let frozen_utxos = load_frozen_utxos(&wallet_dir)?;
+            let original_tx = wallet
+                .get_tx(txid)
+                .map(|tx| tx.tx_node.tx)
+                .ok_or_else(|| Error::Generic(format!("Transaction {txid} not found.")))?;
+
+            let fee_rate = match target {
+                Some(target) => {
+                    // Keep the fraction of the estimate, 250 sat/kwu make a sat/vbyte.
+                    let estimate = estimated_fee_rate(&wallet_dir, target, fee_estimates)?;
+                    let fee_rate = FeeRate::from_sat_per_kwu((estimate * 250.0).ceil() as u64);
+                    let original_fee_rate = wallet
+                        .calculate_fee_rate(&original_tx)
+                        .map_err(|e| Error::Generic(e.to_string()))?;
+                    if fee_rate <= original_fee_rate {
+                        return Err(Error::Generic(format!(
+                            "The fee estimate for a confirmation within {target} blocks is {:.2} sat/vbyte, not above the {:.2} sat/vbyte of the original transaction: a replacement must pay a higher fee rate.",
+                            fee_rate.to_sat_per_kwu() as f64 / 250.0,
+                            original_fee_rate.to_sat_per_kwu() as f64 / 250.0
+                        )));
+                    }
+                    fee_rate
+                }
+                None => FeeRate::from_sat_per_vb(fee_rate as u64).unwrap_or(FeeRate::BROADCAST_MIN),
+            };
+            let mut tx_builder = wallet.build_fee_bump(txid)?;
+            tx_builder.fee_rate(fee_rate);
+
+            let shrink_script = shrink_address.map(|address| address.script_pubkey());
+            if let Some(script_pubkey) = &shrink_script {
+                tx_builder.drain_to(script_pubkey.clone());
+            }
+
+            if offline_signer {
                 tx_builder.include_output_redeem_witness_script();
             }
 
-            if let Some(utxos) = utxos {
+            if let Some(utxos) = &utxos {
                 tx_builder.add_utxos(&utxos[..]).unwrap();
             }
 
@@ -439,13 +1555,194 @@ pub fn handle_offline_wallet_subcommand(
                 tx_builder.unspendable(unspendable);
             }
 
+            for frozen in frozen_utxos
+                .iter()
+                .filter(|frozen| !utxos.iter().flatten().any(|utxo| utxo == *frozen))
+            {
+                tx_builder.add_unspendable(*frozen);
+            }
+
             let psbt = tx_builder.finish()?;
+            if !cli_opts.dry_run {
+                record_replacement(wallet, &wallet_dir, &psbt.unsigned_tx)?;
+            }
+
+            let mut report = fee_bump_report(
+                wallet,
+                &original_tx,
+                &psbt,
+                shrink_script.as_ref(),
+                cli_opts.denom,
+            )?;
+            if let Some(target) = target {
+                report["target"] = json!(target);
+                report["fee_rate"] = json!(fee_rate.to_sat_per_vb_ceil());
+            }
 
             let psbt_base64 = BASE64_STANDARD.encode(psbt.serialize());
+            report["psbt"] = json!(psbt_base64);
 
-            Ok(serde_json::to_string_pretty(
-                &json!({"psbt": psbt_base64 }),
-            )?)
+            Ok(serde_json::to_string_pretty(&report)?)
+        }
+        ResetState { .. } => Err(Error::Generic(
+            "reset_state needs a wallet database and can't be used in the REPL.".to_string(),
+        )),
+        ReplacementHistory { txid } => {
+            let replacements = load_replacements(&prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?)?;
+            let chain = replacement_chain(&replacements, txid);
+            // The wallet only returns the transactions of the canonical chain, the replaced ones
+            // are only in its transaction graph.
+            let history: Vec<Value> = chain
+                .iter()
+                .map(|txid| {
+                    let status = match wallet.get_tx(*txid).map(|tx| tx.chain_position) {
+                        Some(ChainPosition::Confirmed { .. }) => "confirmed",
+                        Some(ChainPosition::Unconfirmed { .. }) => "unconfirmed",
+                        None if wallet.tx_graph().get_tx(*txid).is_some() => "replaced",
+                        None => "unknown",
+                    };
+                    json!({"txid": txid, "status": status})
+                })
+                .collect();
+            let live = history
+                .iter()
+                .find(|tx| tx["status"] == "confirmed" || tx["status"] == "unconfirmed")
+                .map(|tx| tx["txid"].clone());
+
+            if cli_opts.pretty {
+                let table = history
+                    .iter()
+                    .map(|tx| {
+                        vec![
+                            tx["txid"].as_str().unwrap_or_default().cell(),
+                            tx["status"].as_str().unwrap_or_default().cell(),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+                    .table()
+                    .title(vec!["Txid".cell().bold(true), "Status".cell().bold(true)])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(
+                    &json!({"history": history, "live": live}),
+                )?)
+            }
+        }
+        RbfStatus { txid } => {
+            let status = rbf_status(wallet, txid)?;
+
+            if cli_opts.pretty {
+                let ancestors = status["ancestors"].as_array().cloned().unwrap_or_default();
+                let rows = std::iter::once(("transaction", &status))
+                    .chain(ancestors.iter().map(|ancestor| ("ancestor", ancestor)))
+                    .map(|(relation, tx)| {
+                        vec![
+                            tx["txid"].as_str().unwrap_or_default().cell(),
+                            relation.cell(),
+                            tx["signals_rbf"]
+                                .to_string()
+                                .cell()
+                                .justify(Justify::Center),
+                        ]
+                    });
+                let table = rows
+                    .table()
+                    .title(vec![
+                        "Txid".cell().bold(true),
+                        "Relation".cell().bold(true),
+                        "Signals RBF".cell().bold(true),
+                    ])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                let mut output = format!(
+                    "{table}\nStatus: {}\nReplaceable: {}",
+                    status["status"].as_str().unwrap_or_default(),
+                    status["replaceable"]
+                        .as_str()
+                        .map_or_else(|| status["replaceable"].to_string(), str::to_string)
+                );
+                for parent in status["unknown_parents"].as_array().into_iter().flatten() {
+                    output.push_str(&format!(
+                        "\nUnknown parent: {}",
+                        parent.as_str().unwrap_or_default()
+                    ));
+                }
+                Ok(output)
+            } else {
+                Ok(serde_json::to_string_pretty(&status)?)
+            }
+        }
+        InsertTx {
+            raw,
+            height,
+            block_hash,
+            block_time,
+        } => {
+            use bdk_wallet::Update;
+            use bdk_wallet::chain::{BlockId, ConfirmationBlockTime};
+
+            let tx: Transaction = deserialize(&Vec::<u8>::from_hex(raw.trim())?)?;
+            let txid = tx.compute_txid();
+            // Spent outputs are only counted when the wallet already knows the transactions
+            // creating them.
+            let (sent, received) = wallet.sent_and_received(&tx);
+            if sent == Amount::ZERO && received == Amount::ZERO {
+                return Err(Error::Generic(format!(
+                    "Transaction {txid} neither spends from nor pays to the wallet."
+                )));
+            }
+            if received == Amount::ZERO {
+                log::warn!("None of the outputs of transaction {txid} belong to the wallet.");
+            }
+
+            match height {
+                Some(height) => {
+                    let known_hash = wallet.local_chain().get(height).map(|cp| cp.hash());
+                    let hash = match (block_hash, known_hash) {
+                        (Some(hash), Some(known_hash)) if hash != known_hash => {
+                            return Err(Error::Generic(format!(
+                                "The wallet has block {known_hash} at height {height}, not {hash}."
+                            )));
+                        }
+                        (Some(hash), _) | (None, Some(hash)) => hash,
+                        (None, None) => {
+                            return Err(Error::Generic(format!(
+                                "The wallet doesn't know the block at height {height}, give its hash with --block_hash."
+                            )));
+                        }
+                    };
+                    let block_id = BlockId { height, hash };
+                    let mut update = Update::default();
+                    update.tx_update.txs.push(tx.into());
+                    update.tx_update.anchors.insert((
+                        ConfirmationBlockTime {
+                            block_id,
+                            confirmation_time: block_time,
+                        },
+                        txid,
+                    ));
+                    update.chain = Some(wallet.latest_checkpoint().insert(block_id));
+                    wallet.apply_update(update)?;
+                }
+                None if tx.is_coinbase() => {
+                    return Err(Error::Generic(
+                        "A coinbase transaction can't be unconfirmed, give its --height."
+                            .to_string(),
+                    ));
+                }
+                None => wallet.apply_unconfirmed_txs([(tx, unix_now())]),
+            }
+            Ok(serde_json::to_string_pretty(&json!({
+                "txid": txid,
+                "height": height,
+                "sent": amount_value(sent.to_sat(), cli_opts.denom),
+                "received": amount_value(received.to_sat(), cli_opts.denom),
+            }))?)
         }
         Policies => {
             let external_policy = wallet.policies(KeychainKind::External)?;
@@ -498,113 +1795,604 @@ pub fn handle_offline_wallet_subcommand(
                 }))?)
             }
         }
-        Sign {
-            psbt,
-            assume_height,
-            trust_witness_utxo,
-        } => {
-            let psbt_bytes = BASE64_STANDARD.decode(psbt)?;
-            let mut psbt = Psbt::deserialize(&psbt_bytes)?;
-            let signopt = SignOptions {
-                assume_height,
-                trust_witness_utxo: trust_witness_utxo.unwrap_or(false),
-                ..Default::default()
-            };
-            let finalized = wallet.sign(&mut psbt, signopt)?;
-            let psbt_base64 = BASE64_STANDARD.encode(psbt.serialize());
-            if wallet_opts.verbose {
-                Ok(serde_json::to_string_pretty(
-                    &json!({"psbt": &psbt_base64, "is_finalized": finalized, "serialized_psbt": &psbt}),
-                )?)
+        ExportGraph { out } => {
+            let export = wallet_graph_export(wallet);
+            std::fs::write(&out, serde_json::to_string(&export)?)
+                .map_err(|e| Error::Generic(e.to_string()))?;
+            let transactions = wallet.tx_graph().full_txs().count();
+            let blocks = wallet.local_chain().iter_checkpoints().count();
+
+            if cli_opts.pretty {
+                let table = vec![
+                    vec!["Graph File".cell().bold(true), out.display().cell()],
+                    vec!["Transactions".cell().bold(true), transactions.cell()],
+                    vec!["Blocks".cell().bold(true), blocks.cell()],
+                ]
+                .table()
+                .display()
+                .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
             } else {
                 Ok(serde_json::to_string_pretty(
-                    &json!({"psbt": &psbt_base64, "is_finalized": finalized}),
+                    &json!({"file": out, "transactions": transactions, "blocks": blocks}),
                 )?)
             }
         }
-        ExtractPsbt { psbt } => {
-            let psbt_serialized = BASE64_STANDARD.decode(psbt)?;
-            let psbt = Psbt::deserialize(&psbt_serialized)?;
-            let raw_tx = psbt.extract_tx()?;
+        ImportGraph { file } => {
+            let export =
+                std::fs::read_to_string(&file).map_err(|e| Error::Generic(e.to_string()))?;
+            let update = graph_import_update(wallet, serde_json::from_str(&export)?)?;
+            let known = wallet.tx_graph().full_txs().count();
+            wallet.apply_update(update)?;
+            let transactions = wallet.tx_graph().full_txs().count();
+
             if cli_opts.pretty {
-                let table = vec![vec![
-                    "Raw Transaction".cell().bold(true),
-                    serialize_hex(&raw_tx).cell(),
-                ]]
+                let table = vec![
+                    vec![
+                        "Imported Transactions".cell().bold(true),
+                        (transactions - known).cell(),
+                    ],
+                    vec!["Transactions".cell().bold(true), transactions.cell()],
+                    vec![
+                        "Tip".cell().bold(true),
+                        wallet.latest_checkpoint().height().cell(),
+                    ],
+                ]
                 .table()
                 .display()
                 .map_err(|e| Error::Generic(e.to_string()))?;
                 Ok(format!("{table}"))
             } else {
-                Ok(serde_json::to_string_pretty(
-                    &json!({"raw_tx": serialize_hex(&raw_tx)}),
-                )?)
+                Ok(serde_json::to_string_pretty(&json!({
+                    "imported_transactions": transactions - known,
+                    "transactions": transactions,
+                    "tip": wallet.latest_checkpoint().height(),
+                }))?)
             }
         }
-        FinalizePsbt {
-            psbt,
-            assume_height,
-            trust_witness_utxo,
-        } => {
-            let psbt_bytes = BASE64_STANDARD.decode(psbt)?;
-            let mut psbt: Psbt = Psbt::deserialize(&psbt_bytes)?;
+        CheckAddresses { file, gap } => {
+            let addresses =
+                std::fs::read_to_string(&file).map_err(|e| Error::Generic(e.to_string()))?;
+            let results = check_addresses(wallet, &addresses, gap)?;
 
-            let signopt = SignOptions {
-                assume_height,
-                trust_witness_utxo: trust_witness_utxo.unwrap_or(false),
-                ..Default::default()
-            };
-            let finalized = wallet.finalize_psbt(&mut psbt, signopt)?;
-            if wallet_opts.verbose {
-                Ok(serde_json::to_string_pretty(
-                    &json!({ "psbt": BASE64_STANDARD.encode(psbt.serialize()), "is_finalized": finalized, "details": psbt}),
-                )?)
+            if cli_opts.pretty {
+                let table = results
+                    .iter()
+                    .map(|(address, result)| {
+                        vec![
+                            address.cell(),
+                            result["is_mine"]
+                                .to_string()
+                                .cell()
+                                .justify(Justify::Center),
+                            result["keychain"].as_str().unwrap_or_default().cell(),
+                            result
+                                .get("index")
+                                .map(Value::to_string)
+                                .unwrap_or_default()
+                                .cell(),
+                            result["error"].as_str().unwrap_or_default().cell(),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+                    .table()
+                    .title(vec![
+                        "Address".cell().bold(true),
+                        "Mine".cell().bold(true),
+                        "Keychain".cell().bold(true),
+                        "Index".cell().bold(true),
+                        "Error".cell().bold(true),
+                    ])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
             } else {
-                Ok(serde_json::to_string_pretty(
-                    &json!({ "psbt": BASE64_STANDARD.encode(psbt.serialize()), "is_finalized": finalized}),
-                )?)
+                let addresses: serde_json::Map<_, _> = results.into_iter().collect();
+                let owned = addresses
+                    .values()
+                    .filter(|result| result["is_mine"] == true)
+                    .count();
+                Ok(serde_json::to_string_pretty(&json!({
+                    "checked": addresses.len(),
+                    "owned": owned,
+                    "addresses": addresses,
+                }))?)
             }
         }
-        CombinePsbt { psbt } => {
-            let mut psbts = psbt
-                .iter()
-                .map(|s| {
-                    let psbt = BASE64_STANDARD.decode(s)?;
-                    Ok(Psbt::deserialize(&psbt)?)
-                })
-                .collect::<Result<Vec<_>, Error>>()?;
+        SkipIndex { keychain, to } => {
+            let keychain = KeychainKind::from(keychain);
+            if !wallet.keychains().any(|(kind, _)| kind == keychain) {
+                return Err(Error::Generic(format!(
+                    "The wallet has no {} keychain.",
+                    keychain_name(keychain)
+                )));
+            }
+            let next_index = wallet
+                .derivation_index(keychain)
+                .map_or(0, |index| index + 1);
+            if to <= next_index {
+                return Err(Error::Generic(format!(
+                    "The next {} index is already {next_index}, it can only move forward past it.",
+                    keychain_name(keychain)
+                )));
+            }
+            let _ = wallet.reveal_addresses_to(keychain, to - 1);
+            if keychain == KeychainKind::Internal && !cli_opts.dry_run {
+                // Give out the skipped change addresses, so that `create_tx` doesn't fall back to
+                // the first unused one.
+                let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+                let state_file =
+                    prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?.join(CHANGE_INDEX_FILE);
+                store_state_file(&state_file, &Some(to - 1))?;
+            }
 
-            let init_psbt = psbts
-                .pop()
-                .ok_or_else(|| Error::Generic("Invalid PSBT input".to_string()))?;
-            let final_psbt = psbts.into_iter().try_fold::<_, _, Result<Psbt, Error>>(
-                init_psbt,
-                |mut acc, x| {
-                    let _ = acc.combine(x);
-                    Ok(acc)
-                },
-            )?;
-            Ok(serde_json::to_string_pretty(
-                &json!({ "psbt": BASE64_STANDARD.encode(final_psbt.serialize()) }),
-            )?)
+            if cli_opts.pretty {
+                let table = vec![
+                    vec!["Keychain".cell().bold(true), keychain_name(keychain).cell()],
+                    vec![
+                        "Next index".cell().bold(true),
+                        to.to_string().cell().justify(Justify::Right),
+                    ],
+                    vec![
+                        "Skipped".cell().bold(true),
+                        (to - next_index).to_string().cell().justify(Justify::Right),
+                    ],
+                ]
+                .table()
+                .display()
+                .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(&json!({
+                    "keychain": keychain_name(keychain),
+                    "previous_next_index": next_index,
+                    "next_index": to,
+                    "skipped": to - next_index,
+                }))?)
+            }
         }
-    }
-}
+        ImportChangeset { file } => {
+            let changeset =
+                std::fs::read_to_string(&file).map_err(|e| Error::Generic(e.to_string()))?;
+            let report = import_changeset(wallet, serde_json::from_str(&changeset)?)?;
 
-/// Execute an online wallet sub-command
-///
-/// Online wallet sub-commands are described in [`OnlineWalletSubCommand`].
-#[cfg(any(
-    feature = "electrum",
-    feature = "esplora",
+            if cli_opts.pretty {
+                let mut rows = vec![
+                    vec![
+                        "Transactions Added".cell().bold(true),
+                        report["transactions_added"].to_string().cell(),
+                    ],
+                    vec![
+                        "Blocks Added".cell().bold(true),
+                        report["blocks_added"].to_string().cell(),
+                    ],
+                    vec!["Tip".cell().bold(true), report["tip"].to_string().cell()],
+                ];
+                for (keychain, indexes) in
+                    report["revealed_indexes"].as_object().into_iter().flatten()
+                {
+                    rows.push(vec![
+                        format!("Revealed {keychain}").cell().bold(true),
+                        format!("{} -> {}", indexes["before"], indexes["after"]).cell(),
+                    ]);
+                }
+                let table = rows
+                    .table()
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(&report)?)
+            }
+        }
+        Backup { out, force } => {
+            let wallet_dir = prepare_wallet_db_dir(
+                &wallet_opts.wallet,
+                &prepare_home_dir(cli_opts.datadir.clone())?,
+            )?;
+            let payload = wallet_backup_payload(wallet, &wallet_dir)?;
+            let passphrase = read_passphrase("Backup passphrase: ", true)?;
+            let backup = encrypt_backup(&payload, &passphrase)?;
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(!force)
+                .create(force)
+                .truncate(force)
+                .open(&out)
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::AlreadyExists => Error::Generic(format!(
+                        "The backup file {} already exists, use --force to overwrite it.",
+                        out.display()
+                    )),
+                    _ => Error::Generic(e.to_string()),
+                })?;
+            std::io::Write::write_all(&mut file, backup.as_bytes())
+                .map_err(|e| Error::Generic(e.to_string()))?;
+
+            if cli_opts.pretty {
+                let table = vec![vec!["Backup File".cell().bold(true), out.display().cell()]]
+                    .table()
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(
+                    &json!({"file": out, "version": BACKUP_VERSION}),
+                )?)
+            }
+        }
+        DumpPrivKey {
+            address,
+            confirm_danger,
+        } => {
+            if !confirm_danger {
+                return Err(Error::Generic(
+                    "Dumping a private key exposes the funds of the address, pass --confirm_danger to proceed.".to_string(),
+                ));
+            }
+            let (keychain, index) = wallet
+                .derivation_of_spk(address.script_pubkey())
+                .ok_or_else(|| {
+                    Error::Generic(format!("Address {address} is not owned by the wallet."))
+                })?;
+            let private_keys = derive_private_keys(wallet, keychain, index)?;
+            let private_key = match private_keys.as_slice() {
+                [] => {
+                    return Err(Error::Generic(
+                        "The wallet is watch-only, no private key to dump.".to_string(),
+                    ));
+                }
+                [private_key] => private_key,
+                _ => {
+                    return Err(Error::Generic(
+                        "The descriptor has more than one private key, only single key descriptors are supported.".to_string(),
+                    ));
+                }
+            };
+            log::warn!(
+                "Dumping the private key of {address}, anyone with this key can spend its funds."
+            );
+            let wif = private_key.to_wif();
+
+            if cli_opts.pretty {
+                let table = vec![
+                    vec!["Address".cell().bold(true), address.to_string().cell()],
+                    vec!["Keychain".cell().bold(true), keychain.cell()],
+                    vec![
+                        "Index".cell().bold(true),
+                        index.to_string().cell().justify(Justify::Right),
+                    ],
+                    vec!["WIF".cell().bold(true), wif.cell()],
+                ]
+                .table()
+                .display()
+                .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(&json!({
+                    "address": address,
+                    "keychain": keychain,
+                    "index": index,
+                    "wif": wif,
+                }))?)
+            }
+        }
+        Sign {
+            psbt,
+            assume_height,
+            trust_witness_utxo,
+            xprv,
+            also_descriptors,
+            tap_leaves,
+            no_tap_internal_key,
+            grind_r,
+            max_fee_rate,
+            force,
+        } => {
+            let psbt_bytes = BASE64_STANDARD.decode(psbt)?;
+            let mut psbt = Psbt::deserialize(&psbt_bytes)?;
+            if let Some(max_fee_rate) = max_fee_rate {
+                let (fee, fee_rate) = psbt_fee_rate(wallet, &psbt)?;
+                if fee_rate > max_fee_rate {
+                    let message = format!(
+                        "The PSBT pays a fee of {fee}, {fee_rate:.2} sat/vbyte, above the maximum of {max_fee_rate} sat/vbyte."
+                    );
+                    if !force {
+                        return Err(Error::Generic(format!(
+                            "{message} Use --force to sign it anyway."
+                        )));
+                    }
+                    log::warn!("{message}");
+                }
+            }
+            let tap_leaves_options = if tap_leaves.is_empty() {
+                TapLeavesOptions::All
+            } else {
+                TapLeavesOptions::Include(tap_leaves)
+            };
+            let owned_inputs = owned_inputs(wallet, &psbt);
+            // BDK requires the previous transaction of every input, but only the amounts of the
+            // wallet's own inputs matter to its signatures. The foreign inputs of collaborative
+            // transactions usually only have a witness UTXO. Taproot signatures commit to the
+            // amounts of every input, whether an owned input is taproot is taken from the
+            // outputs the wallet knows, never from the PSBT.
+            let spends_taproot = |txin: &TxIn| {
+                wallet
+                    .tx_graph()
+                    .get_txout(txin.previous_output)
+                    .is_some_and(|txout| txout.script_pubkey.is_p2tr())
+            };
+            let unchecked_inputs = psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .zip(&psbt.inputs)
+                .zip(&owned_inputs)
+                .enumerate()
+                .filter(|(_, ((_, input), owned))| {
+                    **owned
+                        && input.non_witness_utxo.is_none()
+                        && input.final_script_sig.is_none()
+                        && input.final_script_witness.is_none()
+                })
+                .filter(|(_, ((txin, _), _))| !spends_taproot(txin))
+                .map(|(index, ((_, input), _))| (index, input))
+                .collect::<Vec<_>>();
+            let trust_witness_utxo = trust_witness_utxo.unwrap_or(unchecked_inputs.is_empty());
+            // BDK skips its own check of the previous transaction for inputs with taproot fields.
+            if let Some((index, _)) = unchecked_inputs
+                .iter()
+                .filter(|_| !trust_witness_utxo)
+                .find(|(_, input)| {
+                    input.tap_internal_key.is_some() || input.tap_merkle_root.is_some()
+                })
+            {
+                return Err(Error::Generic(format!(
+                    "Input {index} has taproot fields but doesn't spend a taproot output of the wallet, its previous transaction is needed to check its amount."
+                )));
+            }
+            let owned_count = owned_inputs.iter().filter(|owned| **owned).count();
+            let inputs = json!({
+                "owned": owned_count,
+                "foreign": owned_inputs.len() - owned_count,
+            });
+            let signopt = SignOptions {
+                assume_height,
+                trust_witness_utxo,
+                tap_leaves_options,
+                sign_with_tap_internal_key: !no_tap_internal_key,
+                allow_grinding: grind_r.unwrap_or(SignOptions::default().allow_grinding),
+                ..Default::default()
+            };
+            let grind_r = signopt.allow_grinding;
+            let xprv_signers = xprv.map(|xprv| xprv_signers(wallet, &xprv)).transpose()?;
+            let also_wallets = also_descriptors
+                .iter()
+                .enumerate()
+                .map(|(index, descriptor)| {
+                    let other = Wallet::create_single(descriptor.clone())
+                        .network(cli_opts.network)
+                        .create_wallet_no_persist()?;
+                    if wallet_signer_ids(&other).is_empty() {
+                        return Err(Error::Generic(format!(
+                            "The descriptor {index} of --also_descriptor has no private key."
+                        )));
+                    }
+                    Ok(other)
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let mut signer_ids = wallet_signer_ids(wallet);
+            signer_ids.extend(
+                xprv_signers
+                    .iter()
+                    .flatten()
+                    .flat_map(|signers| signers.ids())
+                    .cloned(),
+            );
+            signer_ids.extend(also_wallets.iter().flat_map(wallet_signer_ids));
+            // The signing keys are listed before signing, finalization drops the key origins.
+            let signing_keys: Vec<_> = psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .zip(&psbt.inputs)
+                .enumerate()
+                .map(|(index, (txin, psbt_input))| {
+                    json!({
+                        "input": index,
+                        "outpoint": txin.previous_output.to_string(),
+                        "keys": signing_key_origins(&signer_ids, psbt_input),
+                    })
+                })
+                .collect();
+            let signature_count = psbt_signature_count(&psbt);
+            let mut signatures_added = None;
+            let finalized = if xprv_signers.is_some() || !also_wallets.is_empty() {
+                // Let the wallet fill in the PSBT metadata the transient signers rely on,
+                // finalization is deferred until they have signed.
+                let no_finalize = SignOptions {
+                    try_finalize: false,
+                    ..signopt.clone()
+                };
+                wallet.sign(&mut psbt, no_finalize.clone())?;
+                for signer in xprv_signers
+                    .iter()
+                    .flatten()
+                    .flat_map(|signers| signers.signers())
+                {
+                    signer.sign_transaction(&mut psbt, &signopt, wallet.secp_ctx())?;
+                }
+                let mut previous_count = psbt_signature_count(&psbt);
+                let wallet_added = previous_count - signature_count;
+                let mut also_added = Vec::with_capacity(also_wallets.len());
+                for other in &also_wallets {
+                    other.sign(&mut psbt, no_finalize.clone())?;
+                    let count = psbt_signature_count(&psbt);
+                    also_added.push(count - previous_count);
+                    previous_count = count;
+                }
+                if !also_wallets.is_empty() {
+                    signatures_added =
+                        Some(json!({"wallet": wallet_added, "also_descriptors": also_added}));
+                }
+                wallet.finalize_psbt(&mut psbt, signopt)?
+            } else {
+                wallet.sign(&mut psbt, signopt)?
+            };
+            // Nothing was signed and no input has one of the signers' key origins: most likely
+            // the PSBT was created for other keys, which passes silently otherwise.
+            if psbt_signature_count(&psbt) == signature_count
+                && !finalized
+                && signing_keys
+                    .iter()
+                    .all(|input| input["keys"].as_array().map_or(true, Vec::is_empty))
+            {
+                return Err(fingerprint_mismatch_error(&signer_ids, &psbt));
+            }
+            let psbt_base64 = BASE64_STANDARD.encode(psbt.serialize());
+            let mut result = json!({"psbt": &psbt_base64, "is_finalized": finalized, "inputs": inputs, "signing_keys": signing_keys, "grind_r": grind_r});
+            if let Some(signatures_added) = signatures_added {
+                result["signatures_added"] = signatures_added;
+            }
+            if wallet_opts.verbose {
+                result["serialized_psbt"] = json!(&psbt);
+            }
+            Ok(serde_json::to_string_pretty(&result)?)
+        }
+        ExtractPsbt { psbt } => {
+            let psbt_serialized = BASE64_STANDARD.decode(psbt)?;
+            let psbt = Psbt::deserialize(&psbt_serialized)?;
+            let raw_tx = psbt.extract_tx()?;
+            if cli_opts.pretty {
+                let table = vec![vec![
+                    "Raw Transaction".cell().bold(true),
+                    serialize_hex(&raw_tx).cell(),
+                ]]
+                .table()
+                .display()
+                .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(
+                    &json!({"raw_tx": serialize_hex(&raw_tx)}),
+                )?)
+            }
+        }
+        FinalizePsbt {
+            psbt,
+            assume_height,
+            trust_witness_utxo,
+        } => {
+            let psbt_bytes = BASE64_STANDARD.decode(psbt)?;
+            let mut psbt: Psbt = Psbt::deserialize(&psbt_bytes)?;
+
+            let signopt = SignOptions {
+                assume_height,
+                trust_witness_utxo: trust_witness_utxo.unwrap_or(false),
+                ..Default::default()
+            };
+            let finalized = wallet.finalize_psbt(&mut psbt, signopt)?;
+            if wallet_opts.verbose {
+                Ok(serde_json::to_string_pretty(
+                    &json!({ "psbt": BASE64_STANDARD.encode(psbt.serialize()), "is_finalized": finalized, "details": psbt}),
+                )?)
+            } else {
+                Ok(serde_json::to_string_pretty(
+                    &json!({ "psbt": BASE64_STANDARD.encode(psbt.serialize()), "is_finalized": finalized}),
+                )?)
+            }
+        }
+        PsbtStatus { psbt } => {
+            let psbt_bytes = BASE64_STANDARD.decode(psbt)?;
+            let psbt = Psbt::deserialize(&psbt_bytes)?;
+            let status = psbt_signing_status(wallet, &psbt)?;
+
+            if cli_opts.pretty {
+                let inputs = status["inputs"].as_array().cloned().unwrap_or_default();
+                let fingerprints = |value: &Value| {
+                    value
+                        .as_array()
+                        .map(|fps| {
+                            fps.iter()
+                                .filter_map(Value::as_str)
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        })
+                        .unwrap_or_default()
+                };
+                let rows = inputs.iter().map(|input| {
+                    let signatures = if input["is_mine"] == true {
+                        format!("{}/{}", input["signatures"], input["required"])
+                    } else {
+                        "not owned".to_string()
+                    };
+                    vec![
+                        input["outpoint"].as_str().unwrap_or_default().cell(),
+                        signatures.cell(),
+                        input["is_finalized"].to_string().cell(),
+                        fingerprints(&input["signed_by"]).cell(),
+                        fingerprints(&input["not_signed_by"]).cell(),
+                    ]
+                });
+                let table = rows
+                    .table()
+                    .title(vec![
+                        "Outpoint".cell().bold(true),
+                        "Signatures".cell().bold(true),
+                        "Finalized".cell().bold(true),
+                        "Signed By".cell().bold(true),
+                        "Not Signed By".cell().bold(true),
+                    ])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}\nComplete: {}", status["is_complete"]))
+            } else {
+                Ok(serde_json::to_string_pretty(&status)?)
+            }
+        }
+        CombinePsbt { psbt } => {
+            let psbts = psbt
+                .iter()
+                .map(|s| {
+                    let psbt = BASE64_STANDARD.decode(s)?;
+                    Ok(Psbt::deserialize(&psbt)?)
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let final_psbt = combine_psbts(psbts)?;
+            Ok(serde_json::to_string_pretty(
+                &json!({ "psbt": BASE64_STANDARD.encode(final_psbt.serialize()) }),
+            )?)
+        }
+    }
+}
+
+/// Execute an online wallet sub-command
+///
+/// Online wallet sub-commands are described in [`OnlineWalletSubCommand`].
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
     feature = "cbf",
     feature = "rpc"
 ))]
 pub(crate) async fn handle_online_wallet_subcommand(
     wallet: &mut Wallet,
+    wallet_dir: &std::path::Path,
     client: BlockchainClient,
     online_subcommand: OnlineWalletSubCommand,
+    dry_run: bool,
 ) -> Result<String, Error> {
+    if dry_run
+        && matches!(
+            online_subcommand,
+            ReceivePayjoin { .. } | SendPayjoin { .. }
+        )
+    {
+        return Err(Error::Generic(
+            "Payjoins exchange transactions with the other party, they can't be run with --dry-run."
+                .to_string(),
+        ));
+    }
     match online_subcommand {
         FullScan {
             stop_gap: _stop_gap,
@@ -614,6 +2402,9 @@ pub(crate) async fn handle_online_wallet_subcommand(
                 let mut stdout = std::io::stdout();
                 let mut once = HashSet::<KeychainKind>::new();
                 move |k, spk_i, _| {
+                    if is_quiet() {
+                        return;
+                    }
                     if once.insert(k) {
                         print!("\nScanning keychain [{k:?}]");
                     }
@@ -624,23 +2415,24 @@ pub(crate) async fn handle_online_wallet_subcommand(
             match client {
                 #[cfg(feature = "electrum")]
                 Electrum { client, batch_size } => {
-                    // Populate the electrum client's transaction cache so it doesn't re-download transaction we
-                    // already have.
-                    client
-                        .populate_tx_cache(wallet.tx_graph().full_txs().map(|tx_node| tx_node.tx));
-
-                    let update = client.full_scan(request, _stop_gap, batch_size, false)?;
-                    wallet.apply_update(update)?;
+                    electrum_full_scan(&client, wallet, request, _stop_gap, batch_size)?;
+                }
+                #[cfg(all(feature = "electrum", unix))]
+                ElectrumUnix { client, batch_size } => {
+                    electrum_full_scan(&client, wallet, request, _stop_gap, batch_size)?;
                 }
                 #[cfg(feature = "esplora")]
                 Esplora {
                     client,
                     parallel_requests,
+                    backoff_ms,
                 } => {
-                    let update = client
-                        .full_scan(request, _stop_gap, parallel_requests)
-                        .await
-                        .map_err(|e| *e)?;
+                    let update = with_esplora_backoff(
+                        backoff_ms,
+                        client.full_scan(request, _stop_gap, parallel_requests),
+                    )
+                    .await
+                    .map_err(|e| esplora_error(*e))?;
                     wallet.apply_update(update)?;
                 }
 
@@ -654,15 +2446,17 @@ pub(crate) async fn handle_online_wallet_subcommand(
                         height: 0,
                         hash: genesis_block.block_hash(),
                     });
+                    // The blocks before the birthday of a restored wallet hold none of its
+                    // transactions.
                     let mut emitter = Emitter::new(
                         &*client,
                         genesis_cp.clone(),
-                        genesis_cp.height(),
+                        load_birthday(wallet_dir)?.unwrap_or(genesis_cp.height()),
                         NO_EXPECTED_MEMPOOL_TXS,
                     );
 
                     while let Some(block_event) = emitter.next_block()? {
-                        if block_event.block_height() % 10_000 == 0 {
+                        if block_event.block_height() % 10_000 == 0 && !is_quiet() {
                             let percent_done = f64::from(block_event.block_height())
                                 / f64::from(blockchain_info.headers as u32)
                                 * 100f64;
@@ -690,10 +2484,125 @@ pub(crate) async fn handle_online_wallet_subcommand(
             }
             Ok(serde_json::to_string_pretty(&json!({}))?)
         }
-        Sync => {
-            sync_wallet(client, wallet).await?;
+        Sync {
+            from_height,
+            to_height,
+        } => {
+            if let (Some(from_height), Some(to_height)) = (from_height, to_height) {
+                if from_height > to_height {
+                    return Err(Error::Generic(format!(
+                        "--from_height {from_height} is above --to_height {to_height}."
+                    )));
+                }
+            }
+            if from_height.is_none() && to_height.is_none() {
+                sync_wallet(client, wallet, load_birthday(wallet_dir)?).await?;
+            } else {
+                match client {
+                    #[cfg(feature = "rpc")]
+                    RpcClient { client } => {
+                        sync_rpc_client(&client, wallet, from_height, to_height)?
+                    }
+                    #[allow(unreachable_patterns)]
+                    _ => {
+                        return Err(Error::Generic(
+                            "--from_height and --to_height are only supported with the rpc client."
+                                .to_string(),
+                        ));
+                    }
+                }
+            }
             Ok(serde_json::to_string_pretty(&json!({}))?)
         }
+        Rebroadcast { txid } => {
+            let mut skipped = Vec::new();
+            let txs = match txid {
+                Some(txid) => {
+                    let tx = wallet.get_tx(txid).ok_or_else(|| {
+                        Error::Generic(format!("Transaction {txid} not found in the wallet."))
+                    })?;
+                    if tx.chain_position.is_confirmed() {
+                        skipped.push(txid);
+                        vec![]
+                    } else {
+                        vec![tx.tx_node.tx.as_ref().clone()]
+                    }
+                }
+                None => wallet
+                    .transactions()
+                    .filter(|tx| !tx.chain_position.is_confirmed())
+                    .map(|tx| tx.tx_node.tx.as_ref().clone())
+                    .collect(),
+            };
+            let txs = parents_first(txs);
+            let txids: Vec<_> = txs.iter().map(|tx| tx.compute_txid()).collect();
+            if dry_run {
+                return Ok(serde_json::to_string_pretty(&json!({
+                    "rebroadcast": txids,
+                    "rejected": [],
+                    "skipped": skipped,
+                }))?);
+            }
+
+            let mut rebroadcast = Vec::new();
+            let mut rejected = Vec::new();
+            for (txid, result) in txids
+                .into_iter()
+                .zip(broadcast_transactions(client, txs).await?)
+            {
+                match result {
+                    Ok(_) => rebroadcast.push(txid),
+                    Err(e) => rejected.push(json!({"txid": txid, "error": e.to_string()})),
+                }
+            }
+            Ok(serde_json::to_string_pretty(&json!({
+                "rebroadcast": rebroadcast,
+                "rejected": rejected,
+                "skipped": skipped,
+            }))?)
+        }
+        FeeEstimates {
+            when: Some(fee_rate),
+        } => {
+            let blocks = fetch_confirmation_target(client, fee_rate).await?;
+            let caveat = match blocks {
+                Some(_) => "Best effort estimate from the current mempool, it can change quickly."
+                    .to_string(),
+                None => format!(
+                    "The fee rate is below the minimum relay fee rate or the estimate of every target up to {MAX_CONFIRMATION_TARGET} blocks, the transaction may take much longer or never confirm."
+                ),
+            };
+            Ok(serde_json::to_string_pretty(&json!({
+                "fee_rate": fee_rate,
+                "blocks": blocks,
+                "caveat": caveat,
+            }))?)
+        }
+        FeeEstimates { when: None } => {
+            let estimates = fetch_fee_estimates(client).await?;
+            let cache = store_fee_estimates(wallet_dir, &estimates, dry_run)?;
+            Ok(serde_json::to_string_pretty(&cache)?)
+        }
+        GetRawTx { txid, verbose } => {
+            let tx = fetch_transaction(client, txid).await?;
+            let hex = serialize_hex(&tx);
+            if verbose {
+                Ok(serde_json::to_string_pretty(&json!({
+                    "hex": hex,
+                    "txid": txid,
+                    "wtxid": tx.compute_wtxid(),
+                    "version": tx.version,
+                    "locktime": tx.lock_time,
+                    "size": tx.total_size(),
+                    "vsize": tx.vsize(),
+                    "weight": tx.weight().to_wu(),
+                    "inputs": tx.input,
+                    "outputs": tx.output,
+                }))?)
+            } else {
+                Ok(serde_json::to_string_pretty(&json!({ "hex": hex }))?)
+            }
+        }
         Broadcast { psbt, tx } => {
             let tx = match (psbt, tx) {
                 (Some(psbt), None) => {
@@ -711,7 +2620,13 @@ pub(crate) async fn handle_online_wallet_subcommand(
                 (Some(_), Some(_)) => panic!("Both `psbt` and `tx` options not allowed"),
                 (None, None) => panic!("Missing `psbt` and `tx` option"),
             };
-            let txid = broadcast_transaction(client, tx).await?;
+            let txid = if dry_run {
+                tx.compute_txid()
+            } else {
+                let txid = broadcast_transaction(client, tx.clone()).await?;
+                record_replacement(wallet, wallet_dir, &tx)?;
+                txid
+            };
             Ok(serde_json::to_string_pretty(&json!({ "txid": txid }))?)
         }
         ReceivePayjoin {
@@ -776,6 +2691,7 @@ pub(crate) fn is_final(psbt: &Psbt) -> Result<(), Error> {
 pub(crate) fn handle_key_subcommand(
     network: Network,
     subcommand: KeySubCommand,
+    test_seed: Option<[u8; 32]>,
     pretty: bool,
 ) -> Result<String, Error> {
     let secp = Secp256k1::new();
@@ -790,7 +2706,7 @@ pub(crate) fn handle_key_subcommand(
                 _ => WordCount::Words24,
             };
             let mnemonic: GeneratedKey<_, miniscript::BareCtx> =
-                Mnemonic::generate((mnemonic_type, Language::English))
+                generate_mnemonic(mnemonic_type, test_seed)
                     .map_err(|_| Error::Generic("Mnemonic generation error".to_string()))?;
             let mnemonic = mnemonic.into_key();
             let xkey: ExtendedKey = (mnemonic.clone(), password).into_extended_key()?;
@@ -848,6 +2764,71 @@ pub(crate) fn handle_key_subcommand(
                 )?)
             }
         }
+        KeySubCommand::VerifyMnemonic {
+            mnemonic,
+            password,
+            address,
+            bip,
+        } => {
+            let mnemonic = parse_checked_mnemonic(&mnemonic)?;
+            let xkey: ExtendedKey = (mnemonic, password).into_extended_key()?;
+            let xprv = xkey.into_xprv(network).ok_or_else(|| {
+                Error::Generic("Privatekey info not found (should not happen)".to_string())
+            })?;
+            let fingerprint = xprv.fingerprint(&secp);
+            let account = generate_bip_accounts_descriptors(
+                bip_descriptor_type(&bip),
+                &xprv,
+                network,
+                0..=0,
+            )?
+            .remove(0);
+            let (first_address, _) = next_descriptor_address(
+                account["public_descriptors"]["external"]
+                    .as_str()
+                    .unwrap_or_default(),
+                &mut BTreeMap::new(),
+                network,
+            )?;
+            let address_matches = address
+                .map(|address| parse_address(&address).map(|address| address == first_address))
+                .transpose()?;
+            if address_matches == Some(false) {
+                return Err(Error::Generic(format!(
+                    "The first {bip} address of the mnemonic is {first_address}, not the expected one. Check the words, the password and --type."
+                )));
+            }
+            if pretty {
+                let table = vec![
+                    vec![
+                        "Fingerprint".cell().bold(true),
+                        fingerprint.to_string().cell(),
+                    ],
+                    vec![
+                        "First Address".cell().bold(true),
+                        first_address.to_string().cell(),
+                    ],
+                    vec![
+                        "Address Matches".cell().bold(true),
+                        address_matches
+                            .map_or("not checked".to_string(), |matches| matches.to_string())
+                            .cell(),
+                    ],
+                ]
+                .table()
+                .display()
+                .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{table}"))
+            } else {
+                Ok(serde_json::to_string_pretty(&json!({
+                    "valid": true,
+                    "fingerprint": fingerprint.to_string(),
+                    "account_path": account["account_path"],
+                    "first_address": first_address.to_string(),
+                    "address_matches": address_matches,
+                }))?)
+            }
+        }
         KeySubCommand::Derive { xprv, path } => {
             if xprv.network != network.into() {
                 return Err(Error::Generic("Invalid network".to_string()));
@@ -879,17 +2860,59 @@ pub(crate) fn handle_key_subcommand(
                 Err(Error::Generic("Invalid key variant".to_string()))
             }
         }
-    }
-}
-
-/// Handle the miniscript compiler sub-command
-///
-/// Compiler options are described in [`CliSubCommand::Compile`].
-#[cfg(feature = "compiler")]
-pub(crate) fn handle_compile_subcommand(
-    _network: Network,
-    policy: String,
-    script_type: String,
+        KeySubCommand::DeriveAccounts {
+            xprv,
+            accounts,
+            bip,
+        } => {
+            if xprv.network != network.into() {
+                return Err(Error::Generic("Invalid network".to_string()));
+            }
+            let desc_type = bip_descriptor_type(&bip);
+            let accounts = generate_bip_accounts_descriptors(desc_type, &xprv, network, accounts)?;
+            if pretty {
+                let rows = accounts
+                    .iter()
+                    .map(|account| {
+                        vec![
+                            account["account"].to_string().cell(),
+                            account["account_path"].as_str().unwrap_or_default().cell(),
+                            account["private_descriptors"]["external"]
+                                .as_str()
+                                .unwrap_or_default()
+                                .cell(),
+                            account["private_descriptors"]["internal"]
+                                .as_str()
+                                .unwrap_or_default()
+                                .cell(),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+                    .table()
+                    .title(vec![
+                        "Account".cell().bold(true),
+                        "Path".cell().bold(true),
+                        "External Descriptor".cell().bold(true),
+                        "Internal Descriptor".cell().bold(true),
+                    ])
+                    .display()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!("{rows}"))
+            } else {
+                Ok(serde_json::to_string_pretty(&accounts)?)
+            }
+        }
+    }
+}
+
+/// Handle the miniscript compiler sub-command
+///
+/// Compiler options are described in [`CliSubCommand::Compile`].
+#[cfg(feature = "compiler")]
+pub(crate) fn handle_compile_subcommand(
+    _network: Network,
+    policy: String,
+    script_type: String,
     pretty: bool,
 ) -> Result<String, Error> {
     let policy = Concrete::<String>::from_str(policy.as_str())?;
@@ -940,602 +2963,4222 @@ pub(crate) fn handle_compile_subcommand(
     }
 }
 
-/// The global top level handler.
-pub(crate) async fn handle_command(cli_opts: CliOpts) -> Result<String, Error> {
-    let network = cli_opts.network;
-    let pretty = cli_opts.pretty;
+/// Handle the backup restoration sub-command
+///
+/// Restore options are described in [`CliSubCommand::Restore`].
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+pub(crate) fn handle_restore_subcommand(
+    network: Network,
+    persister: &mut Persister,
+    wallet_dir: &std::path::Path,
+    file: std::path::PathBuf,
+    pretty: bool,
+) -> Result<String, Error> {
+    let backup = std::fs::read_to_string(&file).map_err(|e| Error::Generic(e.to_string()))?;
+    let passphrase = read_passphrase("Backup passphrase: ", false)?;
+    let payload = decrypt_backup(&backup, &passphrase)?;
 
-    let result: Result<String, Error> = match cli_opts.subcommand {
-        #[cfg(any(
-            feature = "electrum",
-            feature = "esplora",
-            feature = "cbf",
-            feature = "rpc"
-        ))]
-        CliSubCommand::Wallet {
-            ref wallet_opts,
-            subcommand: WalletSubCommand::OnlineWalletSubCommand(online_subcommand),
-        } => {
-            // let network = cli_opts.network;
-            let home_dir = prepare_home_dir(cli_opts.datadir)?;
-            let wallet_name = &wallet_opts.wallet;
-            let database_path = prepare_wallet_db_dir(wallet_name, &home_dir)?;
+    let backup_network = payload["network"].as_str().unwrap_or_default();
+    if backup_network != network.to_string() {
+        return Err(Error::Generic(format!(
+            "The backup is for the {backup_network} network, not {network}."
+        )));
+    }
+    let external_descriptor = payload["external_descriptor"]
+        .as_str()
+        .ok_or_else(|| Error::Generic("The backup has no external descriptor.".to_string()))?
+        .to_string();
+    let internal_descriptor = payload["internal_descriptor"].as_str().map(str::to_string);
 
-            #[cfg(any(feature = "sqlite", feature = "redb"))]
-            let result = {
-                let mut persister: Persister = match &wallet_opts.database_type {
-                    #[cfg(feature = "sqlite")]
-                    DatabaseType::Sqlite => {
-                        let db_file = database_path.join("wallet.sqlite");
-                        let connection = Connection::open(db_file)?;
-                        log::debug!("Sqlite database opened successfully");
-                        Persister::Connection(connection)
-                    }
-                    #[cfg(feature = "redb")]
-                    DatabaseType::Redb => {
-                        let db = Arc::new(bdk_redb::redb::Database::create(
-                            home_dir.join("wallet.redb"),
-                        )?);
-                        let store = RedbStore::new(
-                            db,
-                            wallet_name.as_deref().unwrap_or("wallet").to_string(),
-                        )?;
-                        log::debug!("Redb database opened successfully");
-                        Persister::RedbStore(store)
-                    }
-                };
+    let existing_wallet = Wallet::load()
+        .load_wallet(persister)
+        .map_err(|e| Error::Generic(e.to_string()))?;
+    if existing_wallet.is_some() {
+        return Err(Error::Generic(
+            "The wallet already exists, restore the backup under another wallet name.".to_string(),
+        ));
+    }
+    let params = match internal_descriptor {
+        Some(internal_descriptor) => Wallet::create(external_descriptor, internal_descriptor),
+        None => Wallet::create_single(external_descriptor),
+    };
+    let wallet = params
+        .network(network)
+        .create_wallet(persister)
+        .map_err(|e| Error::Generic(e.to_string()))?;
 
-                let mut wallet = new_persisted_wallet(network, &mut persister, wallet_opts)?;
-                let blockchain_client = new_blockchain_client(wallet_opts, &wallet, database_path)?;
+    let external = wallet.public_descriptor(KeychainKind::External).to_string();
+    let internal = wallet
+        .keychains()
+        .any(|(keychain, _)| keychain == KeychainKind::Internal)
+        .then(|| wallet.public_descriptor(KeychainKind::Internal).to_string());
+    // The state files and the birthday only refer to the wallet, they are restored along with it.
+    let state_files = payload["state_files"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    for (name, content) in &state_files {
+        if !BACKUP_STATE_FILES.contains(&name.as_str()) {
+            return Err(Error::Generic(format!(
+                "The backup has an unknown state file {name}."
+            )));
+        }
+        store_state_file(&wallet_dir.join(name), content)?;
+    }
+    let birthday = payload["birthday"]
+        .as_u64()
+        .map(|birthday| {
+            u32::try_from(birthday)
+                .map_err(|_| Error::Generic(format!("The backup birthday {birthday} is invalid.")))
+        })
+        .transpose()?;
+    if let Some(birthday) = birthday {
+        store_state_file(&wallet_dir.join(BIRTHDAY_FILE), &birthday)?;
+    }
 
-                let result = handle_online_wallet_subcommand(
-                    &mut wallet,
-                    blockchain_client,
-                    online_subcommand,
-                )
-                .await?;
-                wallet.persist(&mut persister)?;
-                result
-            };
-            #[cfg(not(any(feature = "sqlite", feature = "redb")))]
-            let result = {
-                let wallet = new_wallet(network, wallet_opts)?;
-                let blockchain_client =
-                    crate::utils::new_blockchain_client(wallet_opts, &wallet, database_path)?;
-                let mut wallet = new_wallet(network, wallet_opts)?;
-                handle_online_wallet_subcommand(&mut wallet, blockchain_client, online_subcommand)
-                    .await?
-            };
-            Ok(result)
+    if pretty {
+        let mut rows = vec![vec!["External".cell().bold(true), external.cell()]];
+        if let Some(internal) = &internal {
+            rows.push(vec!["Internal".cell().bold(true), internal.cell()]);
         }
-        CliSubCommand::Wallet {
-            ref wallet_opts,
-            subcommand: WalletSubCommand::OfflineWalletSubCommand(ref offline_subcommand),
-        } => {
-            let network = cli_opts.network;
-            #[cfg(any(feature = "sqlite", feature = "redb"))]
-            let result = {
-                let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
-                let wallet_name = &wallet_opts.wallet;
-                let mut persister: Persister = match &wallet_opts.database_type {
-                    #[cfg(feature = "sqlite")]
-                    DatabaseType::Sqlite => {
-                        let database_path = prepare_wallet_db_dir(wallet_name, &home_dir)?;
-                        let db_file = database_path.join("wallet.sqlite");
-                        let connection = Connection::open(db_file)?;
-                        log::debug!("Sqlite database opened successfully");
-                        Persister::Connection(connection)
-                    }
-                    #[cfg(feature = "redb")]
-                    DatabaseType::Redb => {
-                        let db = Arc::new(bdk_redb::redb::Database::create(
-                            home_dir.join("wallet.redb"),
-                        )?);
-                        let store = RedbStore::new(
-                            db,
-                            wallet_name.as_deref().unwrap_or("wallet").to_string(),
-                        )?;
-                        log::debug!("Redb database opened successfully");
-                        Persister::RedbStore(store)
-                    }
-                };
+        if let Some(birthday) = birthday {
+            rows.push(vec!["Birthday".cell().bold(true), birthday.cell()]);
+        }
+        let table = rows
+            .table()
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&json!({
+            "external": external,
+            "internal": internal,
+            "birthday": birthday,
+            "state_files": state_files.keys().collect::<Vec<_>>(),
+        }))?)
+    }
+}
 
-                let mut wallet = new_persisted_wallet(network, &mut persister, wallet_opts)?;
+/// Handle the vault descriptor generation sub-command
+///
+/// Vault options are described in [`CliSubCommand::GenerateVault`].
+pub(crate) fn handle_generate_vault_subcommand(
+    network: Network,
+    primary: String,
+    recovery: String,
+    blocks: u16,
+    pretty: bool,
+) -> Result<String, Error> {
+    let (descriptor, keymap) = vault_descriptor(&primary, &recovery, blocks, network)?;
+    let private_descriptor =
+        (!keymap.is_empty()).then(|| descriptor.to_string_with_secret(&keymap));
 
-                let result = handle_offline_wallet_subcommand(
-                    &mut wallet,
-                    wallet_opts,
-                    &cli_opts,
-                    offline_subcommand.clone(),
-                )?;
-                wallet.persist(&mut persister)?;
-                result
-            };
-            #[cfg(not(any(feature = "sqlite", feature = "redb")))]
-            let result = {
-                let mut wallet = new_wallet(network, wallet_opts)?;
-                handle_offline_wallet_subcommand(
-                    &mut wallet,
-                    wallet_opts,
-                    &cli_opts,
-                    offline_subcommand.clone(),
-                )?
-            };
-            Ok(result)
-        }
-        CliSubCommand::Key {
-            subcommand: key_subcommand,
-        } => {
-            let result = handle_key_subcommand(network, key_subcommand, pretty)?;
-            Ok(result)
+    if pretty {
+        let mut rows = vec![vec![
+            "Descriptor".cell().bold(true),
+            descriptor.to_string().cell(),
+        ]];
+        if let Some(private_descriptor) = &private_descriptor {
+            rows.push(vec![
+                "Private Descriptor".cell().bold(true),
+                private_descriptor.cell(),
+            ]);
         }
-        #[cfg(feature = "compiler")]
-        CliSubCommand::Compile {
-            policy,
-            script_type,
-        } => {
-            let result = handle_compile_subcommand(network, policy, script_type, pretty)?;
-            Ok(result)
+        let table = rows
+            .table()
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        let mut result = json!({"descriptor": descriptor.to_string()});
+        if let Some(private_descriptor) = private_descriptor {
+            result["private_descriptor"] = json!(private_descriptor);
         }
-        #[cfg(feature = "repl")]
-        CliSubCommand::Repl { ref wallet_opts } => {
-            let network = cli_opts.network;
-            #[cfg(any(feature = "sqlite", feature = "redb"))]
-            let (mut wallet, mut persister) = {
-                let wallet_name = &wallet_opts.wallet;
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+}
 
-                let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+/// Handle the raw transaction creation sub-command
+///
+/// Inputs signal RBF and the transaction has no locktime, like the ones built by the wallet.
+pub(crate) fn handle_create_raw_transaction_subcommand(
+    inputs: Vec<OutPoint>,
+    outputs: Vec<(ScriptBuf, u64)>,
+    pretty: bool,
+) -> Result<String, Error> {
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            })
+            .collect(),
+        output: outputs
+            .into_iter()
+            .map(|(script_pubkey, value)| TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey,
+            })
+            .collect(),
+    };
+    let raw_tx = serialize_hex(&tx);
+    let psbt = BASE64_STANDARD.encode(Psbt::from_unsigned_tx(tx)?.serialize());
 
-                let mut persister: Persister = match &wallet_opts.database_type {
-                    #[cfg(feature = "sqlite")]
-                    DatabaseType::Sqlite => {
-                        let database_path = prepare_wallet_db_dir(wallet_name, &home_dir)?;
-                        let db_file = database_path.join("wallet.sqlite");
-                        let connection = Connection::open(db_file)?;
-                        log::debug!("Sqlite database opened successfully");
-                        Persister::Connection(connection)
-                    }
-                    #[cfg(feature = "redb")]
-                    DatabaseType::Redb => {
-                        let db = Arc::new(bdk_redb::redb::Database::create(
-                            home_dir.join("wallet.redb"),
-                        )?);
-                        let store = RedbStore::new(
-                            db,
-                            wallet_name.as_deref().unwrap_or("wallet").to_string(),
-                        )?;
-                        log::debug!("Redb database opened successfully");
-                        Persister::RedbStore(store)
-                    }
-                };
-                let wallet = new_persisted_wallet(network, &mut persister, wallet_opts)?;
-                (wallet, persister)
-            };
-            #[cfg(not(any(feature = "sqlite", feature = "redb")))]
-            let mut wallet = new_wallet(network, &wallet_opts)?;
-            let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
-            let database_path = prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?;
+    if pretty {
+        let table = vec![
+            vec!["Raw Transaction".cell().bold(true), raw_tx.cell()],
+            vec!["PSBT".cell().bold(true), psbt.cell()],
+        ]
+        .table()
+        .display()
+        .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(
+            &json!({"raw_tx": raw_tx, "psbt": psbt}),
+        )?)
+    }
+}
 
-            loop {
-                let line = readline()?;
-                let line = line.trim();
-                if line.is_empty() {
-                    continue;
-                }
+/// Handle the shell completion script sub-command
+pub(crate) fn handle_completions_subcommand(shell: clap_complete::Shell) -> Result<String, Error> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut CliOpts::command(), "bdk-cli", &mut script);
+    String::from_utf8(script).map_err(|e| Error::Generic(e.to_string()))
+}
 
-                let result = respond(
-                    network,
-                    &mut wallet,
-                    wallet_opts,
-                    line,
-                    database_path.clone(),
-                    &cli_opts,
-                )
-                .await;
-                #[cfg(any(feature = "sqlite", feature = "redb"))]
-                wallet.persist(&mut persister)?;
+/// Handle the build information sub-command
+pub(crate) fn handle_version_subcommand(pretty: bool) -> Result<String, Error> {
+    let features = enabled_features();
+    if pretty {
+        let table = vec![
+            vec![
+                "Version".cell().bold(true),
+                env!("CARGO_PKG_VERSION").cell(),
+            ],
+            vec![
+                "BDK Wallet".cell().bold(true),
+                env!("BDK_CLI_BDK_WALLET_VERSION").cell(),
+            ],
+            vec!["Features".cell().bold(true), features.join(", ").cell()],
+            vec!["Target".cell().bold(true), env!("BDK_CLI_TARGET").cell()],
+        ]
+        .table()
+        .display()
+        .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "bdk_wallet": env!("BDK_CLI_BDK_WALLET_VERSION"),
+            "features": features,
+            "target": env!("BDK_CLI_TARGET"),
+        }))?)
+    }
+}
 
-                match result {
-                    Ok(quit) => {
-                        if quit {
-                            break;
-                        }
-                    }
-                    Err(err) => {
-                        writeln!(std::io::stdout(), "{err}")
-                            .map_err(|e| Error::Generic(e.to_string()))?;
-                        std::io::stdout()
-                            .flush()
-                            .map_err(|e| Error::Generic(e.to_string()))?;
-                    }
-                }
-            }
-            Ok("".to_string())
-        }
-        CliSubCommand::Descriptor { desc_type, key } => {
-            let descriptor = handle_descriptor_command(cli_opts.network, desc_type, key, pretty)?;
-            Ok(descriptor)
+/// Handle the descriptor normalization sub-command
+pub(crate) fn handle_normalize_subcommand(
+    descriptor: String,
+    pretty: bool,
+) -> Result<String, Error> {
+    let descriptor = normalize_descriptor(&descriptor)?.to_string();
+    let hash = sha256::Hash::hash(descriptor.as_bytes()).to_string();
+
+    if pretty {
+        let table = vec![
+            vec!["Descriptor".cell().bold(true), descriptor.cell()],
+            vec!["Hash".cell().bold(true), hash.cell()],
+        ]
+        .table()
+        .display()
+        .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(
+            &json!({"descriptor": descriptor, "hash": hash}),
+        )?)
+    }
+}
+
+/// Handle the inspect_descriptor command
+pub(crate) fn handle_inspect_descriptor_subcommand(
+    descriptor: String,
+    pretty: bool,
+) -> Result<String, Error> {
+    let inspection = inspect_descriptor(&descriptor)?;
+
+    if pretty {
+        let mut rows = vec![
+            vec![
+                "Script Type".cell().bold(true),
+                inspection["script_type"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .cell(),
+            ],
+            vec![
+                "Taproot".cell().bold(true),
+                inspection["is_taproot"].to_string().cell(),
+            ],
+            vec![
+                "Policy".cell().bold(true),
+                inspection["policy"].as_str().unwrap_or_default().cell(),
+            ],
+            vec![
+                "Max Satisfaction Weight".cell().bold(true),
+                inspection["max_satisfaction_weight"].to_string().cell(),
+            ],
+        ];
+        for ms in inspection["miniscripts"].as_array().into_iter().flatten() {
+            rows.push(vec![
+                "Miniscript".cell().bold(true),
+                ms["miniscript"].as_str().unwrap_or_default().cell(),
+            ]);
         }
-    };
-    result
+        let table = rows
+            .table()
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&inspection)?)
+    }
 }
 
-#[cfg(feature = "repl")]
-async fn respond(
-    network: Network,
-    wallet: &mut Wallet,
-    wallet_opts: &WalletOpts,
-    line: &str,
-    _datadir: std::path::PathBuf,
-    cli_opts: &CliOpts,
-) -> Result<bool, String> {
-    use clap::Parser;
+/// Handle the get_descriptor_info command
+pub(crate) fn handle_get_descriptor_info_subcommand(
+    descriptor: String,
+    pretty: bool,
+) -> Result<String, Error> {
+    let info = descriptor_info(&descriptor)?;
 
-    let args = shlex::split(line).ok_or("error: Invalid quoting".to_string())?;
-    let repl_subcommand = ReplSubCommand::try_parse_from(args).map_err(|e| e.to_string())?;
-    let response = match repl_subcommand {
-        #[cfg(any(
-            feature = "electrum",
-            feature = "esplora",
-            feature = "cbf",
-            feature = "rpc"
-        ))]
-        ReplSubCommand::Wallet {
-            subcommand: WalletSubCommand::OnlineWalletSubCommand(online_subcommand),
-        } => {
-            let blockchain =
-                new_blockchain_client(wallet_opts, wallet, _datadir).map_err(|e| e.to_string())?;
-            let value = handle_online_wallet_subcommand(wallet, blockchain, online_subcommand)
-                .await
-                .map_err(|e| e.to_string())?;
-            Some(value)
+    if pretty {
+        let rows = [
+            ("Descriptor", "descriptor"),
+            ("Checksum", "checksum"),
+            ("Canonical", "canonical"),
+            ("Wildcard", "has_wildcard"),
+            ("Multipath", "is_multipath"),
+            ("Private Keys", "has_private_keys"),
+        ];
+        let table = rows
+            .iter()
+            .map(|(title, field)| {
+                let value = match &info[field] {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                vec![title.cell().bold(true), value.cell()]
+            })
+            .collect::<Vec<_>>()
+            .table()
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&info)?)
+    }
+}
+
+/// Handle the test_vectors command
+pub(crate) fn handle_test_vectors_subcommand(
+    network: Network,
+    descriptor: String,
+    count: u32,
+    pretty: bool,
+) -> Result<String, Error> {
+    let vectors = derivation_test_vectors(&descriptor, count, network)?;
+
+    if pretty {
+        let table = vectors
+            .iter()
+            .map(|vector| {
+                let wif = vector["wif"]
+                    .as_array()
+                    .map(|wif| {
+                        wif.iter()
+                            .filter_map(Value::as_str)
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    })
+                    .unwrap_or_default();
+                vec![
+                    vector["index"].to_string().cell(),
+                    vector["address"].as_str().unwrap_or("").cell(),
+                    vector["script_pubkey"].as_str().unwrap_or("").cell(),
+                    wif.cell(),
+                ]
+            })
+            .collect::<Vec<_>>()
+            .table()
+            .title(vec![
+                "Index".cell().bold(true),
+                "Address".cell().bold(true),
+                "Script Pubkey".cell().bold(true),
+                "WIF".cell().bold(true),
+            ])
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&vectors)?)
+    }
+}
+
+/// Handle the verify_address command
+pub(crate) fn handle_verify_address_subcommand(
+    network: Network,
+    descriptor: String,
+    index: u32,
+    expect: String,
+    pretty: bool,
+) -> Result<String, Error> {
+    let expected = Address::from_str(expect.trim())?
+        .require_network(network)
+        .map_err(|_| Error::Generic(format!("Address {expect} is not valid on {network}.")))?;
+    let derived = derive_script_pubkey(&descriptor, index)?;
+    let address = Address::from_script(&derived, network)
+        .map(|address| address.to_string())
+        .unwrap_or_else(|_| derived.to_hex_string());
+    let matches = derived == expected.script_pubkey();
+
+    if pretty {
+        let table = vec![
+            vec!["Index".cell().bold(true), index.cell()],
+            vec!["Derived".cell().bold(true), address.cell()],
+            vec!["Expected".cell().bold(true), expected.cell()],
+            vec![
+                "Result".cell().bold(true),
+                if matches { "pass" } else { "fail" }.cell(),
+            ],
+        ]
+        .table()
+        .display()
+        .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&json!({
+            "index": index,
+            "derived": address,
+            "expected": expected.to_string(),
+            "matches": matches,
+        }))?)
+    }
+}
+
+/// Handle the scripthash command
+pub(crate) fn handle_scripthash_subcommand(
+    network: Network,
+    addresses: Vec<String>,
+    pretty: bool,
+) -> Result<String, Error> {
+    let scripthashes = addresses
+        .iter()
+        .map(|address| {
+            let address = Address::from_str(address.trim())?
+                .require_network(network)
+                .map_err(|_| {
+                    Error::Generic(format!("Address {address} is not valid on {network}."))
+                })?;
+            Ok((
+                address.to_string(),
+                electrum_scripthash(&address.script_pubkey()),
+            ))
+        })
+        .collect::<Result<BTreeMap<_, _>, Error>>()?;
+
+    if pretty {
+        let table = scripthashes
+            .iter()
+            .map(|(address, scripthash)| vec![address.cell(), scripthash.cell()])
+            .collect::<Vec<_>>()
+            .table()
+            .title(vec![
+                "Address".cell().bold(true),
+                "Scripthash".cell().bold(true),
+            ])
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&scripthashes)?)
+    }
+}
+
+/// Execute an Elements sub-command
+///
+/// Elements sub-commands are described in [`ElementsSubCommand`].
+#[cfg(feature = "elements")]
+pub(crate) fn handle_elements_subcommand(
+    network: Network,
+    subcommand: ElementsSubCommand,
+    pretty: bool,
+) -> Result<String, Error> {
+    use bdk_wallet::bitcoin::hex::DisplayHex;
+    use elements_miniscript::elements::{self, secp256k1_zkp};
+    use elements_miniscript::{ConfidentialDescriptor, DescriptorPublicKey};
+
+    let (chain, params) = elements_address_params(network);
+    let result = match subcommand {
+        ElementsSubCommand::Address { address } => {
+            let address = elements::Address::from_str(&address)
+                .map_err(|e| Error::Generic(format!("Invalid Elements address: {e}")))?;
+            if address.params != params {
+                return Err(Error::Generic(format!(
+                    "Address {address} is not valid on {chain}."
+                )));
+            }
+            json!({
+                "address": address.to_string(),
+                "chain": chain,
+                "is_confidential": address.is_blinded(),
+                "blinding_pubkey": address.blinding_pubkey.map(|key| key.to_string()),
+                "unconfidential_address": address.to_unconfidential().to_string(),
+                "script_pubkey": address.script_pubkey().as_bytes().to_lower_hex_string(),
+            })
         }
-        ReplSubCommand::Wallet {
-            subcommand: WalletSubCommand::OfflineWalletSubCommand(offline_subcommand),
-        } => {
-            let value =
-                handle_offline_wallet_subcommand(wallet, wallet_opts, cli_opts, offline_subcommand)
-                    .map_err(|e| e.to_string())?;
-            Some(value)
+        ElementsSubCommand::Descriptor { descriptor, index } => {
+            let invalid = |e: &dyn std::fmt::Display| {
+                Error::Generic(format!("Invalid Elements descriptor: {e}"))
+            };
+            if descriptor.starts_with("ct(") {
+                let descriptor =
+                    ConfidentialDescriptor::<DescriptorPublicKey>::from_str(&descriptor)
+                        .map_err(|e| invalid(&e))?;
+                let derived = descriptor
+                    .at_derivation_index(index)
+                    .map_err(|e| invalid(&e))?;
+                let secp = secp256k1_zkp::Secp256k1::new();
+                json!({
+                    "descriptor": descriptor.to_string(),
+                    "chain": chain,
+                    "is_confidential": true,
+                    "index": index,
+                    "address": derived.address(&secp, params).map_err(|e| invalid(&e))?.to_string(),
+                    "unconfidential_address": derived
+                        .unconfidential_address(params)
+                        .map_err(|e| invalid(&e))?
+                        .to_string(),
+                })
+            } else {
+                let descriptor =
+                    elements_miniscript::Descriptor::<DescriptorPublicKey>::from_str(&descriptor)
+                        .map_err(|e| invalid(&e))?;
+                let address = descriptor
+                    .at_derivation_index(index)
+                    .map_err(|e| invalid(&e))?
+                    .address(params)
+                    .map_err(|e| invalid(&e))?;
+                json!({
+                    "descriptor": descriptor.to_string(),
+                    "chain": chain,
+                    "is_confidential": false,
+                    "index": index,
+                    "address": address.to_string(),
+                })
+            }
         }
-        ReplSubCommand::Key { subcommand } => {
-            let value = handle_key_subcommand(network, subcommand, cli_opts.pretty)
-                .map_err(|e| e.to_string())?;
-            Some(value)
+    };
+
+    if pretty {
+        let rows = result
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| {
+                let value = value
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| value.to_string());
+                vec![key.clone().cell().bold(true), value.cell()]
+            })
+            .collect::<Vec<_>>();
+        let table = rows
+            .table()
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+}
+
+/// Handle the raw transaction decoding sub-command
+pub(crate) fn handle_decode_raw_transaction_subcommand(
+    network: Network,
+    tx: String,
+    pretty: bool,
+) -> Result<String, Error> {
+    let tx_bytes = Vec::<u8>::from_hex(tx.trim())?;
+    let tx: Transaction = deserialize(&tx_bytes)?;
+    if pretty {
+        let mut rows = vec![
+            vec!["Txid".cell().bold(true), tx.compute_txid().cell()],
+            vec!["Wtxid".cell().bold(true), tx.compute_wtxid().cell()],
+            vec!["Version".cell().bold(true), tx.version.0.cell()],
+            vec!["Locktime".cell().bold(true), tx.lock_time.cell()],
+            vec!["Vsize".cell().bold(true), tx.vsize().cell()],
+        ];
+        for (index, input) in tx.input.iter().enumerate() {
+            rows.push(vec![
+                format!("Input {index}").cell().bold(true),
+                format!("{} (sequence {})", input.previous_output, input.sequence).cell(),
+            ]);
         }
-        ReplSubCommand::Descriptor { desc_type, key } => {
-            let value = handle_descriptor_command(network, desc_type, key, cli_opts.pretty)
-                .map_err(|e| e.to_string())?;
-            Some(value)
+        for (index, output) in tx.output.iter().enumerate() {
+            let destination = Address::from_script(&output.script_pubkey, network)
+                .map(|address| address.to_string())
+                .unwrap_or_else(|_| output.script_pubkey.to_hex_string());
+            rows.push(vec![
+                format!("Output {index}").cell().bold(true),
+                format!("{} sat to {destination}", output.value.to_sat()).cell(),
+            ]);
         }
-        ReplSubCommand::Exit => None,
-    };
-    if let Some(value) = response {
-        writeln!(std::io::stdout(), "{value}").map_err(|e| e.to_string())?;
-        std::io::stdout().flush().map_err(|e| e.to_string())?;
-        Ok(false)
+        let table = rows
+            .table()
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
     } else {
-        writeln!(std::io::stdout(), "Exiting...").map_err(|e| e.to_string())?;
-        std::io::stdout().flush().map_err(|e| e.to_string())?;
-        Ok(true)
+        Ok(serde_json::to_string_pretty(&decode_transaction(
+            &tx, network,
+        ))?)
+    }
+}
+
+/// Handler for the `reset_state` wallet subcommand, the wallet must not be persisted afterwards.
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+fn handle_reset_state(
+    wallet: &Wallet,
+    persister: &mut Persister,
+    confirm: bool,
+    dry_run: bool,
+) -> Result<String, Error> {
+    if dry_run {
+        let blocks = wallet
+            .local_chain()
+            .iter_checkpoints()
+            .filter(|cp| cp.height() > 0)
+            .count();
+        return Ok(serde_json::to_string_pretty(&json!({
+            "removed_transactions": wallet.transactions().count(),
+            "removed_blocks": blocks,
+        }))?);
+    }
+    if !confirm {
+        return Err(Error::Generic(format!(
+            "This removes the {} transactions and the chain data of the wallet, use --confirm to proceed.",
+            wallet.transactions().count()
+        )));
+    }
+    let (transactions, blocks) = persister.reset_chain_data()?;
+    Ok(serde_json::to_string_pretty(&json!({
+        "removed_transactions": transactions,
+        "removed_blocks": blocks,
+    }))?)
+}
+
+/// Handle the total_balance command
+///
+/// The wallets are the data directory itself and its subdirectories holding a SQLite database.
+/// A wallet that can't be loaded, for instance because another process holds its lock, is
+/// reported with its error instead of failing the whole command.
+#[cfg(feature = "sqlite")]
+pub(crate) fn handle_total_balance_subcommand(
+    home_dir: &std::path::Path,
+    denom: Denom,
+    pretty: bool,
+) -> Result<String, Error> {
+    let mut wallet_names: Vec<String> = std::fs::read_dir(home_dir)
+        .map_err(|e| Error::Generic(e.to_string()))?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().join("wallet.sqlite").is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    wallet_names.sort();
+    let mut wallet_names: Vec<Option<String>> = wallet_names.into_iter().map(Some).collect();
+    if home_dir.join("wallet.sqlite").is_file() {
+        wallet_names.insert(0, None);
+    }
+
+    let mut networks: BTreeMap<String, Vec<(Option<String>, bdk_wallet::Balance)>> =
+        BTreeMap::new();
+    let mut errors = Vec::new();
+    for wallet_name in wallet_names {
+        let loaded = open_persister(&wallet_name, &DatabaseType::Sqlite, home_dir, true).and_then(
+            |mut persister| {
+                Wallet::load()
+                    .load_wallet(&mut persister)
+                    .map_err(|e| Error::Generic(e.to_string()))
+            },
+        );
+        match loaded {
+            Ok(Some(wallet)) => networks
+                .entry(wallet.network().to_string())
+                .or_default()
+                .push((wallet_name, wallet.balance())),
+            // The database holds no wallet yet.
+            Ok(None) => {}
+            Err(e) => errors.push((wallet_name, e.to_string())),
+        }
+    }
+
+    // Only the balances of the wallets of a same network are added together.
+    let totals: BTreeMap<&String, bdk_wallet::Balance> = networks
+        .iter()
+        .map(|(network, wallets)| {
+            let total = wallets
+                .iter()
+                .fold(bdk_wallet::Balance::default(), |total, (_, balance)| {
+                    total + balance.clone()
+                });
+            (network, total)
+        })
+        .collect();
+
+    if pretty {
+        let amount_cell = |amount: Amount| {
+            format_amount(amount.to_sat(), denom)
+                .cell()
+                .justify(Justify::Right)
+        };
+        let mut rows = Vec::new();
+        for (network, wallets) in &networks {
+            let total = &totals[network];
+            for (wallet_name, balance) in wallets {
+                rows.push(vec![
+                    network.cell(),
+                    wallet_name.as_deref().unwrap_or("-").cell(),
+                    amount_cell(balance.confirmed),
+                    amount_cell(balance.trusted_pending + balance.untrusted_pending),
+                    amount_cell(balance.immature),
+                    amount_cell(balance.total()),
+                ]);
+            }
+            rows.push(vec![
+                network.cell().bold(true),
+                "Total".cell().bold(true),
+                amount_cell(total.confirmed).bold(true),
+                amount_cell(total.trusted_pending + total.untrusted_pending).bold(true),
+                amount_cell(total.immature).bold(true),
+                amount_cell(total.total()).bold(true),
+            ]);
+        }
+        for (wallet_name, error) in &errors {
+            log::warn!(
+                "Wallet {} was skipped: {error}",
+                wallet_name.as_deref().unwrap_or("-")
+            );
+        }
+        let table = rows
+            .table()
+            .title(vec![
+                "Network".cell().bold(true),
+                "Wallet".cell().bold(true),
+                format!("Confirmed ({})", denom.name()).cell().bold(true),
+                "Pending".cell().bold(true),
+                "Immature".cell().bold(true),
+                "Total".cell().bold(true),
+            ])
+            .display()
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(format!("{table}"))
+    } else {
+        let balance_json = |balance: &bdk_wallet::Balance| {
+            json!({
+                "confirmed": amount_value(balance.confirmed.to_sat(), denom),
+                "trusted_pending": amount_value(balance.trusted_pending.to_sat(), denom),
+                "untrusted_pending": amount_value(balance.untrusted_pending.to_sat(), denom),
+                "immature": amount_value(balance.immature.to_sat(), denom),
+                "total": amount_value(balance.total().to_sat(), denom),
+            })
+        };
+        let networks: serde_json::Map<String, Value> = networks
+            .iter()
+            .map(|(network, wallets)| {
+                let wallets: Vec<Value> = wallets
+                    .iter()
+                    .map(|(wallet_name, balance)| {
+                        json!({"wallet": wallet_name, "balance": balance_json(balance)})
+                    })
+                    .collect();
+                (
+                    network.clone(),
+                    json!({"wallets": wallets, "total": balance_json(&totals[network])}),
+                )
+            })
+            .collect();
+        let errors: Vec<Value> = errors
+            .iter()
+            .map(|(wallet_name, error)| json!({"wallet": wallet_name, "error": error}))
+            .collect();
+        Ok(serde_json::to_string_pretty(&json!({
+            "denomination": denom.name(),
+            "networks": networks,
+            "errors": errors,
+        }))?)
     }
 }
 
+/// Open the persister of a wallet for the given database type.
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+fn open_persister(
+    wallet_name: &Option<String>,
+    database_type: &DatabaseType,
+    home_dir: &std::path::Path,
+    read_only: bool,
+) -> Result<Persister, Error> {
+    let lock = WalletLock::acquire(&prepare_wallet_db_dir(wallet_name, home_dir)?, read_only)?;
+    let persister = match database_type {
+        #[cfg(feature = "sqlite")]
+        DatabaseType::Sqlite => {
+            let database_path = prepare_wallet_db_dir(wallet_name, home_dir)?;
+            let db_file = database_path.join("wallet.sqlite");
+            // A read-only connection can't write the database, whatever the lock allows.
+            let connection = if read_only {
+                if !db_file.is_file() {
+                    return Err(Error::Generic(
+                        "The wallet database doesn't exist, it can't be created with --read-only."
+                            .to_string(),
+                    ));
+                }
+                Connection::open_with_flags(
+                    db_file,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY
+                        | OpenFlags::SQLITE_OPEN_URI
+                        | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?
+            } else {
+                Connection::open(db_file)?
+            };
+            log::debug!("Sqlite database opened successfully");
+            Persister::Connection(connection, lock)
+        }
+        #[cfg(feature = "redb")]
+        DatabaseType::Redb => {
+            let db = Arc::new(bdk_redb::redb::Database::create(
+                home_dir.join("wallet.redb"),
+            )?);
+            let store = RedbStore::new(db, wallet_name.as_deref().unwrap_or("wallet").to_string())?;
+            log::debug!("Redb database opened successfully");
+            Persister::RedbStore(store, lock)
+        }
+    };
+    Ok(persister)
+}
+
+/// Sync the wallet before the offline commands reporting its balance, UTXOs or transactions, when
+/// `--sync-first` is set. Other commands don't read the chain state, so they aren't synced.
 #[cfg(any(
     feature = "electrum",
     feature = "esplora",
     feature = "cbf",
     feature = "rpc"
 ))]
-/// Syncs a given wallet using the blockchain client.
-pub async fn sync_wallet(client: BlockchainClient, wallet: &mut Wallet) -> Result<(), Error> {
-    #[cfg(any(feature = "electrum", feature = "esplora"))]
-    let request = wallet
-        .start_sync_with_revealed_spks()
-        .inspect(|item, progress| {
-            let pc = (100 * progress.consumed()) as f32 / progress.total() as f32;
-            eprintln!("[ SCANNING {pc:03.0}% ] {item}");
-        });
-    match client {
-        #[cfg(feature = "electrum")]
-        Electrum { client, batch_size } => {
-            // Populate the electrum client's transaction cache so it doesn't re-download transaction we
-            // already have.
-            client.populate_tx_cache(wallet.tx_graph().full_txs().map(|tx_node| tx_node.tx));
+async fn sync_first(
+    cli_opts: &CliOpts,
+    wallet_opts: &WalletOpts,
+    wallet: &mut Wallet,
+    subcommand: &OfflineWalletSubCommand,
+) -> Result<(), Error> {
+    let reads_chain_state = matches!(
+        subcommand,
+        Unspent { .. } | ListFrozen | UtxoStats | Transactions { .. } | Balance { .. }
+    );
+    if !wallet_opts.sync_first || !reads_chain_state {
+        return Ok(());
+    }
+    let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+    let database_path = prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?;
+    let birthday = load_birthday(&database_path)?;
+    let blockchain_client =
+        new_blockchain_client(wallet_opts, wallet, database_path, cli_opts.timeout)?;
+    check_signet_challenge(
+        &blockchain_client,
+        cli_opts.signet_challenge.as_deref(),
+        cli_opts.unverified_signet,
+    )?;
+    with_timeout(
+        cli_opts.timeout,
+        sync_wallet(blockchain_client, wallet, birthday),
+    )
+    .await?;
+    // Reported like the progress messages of the sync.
+    if !is_quiet() {
+        eprintln!("Synced the wallet before running the command.");
+    }
+    Ok(())
+}
 
-            let update = client.sync(request, batch_size, false)?;
-            wallet
-                .apply_update(update)
-                .map_err(|e| Error::Generic(e.to_string()))
+/// Fetch fresh fee estimates from the blockchain server for the sub-commands estimating their fee
+/// rate by confirmation target, and cache them unless it's a dry run.
+///
+/// The cached estimates are used when the server can't be reached, a warning tells they may be
+/// out of date.
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "cbf",
+    feature = "rpc"
+))]
+async fn fresh_fee_estimates(
+    cli_opts: &CliOpts,
+    wallet_opts: &WalletOpts,
+    wallet: &Wallet,
+    subcommand: &OfflineWalletSubCommand,
+) -> Option<BTreeMap<u16, f64>> {
+    if !matches!(
+        subcommand,
+        CreateTx {
+            fee_rate: Some(TxFeeRate::Auto),
+            ..
+        } | BumpFee {
+            target: Some(_),
+            ..
         }
-        #[cfg(feature = "esplora")]
-        Esplora {
-            client,
-            parallel_requests,
+    ) {
+        return None;
+    }
+    let fetch = async {
+        let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+        let database_path = prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?;
+        let blockchain_client =
+            new_blockchain_client(wallet_opts, wallet, database_path.clone(), cli_opts.timeout)?;
+        check_signet_challenge(
+            &blockchain_client,
+            cli_opts.signet_challenge.as_deref(),
+            cli_opts.unverified_signet,
+        )?;
+        let estimates =
+            with_timeout(cli_opts.timeout, fetch_fee_estimates(blockchain_client)).await?;
+        store_fee_estimates(&database_path, &estimates, cli_opts.dry_run)?;
+        Ok::<_, Error>(estimates)
+    };
+    match fetch.await {
+        Ok(estimates) => Some(estimates),
+        Err(e) => {
+            log::warn!(
+                "The fee estimates couldn't be fetched from the blockchain server, the cached ones are used: {e}"
+            );
+            None
+        }
+    }
+}
+
+/// The global top level handler.
+pub(crate) async fn handle_command(cli_opts: CliOpts) -> Result<String, Error> {
+    let network = cli_opts.network;
+    let pretty = cli_opts.pretty;
+
+    if cli_opts.signet_challenge.is_some() && network != Network::Signet {
+        return Err(Error::Generic(
+            "A signet challenge can only be used with the signet network.".to_string(),
+        ));
+    }
+    if cli_opts.test_seed.is_some() && network == Network::Bitcoin {
+        return Err(Error::Generic(
+            "A test seed can't be used on mainnet, its keys aren't secret.".to_string(),
+        ));
+    }
+    // The overridden descriptors only live for the session, which saves and broadcasts nothing.
+    // A read-only wallet runs the same way, sharing its lock with other readers.
+    #[cfg(any(feature = "sqlite", feature = "redb"))]
+    let cli_opts = {
+        let mut cli_opts = cli_opts;
+        match &cli_opts.subcommand {
+            CliSubCommand::Wallet { wallet_opts, .. }
+                if wallet_opts.descriptor_override || wallet_opts.read_only =>
+            {
+                cli_opts.dry_run = true;
+            }
+            #[cfg(feature = "repl")]
+            CliSubCommand::Repl { wallet_opts }
+                if wallet_opts.descriptor_override || wallet_opts.read_only =>
+            {
+                cli_opts.dry_run = true;
+            }
+            _ => {}
+        }
+        cli_opts
+    };
+
+    let result: Result<String, Error> = match cli_opts.subcommand {
+        #[cfg(any(
+            feature = "electrum",
+            feature = "esplora",
+            feature = "cbf",
+            feature = "rpc"
+        ))]
+        CliSubCommand::Wallet {
+            ref wallet_opts,
+            subcommand: WalletSubCommand::OnlineWalletSubCommand(online_subcommand),
         } => {
-            let update = client
-                .sync(request, parallel_requests)
-                .await
-                .map_err(|e| *e)?;
-            wallet
-                .apply_update(update)
-                .map_err(|e| Error::Generic(e.to_string()))
+            // let network = cli_opts.network;
+            let home_dir = prepare_home_dir(cli_opts.datadir)?;
+            let wallet_name = &wallet_opts.wallet;
+            let database_path = prepare_wallet_db_dir(wallet_name, &home_dir)?;
+
+            #[cfg(any(feature = "sqlite", feature = "redb"))]
+            let result = {
+                let mut persister = open_persister(
+                    wallet_name,
+                    &wallet_opts.database_type,
+                    &home_dir,
+                    wallet_opts.read_only || wallet_opts.descriptor_override,
+                )?;
+
+                let mut wallet = new_persisted_wallet(network, &mut persister, wallet_opts)?;
+                let blockchain_client = new_blockchain_client(
+                    wallet_opts,
+                    &wallet,
+                    database_path.clone(),
+                    cli_opts.timeout,
+                )?;
+                check_signet_challenge(
+                    &blockchain_client,
+                    cli_opts.signet_challenge.as_deref(),
+                    cli_opts.unverified_signet,
+                )?;
+
+                let result = with_timeout(
+                    cli_opts.timeout,
+                    handle_online_wallet_subcommand(
+                        &mut wallet,
+                        &database_path,
+                        blockchain_client,
+                        online_subcommand,
+                        cli_opts.dry_run,
+                    ),
+                )
+                .await?;
+                if !cli_opts.dry_run {
+                    wallet.persist(&mut persister)?;
+                }
+                result
+            };
+            #[cfg(not(any(feature = "sqlite", feature = "redb")))]
+            let result = {
+                let wallet = new_wallet(network, wallet_opts)?;
+                let blockchain_client = crate::utils::new_blockchain_client(
+                    wallet_opts,
+                    &wallet,
+                    database_path.clone(),
+                    cli_opts.timeout,
+                )?;
+                check_signet_challenge(
+                    &blockchain_client,
+                    cli_opts.signet_challenge.as_deref(),
+                    cli_opts.unverified_signet,
+                )?;
+                let mut wallet = new_wallet(network, wallet_opts)?;
+                with_timeout(
+                    cli_opts.timeout,
+                    handle_online_wallet_subcommand(
+                        &mut wallet,
+                        &database_path,
+                        blockchain_client,
+                        online_subcommand,
+                        cli_opts.dry_run,
+                    ),
+                )
+                .await?
+            };
+            Ok(mark_if_dry_run(result, cli_opts.dry_run))
         }
-        #[cfg(feature = "rpc")]
-        RpcClient { client } => {
-            let blockchain_info = client.get_blockchain_info()?;
-            let wallet_cp = wallet.latest_checkpoint();
-
-            // reload the last 200 blocks in case of a reorg
-            let emitter_height = wallet_cp.height().saturating_sub(200);
-            let mut emitter = Emitter::new(
-                &*client,
-                wallet_cp,
-                emitter_height,
-                wallet
-                    .tx_graph()
-                    .list_canonical_txs(
-                        wallet.local_chain(),
-                        wallet.local_chain().tip().block_id(),
-                        CanonicalizationParams::default(),
-                    )
-                    .filter(|tx| tx.chain_position.is_unconfirmed()),
-            );
+        CliSubCommand::Wallet {
+            ref wallet_opts,
+            subcommand: WalletSubCommand::OfflineWalletSubCommand(ref offline_subcommand),
+        } => {
+            let network = cli_opts.network;
+            #[cfg(any(feature = "sqlite", feature = "redb"))]
+            let result = {
+                let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+                let wallet_name = &wallet_opts.wallet;
+                let mut persister = open_persister(
+                    wallet_name,
+                    &wallet_opts.database_type,
+                    &home_dir,
+                    wallet_opts.read_only || wallet_opts.descriptor_override,
+                )?;
+
+                let mut wallet = new_persisted_wallet(network, &mut persister, wallet_opts)?;
+                #[cfg(any(
+                    feature = "electrum",
+                    feature = "esplora",
+                    feature = "cbf",
+                    feature = "rpc"
+                ))]
+                sync_first(&cli_opts, wallet_opts, &mut wallet, offline_subcommand).await?;
+                #[cfg(any(
+                    feature = "electrum",
+                    feature = "esplora",
+                    feature = "cbf",
+                    feature = "rpc"
+                ))]
+                let fee_estimates =
+                    fresh_fee_estimates(&cli_opts, wallet_opts, &wallet, offline_subcommand).await;
+                #[cfg(not(any(
+                    feature = "electrum",
+                    feature = "esplora",
+                    feature = "cbf",
+                    feature = "rpc"
+                )))]
+                let fee_estimates = None;
+
+                if let ResetState { confirm } = offline_subcommand {
+                    handle_reset_state(&wallet, &mut persister, *confirm, cli_opts.dry_run)?
+                } else {
+                    let result = handle_offline_wallet_subcommand(
+                        &mut wallet,
+                        wallet_opts,
+                        &cli_opts,
+                        offline_subcommand.clone(),
+                        fee_estimates.as_ref(),
+                    )?;
+                    if !cli_opts.dry_run {
+                        wallet.persist(&mut persister)?;
+                    }
+                    result
+                }
+            };
+            #[cfg(not(any(feature = "sqlite", feature = "redb")))]
+            let result = {
+                let mut wallet = new_wallet(network, wallet_opts)?;
+                #[cfg(any(
+                    feature = "electrum",
+                    feature = "esplora",
+                    feature = "cbf",
+                    feature = "rpc"
+                ))]
+                sync_first(&cli_opts, wallet_opts, &mut wallet, offline_subcommand).await?;
+                #[cfg(any(
+                    feature = "electrum",
+                    feature = "esplora",
+                    feature = "cbf",
+                    feature = "rpc"
+                ))]
+                let fee_estimates =
+                    fresh_fee_estimates(&cli_opts, wallet_opts, &wallet, offline_subcommand).await;
+                #[cfg(not(any(
+                    feature = "electrum",
+                    feature = "esplora",
+                    feature = "cbf",
+                    feature = "rpc"
+                )))]
+                let fee_estimates = None;
+                handle_offline_wallet_subcommand(
+                    &mut wallet,
+                    wallet_opts,
+                    &cli_opts,
+                    offline_subcommand.clone(),
+                    fee_estimates.as_ref(),
+                )?
+            };
+            Ok(mark_if_dry_run(result, cli_opts.dry_run))
+        }
+        CliSubCommand::Key {
+            subcommand: key_subcommand,
+        } => {
+            let result =
+                handle_key_subcommand(network, key_subcommand, cli_opts.test_seed, pretty)?;
+            Ok(result)
+        }
+        #[cfg(feature = "compiler")]
+        CliSubCommand::Compile {
+            policy,
+            script_type,
+        } => {
+            let result = handle_compile_subcommand(network, policy, script_type, pretty)?;
+            Ok(result)
+        }
+        #[cfg(feature = "repl")]
+        CliSubCommand::Repl { ref wallet_opts } => {
+            let network = cli_opts.network;
+            #[cfg(any(feature = "sqlite", feature = "redb"))]
+            let (mut wallet, mut persister) = {
+                let wallet_name = &wallet_opts.wallet;
+
+                let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+
+                let mut persister = open_persister(
+                    wallet_name,
+                    &wallet_opts.database_type,
+                    &home_dir,
+                    wallet_opts.read_only || wallet_opts.descriptor_override,
+                )?;
+                let wallet = new_persisted_wallet(network, &mut persister, wallet_opts)?;
+                (wallet, persister)
+            };
+            #[cfg(not(any(feature = "sqlite", feature = "redb")))]
+            let mut wallet = new_wallet(network, &wallet_opts)?;
+            let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+            let database_path = prepare_wallet_db_dir(&wallet_opts.wallet, &home_dir)?;
+
+            // Set by `begin`, the staged changes are only persisted once `commit` clears it. The
+            // persister writes each changeset atomically, so the database never holds part of them.
+            let mut deferred = false;
+            loop {
+                let line = readline()?;
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+
+                let result = respond(
+                    network,
+                    &mut wallet,
+                    wallet_opts,
+                    line,
+                    database_path.clone(),
+                    &cli_opts,
+                    &mut deferred,
+                )
+                .await;
+                #[cfg(any(feature = "sqlite", feature = "redb"))]
+                if !deferred && !cli_opts.dry_run {
+                    wallet.persist(&mut persister)?;
+                }
+
+                match result {
+                    Ok(quit) => {
+                        if quit {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        writeln!(std::io::stdout(), "{err}")
+                            .map_err(|e| Error::Generic(e.to_string()))?;
+                        std::io::stdout()
+                            .flush()
+                            .map_err(|e| Error::Generic(e.to_string()))?;
+                    }
+                }
+            }
+            Ok("".to_string())
+        }
+        CliSubCommand::Descriptor {
+            desc_type,
+            key,
+            change_key,
+            account_path,
+            #[cfg(feature = "hwi")]
+            hwi,
+        } => {
+            #[cfg(feature = "hwi")]
+            if hwi {
+                let descriptors =
+                    generate_hwi_descriptors(&desc_type, cli_opts.network, account_path.as_ref())?;
+                return format_descriptor_output(&descriptors, pretty);
+            }
+            let descriptor = handle_descriptor_command(
+                cli_opts.network,
+                desc_type,
+                key,
+                change_key,
+                account_path,
+                cli_opts.test_seed,
+                pretty,
+            )?;
+            Ok(descriptor)
+        }
+        #[cfg(any(feature = "sqlite", feature = "redb"))]
+        CliSubCommand::Restore {
+            wallet,
+            database_type,
+            file,
+        } => {
+            let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+            let mut persister = open_persister(&wallet, &database_type, &home_dir, false)?;
+            let wallet_dir = prepare_wallet_db_dir(&wallet, &home_dir)?;
+            let result =
+                handle_restore_subcommand(network, &mut persister, &wallet_dir, file, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::GenerateVault {
+            primary,
+            recovery,
+            blocks,
+        } => {
+            let result =
+                handle_generate_vault_subcommand(network, primary, recovery, blocks, pretty)?;
+            Ok(result)
+        }
+        #[cfg(feature = "elements")]
+        CliSubCommand::Elements { subcommand } => {
+            let result = handle_elements_subcommand(network, subcommand, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::Normalize { descriptor } => {
+            let result = handle_normalize_subcommand(descriptor, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::InspectDescriptor { descriptor } => {
+            let result = handle_inspect_descriptor_subcommand(descriptor, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::GetDescriptorInfo { descriptor } => {
+            let result = handle_get_descriptor_info_subcommand(descriptor, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::TestVectors { descriptor, count } => {
+            let result =
+                handle_test_vectors_subcommand(cli_opts.network, descriptor, count, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::VerifyAddress {
+            descriptor,
+            index,
+            expect,
+        } => {
+            let result =
+                handle_verify_address_subcommand(network, descriptor, index, expect, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::Scripthash { addresses } => {
+            let result = handle_scripthash_subcommand(network, addresses, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::DecodeRawTransaction { tx } => {
+            let result = handle_decode_raw_transaction_subcommand(network, tx, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::CreateRawTransaction { inputs, outputs } => {
+            let result = handle_create_raw_transaction_subcommand(inputs, outputs, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::Completions { shell } => handle_completions_subcommand(shell),
+        #[cfg(feature = "sqlite")]
+        CliSubCommand::TotalBalance => {
+            let home_dir = prepare_home_dir(cli_opts.datadir.clone())?;
+            let result = handle_total_balance_subcommand(&home_dir, cli_opts.denom, pretty)?;
+            Ok(result)
+        }
+        CliSubCommand::Schema => {
+            let schema = command_schema(&CliOpts::command());
+            Ok(serde_json::to_string_pretty(&schema)?)
+        }
+        CliSubCommand::Version => {
+            let result = handle_version_subcommand(pretty)?;
+            Ok(result)
+        }
+    };
+    result
+}
+
+#[cfg(feature = "repl")]
+async fn respond(
+    network: Network,
+    wallet: &mut Wallet,
+    wallet_opts: &WalletOpts,
+    line: &str,
+    _datadir: std::path::PathBuf,
+    cli_opts: &CliOpts,
+    deferred: &mut bool,
+) -> Result<bool, String> {
+    use clap::Parser;
+
+    let args = shlex::split(line).ok_or("error: Invalid quoting".to_string())?;
+    let repl_subcommand = ReplSubCommand::try_parse_from(args).map_err(|e| e.to_string())?;
+    let is_wallet_command = matches!(repl_subcommand, ReplSubCommand::Wallet { .. });
+    let response = match repl_subcommand {
+        #[cfg(any(
+            feature = "electrum",
+            feature = "esplora",
+            feature = "cbf",
+            feature = "rpc"
+        ))]
+        ReplSubCommand::Wallet {
+            subcommand: WalletSubCommand::OnlineWalletSubCommand(online_subcommand),
+        } => {
+            let blockchain =
+                new_blockchain_client(wallet_opts, wallet, _datadir.clone(), cli_opts.timeout)
+                    .map_err(|e| e.to_string())?;
+            check_signet_challenge(
+                &blockchain,
+                cli_opts.signet_challenge.as_deref(),
+                cli_opts.unverified_signet,
+            )
+            .map_err(|e| e.to_string())?;
+            let value = with_timeout(
+                cli_opts.timeout,
+                handle_online_wallet_subcommand(
+                    wallet,
+                    &_datadir,
+                    blockchain,
+                    online_subcommand,
+                    cli_opts.dry_run,
+                ),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            Some(value)
+        }
+        ReplSubCommand::Wallet {
+            subcommand: WalletSubCommand::OfflineWalletSubCommand(offline_subcommand),
+        } => {
+            #[cfg(any(
+                feature = "electrum",
+                feature = "esplora",
+                feature = "cbf",
+                feature = "rpc"
+            ))]
+            sync_first(cli_opts, wallet_opts, wallet, &offline_subcommand)
+                .await
+                .map_err(|e| e.to_string())?;
+            #[cfg(any(
+                feature = "electrum",
+                feature = "esplora",
+                feature = "cbf",
+                feature = "rpc"
+            ))]
+            let fee_estimates =
+                fresh_fee_estimates(cli_opts, wallet_opts, wallet, &offline_subcommand).await;
+            #[cfg(not(any(
+                feature = "electrum",
+                feature = "esplora",
+                feature = "cbf",
+                feature = "rpc"
+            )))]
+            let fee_estimates = None;
+            let value = handle_offline_wallet_subcommand(
+                wallet,
+                wallet_opts,
+                cli_opts,
+                offline_subcommand,
+                fee_estimates.as_ref(),
+            )
+            .map_err(|e| e.to_string())?;
+            Some(value)
+        }
+        ReplSubCommand::Key { subcommand } => {
+            let value =
+                handle_key_subcommand(network, subcommand, cli_opts.test_seed, cli_opts.pretty)
+                    .map_err(|e| e.to_string())?;
+            Some(value)
+        }
+        ReplSubCommand::Descriptor {
+            desc_type,
+            key,
+            change_key,
+            account_path,
+        } => {
+            let value = handle_descriptor_command(
+                network,
+                desc_type,
+                key,
+                change_key,
+                account_path,
+                cli_opts.test_seed,
+                cli_opts.pretty,
+            )
+            .map_err(|e| e.to_string())?;
+            Some(value)
+        }
+        ReplSubCommand::Completions { shell } => {
+            Some(handle_completions_subcommand(shell).map_err(|e| e.to_string())?)
+        }
+        ReplSubCommand::Begin => {
+            if *deferred {
+                return Err("error: Wallet changes are already staged until `commit`.".to_string());
+            }
+            *deferred = true;
+            Some("Staging wallet changes until `commit`.".to_string())
+        }
+        ReplSubCommand::Commit => {
+            if !*deferred {
+                return Err(
+                    "error: No `begin` to commit, wallet changes are persisted after each command."
+                        .to_string(),
+                );
+            }
+            *deferred = false;
+            Some("Committing the staged wallet changes.".to_string())
+        }
+        ReplSubCommand::Exit => {
+            if *deferred && wallet.staged().is_some() {
+                log::warn!("Discarding the wallet changes staged since `begin`.");
+            }
+            None
+        }
+    };
+    if let Some(value) = response {
+        let mut value = mark_if_dry_run(value, is_wallet_command && cli_opts.dry_run);
+        if !use_color(cli_opts.color, &std::io::stdout()) {
+            value = strip_ansi(&value);
+        }
+        writeln!(std::io::stdout(), "{value}").map_err(|e| e.to_string())?;
+        std::io::stdout().flush().map_err(|e| e.to_string())?;
+        Ok(false)
+    } else {
+        writeln!(std::io::stdout(), "Exiting...").map_err(|e| e.to_string())?;
+        std::io::stdout().flush().map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "electrum")]
+/// Runs a full scan of the wallet with an Electrum client.
+fn electrum_full_scan<E: bdk_electrum::electrum_client::ElectrumApi>(
+    client: &bdk_electrum::BdkElectrumClient<E>,
+    wallet: &mut Wallet,
+    request: impl Into<bdk_wallet::chain::spk_client::FullScanRequest<KeychainKind>>,
+    stop_gap: usize,
+    batch_size: usize,
+) -> Result<(), Error> {
+    // Populate the electrum client's transaction cache so it doesn't re-download transaction we
+    // already have.
+    client.populate_tx_cache(wallet.tx_graph().full_txs().map(|tx_node| tx_node.tx));
+
+    let update = client.full_scan(request, stop_gap, batch_size, false)?;
+    wallet.apply_update(update)?;
+    Ok(())
+}
+
+#[cfg(feature = "electrum")]
+/// Syncs the revealed scripts of the wallet with an Electrum client.
+fn electrum_sync<E: bdk_electrum::electrum_client::ElectrumApi>(
+    client: &bdk_electrum::BdkElectrumClient<E>,
+    wallet: &mut Wallet,
+    request: impl Into<bdk_wallet::chain::spk_client::SyncRequest<(KeychainKind, u32)>>,
+    batch_size: usize,
+) -> Result<(), Error> {
+    // Populate the electrum client's transaction cache so it doesn't re-download transaction we
+    // already have.
+    client.populate_tx_cache(wallet.tx_graph().full_txs().map(|tx_node| tx_node.tx));
+
+    let update = client.sync(request, batch_size, false)?;
+    wallet
+        .apply_update(update)
+        .map_err(|e| Error::Generic(e.to_string()))
+}
+
+#[cfg(feature = "electrum")]
+/// Fetches fee estimates, in sat/vbyte by confirmation target, from an Electrum client.
+fn electrum_fee_estimates<E: bdk_electrum::electrum_client::ElectrumApi>(
+    client: &bdk_electrum::BdkElectrumClient<E>,
+) -> Result<BTreeMap<u16, f64>, Error> {
+    let mut estimates = BTreeMap::new();
+    for target in FEE_ESTIMATE_TARGETS {
+        // Electrum servers return BTC/kvB, or a negative value without an estimate.
+        let btc_per_kvb = client
+            .inner
+            .estimate_fee(target as usize)
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        if btc_per_kvb > 0.0 {
+            estimates.insert(target, btc_per_kvb * 100_000.0);
+        }
+    }
+    Ok(estimates)
+}
+
+#[cfg(feature = "electrum")]
+/// Estimates the number of blocks a transaction paying `fee_rate`, in sat/vbyte, takes to confirm
+/// from the mempool fee histogram of an Electrum server.
+fn electrum_confirmation_target<E: bdk_electrum::electrum_client::ElectrumApi>(
+    client: &bdk_electrum::BdkElectrumClient<E>,
+    fee_rate: f64,
+) -> Result<Option<u16>, Error> {
+    let histogram = client
+        .inner
+        .raw_call("mempool.get_fee_histogram", [])
+        .map_err(|e| Error::Generic(e.to_string()))?;
+    let histogram: Vec<(f64, f64)> = serde_json::from_value(histogram)?;
+    Ok(histogram_confirmation_target(&histogram, fee_rate))
+}
+
+#[cfg(feature = "electrum")]
+/// Fetches a transaction with an Electrum client.
+fn electrum_fetch_transaction<E: bdk_electrum::electrum_client::ElectrumApi>(
+    client: &bdk_electrum::BdkElectrumClient<E>,
+    txid: Txid,
+) -> Result<Transaction, Error> {
+    client
+        .fetch_tx(txid)
+        .map(|tx| tx.as_ref().clone())
+        .map_err(|e| Error::Generic(e.to_string()))
+}
+
+#[cfg(feature = "electrum")]
+/// Broadcasts the given transactions with an Electrum client, returning the result of each
+/// broadcast in order.
+fn electrum_broadcast_transactions<E: bdk_electrum::electrum_client::ElectrumApi>(
+    client: &bdk_electrum::BdkElectrumClient<E>,
+    txs: &[Transaction],
+) -> Vec<Result<Txid, Error>> {
+    txs.iter()
+        .map(|tx| {
+            client
+                .transaction_broadcast(tx)
+                .map_err(|e| Error::Generic(e.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "cbf",
+    feature = "rpc"
+))]
+/// Syncs a given wallet using the blockchain client.
+///
+/// The rpc client scans the blocks of a wallet that never synced from its `birthday`.
+pub async fn sync_wallet(
+    client: BlockchainClient,
+    wallet: &mut Wallet,
+    #[cfg_attr(not(feature = "rpc"), allow(unused_variables))] birthday: Option<u32>,
+) -> Result<(), Error> {
+    #[cfg(any(feature = "electrum", feature = "esplora"))]
+    let request = wallet
+        .start_sync_with_revealed_spks()
+        .inspect(|item, progress| {
+            if is_quiet() {
+                return;
+            }
+            let pc = (100 * progress.consumed()) as f32 / progress.total() as f32;
+            eprintln!("[ SCANNING {pc:03.0}% ] {item}");
+        });
+    match client {
+        #[cfg(feature = "electrum")]
+        Electrum { client, batch_size } => electrum_sync(&client, wallet, request, batch_size),
+        #[cfg(all(feature = "electrum", unix))]
+        ElectrumUnix { client, batch_size } => electrum_sync(&client, wallet, request, batch_size),
+        #[cfg(feature = "esplora")]
+        Esplora {
+            client,
+            parallel_requests,
+            backoff_ms,
+        } => {
+            let update = with_esplora_backoff(backoff_ms, client.sync(request, parallel_requests))
+                .await
+                .map_err(|e| esplora_error(*e))?;
+            wallet
+                .apply_update(update)
+                .map_err(|e| Error::Generic(e.to_string()))
+        }
+        #[cfg(feature = "rpc")]
+        RpcClient { client } => {
+            let from_height = birthday.filter(|_| wallet.latest_checkpoint().height() == 0);
+            sync_rpc_client(&client, wallet, from_height, None)
+        }
+        #[cfg(feature = "cbf")]
+        KyotoClient { client } => sync_kyoto_client(wallet, client)
+            .await
+            .map_err(|e| Error::Generic(e.to_string())),
+    }
+}
+
+/// Syncs a wallet with a bitcoind node, from the last checkpoint of the wallet to the tip or
+/// over an explicit block range.
+///
+/// Blocks from `from_height` are emitted again even when the wallet already synced past them, and
+/// the sync stops after `to_height`. The mempool is only synced up to the tip.
+#[cfg(feature = "rpc")]
+fn sync_rpc_client(
+    client: &bdk_bitcoind_rpc::bitcoincore_rpc::Client,
+    wallet: &mut Wallet,
+    from_height: Option<u32>,
+    to_height: Option<u32>,
+) -> Result<(), Error> {
+    let blockchain_info = client.get_blockchain_info()?;
+    let wallet_cp = wallet.latest_checkpoint();
+
+    let (last_cp, emitter_height) = match from_height {
+        // Connect to the checkpoint below the range so its blocks are emitted again.
+        Some(from_height) => (
+            wallet_cp
+                .range(..=from_height.saturating_sub(1))
+                .next()
+                .unwrap_or(wallet_cp.clone()),
+            from_height,
+        ),
+        // reload the last 200 blocks in case of a reorg
+        None => (wallet_cp.clone(), wallet_cp.height().saturating_sub(200)),
+    };
+    let mut emitter = Emitter::new(
+        client,
+        last_cp,
+        emitter_height,
+        wallet
+            .tx_graph()
+            .list_canonical_txs(
+                wallet.local_chain(),
+                wallet.local_chain().tip().block_id(),
+                CanonicalizationParams::default(),
+            )
+            .filter(|tx| tx.chain_position.is_unconfirmed()),
+    );
+
+    while let Some(block_event) = emitter.next_block()? {
+        if to_height.is_some_and(|to_height| block_event.block_height() > to_height) {
+            return Ok(());
+        }
+        if block_event.block_height() % 10_000 == 0 && !is_quiet() {
+            let percent_done = f64::from(block_event.block_height())
+                / f64::from(blockchain_info.headers as u32)
+                * 100f64;
+            println!(
+                "Applying block at height: {}, {:.2}% done.",
+                block_event.block_height(),
+                percent_done
+            );
+        }
+
+        wallet.apply_block_connected_to(
+            &block_event.block,
+            block_event.block_height(),
+            block_event.connected_to(),
+        )?;
+    }
+
+    let mempool_txs = emitter.mempool()?;
+    wallet.apply_unconfirmed_txs(mempool_txs.update);
+    Ok(())
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "cbf",
+    feature = "rpc"
+))]
+/// Fetches fee estimates, in sat/vbyte by confirmation target, using the blockchain client.
+pub async fn fetch_fee_estimates(client: BlockchainClient) -> Result<BTreeMap<u16, f64>, Error> {
+    match client {
+        #[cfg(feature = "electrum")]
+        Electrum {
+            client,
+            batch_size: _,
+        } => electrum_fee_estimates(&client),
+        #[cfg(all(feature = "electrum", unix))]
+        ElectrumUnix {
+            client,
+            batch_size: _,
+        } => electrum_fee_estimates(&client),
+        #[cfg(feature = "esplora")]
+        Esplora {
+            client,
+            parallel_requests: _,
+            backoff_ms,
+        } => {
+            let all_estimates = with_esplora_backoff(backoff_ms, client.get_fee_estimates())
+                .await
+                .map_err(|e| Error::Generic(e.to_string()))?;
+            Ok(FEE_ESTIMATE_TARGETS
+                .into_iter()
+                .filter_map(|target| Some((target, *all_estimates.get(&target)?)))
+                .collect())
+        }
+        #[cfg(feature = "rpc")]
+        RpcClient { client } => {
+            let mut estimates = BTreeMap::new();
+            for target in FEE_ESTIMATE_TARGETS {
+                let estimate = client
+                    .estimate_smart_fee(target, None)
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                if let Some(fee_rate) = estimate.fee_rate {
+                    estimates.insert(target, fee_rate.to_sat() as f64 / 1000.0);
+                }
+            }
+            Ok(estimates)
+        }
+        #[cfg(feature = "cbf")]
+        KyotoClient { client: _ } => Err(Error::Generic(
+            "Fee estimates are not available with the cbf client.".to_string(),
+        )),
+    }
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "cbf",
+    feature = "rpc"
+))]
+/// Estimates the number of blocks a transaction paying `fee_rate`, in sat/vbyte, takes to confirm
+/// using the blockchain client.
+///
+/// Electrum and Esplora servers are asked for their mempool fee histogram, bitcoind for its
+/// estimate of every target up to [`MAX_CONFIRMATION_TARGET`] blocks until the lowest one covered
+/// by `fee_rate` is found.
+pub async fn fetch_confirmation_target(
+    client: BlockchainClient,
+    #[cfg_attr(
+        not(any(feature = "electrum", feature = "esplora", feature = "rpc")),
+        allow(unused_variables)
+    )]
+    fee_rate: f64,
+) -> Result<Option<u16>, Error> {
+    match client {
+        #[cfg(feature = "electrum")]
+        Electrum {
+            client,
+            batch_size: _,
+        } => electrum_confirmation_target(&client, fee_rate),
+        #[cfg(all(feature = "electrum", unix))]
+        ElectrumUnix {
+            client,
+            batch_size: _,
+        } => electrum_confirmation_target(&client, fee_rate),
+        #[cfg(feature = "esplora")]
+        Esplora {
+            client,
+            parallel_requests: _,
+            backoff_ms: _,
+        } => {
+            let mempool = client
+                .client()
+                .get(format!("{}/mempool", client.url()))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| Error::Generic(e.to_string()))?
+                .text()
+                .await
+                .map_err(|e| Error::Generic(e.to_string()))?;
+            let mut mempool: serde_json::Value = serde_json::from_str(&mempool)?;
+            let histogram = mempool
+                .get_mut("fee_histogram")
+                .map(serde_json::Value::take)
+                .ok_or_else(|| {
+                    Error::Generic("The Esplora server returned no mempool fee histogram.".into())
+                })?;
+            let histogram: Vec<(f64, f64)> = serde_json::from_value(histogram)?;
+            Ok(histogram_confirmation_target(&histogram, fee_rate))
+        }
+        #[cfg(feature = "rpc")]
+        RpcClient { client } => {
+            // Estimates don't increase with the target, so the lowest target covered by
+            // `fee_rate` is searched by bisection.
+            let covered = |target: u16| -> Result<bool, Error> {
+                let estimate = client
+                    .estimate_smart_fee(target, None)
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(estimate
+                    .fee_rate
+                    .is_some_and(|estimate| estimate.to_sat() as f64 / 1000.0 <= fee_rate))
+            };
+            if !covered(MAX_CONFIRMATION_TARGET)? {
+                return Ok(None);
+            }
+            let (mut low, mut high) = (1, MAX_CONFIRMATION_TARGET);
+            while low < high {
+                let middle = low + (high - low) / 2;
+                if covered(middle)? {
+                    high = middle;
+                } else {
+                    low = middle + 1;
+                }
+            }
+            Ok(Some(low))
+        }
+        #[cfg(feature = "cbf")]
+        KyotoClient { client: _ } => Err(Error::Generic(
+            "Fee estimates are not available with the cbf client.".to_string(),
+        )),
+    }
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "cbf",
+    feature = "rpc"
+))]
+/// Fetches a transaction, which doesn't need to belong to the wallet, using the blockchain client.
+pub async fn fetch_transaction(
+    client: BlockchainClient,
+    #[cfg_attr(
+        not(any(feature = "electrum", feature = "esplora", feature = "rpc")),
+        allow(unused_variables)
+    )]
+    txid: Txid,
+) -> Result<Transaction, Error> {
+    match client {
+        #[cfg(feature = "electrum")]
+        Electrum {
+            client,
+            batch_size: _,
+        } => electrum_fetch_transaction(&client, txid),
+        #[cfg(all(feature = "electrum", unix))]
+        ElectrumUnix {
+            client,
+            batch_size: _,
+        } => electrum_fetch_transaction(&client, txid),
+        #[cfg(feature = "esplora")]
+        Esplora {
+            client,
+            parallel_requests: _,
+            backoff_ms,
+        } => with_esplora_backoff(backoff_ms, client.get_tx(&txid))
+            .await
+            .map_err(|e| Error::Generic(e.to_string()))?
+            .ok_or_else(|| Error::Generic(format!("Transaction {txid} not found."))),
+        // Bitcoin Core only finds transactions outside its wallet and mempool with `-txindex`.
+        #[cfg(feature = "rpc")]
+        RpcClient { client } => client
+            .get_raw_transaction(&txid, None)
+            .map_err(|e| Error::Generic(e.to_string())),
+        #[cfg(feature = "cbf")]
+        KyotoClient { client: _ } => Err(Error::Generic(
+            "Fetching arbitrary transactions is not supported with the cbf client.".to_string(),
+        )),
+    }
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "cbf",
+    feature = "rpc"
+))]
+/// Broadcasts a given transaction using the blockchain client.
+pub async fn broadcast_transaction(
+    client: BlockchainClient,
+    tx: Transaction,
+) -> Result<Txid, Error> {
+    broadcast_transactions(client, vec![tx])
+        .await?
+        .pop()
+        .expect("one result per transaction")
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "cbf",
+    feature = "rpc"
+))]
+/// Broadcasts the given transactions using the blockchain client, returning the result of each
+/// broadcast in order.
+pub async fn broadcast_transactions(
+    client: BlockchainClient,
+    txs: Vec<Transaction>,
+) -> Result<Vec<Result<Txid, Error>>, Error> {
+    match client {
+        #[cfg(feature = "electrum")]
+        Electrum {
+            client,
+            batch_size: _,
+        } => Ok(electrum_broadcast_transactions(&client, &txs)),
+        #[cfg(all(feature = "electrum", unix))]
+        ElectrumUnix {
+            client,
+            batch_size: _,
+        } => Ok(electrum_broadcast_transactions(&client, &txs)),
+        #[cfg(feature = "esplora")]
+        Esplora {
+            client,
+            parallel_requests: _,
+            backoff_ms,
+        } => {
+            let mut results = Vec::with_capacity(txs.len());
+            for tx in txs {
+                results.push(
+                    with_esplora_backoff(backoff_ms, client.broadcast(&tx))
+                        .await
+                        .map(|()| tx.compute_txid())
+                        .map_err(|e| Error::Generic(e.to_string())),
+                );
+            }
+            Ok(results)
+        }
+        #[cfg(feature = "rpc")]
+        RpcClient { client } => Ok(txs
+            .iter()
+            .map(|tx| {
+                client
+                    .send_raw_transaction(tx)
+                    .map_err(|e| Error::Generic(e.to_string()))
+            })
+            .collect()),
+
+        #[cfg(feature = "cbf")]
+        KyotoClient { client } => {
+            let LightClient {
+                requester,
+                mut info_subscriber,
+                mut warning_subscriber,
+                update_subscriber: _,
+                node,
+            } = *client;
+
+            tokio::task::spawn(async move { node.run().await });
+            tokio::task::spawn(async move {
+                select! {
+                    info = info_subscriber.recv() => {
+                        if let Some(info) = info {
+                            tracing::info!("{info}");
+                        }
+                    },
+                    warn = warning_subscriber.recv() => {
+                        if let Some(warn) = warn {
+                            tracing::warn!("{warn}");
+                        }
+                    }
+                }
+            });
+            let mut results = Vec::with_capacity(txs.len());
+            for tx in txs {
+                let txid = tx.compute_txid();
+                let result = requester.broadcast_random(tx).await.map(|wtxid| {
+                    tracing::info!("Successfully broadcast WTXID: {wtxid}");
+                    txid
+                });
+                results.push(result.map_err(|_| {
+                    tracing::warn!("Broadcast was unsuccessful");
+                    Error::Generic("Transaction broadcast timed out after 30 seconds".into())
+                }));
+            }
+            Ok(results)
+        }
+    }
+}
+
+#[cfg(feature = "repl")]
+fn readline() -> Result<String, Error> {
+    write!(std::io::stdout(), "> ").map_err(|e| Error::Generic(e.to_string()))?;
+    std::io::stdout()
+        .flush()
+        .map_err(|e| Error::Generic(e.to_string()))?;
+    let mut buffer = String::new();
+    std::io::stdin()
+        .read_line(&mut buffer)
+        .map_err(|e| Error::Generic(e.to_string()))?;
+    Ok(buffer)
+}
+
+/// Handle the descriptor command
+pub fn handle_descriptor_command(
+    network: Network,
+    desc_type: String,
+    key: Option<String>,
+    change_key: Option<String>,
+    account_path: Option<DerivationPath>,
+    test_seed: Option<[u8; 32]>,
+    pretty: bool,
+) -> Result<String, Error> {
+    let account_path = account_path.as_ref();
+    let result = match key {
+        Some(key) if change_key.is_some() => generate_paired_descriptors(
+            &desc_type,
+            &key,
+            change_key.as_deref().unwrap_or_default(),
+            network,
+            account_path,
+        ),
+        Some(key) => {
+            if is_mnemonic(&key) {
+                // User provided mnemonic
+                generate_descriptor_from_mnemonic(&key, network, &desc_type, account_path)
+            } else {
+                // User provided xprv/xpub
+                generate_descriptors(&desc_type, &key, network, account_path)
+            }
+        }
+        // Generate new mnemonic and descriptors
+        None => generate_descriptor_with_mnemonic(network, &desc_type, account_path, test_seed),
+    }?;
+    format_descriptor_output(&result, pretty)
+}
+
+// The tests run in every build, the ones needing a blockchain client are gated on their own and
+// the wallet sub-commands are parsed by `run_offline_wallet_subcommand` with the options that the
+// enabled features require.
+#[cfg(test)]
+mod test {
+    #[cfg(any(
+        feature = "electrum",
+        feature = "esplora",
+        feature = "cbf",
+        feature = "rpc"
+    ))]
+    #[test]
+    fn test_psbt_is_final() {
+        use super::is_final;
+        use bdk_wallet::bitcoin::Psbt;
+        use std::str::FromStr;
+
+        let unsigned_psbt = Psbt::from_str("cHNidP8BAIkBAAAAASWJHzxzyVORV/C3lAynKHVVL7+Rw7/Jj8U9fuvD24olAAAAAAD+////AiBOAAAAAAAAIgAgLzY9yE4jzTFJnHtTjkc+rFAtJ9NB7ENFQ1xLYoKsI1cfqgKVAAAAACIAIFsbWgDeLGU8EA+RGwBDIbcv4gaGG0tbEIhDvwXXa/E7LwEAAAABALUCAAAAAAEBAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAD/////BALLAAD/////AgD5ApUAAAAAIgAgWxtaAN4sZTwQD5EbAEMhty/iBoYbS1sQiEO/Bddr8TsAAAAAAAAAACZqJKohqe3i9hw/cdHe/T+pmd+jaVN1XGkGiXmZYrSL69g2l06M+QEgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQErAPkClQAAAAAiACBbG1oA3ixlPBAPkRsAQyG3L+IGhhtLWxCIQ78F12vxOwEFR1IhA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDIQLKhV/gEZYmlsQXnsL5/Uqv5Y8O31tmWW1LQqIBkiqzCVKuIgYCyoVf4BGWJpbEF57C+f1Kr+WPDt9bZlltS0KiAZIqswkEboH3lCIGA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDBDS6ZSEAACICAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJBG6B95QiAgPyVdlP9KV1voj+PUHLGIpRL5GRHeKYZgzPJ1fMAvjHgwQ0umUhAA==").unwrap();
+        assert!(is_final(&unsigned_psbt).is_err());
+
+        let part_signed_psbt = Psbt::from_str("cHNidP8BAIkBAAAAASWJHzxzyVORV/C3lAynKHVVL7+Rw7/Jj8U9fuvD24olAAAAAAD+////AiBOAAAAAAAAIgAgLzY9yE4jzTFJnHtTjkc+rFAtJ9NB7ENFQ1xLYoKsI1cfqgKVAAAAACIAIFsbWgDeLGU8EA+RGwBDIbcv4gaGG0tbEIhDvwXXa/E7LwEAAAABALUCAAAAAAEBAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAD/////BALLAAD/////AgD5ApUAAAAAIgAgWxtaAN4sZTwQD5EbAEMhty/iBoYbS1sQiEO/Bddr8TsAAAAAAAAAACZqJKohqe3i9hw/cdHe/T+pmd+jaVN1XGkGiXmZYrSL69g2l06M+QEgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQErAPkClQAAAAAiACBbG1oA3ixlPBAPkRsAQyG3L+IGhhtLWxCIQ78F12vxOyICA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDSDBFAiEAnNPpu6wNX2HXYz8s2q5nXug4cWfvCGD3SSH2CNKm+yECIEQO7/URhUPsGoknMTE+GrYJf9Wxqn9QsuN9FGj32cQpAQEFR1IhA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDIQLKhV/gEZYmlsQXnsL5/Uqv5Y8O31tmWW1LQqIBkiqzCVKuIgYCyoVf4BGWJpbEF57C+f1Kr+WPDt9bZlltS0KiAZIqswkEboH3lCIGA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDBDS6ZSEAACICAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJBG6B95QiAgPyVdlP9KV1voj+PUHLGIpRL5GRHeKYZgzPJ1fMAvjHgwQ0umUhAA==").unwrap();
+        assert!(is_final(&part_signed_psbt).is_err());
+
+        let full_signed_psbt = Psbt::from_str("cHNidP8BAIkBAAAAASWJHzxzyVORV/C3lAynKHVVL7+Rw7/Jj8U9fuvD24olAAAAAAD+////AiBOAAAAAAAAIgAgLzY9yE4jzTFJnHtTjkc+rFAtJ9NB7ENFQ1xLYoKsI1cfqgKVAAAAACIAIFsbWgDeLGU8EA+RGwBDIbcv4gaGG0tbEIhDvwXXa/E7LwEAAAABALUCAAAAAAEBAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAD/////BALLAAD/////AgD5ApUAAAAAIgAgWxtaAN4sZTwQD5EbAEMhty/iBoYbS1sQiEO/Bddr8TsAAAAAAAAAACZqJKohqe3i9hw/cdHe/T+pmd+jaVN1XGkGiXmZYrSL69g2l06M+QEgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQErAPkClQAAAAAiACBbG1oA3ixlPBAPkRsAQyG3L+IGhhtLWxCIQ78F12vxOwEFR1IhA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDIQLKhV/gEZYmlsQXnsL5/Uqv5Y8O31tmWW1LQqIBkiqzCVKuIgYCyoVf4BGWJpbEF57C+f1Kr+WPDt9bZlltS0KiAZIqswkEboH3lCIGA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDBDS6ZSEBBwABCNsEAEgwRQIhAJzT6busDV9h12M/LNquZ17oOHFn7whg90kh9gjSpvshAiBEDu/1EYVD7BqJJzExPhq2CX/Vsap/ULLjfRRo99nEKQFHMEQCIGoFCvJ2zPB7PCpznh4+1jsY03kMie49KPoPDdr7/T9TAiB3jV7wzR9BH11FSbi+8U8gSX95PrBlnp1lOBgTUIUw3QFHUiED8lXZT/Sldb6I/j1ByxiKUS+RkR3imGYMzydXzAL4x4MhAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJUq4AACICAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJBG6B95QiAgPyVdlP9KV1voj+PUHLGIpRL5GRHeKYZgzPJ1fMAvjHgwQ0umUhAA==").unwrap();
+        assert!(is_final(&full_signed_psbt).is_ok());
+    }
+
+    #[cfg(feature = "compiler")]
+    #[test]
+    fn test_compile_taproot() {
+        use super::{NUMS_UNSPENDABLE_KEY_HEX, handle_compile_subcommand};
+        use bdk_wallet::bitcoin::Network;
+
+        // Expected taproot descriptors with checksums (using NUMS key from constant)
+        let expected_pk_a = format!("tr({},pk(A))#a2mlskt0", NUMS_UNSPENDABLE_KEY_HEX);
+        let expected_and_ab = format!(
+            "tr({},and_v(v:pk(A),pk(B)))#sfplm6kv",
+            NUMS_UNSPENDABLE_KEY_HEX
+        );
+
+        // Test simple pk policy compilation to taproot
+        let result = handle_compile_subcommand(
+            Network::Testnet,
+            "pk(A)".to_string(),
+            "tr".to_string(),
+            false,
+        );
+        assert!(result.is_ok());
+        let json_string = result.unwrap();
+        let json_result: serde_json::Value = serde_json::from_str(&json_string).unwrap();
+        let descriptor = json_result.get("descriptor").unwrap().as_str().unwrap();
+        assert_eq!(descriptor, expected_pk_a);
+
+        // Test more complex policy
+        let result = handle_compile_subcommand(
+            Network::Testnet,
+            "and(pk(A),pk(B))".to_string(),
+            "tr".to_string(),
+            false,
+        );
+        assert!(result.is_ok());
+        let json_string = result.unwrap();
+        let json_result: serde_json::Value = serde_json::from_str(&json_string).unwrap();
+        let descriptor = json_result.get("descriptor").unwrap().as_str().unwrap();
+        assert_eq!(descriptor, expected_and_ab);
+    }
+
+    #[cfg(feature = "compiler")]
+    #[test]
+    fn test_compile_invalid_cases() {
+        use super::handle_compile_subcommand;
+        use bdk_wallet::bitcoin::Network;
+
+        // Test invalid policy syntax
+        let result = handle_compile_subcommand(
+            Network::Testnet,
+            "invalid_policy".to_string(),
+            "tr".to_string(),
+            false,
+        );
+        assert!(result.is_err());
+
+        // Test invalid script type
+        let result = handle_compile_subcommand(
+            Network::Testnet,
+            "pk(A)".to_string(),
+            "invalid_type".to_string(),
+            false,
+        );
+        assert!(result.is_err());
+
+        // Test empty policy
+        let result =
+            handle_compile_subcommand(Network::Testnet, "".to_string(), "tr".to_string(), false);
+        assert!(result.is_err());
+
+        // Test malformed policy with unmatched parentheses
+        let result = handle_compile_subcommand(
+            Network::Testnet,
+            "pk(A".to_string(),
+            "tr".to_string(),
+            false,
+        );
+        assert!(result.is_err());
+
+        // Test policy with unknown function
+        let result = handle_compile_subcommand(
+            Network::Testnet,
+            "unknown_func(A)".to_string(),
+            "tr".to_string(),
+            false,
+        );
+        assert!(result.is_err());
+    }
+
+    /// Receive a payment of `sats` spending the previous output `funding_id`, confirmed at
+    /// `height` or left unconfirmed.
+    #[cfg(feature = "sqlite")]
+    fn receive(
+        wallet: &mut bdk_wallet::Wallet,
+        funding_id: u8,
+        sats: u64,
+        height: Option<u32>,
+    ) -> bdk_wallet::bitcoin::OutPoint {
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{OutPoint, TxIn, Txid};
+
+        let input = TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([funding_id; 32]), 0),
+            ..Default::default()
+        };
+        apply_payment(wallet, input, sats, height)
+    }
+
+    /// Apply a transaction spending `input` and paying `sats` to the wallet.
+    #[cfg(feature = "sqlite")]
+    fn apply_payment(
+        wallet: &mut bdk_wallet::Wallet,
+        input: bdk_wallet::bitcoin::TxIn,
+        sats: u64,
+        height: Option<u32>,
+    ) -> bdk_wallet::bitcoin::OutPoint {
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{
+            Amount, BlockHash, OutPoint, Transaction, TxOut, absolute::LockTime,
+            transaction::Version,
+        };
+        use bdk_wallet::chain::{BlockId, ConfirmationBlockTime};
+        use bdk_wallet::{KeychainKind, Update};
+
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let funding_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![input],
+            output: vec![TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let outpoint = OutPoint::new(funding_tx.compute_txid(), 0);
+        match height {
+            Some(height) => {
+                let block_id = BlockId {
+                    height,
+                    hash: BlockHash::from_byte_array(outpoint.txid.to_byte_array()),
+                };
+                let mut update = Update::default();
+                update.tx_update.txs.push(funding_tx.into());
+                update.tx_update.anchors.insert((
+                    ConfirmationBlockTime {
+                        block_id,
+                        confirmation_time: 0,
+                    },
+                    outpoint.txid,
+                ));
+                update.chain = Some(wallet.latest_checkpoint().insert(block_id));
+                wallet.apply_update(update).unwrap();
+            }
+            None => wallet.apply_unconfirmed_txs([(funding_tx, 0)]),
+        }
+        outpoint
+    }
+
+    #[test]
+    fn test_to_csv_quotes_fields() {
+        use crate::utils::to_csv;
+
+        let rows = vec![
+            vec!["plain".to_string(), "a,b".to_string()],
+            vec!["say \"hi\"".to_string(), "two\nlines".to_string()],
+        ];
+        assert_eq!(
+            to_csv(&["name", "note"], &rows),
+            "name,note\nplain,\"a,b\"\n\"say \"\"hi\"\"\",\"two\nlines\""
+        );
+    }
+
+    /// The master key of the test wallets.
+    #[cfg(feature = "sqlite")]
+    const TEST_XPRV: &str = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+
+    /// A temporary directory of its own for each test, removed with its content when dropped.
+    #[cfg(feature = "sqlite")]
+    struct TestDir(std::path::PathBuf);
+
+    #[cfg(feature = "sqlite")]
+    impl TestDir {
+        fn new() -> Self {
+            use std::sync::atomic::{AtomicUsize, Ordering};
+
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "bdk-cli-test-{}-{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The descriptors of a test wallet and the data directory its sub-commands run in.
+    #[cfg(feature = "sqlite")]
+    struct WalletFixture {
+        ext_descriptor: String,
+        int_descriptor: String,
+        datadir: TestDir,
+    }
+
+    #[cfg(feature = "sqlite")]
+    impl WalletFixture {
+        fn new(ext_descriptor: String, int_descriptor: String) -> Self {
+            Self {
+                ext_descriptor,
+                int_descriptor,
+                datadir: TestDir::new(),
+            }
+        }
+
+        /// A new regtest wallet of the descriptors, that isn't persisted.
+        fn wallet(&self) -> bdk_wallet::Wallet {
+            bdk_wallet::Wallet::create(self.ext_descriptor.clone(), self.int_descriptor.clone())
+                .network(bdk_wallet::bitcoin::Network::Regtest)
+                .create_wallet_no_persist()
+                .unwrap()
+        }
+    }
+
+    /// A regtest wallet of the BIP84 account 0 of the test key, and its fixture.
+    #[cfg(feature = "sqlite")]
+    fn test_wallet() -> (bdk_wallet::Wallet, WalletFixture) {
+        let fixture = WalletFixture::new(
+            format!("wpkh({TEST_XPRV}/84'/1'/0'/0/*)"),
+            format!("wpkh({TEST_XPRV}/84'/1'/0'/1/*)"),
+        );
+        (fixture.wallet(), fixture)
+    }
+
+    /// Run an offline wallet sub-command from its command line arguments, in the data directory
+    /// of the fixture.
+    #[cfg(feature = "sqlite")]
+    fn run_offline_wallet_subcommand(
+        wallet: &mut bdk_wallet::Wallet,
+        fixture: &WalletFixture,
+        args: &[&str],
+    ) -> Result<serde_json::Value, crate::error::BDKCliError> {
+        use super::handle_offline_wallet_subcommand;
+        use crate::commands::{CliOpts, CliSubCommand, WalletSubCommand};
+        use clap::Parser;
+
+        let mut cli_args = vec![
+            "bdk-cli",
+            "-n",
+            "regtest",
+            "--datadir",
+            fixture.datadir.path().to_str().unwrap(),
+            "wallet",
+            "-e",
+            &fixture.ext_descriptor,
+            "-i",
+            &fixture.int_descriptor,
+            "-d",
+            "sqlite",
+        ];
+        // Online builds require a blockchain client, that offline sub-commands never connect to.
+        #[cfg(any(
+            feature = "electrum",
+            feature = "esplora",
+            feature = "cbf",
+            feature = "rpc"
+        ))]
+        let client_type = {
+            use clap::ValueEnum;
+            crate::commands::ClientType::value_variants()[0]
+                .to_possible_value()
+                .unwrap()
+        };
+        #[cfg(any(
+            feature = "electrum",
+            feature = "esplora",
+            feature = "cbf",
+            feature = "rpc"
+        ))]
+        cli_args.extend(["-c", client_type.get_name()]);
+        #[cfg(any(feature = "electrum", feature = "esplora", feature = "rpc"))]
+        cli_args.extend(["-u", "127.0.0.1:1"]);
+        cli_args.extend(args);
+        let cli_opts = CliOpts::try_parse_from(cli_args).unwrap();
+        let CliSubCommand::Wallet {
+            wallet_opts,
+            subcommand: WalletSubCommand::OfflineWalletSubCommand(subcommand),
+        } = cli_opts.subcommand.clone()
+        else {
+            panic!("expected an offline wallet subcommand");
+        };
+        let result =
+            handle_offline_wallet_subcommand(wallet, &wallet_opts, &cli_opts, subcommand, None)?;
+        Ok(serde_json::from_str(&result).unwrap())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sign_taproot_key_and_script_path() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+
+        // The internal key and the leaf key come from different masters, signers are keyed by
+        // their master fingerprint.
+        let internal_xprv = TEST_XPRV;
+        let leaf_xprv = "tprv8ZgxMBicQKsPeKiWapAW4Kg6kxiL8garwu1gBk55h1e8Quy9p6CiRoCkEPQj1WdD4M3BhRy7cCyB8ymGBEYmgjREuPMrXVVoxXSybjUcKwb";
+        let fixture = WalletFixture::new(
+            format!("tr({internal_xprv}/86'/1'/0'/0/*,pk({leaf_xprv}/0/*))"),
+            format!("tr({internal_xprv}/86'/1'/0'/1/*,pk({leaf_xprv}/1/*))"),
+        );
+        let mut wallet = fixture.wallet();
+        receive(&mut wallet, 1, 50_000, None);
+
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(address.script_pubkey());
+        let psbt = builder.finish().unwrap();
+
+        // The wallet fills in the taproot fields of the input it spends.
+        let input = &psbt.inputs[0];
+        assert!(input.tap_internal_key.is_some());
+        assert!(input.tap_merkle_root.is_some());
+        assert_eq!(input.tap_scripts.len(), 1);
+        assert_eq!(input.tap_key_origins.len(), 2);
+        let (control_block, (leaf_script, _)) = input.tap_scripts.first_key_value().unwrap();
+        let control_block = control_block.serialize();
+        let leaf_script = leaf_script.clone();
+
+        let mut sign = |args: &[&str]| -> Psbt {
+            let psbt = BASE64_STANDARD.encode(psbt.serialize());
+            let mut sign_args = vec!["sign", &psbt];
+            sign_args.extend(args);
+            let result = run_offline_wallet_subcommand(&mut wallet, &fixture, &sign_args).unwrap();
+            assert_eq!(result["is_finalized"], true);
+            let psbt_bytes = BASE64_STANDARD
+                .decode(result["psbt"].as_str().unwrap())
+                .unwrap();
+            Psbt::deserialize(&psbt_bytes).unwrap()
+        };
+
+        // Key path spend, the witness only holds the schnorr signature.
+        let key_path = sign(&[]);
+        let witness = key_path.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 1);
+        assert_eq!(witness.nth(0).unwrap().len(), 64);
+
+        // Script path spend, the witness holds the leaf signature, script and control block.
+        let script_path = sign(&["--no_tap_internal_key"]);
+        let witness = script_path.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness.nth(0).unwrap().len(), 64);
+        assert_eq!(witness.nth(1).unwrap(), leaf_script.as_bytes());
+        assert_eq!(witness.nth(2).unwrap(), control_block.as_slice());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_psbt_status_multisig() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::NetworkKind;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::bip32::{Xpriv, Xpub};
+        use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let xprv_a = Xpriv::from_str(TEST_XPRV).unwrap();
+        let xprv_b = Xpriv::from_str("tprv8ZgxMBicQKsPeKiWapAW4Kg6kxiL8garwu1gBk55h1e8Quy9p6CiRoCkEPQj1WdD4M3BhRy7cCyB8ymGBEYmgjREuPMrXVVoxXSybjUcKwb").unwrap();
+        let xprv_c = Xpriv::new_master(NetworkKind::Test, &[3; 32]).unwrap();
+        let xpub_b = Xpub::from_priv(&secp, &xprv_b);
+        let xpub_c = Xpub::from_priv(&secp, &xprv_c);
+        let fixture = WalletFixture::new(
+            format!("wsh(multi(2,{xprv_a}/0/*,{xpub_b}/0/*,{xpub_c}/0/*))"),
+            format!("wsh(multi(2,{xprv_a}/1/*,{xpub_b}/1/*,{xpub_c}/1/*))"),
+        );
+        let mut wallet = fixture.wallet();
+        receive(&mut wallet, 1, 50_000, None);
+
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(address.script_pubkey());
+        let psbt = BASE64_STANDARD.encode(builder.finish().unwrap().serialize());
+
+        let status =
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["psbt_status", &psbt]).unwrap();
+        assert_eq!(status["is_complete"], false);
+        let input = &status["inputs"][0];
+        assert_eq!(input["signatures"], 0);
+        assert_eq!(input["required"], 2);
+        assert_eq!(input["remaining"], 2);
+        assert_eq!(input["not_signed_by"].as_array().unwrap().len(), 3);
+        let fingerprint_a = xprv_a.fingerprint(&secp).to_string();
+        assert_eq!(input["signing_keys"].as_array().unwrap().len(), 1);
+        assert_eq!(input["signing_keys"][0]["fingerprint"], fingerprint_a);
+        assert_eq!(input["signing_keys"][0]["path"], "m/0/0");
+
+        // The wallet holds the first key only.
+        let signed =
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["sign", &psbt]).unwrap();
+        assert_eq!(signed["is_finalized"], false);
+        let psbt = signed["psbt"].as_str().unwrap();
+        let status =
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["psbt_status", psbt]).unwrap();
+        let input = &status["inputs"][0];
+        assert_eq!(input["signatures"], 1);
+        assert_eq!(input["remaining"], 1);
+        assert_eq!(input["signed_by"], serde_json::json!([fingerprint_a]));
+
+        let xprv_b = xprv_b.to_string();
+        let signed = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["sign", psbt, "--xprv", &xprv_b],
+        )
+        .unwrap();
+        assert_eq!(signed["is_finalized"], true);
+        let signing_keys = signed["signing_keys"][0]["keys"].as_array().unwrap();
+        assert_eq!(signing_keys.len(), 2);
+        let status = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["psbt_status", signed["psbt"].as_str().unwrap()],
+        )
+        .unwrap();
+        assert_eq!(status["is_complete"], true);
+        assert_eq!(status["inputs"][0]["is_finalized"], true);
+    }
+
+    #[cfg(feature = "elements")]
+    #[test]
+    fn test_elements_confidential_descriptor_address() {
+        use super::handle_elements_subcommand;
+        use crate::commands::ElementsSubCommand;
+        use bdk_wallet::bitcoin::Network;
+
+        let xpub = "tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+        let descriptor = format!(
+            "ct(slip77(b2396b3ee20509cdb64fe24180a14a72dbd671728eaa49bac69d2bdecb5f5a04),elwpkh({xpub}/0/*))"
+        );
+        let result = handle_elements_subcommand(
+            Network::Bitcoin,
+            ElementsSubCommand::Descriptor {
+                descriptor,
+                index: 0,
+            },
+            false,
+        )
+        .unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+        let address = result["address"].as_str().unwrap().to_string();
+        assert!(address.starts_with("lq1"));
+
+        let result = handle_elements_subcommand(
+            Network::Bitcoin,
+            ElementsSubCommand::Address {
+                address: address.clone(),
+            },
+            false,
+        )
+        .unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["is_confidential"], true);
+        assert_eq!(result["chain"], "liquid");
+
+        // Liquid addresses are rejected on the regtest chain.
+        assert!(
+            handle_elements_subcommand(
+                Network::Regtest,
+                ElementsSubCommand::Address { address },
+                false
+            )
+            .is_err()
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sign_foreign_inputs() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::bip32::Xpriv;
+        use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+        use bdk_wallet::bitcoin::{Amount, NetworkKind, Psbt, psbt};
+
+        let (mut wallet_a, fixture_a) = test_wallet();
+        let xprv_b = Xpriv::new_master(NetworkKind::Test, &[5; 32]).unwrap();
+        let fixture_b = WalletFixture::new(
+            format!("wpkh({xprv_b}/84'/1'/0'/0/*)"),
+            format!("wpkh({xprv_b}/84'/1'/0'/1/*)"),
+        );
+        let mut wallet_b = fixture_b.wallet();
+        receive(&mut wallet_a, 1, 30_000, Some(1));
+        let foreign = receive(&mut wallet_b, 2, 40_000, Some(1));
+
+        let foreign_input = psbt::Input {
+            witness_utxo: Some(wallet_b.get_utxo(foreign).unwrap().txout),
+            non_witness_utxo: Some((*wallet_b.get_tx(foreign.txid).unwrap().tx_node.tx).clone()),
+            ..Default::default()
+        };
+        let satisfaction_weight = wallet_b
+            .public_descriptor(KeychainKind::External)
+            .max_weight_to_satisfy()
+            .unwrap();
+        let address = wallet_a.reveal_next_address(KeychainKind::External).address;
+        let mut builder = wallet_a.build_tx();
+        builder
+            .add_recipient(address.script_pubkey(), Amount::from_sat(60_000))
+            .add_foreign_utxo(foreign, foreign_input, satisfaction_weight)
+            .unwrap();
+        let mut psbt = builder.finish().unwrap();
+        let foreign_index = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|txin| txin.previous_output == foreign)
+            .unwrap();
+        // The foreign input only has a witness UTXO, as in PayJoin proposals.
+        psbt.inputs[foreign_index].non_witness_utxo = None;
+        let psbt = BASE64_STANDARD.encode(psbt.serialize());
+
+        let signed =
+            run_offline_wallet_subcommand(&mut wallet_a, &fixture_a, &["sign", &psbt]).unwrap();
+        assert_eq!(
+            signed["inputs"],
+            serde_json::json!({"owned": 1, "foreign": 1})
+        );
+        assert_eq!(signed["is_finalized"], false);
+        let psbt_bytes = BASE64_STANDARD
+            .decode(signed["psbt"].as_str().unwrap())
+            .unwrap();
+        let signed_psbt = Psbt::deserialize(&psbt_bytes).unwrap();
+        for (index, input) in signed_psbt.inputs.iter().enumerate() {
+            assert_eq!(input.final_script_witness.is_some(), index != foreign_index);
+        }
+
+        // Taproot fields the PSBT adds to a segwit v0 input don't make its witness UTXO trusted.
+        let mut forged = Psbt::deserialize(&psbt_bytes).unwrap();
+        forged.inputs[foreign_index].tap_internal_key =
+            Some(xprv_b.to_keypair(&Secp256k1::new()).x_only_public_key().0);
+        let forged = BASE64_STANDARD.encode(forged.serialize());
+        let error = run_offline_wallet_subcommand(&mut wallet_b, &fixture_b, &["sign", &forged])
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains(&format!("Input {foreign_index} has taproot fields"))
+        );
+
+        // The other party signs its input, which also only has a witness UTXO.
+        let signed = run_offline_wallet_subcommand(
+            &mut wallet_b,
+            &fixture_b,
+            &[
+                "sign",
+                "--trust_witness_utxo",
+                "true",
+                signed["psbt"].as_str().unwrap(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            signed["inputs"],
+            serde_json::json!({"owned": 1, "foreign": 1})
+        );
+        assert_eq!(signed["is_finalized"], true);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sign_max_fee_rate() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::{Amount, FeeRate, Psbt};
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(address.script_pubkey(), Amount::from_sat(10_000))
+            .fee_rate(FeeRate::from_sat_per_vb_unchecked(10));
+        let psbt = builder.finish().unwrap();
+
+        // The estimate counts the signature weight the wallet will add.
+        let (fee, fee_rate) = super::psbt_fee_rate(&wallet, &psbt).unwrap();
+        let psbt = BASE64_STANDARD.encode(psbt.serialize());
+        let signed = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["sign", &psbt, "--max_fee_rate", "20"],
+        )
+        .unwrap();
+        let psbt_bytes = BASE64_STANDARD
+            .decode(signed["psbt"].as_str().unwrap())
+            .unwrap();
+        let tx = Psbt::deserialize(&psbt_bytes)
+            .unwrap()
+            .extract_tx()
+            .unwrap();
+        let signed_fee_rate = fee.to_sat() as f64 / tx.vsize() as f64;
+        assert!(fee_rate >= signed_fee_rate && fee_rate - signed_fee_rate < 0.1);
+
+        let error = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["sign", &psbt, "--max_fee_rate", "5"],
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("sat/vbyte, above the maximum of 5")
+        );
+
+        let signed = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["sign", &psbt, "--max_fee_rate", "5", "--force"],
+        )
+        .unwrap();
+        assert_eq!(signed["is_finalized"], true);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sign_grind_r() {
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::{Amount, Psbt};
+        use bdk_wallet::{KeychainKind, TxOrdering};
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+
+        // The DER encoding of a signature takes a byte more when its R value is high, about half
+        // of the signatures without grinding. The signatures are deterministic with the outputs
+        // left in order, some of these eight transactions are known to get a high R value.
+        let mut signature_sizes = |grind_r: &str| -> Vec<usize> {
+            (0..8)
+                .map(|i| {
+                    let mut builder = wallet.build_tx();
+                    builder.ordering(TxOrdering::Untouched);
+                    builder.add_recipient(address.script_pubkey(), Amount::from_sat(10_000 + i));
+                    let psbt = BASE64_STANDARD.encode(builder.finish().unwrap().serialize());
+                    let signed = run_offline_wallet_subcommand(
+                        &mut wallet,
+                        &fixture,
+                        &["sign", &psbt, "--grind_r", grind_r],
+                    )
+                    .unwrap();
+                    assert_eq!(signed["grind_r"], grind_r == "true");
+                    let psbt_bytes = BASE64_STANDARD
+                        .decode(signed["psbt"].as_str().unwrap())
+                        .unwrap();
+                    let tx = Psbt::deserialize(&psbt_bytes)
+                        .unwrap()
+                        .extract_tx()
+                        .unwrap();
+                    tx.input[0].witness.nth(0).unwrap().len()
+                })
+                .collect()
+        };
+        assert!(signature_sizes("true").iter().all(|size| *size <= 71));
+        assert!(signature_sizes("false").contains(&72));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_ordering() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+
+        let (mut wallet, fixture) = test_wallet();
+        for funding_id in 1..=4 {
+            receive(&mut wallet, funding_id, 20_000, Some(1));
+        }
+        let recipients: Vec<String> = [30_000, 10_000, 20_000]
+            .iter()
+            .map(|sats| {
+                let address = wallet.reveal_next_address(KeychainKind::External).address;
+                format!("{address}:{sats}")
+            })
+            .collect();
+
+        let mut create_tx = |ordering: &str| -> Psbt {
+            let mut args = vec!["create_tx", ordering];
+            for recipient in &recipients {
+                args.extend(["--to", recipient]);
+            }
+            let result = run_offline_wallet_subcommand(&mut wallet, &fixture, &args).unwrap();
+            let expected = if ordering == "--bip69" {
+                "bip69"
+            } else {
+                "untouched"
+            };
+            assert_eq!(result["ordering"], expected);
+            let psbt_bytes = BASE64_STANDARD
+                .decode(result["psbt"].as_str().unwrap())
+                .unwrap();
+            Psbt::deserialize(&psbt_bytes).unwrap()
+        };
+
+        let tx = create_tx("--bip69").unsigned_tx;
+        assert!(tx.input.len() > 1);
+        assert!(tx.input.windows(2).all(|pair| {
+            let txid = |index: usize| pair[index].previous_output.txid.to_string();
+            txid(0) < txid(1)
+        }));
+        assert!(
+            tx.output
+                .windows(2)
+                .all(|pair| pair[0].value <= pair[1].value)
+        );
+
+        // The change output is added after the recipients.
+        let tx = create_tx("--preserve_order").unsigned_tx;
+        let values: Vec<u64> = tx.output.iter().map(|txout| txout.value.to_sat()).collect();
+        assert_eq!(values[..3], [30_000, 10_000, 20_000]);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_split_change() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 100_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let recipient = format!("{address}:10000");
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &recipient, "--split_change", "3"],
+        )
+        .unwrap();
+        let psbt_bytes = BASE64_STANDARD
+            .decode(result["psbt"].as_str().unwrap())
+            .unwrap();
+        let tx = Psbt::deserialize(&psbt_bytes).unwrap().unsigned_tx;
+        assert_eq!(tx.output.len(), 4);
+
+        let change_outputs = result["change_outputs"].as_array().unwrap();
+        assert_eq!(change_outputs.len(), 3);
+        let amounts: Vec<u64> = change_outputs
+            .iter()
+            .map(|output| output["amount"].as_u64().unwrap())
+            .collect();
+        assert!(amounts.iter().max().unwrap() - amounts.iter().min().unwrap() <= 1);
+        for output in change_outputs {
+            let txout = &tx.output[output["vout"].as_u64().unwrap() as usize];
+            assert_eq!(txout.value.to_sat(), output["amount"].as_u64().unwrap());
+            assert!(matches!(
+                wallet.derivation_of_spk(txout.script_pubkey.clone()),
+                Some((KeychainKind::Internal, _))
+            ));
+        }
+
+        let error = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &recipient, "--split_change", "1000"],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("too small to split"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_subtract_fee_from() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 100_000, Some(1));
+        let first = format!(
+            "{}:10000",
+            wallet.reveal_next_address(KeychainKind::External).address
+        );
+        let second = format!(
+            "{}:20000",
+            wallet.reveal_next_address(KeychainKind::External).address
+        );
+        let third = format!(
+            "{}:30000",
+            wallet.reveal_next_address(KeychainKind::External).address
+        );
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "create_tx",
+                "--to",
+                &first,
+                "--to",
+                &second,
+                "--to",
+                &third,
+                "--fee_rate",
+                "5",
+                "--subtract_fee_from",
+                "1,2",
+            ],
+        )
+        .unwrap();
+        let psbt_bytes = BASE64_STANDARD
+            .decode(result["psbt"].as_str().unwrap())
+            .unwrap();
+        let psbt = Psbt::deserialize(&psbt_bytes).unwrap();
+        let fee = psbt.fee().unwrap().to_sat();
+        let amounts: Vec<u64> = result["recipients"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|recipient| recipient["amount"].as_u64().unwrap())
+            .collect();
+        assert_eq!(amounts[0], 10_000);
+        assert_eq!(amounts[1] + amounts[2], 50_000 - fee);
+        // The 20000 and 30000 sats recipients pay 2/5 and 3/5 of the fee, the last one also pays
+        // the rounding remainder.
+        assert_eq!(amounts[1], 20_000 - fee * 2 / 5);
+        // The change pays nothing towards the fee.
+        let change: u64 = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|txout| !amounts.contains(&txout.value.to_sat()))
+            .map(|txout| txout.value.to_sat())
+            .sum();
+        assert_eq!(change, 40_000);
+
+        let error = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &first, "--subtract_fee_from", "1"],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("out of range"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_sweep_small() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+
+        let (mut wallet, fixture) = test_wallet();
+        // The large UTXO is selected explicitly, so coin selection doesn't add the others.
+        let funding = receive(&mut wallet, 1, 100_000, Some(1)).to_string();
+        // Spending a P2WPKH input costs about 340 sats at 5 sat/vbyte.
+        let uneconomical = receive(&mut wallet, 2, 300, Some(2));
+        let small = receive(&mut wallet, 3, 600, Some(3));
+        receive(&mut wallet, 4, 2_000, Some(4));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let recipient = format!("{address}:10000");
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "create_tx",
+                "--to",
+                &recipient,
+                "--utxos",
+                &funding,
+                "--fee_rate",
+                "5",
+                "--sweep_small",
+                "1000",
+            ],
+        )
+        .unwrap();
+        assert_eq!(result["swept_small_utxos"], 1);
+        let psbt_bytes = BASE64_STANDARD
+            .decode(result["psbt"].as_str().unwrap())
+            .unwrap();
+        let inputs: Vec<_> = Psbt::deserialize(&psbt_bytes)
+            .unwrap()
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect();
+        assert!(inputs.contains(&small));
+        assert!(!inputs.contains(&uneconomical));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_no_change() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 10_000, Some(1));
+        receive(&mut wallet, 2, 20_000, Some(2));
+        receive(&mut wallet, 3, 30_000, Some(3));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let mut create_tx = |amount: u64| {
+            let recipient = format!("{address}:{amount}");
+            run_offline_wallet_subcommand(
+                &mut wallet,
+                &fixture,
+                &[
+                    "create_tx",
+                    "--to",
+                    &recipient,
+                    "--fee_rate",
+                    "2",
+                    "--no_change",
+                ],
+            )
+        };
+
+        // The 30000 and 20000 sats UTXOs minus the fee of two inputs at 2 sat/vbyte.
+        let result = create_tx(49_600).unwrap();
+        let excess = result["excess"].as_u64().unwrap();
+        assert!(excess < 62);
+        assert_eq!(result["exact_match"], excess == 0);
+        let psbt_bytes = BASE64_STANDARD
+            .decode(result["psbt"].as_str().unwrap())
+            .unwrap();
+        let tx = Psbt::deserialize(&psbt_bytes).unwrap().unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 1);
+
+        assert_eq!(
+            create_tx(25_000).unwrap_err().to_string(),
+            "Generic error: No selection of inputs pays the recipients and the fee without change, within the cost of a change output."
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_no_broadcast() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::consensus::encode::serialize_hex;
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let recipient = format!("{address}:20000");
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &recipient, "--no_broadcast"],
+        )
+        .unwrap();
+        let psbt_bytes = BASE64_STANDARD
+            .decode(result["psbt"].as_str().unwrap())
+            .unwrap();
+        let tx = Psbt::deserialize(&psbt_bytes)
+            .unwrap()
+            .extract_tx()
+            .unwrap();
+        assert_eq!(result["raw_tx"], serialize_hex(&tx));
+        assert_eq!(result["txid"], tx.compute_txid().to_string());
+        // The change address is reserved, the next transaction doesn't reuse it.
+        assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(0));
+
+        let watch_only_fixture = WalletFixture::new(
+            "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/0/*)".to_string(),
+            "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/1/*)".to_string(),
+        );
+        let mut watch_only = watch_only_fixture.wallet();
+        receive(&mut watch_only, 1, 50_000, Some(1));
+        let error = run_offline_wallet_subcommand(
+            &mut watch_only,
+            &watch_only_fixture,
+            &["create_tx", "--to", &recipient, "--no_broadcast"],
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("can't finalize the transaction alone")
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_estimate_satisfaction() {
+        use bdk_wallet::KeychainKind;
+
+        let tpub = "tpubDDA3frU31t8hSUKpX7UmhvJuDGG1r7UNzx85fQ49Xa15ax6Yby2sLQYCwCDWgdXQeb4iK8EZGKwRHDdKZTgvpHNi5WeUQn5ZBpEFSnM8FkE";
+        // The wallet key spends alone, the recovery path needs two other keys after 144 blocks.
+        let descriptor = |branch: u32| {
+            format!(
+                "wsh(or_d(pk({TEST_XPRV}/{branch}/*),and_v(v:multi(2,{tpub}/0/{branch}/*,{tpub}/1/{branch}/*),older(144))))"
+            )
+        };
+        let fixture = WalletFixture::new(descriptor(0), descriptor(1));
+        let mut wallet = fixture.wallet();
+        let outpoint = receive(&mut wallet, 1, 50_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let recipient = format!("{address}:20000");
+        let policy = |keychain| {
+            let policy_id = wallet.policies(keychain).unwrap().unwrap().id;
+            format!(r#"{{"{policy_id}":[0]}}"#)
+        };
+        let (external_policy, internal_policy) = (
+            policy(KeychainKind::External),
+            policy(KeychainKind::Internal),
+        );
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "create_tx",
+                "--to",
+                &recipient,
+                "--fee_rate",
+                "2",
+                "--external_policy",
+                &external_policy,
+                "--internal_policy",
+                &internal_policy,
+                "--estimate_satisfaction",
+            ],
+        )
+        .unwrap();
+        let satisfaction = &result["satisfaction"];
+        let input = &satisfaction["inputs"][0];
+        assert_eq!(input["outpoint"], outpoint.to_string());
+        assert_eq!(input["keychain"], "external");
+        assert_eq!(input["planned_with"], "wallet_keys");
+        let (max, planned) = (
+            input["max_weight"].as_u64().unwrap(),
+            input["planned_weight"].as_u64().unwrap(),
+        );
+        // Only one signature instead of the two of the recovery path.
+        assert!(planned + 72 < max);
+        let (max_vsize, planned_vsize) = (
+            satisfaction["vsize"]["max"].as_u64().unwrap(),
+            satisfaction["vsize"]["planned"].as_u64().unwrap(),
+        );
+        assert_eq!(max_vsize - planned_vsize, (max - planned) / 4);
+        // The fee covers the worst case, so the planned path pays more than asked.
+        assert!(satisfaction["fee_rate"]["max"].as_f64().unwrap() >= 2.0);
+        assert!(
+            satisfaction["fee_rate"]["planned"].as_f64().unwrap()
+                > satisfaction["fee_rate"]["max"].as_f64().unwrap()
+        );
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "create_tx",
+                "--to",
+                &recipient,
+                "--external_policy",
+                &external_policy,
+                "--internal_policy",
+                &internal_policy,
+            ],
+        )
+        .unwrap();
+        assert!(result.get("satisfaction").is_none());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_min_change() {
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::{Network, Psbt};
+        use bdk_wallet::{KeychainKind, Wallet};
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 30_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let recipient = format!("{address}:27000");
+        let mut create_tx = |min_change: &str| {
+            let result = run_offline_wallet_subcommand(
+                &mut wallet,
+                &fixture,
+                &[
+                    "create_tx",
+                    "--to",
+                    &recipient,
+                    "--fee_rate",
+                    "2",
+                    "--min_change",
+                    min_change,
+                ],
+            )
+            .unwrap();
+            let psbt_bytes = BASE64_STANDARD
+                .decode(result["psbt"].as_str().unwrap())
+                .unwrap();
+            let psbt = Psbt::deserialize(&psbt_bytes).unwrap();
+            (result["change"].clone(), psbt)
+        };
+
+        // About 2700 sats of change are left after the fee.
+        let (change, psbt) = create_tx("1000");
+        assert_eq!(change["created"], true);
+        assert_eq!(change["absorbed_into_fee"], 0);
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+
+        let (change, psbt) = create_tx("5000");
+        assert_eq!(change["created"], false);
+        assert_eq!(change["amount"], 0);
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert!(psbt.fee().unwrap().to_sat() > 2_700);
+        assert!(change["absorbed_into_fee"].as_u64().unwrap() > 2_600);
+
+        // A single descriptor wallet sends its change to the external keychain, the recipient
+        // belongs to the other descriptor.
+        let mut wallet = Wallet::create_single(fixture.int_descriptor.clone())
+            .network(Network::Regtest)
+            .create_wallet_no_persist()
+            .unwrap();
+        receive(&mut wallet, 1, 30_000, Some(1));
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "create_tx",
+                "--to",
+                &recipient,
+                "--fee_rate",
+                "2",
+                "--min_change",
+                "1000",
+            ],
+        )
+        .unwrap();
+        assert_eq!(result["change"]["created"], true);
+        assert!(result["change"]["amount"].as_u64().unwrap() > 2_600);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_annotate() {
+        // The annotations are stored in the data directory of the fixture.
+        let (mut wallet, fixture) = test_wallet();
+        let txid = receive(&mut wallet, 1, 10_000, Some(1)).txid.to_string();
+        let descriptors = &fixture;
+
+        run_offline_wallet_subcommand(
+            &mut wallet,
+            descriptors,
+            &["annotate", "--txid", &txid, "--category", "income"],
+        )
+        .unwrap();
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            descriptors,
+            &[
+                "annotate",
+                "--txid",
+                &txid,
+                "--fiat",
+                "12.50",
+                "--currency",
+                "usd",
+            ],
+        )
+        .unwrap();
+        let expected =
+            serde_json::json!({"category": "income", "fiat": "12.50", "currency": "USD"});
+        assert_eq!(result["annotation"], expected);
+
+        let result =
+            run_offline_wallet_subcommand(&mut wallet, descriptors, &["transactions"]).unwrap();
+        assert_eq!(result["transactions"][0]["annotation"], expected);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_denom_amounts() {
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 123_456_789, Some(1));
+        receive(&mut wallet, 2, 1_500, Some(2));
+
+        let balance =
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["balance", "--denom", "btc"])
+                .unwrap();
+        assert_eq!(balance["btc"]["confirmed"], "1.23458289");
+
+        let unspent =
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["unspent", "--denom", "mbtc"])
+                .unwrap();
+        let mut values: Vec<_> = unspent
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|utxo| utxo["txout"]["value"].as_str().unwrap().to_string())
+            .collect();
+        values.sort();
+        assert_eq!(values, ["0.015", "1234.56789"]);
+
+        // The fees of the transaction reports too.
+        let address = wallet
+            .peek_address(bdk_wallet::KeychainKind::External, 5)
+            .address;
+        let simulation = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "simulate_send",
+                "--to",
+                &format!("{address}:10000"),
+                "--fee_rates",
+                "2",
+                "--denom",
+                "bit",
+            ],
+        )
+        .unwrap();
+        let fee = simulation["simulations"][0]["fee"].as_str().unwrap();
+        assert!(fee.parse::<f64>().unwrap() > 0.0);
+
+        // Sats stay JSON numbers.
+        let balance = run_offline_wallet_subcommand(&mut wallet, &fixture, &["balance"]).unwrap();
+        assert_eq!(balance["satoshi"]["confirmed"], 123_458_289);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sign_reports_fingerprint_mismatch() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+
+        let (mut wallet, _fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(address.script_pubkey());
+        let psbt = BASE64_STANDARD.encode(builder.finish().unwrap().serialize());
+
+        let other_xprv = "tprv8ZgxMBicQKsPdmJDTZGgBJBD9GSh9T19Y5X3Yph6QxF1LkWqkBDqqw7S8zrEB3KK8MV5eEv5TsyqbMM937JryFRftXC6rDoXtVohzzQovoo";
+        let other_fixture = WalletFixture::new(
+            format!("wpkh({other_xprv}/84'/1'/0'/0/*)"),
+            format!("wpkh({other_xprv}/84'/1'/0'/1/*)"),
+        );
+        let mut other_wallet = other_fixture.wallet();
+        let error =
+            run_offline_wallet_subcommand(&mut other_wallet, &other_fixture, &["sign", &psbt])
+                .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Generic error: No signature was added. The PSBT expects fingerprint 73c5da0a, the wallet has b8688df1."
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sign_also_descriptor() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::Psbt;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::bip32::{Xpriv, Xpub};
+        use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let xprv_a = TEST_XPRV;
+        let xprv_b = "tprv8ZgxMBicQKsPdmJDTZGgBJBD9GSh9T19Y5X3Yph6QxF1LkWqkBDqqw7S8zrEB3KK8MV5eEv5TsyqbMM937JryFRftXC6rDoXtVohzzQovoo";
+        let xpub = |xprv: &str| Xpub::from_priv(&secp, &Xpriv::from_str(xprv).unwrap());
+        let fixture = WalletFixture::new(
+            format!("wsh(multi(2,{xprv_a}/0/*,{}/0/*))", xpub(xprv_b)),
+            format!("wsh(multi(2,{xprv_a}/1/*,{}/1/*))", xpub(xprv_b)),
+        );
+        let other_descriptor = format!("wsh(multi(2,{}/0/*,{xprv_b}/0/*))", xpub(xprv_a));
+        let mut wallet = fixture.wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(address.script_pubkey());
+        let psbt = BASE64_STANDARD.encode(builder.finish().unwrap().serialize());
+        let descriptors = &fixture;
+
+        let result =
+            run_offline_wallet_subcommand(&mut wallet, descriptors, &["sign", &psbt]).unwrap();
+        assert_eq!(result["is_finalized"], false);
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            descriptors,
+            &["sign", &psbt, "--also_descriptor", &other_descriptor],
+        )
+        .unwrap();
+        assert_eq!(result["is_finalized"], true);
+        assert_eq!(result["signatures_added"]["wallet"], 1);
+        assert_eq!(result["signatures_added"]["also_descriptors"][0], 1);
+        let psbt = BASE64_STANDARD
+            .decode(result["psbt"].as_str().unwrap())
+            .unwrap();
+        assert!(Psbt::deserialize(&psbt).unwrap().extract_tx().is_ok());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_combine_psbt_canonical() {
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::bip32::{Xpriv, Xpub};
+        use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+        use bdk_wallet::bitcoin::{EcdsaSighashType, Network, Psbt};
+        use bdk_wallet::{KeychainKind, SignOptions, Wallet};
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let xprv_a = TEST_XPRV;
+        let xprv_b = "tprv8ZgxMBicQKsPdmJDTZGgBJBD9GSh9T19Y5X3Yph6QxF1LkWqkBDqqw7S8zrEB3KK8MV5eEv5TsyqbMM937JryFRftXC6rDoXtVohzzQovoo";
+        let xpub = |xprv: &str| Xpub::from_priv(&secp, &Xpriv::from_str(xprv).unwrap());
+        let fixture = WalletFixture::new(
+            format!("wsh(multi(2,{xprv_a}/0/*,{}/0/*))", xpub(xprv_b)),
+            format!("wsh(multi(2,{xprv_a}/1/*,{}/1/*))", xpub(xprv_b)),
+        );
+        let mut wallet = fixture.wallet();
+        let other = Wallet::create(
+            format!("wsh(multi(2,{}/0/*,{xprv_b}/0/*))", xpub(xprv_a)),
+            format!("wsh(multi(2,{}/1/*,{xprv_b}/1/*))", xpub(xprv_a)),
+        )
+        .network(Network::Regtest)
+        .create_wallet_no_persist()
+        .unwrap();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let mut builder = wallet.build_tx();
+        builder.drain_wallet().drain_to(address.script_pubkey());
+        let unsigned = builder.finish().unwrap();
+        let sign_options = SignOptions {
+            try_finalize: false,
+            ..Default::default()
+        };
+        let mut signed_a = unsigned.clone();
+        wallet.sign(&mut signed_a, sign_options.clone()).unwrap();
+        let mut signed_b = unsigned.clone();
+        other.sign(&mut signed_b, sign_options).unwrap();
+        let encode = |psbt: &Psbt| BASE64_STANDARD.encode(psbt.serialize());
+        let descriptors = &fixture;
+        let mut combine = |psbts: &[&Psbt]| {
+            let mut args = vec!["combine_psbt".to_string()];
+            args.extend(psbts.iter().map(|psbt| encode(psbt)));
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            run_offline_wallet_subcommand(&mut wallet, descriptors, &args)
+        };
+
+        let combined = combine(&[&signed_a, &unsigned, &signed_b]).unwrap();
+        assert_eq!(
+            combine(&[&signed_b, &signed_a, &signed_a, &unsigned]).unwrap(),
+            combined
+        );
+        let psbt = BASE64_STANDARD
+            .decode(combined["psbt"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(
+            Psbt::deserialize(&psbt).unwrap().inputs[0]
+                .partial_sigs
+                .len(),
+            2
+        );
+
+        let mut conflicting = signed_a.clone();
+        for sig in conflicting.inputs[0].partial_sigs.values_mut() {
+            sig.sighash_type = EcdsaSighashType::None;
+        }
+        assert_eq!(
+            combine(&[&signed_a, &signed_b, &conflicting])
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Generic error: The PSBTs have different signatures of {} for the input 0.",
+                signed_a.inputs[0].partial_sigs.keys().next().unwrap()
+            )
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_to_script() {
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::{Psbt, ScriptBuf};
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 100_000, Some(1));
+
+        // OP_TRUE, anyone can spend it.
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to_script", "51:5000"],
+        )
+        .unwrap();
+        let psbt = BASE64_STANDARD
+            .decode(result["psbt"].as_str().unwrap())
+            .unwrap();
+        let psbt = Psbt::deserialize(&psbt).unwrap();
+        let op_true = ScriptBuf::from_hex("51").unwrap();
+        assert!(
+            psbt.unsigned_tx
+                .output
+                .iter()
+                .any(|output| output.script_pubkey == op_true && output.value.to_sat() == 5000)
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_new_address_type() {
+        use bdk_wallet::KeychainKind;
+
+        let fixture = WalletFixture::new(
+            format!("sh(wpkh({TEST_XPRV}/49'/1'/0'/0/*))"),
+            format!("sh(wpkh({TEST_XPRV}/49'/1'/0'/1/*))"),
+        );
+        let mut wallet = fixture.wallet();
+        let descriptors = &fixture;
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            descriptors,
+            &["new_address", "--type", "p2sh"],
+        )
+        .unwrap();
+        assert!(result["address"].as_str().unwrap().starts_with('2'));
+
+        let error = run_offline_wallet_subcommand(
+            &mut wallet,
+            descriptors,
+            &["new_address", "--type", "bech32"],
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("only has p2sh addresses, it can't give a bech32 one")
+        );
+        // Nothing is revealed when the encoding doesn't match.
+        assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_skip_index() {
+        use bdk_wallet::{KeychainKind, Wallet};
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let run = |wallet: &mut Wallet, args: &[&str]| {
+            run_offline_wallet_subcommand(wallet, &fixture, args)
+        };
+
+        let result = run(
+            &mut wallet,
+            &["skip_index", "--keychain", "internal", "--to", "5"],
+        )
+        .unwrap();
+        assert_eq!(result["previous_next_index"], 0);
+        assert_eq!(result["next_index"], 5);
+        assert_eq!(result["skipped"], 5);
+        assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(4));
+
+        // The change goes past the skipped addresses, even though they never received funds.
+        let address = wallet.peek_address(KeychainKind::External, 0).address;
+        let recipient = format!("{address}:20000");
+        run(&mut wallet, &["create_tx", "--to", &recipient]).unwrap();
+        assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(5));
+
+        let error = run(
+            &mut wallet,
+            &["skip_index", "--keychain", "internal", "--to", "6"],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("already 6"));
+
+        run(
+            &mut wallet,
+            &["skip_index", "--keychain", "external", "--to", "3"],
+        )
+        .unwrap();
+        let result = run(&mut wallet, &["new_address"]).unwrap();
+        assert_eq!(
+            result["address"],
+            wallet
+                .peek_address(KeychainKind::External, 3)
+                .address
+                .to_string()
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_next_addresses() {
+        use bdk_wallet::KeychainKind;
+
+        let (mut wallet, fixture) = test_wallet();
+        // Index 1 receives funds, indexes 0 and 2 are revealed but never used.
+        let _ = wallet.reveal_next_address(KeychainKind::External);
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let _ = wallet.reveal_next_address(KeychainKind::External);
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["next_addresses", "--count", "4"],
+        )
+        .unwrap();
+        assert_eq!(result["next_index"], 3);
+        let addresses = result["addresses"].as_array().unwrap();
+        assert_eq!(addresses.len(), 4);
+        for (index, address) in addresses.iter().enumerate() {
+            assert_eq!(address["index"], index);
+            assert_eq!(
+                address["address"],
+                wallet
+                    .peek_address(KeychainKind::External, index as u32)
+                    .address
+                    .to_string()
+            );
+            assert_eq!(address["revealed"], index < 3);
+            assert_eq!(address["used"], index == 1);
+        }
+        // Nothing is revealed.
+        assert_eq!(wallet.derivation_index(KeychainKind::External), Some(2));
+    }
 
-            while let Some(block_event) = emitter.next_block()? {
-                if block_event.block_height() % 10_000 == 0 {
-                    let percent_done = f64::from(block_event.block_height())
-                        / f64::from(blockchain_info.headers as u32)
-                        * 100f64;
-                    println!(
-                        "Applying block at height: {}, {:.2}% done.",
-                        block_event.block_height(),
-                        percent_done
-                    );
-                }
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_check_addresses() {
+        use bdk_wallet::KeychainKind;
 
-                wallet.apply_block_connected_to(
-                    &block_event.block,
-                    block_event.block_height(),
-                    block_event.connected_to(),
-                )?;
-            }
+        let (mut wallet, fixture) = test_wallet();
+        let change = wallet.peek_address(KeychainKind::Internal, 3).address;
+        let beyond_gap = wallet.peek_address(KeychainKind::External, 5).address;
+        let addresses = format!("# records\n{change}\n{beyond_gap}\nnot-an-address\n");
+        let file = fixture.datadir.path().join("addresses.txt");
+        std::fs::write(&file, addresses).unwrap();
 
-            let mempool_txs = emitter.mempool()?;
-            wallet.apply_unconfirmed_txs(mempool_txs.update);
-            Ok(())
-        }
-        #[cfg(feature = "cbf")]
-        KyotoClient { client } => sync_kyoto_client(wallet, client)
-            .await
-            .map_err(|e| Error::Generic(e.to_string())),
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "check_addresses",
+                "--file",
+                file.to_str().unwrap(),
+                "--gap",
+                "5",
+            ],
+        )
+        .unwrap();
+        assert_eq!(result["checked"], 3);
+        assert_eq!(result["owned"], 1);
+        let change = &result["addresses"][change.to_string()];
+        assert_eq!(change["keychain"], "internal");
+        assert_eq!(change["index"], 3);
+        assert_eq!(
+            result["addresses"][beyond_gap.to_string()]["is_mine"],
+            false
+        );
+        assert!(result["addresses"]["not-an-address"]["error"].is_string());
+        assert_eq!(wallet.derivation_index(KeychainKind::External), None);
     }
-}
 
-#[cfg(any(
-    feature = "electrum",
-    feature = "esplora",
-    feature = "cbf",
-    feature = "rpc"
-))]
-/// Broadcasts a given transaction using the blockchain client.
-pub async fn broadcast_transaction(
-    client: BlockchainClient,
-    tx: Transaction,
-) -> Result<Txid, Error> {
-    match client {
-        #[cfg(feature = "electrum")]
-        Electrum {
-            client,
-            batch_size: _,
-        } => client
-            .transaction_broadcast(&tx)
-            .map_err(|e| Error::Generic(e.to_string())),
-        #[cfg(feature = "esplora")]
-        Esplora {
-            client,
-            parallel_requests: _,
-        } => client
-            .broadcast(&tx)
-            .await
-            .map(|()| tx.compute_txid())
-            .map_err(|e| Error::Generic(e.to_string())),
-        #[cfg(feature = "rpc")]
-        RpcClient { client } => client
-            .send_raw_transaction(&tx)
-            .map_err(|e| Error::Generic(e.to_string())),
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_insert_tx() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::consensus::encode::serialize_hex;
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{
+            Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid, absolute::LockTime,
+            transaction::Version,
+        };
 
-        #[cfg(feature = "cbf")]
-        KyotoClient { client } => {
-            let LightClient {
-                requester,
-                mut info_subscriber,
-                mut warning_subscriber,
-                update_subscriber: _,
-                node,
-            } = *client;
+        let (mut wallet, fixture) = test_wallet();
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let tx = |script_pubkey: ScriptBuf| Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(20_000),
+                script_pubkey,
+            }],
+        };
 
-            let subscriber = tracing_subscriber::FmtSubscriber::new();
-            tracing::subscriber::set_global_default(subscriber)
-                .map_err(|e| Error::Generic(format!("SetGlobalDefault error: {e}")))?;
+        let foreign = serialize_hex(&tx(ScriptBuf::new_op_return([1; 4])));
+        let error =
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["insert_tx", "--raw", &foreign])
+                .unwrap_err();
+        assert!(error.to_string().contains("neither spends from nor pays"));
 
-            tokio::task::spawn(async move { node.run().await });
-            tokio::task::spawn(async move {
-                select! {
-                    info = info_subscriber.recv() => {
-                        if let Some(info) = info {
-                            tracing::info!("{info}");
-                        }
-                    },
-                    warn = warning_subscriber.recv() => {
-                        if let Some(warn) = warn {
-                            tracing::warn!("{warn}");
-                        }
-                    }
-                }
-            });
-            let txid = tx.compute_txid();
-            let wtxid = requester.broadcast_random(tx.clone()).await.map_err(|_| {
-                tracing::warn!("Broadcast was unsuccessful");
-                Error::Generic("Transaction broadcast timed out after 30 seconds".into())
-            })?;
-            tracing::info!("Successfully broadcast WTXID: {wtxid}");
-            Ok(txid)
-        }
+        let payment = serialize_hex(&tx(address.script_pubkey()));
+        let error = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["insert_tx", "--raw", &payment, "--height", "10"],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("--block_hash"));
+
+        let result =
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["insert_tx", "--raw", &payment])
+                .unwrap();
+        assert_eq!(result["received"], 20_000);
+        assert_eq!(wallet.balance().untrusted_pending.to_sat(), 20_000);
     }
-}
 
-#[cfg(feature = "repl")]
-fn readline() -> Result<String, Error> {
-    write!(std::io::stdout(), "> ").map_err(|e| Error::Generic(e.to_string()))?;
-    std::io::stdout()
-        .flush()
-        .map_err(|e| Error::Generic(e.to_string()))?;
-    let mut buffer = String::new();
-    std::io::stdin()
-        .read_line(&mut buffer)
-        .map_err(|e| Error::Generic(e.to_string()))?;
-    Ok(buffer)
-}
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_rbf_status_inherited() {
+        use bdk_wallet::bitcoin::hashes::Hash;
+        use bdk_wallet::bitcoin::{OutPoint, Sequence, TxIn, Txid};
 
-/// Handle the descriptor command
-pub fn handle_descriptor_command(
-    network: Network,
-    desc_type: String,
-    key: Option<String>,
-    pretty: bool,
-) -> Result<String, Error> {
-    let result = match key {
-        Some(key) => {
-            if is_mnemonic(&key) {
-                // User provided mnemonic
-                generate_descriptor_from_mnemonic(&key, network, &desc_type)
-            } else {
-                // User provided xprv/xpub
-                generate_descriptors(&desc_type, &key, network)
-            }
-        }
-        // Generate new mnemonic and descriptors
-        None => generate_descriptor_with_mnemonic(network, &desc_type),
-    }?;
-    format_descriptor_output(&result, pretty)
-}
+        let (mut wallet, fixture) = test_wallet();
 
-#[cfg(any(
-    feature = "electrum",
-    feature = "esplora",
-    feature = "cbf",
-    feature = "rpc"
-))]
-#[cfg(test)]
-mod test {
-    #[cfg(any(
-        feature = "electrum",
-        feature = "esplora",
-        feature = "cbf",
-        feature = "rpc"
-    ))]
-    #[test]
-    fn test_psbt_is_final() {
-        use super::is_final;
-        use bdk_wallet::bitcoin::Psbt;
-        use std::str::FromStr;
+        let confirmed = receive(&mut wallet, 1, 10_000, Some(100));
+        let foreign_parent = Txid::from_byte_array([2; 32]);
+        let signaling = apply_payment(
+            &mut wallet,
+            TxIn {
+                previous_output: OutPoint::new(foreign_parent, 0),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            },
+            20_000,
+            None,
+        );
+        let child = apply_payment(
+            &mut wallet,
+            TxIn {
+                previous_output: signaling,
+                ..Default::default()
+            },
+            15_000,
+            None,
+        );
 
-        let unsigned_psbt = Psbt::from_str("cHNidP8BAIkBAAAAASWJHzxzyVORV/C3lAynKHVVL7+Rw7/Jj8U9fuvD24olAAAAAAD+////AiBOAAAAAAAAIgAgLzY9yE4jzTFJnHtTjkc+rFAtJ9NB7ENFQ1xLYoKsI1cfqgKVAAAAACIAIFsbWgDeLGU8EA+RGwBDIbcv4gaGG0tbEIhDvwXXa/E7LwEAAAABALUCAAAAAAEBAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAD/////BALLAAD/////AgD5ApUAAAAAIgAgWxtaAN4sZTwQD5EbAEMhty/iBoYbS1sQiEO/Bddr8TsAAAAAAAAAACZqJKohqe3i9hw/cdHe/T+pmd+jaVN1XGkGiXmZYrSL69g2l06M+QEgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQErAPkClQAAAAAiACBbG1oA3ixlPBAPkRsAQyG3L+IGhhtLWxCIQ78F12vxOwEFR1IhA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDIQLKhV/gEZYmlsQXnsL5/Uqv5Y8O31tmWW1LQqIBkiqzCVKuIgYCyoVf4BGWJpbEF57C+f1Kr+WPDt9bZlltS0KiAZIqswkEboH3lCIGA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDBDS6ZSEAACICAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJBG6B95QiAgPyVdlP9KV1voj+PUHLGIpRL5GRHeKYZgzPJ1fMAvjHgwQ0umUhAA==").unwrap();
-        assert!(is_final(&unsigned_psbt).is_err());
+        let mut status = |txid: Txid| {
+            run_offline_wallet_subcommand(&mut wallet, &fixture, &["rbf_status", &txid.to_string()])
+        };
+        let result = status(signaling.txid).unwrap();
+        assert_eq!(result["signals_rbf"], true);
+        assert_eq!(result["replaceable"], true);
+        assert_eq!(result["unknown_parents"][0], foreign_parent.to_string());
 
-        let part_signed_psbt = Psbt::from_str("cHNidP8BAIkBAAAAASWJHzxzyVORV/C3lAynKHVVL7+Rw7/Jj8U9fuvD24olAAAAAAD+////AiBOAAAAAAAAIgAgLzY9yE4jzTFJnHtTjkc+rFAtJ9NB7ENFQ1xLYoKsI1cfqgKVAAAAACIAIFsbWgDeLGU8EA+RGwBDIbcv4gaGG0tbEIhDvwXXa/E7LwEAAAABALUCAAAAAAEBAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAD/////BALLAAD/////AgD5ApUAAAAAIgAgWxtaAN4sZTwQD5EbAEMhty/iBoYbS1sQiEO/Bddr8TsAAAAAAAAAACZqJKohqe3i9hw/cdHe/T+pmd+jaVN1XGkGiXmZYrSL69g2l06M+QEgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQErAPkClQAAAAAiACBbG1oA3ixlPBAPkRsAQyG3L+IGhhtLWxCIQ78F12vxOyICA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDSDBFAiEAnNPpu6wNX2HXYz8s2q5nXug4cWfvCGD3SSH2CNKm+yECIEQO7/URhUPsGoknMTE+GrYJf9Wxqn9QsuN9FGj32cQpAQEFR1IhA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDIQLKhV/gEZYmlsQXnsL5/Uqv5Y8O31tmWW1LQqIBkiqzCVKuIgYCyoVf4BGWJpbEF57C+f1Kr+WPDt9bZlltS0KiAZIqswkEboH3lCIGA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDBDS6ZSEAACICAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJBG6B95QiAgPyVdlP9KV1voj+PUHLGIpRL5GRHeKYZgzPJ1fMAvjHgwQ0umUhAA==").unwrap();
-        assert!(is_final(&part_signed_psbt).is_err());
+        let result = status(child.txid).unwrap();
+        assert_eq!(result["signals_rbf"], false);
+        assert_eq!(result["inherits_rbf"], true);
+        assert_eq!(result["replaceable"], "inherited");
+        assert_eq!(result["ancestors"][0]["txid"], signaling.txid.to_string());
 
-        let full_signed_psbt = Psbt::from_str("cHNidP8BAIkBAAAAASWJHzxzyVORV/C3lAynKHVVL7+Rw7/Jj8U9fuvD24olAAAAAAD+////AiBOAAAAAAAAIgAgLzY9yE4jzTFJnHtTjkc+rFAtJ9NB7ENFQ1xLYoKsI1cfqgKVAAAAACIAIFsbWgDeLGU8EA+RGwBDIbcv4gaGG0tbEIhDvwXXa/E7LwEAAAABALUCAAAAAAEBAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAD/////BALLAAD/////AgD5ApUAAAAAIgAgWxtaAN4sZTwQD5EbAEMhty/iBoYbS1sQiEO/Bddr8TsAAAAAAAAAACZqJKohqe3i9hw/cdHe/T+pmd+jaVN1XGkGiXmZYrSL69g2l06M+QEgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQErAPkClQAAAAAiACBbG1oA3ixlPBAPkRsAQyG3L+IGhhtLWxCIQ78F12vxOwEFR1IhA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDIQLKhV/gEZYmlsQXnsL5/Uqv5Y8O31tmWW1LQqIBkiqzCVKuIgYCyoVf4BGWJpbEF57C+f1Kr+WPDt9bZlltS0KiAZIqswkEboH3lCIGA/JV2U/0pXW+iP49QcsYilEvkZEd4phmDM8nV8wC+MeDBDS6ZSEBBwABCNsEAEgwRQIhAJzT6busDV9h12M/LNquZ17oOHFn7whg90kh9gjSpvshAiBEDu/1EYVD7BqJJzExPhq2CX/Vsap/ULLjfRRo99nEKQFHMEQCIGoFCvJ2zPB7PCpznh4+1jsY03kMie49KPoPDdr7/T9TAiB3jV7wzR9BH11FSbi+8U8gSX95PrBlnp1lOBgTUIUw3QFHUiED8lXZT/Sldb6I/j1ByxiKUS+RkR3imGYMzydXzAL4x4MhAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJUq4AACICAsqFX+ARliaWxBeewvn9Sq/ljw7fW2ZZbUtCogGSKrMJBG6B95QiAgPyVdlP9KV1voj+PUHLGIpRL5GRHeKYZgzPJ1fMAvjHgwQ0umUhAA==").unwrap();
-        assert!(is_final(&full_signed_psbt).is_ok());
+        let result = status(confirmed.txid).unwrap();
+        assert_eq!(result["status"], "confirmed");
+        assert_eq!(result["replaceable"], false);
+
+        let error = status(Txid::from_byte_array([3; 32])).unwrap_err();
+        assert!(error.to_string().contains("isn't in the wallet"));
     }
 
-    #[cfg(feature = "compiler")]
+    #[cfg(feature = "sqlite")]
     #[test]
-    fn test_compile_taproot() {
-        use super::{NUMS_UNSPENDABLE_KEY_HEX, handle_compile_subcommand};
+    fn test_export_import_graph_round_trip() {
+        use bdk_wallet::Wallet;
         use bdk_wallet::bitcoin::Network;
 
-        // Expected taproot descriptors with checksums (using NUMS key from constant)
-        let expected_pk_a = format!("tr({},pk(A))#a2mlskt0", NUMS_UNSPENDABLE_KEY_HEX);
-        let expected_and_ab = format!(
-            "tr({},and_v(v:pk(A),pk(B)))#sfplm6kv",
-            NUMS_UNSPENDABLE_KEY_HEX
+        let (mut wallet, fixture) = test_wallet();
+        let descriptors = &fixture;
+        receive(&mut wallet, 1, 10_000, Some(100));
+        receive(&mut wallet, 2, 20_000, Some(120));
+        receive(&mut wallet, 3, 30_000, None);
+        let file = fixture.datadir.path().join("graph.json");
+        let file = file.to_str().unwrap();
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            descriptors,
+            &["export_graph", "--out", file],
+        )
+        .unwrap();
+        assert_eq!(result["transactions"], 3);
+
+        let mut imported = fixture.wallet();
+        let result = run_offline_wallet_subcommand(
+            &mut imported,
+            descriptors,
+            &["import_graph", "--file", file],
+        )
+        .unwrap();
+        assert_eq!(result["imported_transactions"], 3);
+        assert_eq!(
+            imported.tx_graph().initial_changeset(),
+            wallet.tx_graph().initial_changeset()
         );
+        assert_eq!(
+            imported.local_chain().initial_changeset(),
+            wallet.local_chain().initial_changeset()
+        );
+        assert_eq!(imported.balance(), wallet.balance());
 
-        // Test simple pk policy compilation to taproot
-        let result = handle_compile_subcommand(
-            Network::Testnet,
-            "pk(A)".to_string(),
-            "tr".to_string(),
-            false,
+        let mut other = Wallet::create_single(format!("wpkh({TEST_XPRV}/84'/1'/1'/0/*)"))
+            .network(Network::Regtest)
+            .create_wallet_no_persist()
+            .unwrap();
+        let error = run_offline_wallet_subcommand(
+            &mut other,
+            descriptors,
+            &["import_graph", "--file", file],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("another descriptor"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_import_changeset() {
+        use bdk_wallet::KeychainKind;
+
+        let (mut wallet, fixture) = test_wallet();
+        let descriptors = &fixture;
+        receive(&mut wallet, 1, 10_000, Some(100));
+        receive(&mut wallet, 2, 20_000, None);
+        let _ = wallet.reveal_addresses_to(KeychainKind::Internal, 4);
+        // Nothing was persisted, the staged changes are the whole wallet.
+        let mut changeset = serde_json::to_value(wallet.staged().unwrap()).unwrap();
+        let file = fixture.datadir.path().join("changeset.json");
+        std::fs::write(&file, changeset.to_string()).unwrap();
+        let file_arg = file.to_str().unwrap();
+
+        let mut imported = fixture.wallet();
+        let result = run_offline_wallet_subcommand(
+            &mut imported,
+            descriptors,
+            &["import_changeset", "--file", file_arg],
+        )
+        .unwrap();
+        assert_eq!(result["transactions_added"], 2);
+        assert_eq!(result["revealed_indexes"]["internal"]["after"], 4);
+        assert_eq!(
+            imported.tx_graph().initial_changeset(),
+            wallet.tx_graph().initial_changeset()
         );
-        assert!(result.is_ok());
-        let json_string = result.unwrap();
-        let json_result: serde_json::Value = serde_json::from_str(&json_string).unwrap();
-        let descriptor = json_result.get("descriptor").unwrap().as_str().unwrap();
-        assert_eq!(descriptor, expected_pk_a);
+        assert_eq!(
+            imported.derivation_index(KeychainKind::External),
+            wallet.derivation_index(KeychainKind::External)
+        );
+        assert_eq!(imported.balance(), wallet.balance());
 
-        // Test more complex policy
-        let result = handle_compile_subcommand(
-            Network::Testnet,
-            "and(pk(A),pk(B))".to_string(),
-            "tr".to_string(),
-            false,
+        changeset["network"] = serde_json::json!("bitcoin");
+        std::fs::write(&file, changeset.to_string()).unwrap();
+        let error = run_offline_wallet_subcommand(
+            &mut imported,
+            descriptors,
+            &["import_changeset", "--file", file_arg],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("bitcoin wallet, not regtest"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_simulate_send_leaves_wallet_unchanged() {
+        use bdk_wallet::KeychainKind;
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        let staged = wallet.staged().cloned();
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &[
+                "simulate_send",
+                "--to",
+                &format!("{address}:10000"),
+                "--fee_rates",
+                "1,10,1000",
+            ],
+        )
+        .unwrap();
+        let simulations = result["simulations"].as_array().unwrap();
+        assert_eq!(simulations.len(), 3);
+        let (low, high) = (&simulations[0], &simulations[1]);
+        assert!(low["fee"].as_u64().unwrap() < high["fee"].as_u64().unwrap());
+        assert_eq!(
+            low["fee"].as_u64().unwrap() + low["change"].as_u64().unwrap(),
+            high["fee"].as_u64().unwrap() + high["change"].as_u64().unwrap()
         );
-        assert!(result.is_ok());
-        let json_string = result.unwrap();
-        let json_result: serde_json::Value = serde_json::from_str(&json_string).unwrap();
-        let descriptor = json_result.get("descriptor").unwrap().as_str().unwrap();
-        assert_eq!(descriptor, expected_and_ab);
+        assert!(simulations[2]["error"].is_string());
+        assert_eq!(wallet.staged().cloned(), staged);
+        assert_eq!(wallet.next_unused_address(KeychainKind::Internal).index, 0);
     }
 
-    #[cfg(feature = "compiler")]
+    #[cfg(feature = "sqlite")]
     #[test]
-    fn test_compile_invalid_cases() {
-        use super::handle_compile_subcommand;
-        use bdk_wallet::bitcoin::Network;
+    fn test_bump_fee_keeps_recipients() {
+        use bdk_wallet::SignOptions;
+        use bdk_wallet::bitcoin::base64::Engine;
+        use bdk_wallet::bitcoin::base64::prelude::BASE64_STANDARD;
+        use bdk_wallet::bitcoin::{Amount, FeeRate, Psbt, ScriptBuf};
 
-        // Test invalid policy syntax
-        let result = handle_compile_subcommand(
-            Network::Testnet,
-            "invalid_policy".to_string(),
-            "tr".to_string(),
-            false,
+        let (mut wallet, fixture) = test_wallet();
+        let utxo = receive(&mut wallet, 1, 50_000, None);
+        // Fee bumps can't spend new unconfirmed inputs.
+        receive(&mut wallet, 2, 20_000, Some(1));
+
+        let recipient = ScriptBuf::new_p2wsh(&ScriptBuf::new().wscript_hash());
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(recipient.clone(), Amount::from_sat(40_000))
+            .add_utxo(utxo)
+            .unwrap()
+            .manually_selected_only()
+            .fee_rate(FeeRate::from_sat_per_vb_unchecked(1));
+        let mut psbt = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let tx = psbt.extract_tx().unwrap();
+        let txid = tx.compute_txid();
+        wallet.apply_unconfirmed_txs([(tx, 1)]);
+
+        let mut bump_fee = |fee_rate: &str| {
+            let txid = txid.to_string();
+            let report = run_offline_wallet_subcommand(
+                &mut wallet,
+                &fixture,
+                &["bump_fee", "--txid", &txid, "--fee_rate", fee_rate],
+            )
+            .unwrap();
+            let sources = &report["fee_sources"];
+            assert_eq!(
+                report["extra_fee"].as_i64().unwrap(),
+                sources["change"].as_i64().unwrap()
+                    + sources["added_inputs"].as_i64().unwrap()
+                    + sources["shrink_address"].as_i64().unwrap()
+            );
+            report
+        };
+
+        // The change output covers the extra fee.
+        let first = bump_fee("5");
+        assert!(first["fee_sources"]["change"].as_i64().unwrap() > 0);
+        assert_eq!(first["fee_sources"]["added_inputs"], 0);
+        assert_eq!(first["added_inputs"].as_array().unwrap().len(), 0);
+
+        // The change output is too small, another UTXO is spent.
+        let second = bump_fee("100");
+        assert_eq!(second["fee_sources"]["added_inputs"], 20_000);
+        assert_eq!(second["added_inputs"].as_array().unwrap().len(), 1);
+
+        let bump_txid = |report: &serde_json::Value| {
+            let psbt = BASE64_STANDARD
+                .decode(report["psbt"].as_str().unwrap())
+                .unwrap();
+            Psbt::deserialize(&psbt)
+                .unwrap()
+                .unsigned_tx
+                .compute_txid()
+                .to_string()
+        };
+
+        // Both fee bumps are appended to the chain of the original transaction.
+        let history = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["replacement_history", &txid.to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            history["history"],
+            serde_json::json!([
+                {"txid": txid.to_string(), "status": "unconfirmed"},
+                {"txid": bump_txid(&first), "status": "unknown"},
+                {"txid": bump_txid(&second), "status": "unknown"},
+            ])
         );
-        assert!(result.is_err());
+        assert_eq!(history["live"], txid.to_string());
+    }
 
-        // Test invalid script type
-        let result = handle_compile_subcommand(
-            Network::Testnet,
-            "pk(A)".to_string(),
-            "invalid_type".to_string(),
-            false,
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_bump_fee_self_payment() {
+        use bdk_wallet::bitcoin::{Amount, FeeRate};
+        use bdk_wallet::{KeychainKind, SignOptions};
+
+        let (mut wallet, fixture) = test_wallet();
+        let utxo = receive(&mut wallet, 1, 50_000, None);
+        receive(&mut wallet, 2, 20_000, Some(1));
+
+        // The payment to an address of the wallet is a recipient, not change.
+        let own_script = wallet
+            .reveal_next_address(KeychainKind::External)
+            .script_pubkey();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(own_script, Amount::from_sat(40_000))
+            .add_utxo(utxo)
+            .unwrap()
+            .manually_selected_only()
+            .fee_rate(FeeRate::from_sat_per_vb_unchecked(1));
+        let mut psbt = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let tx = psbt.extract_tx().unwrap();
+        let txid = tx.compute_txid().to_string();
+        wallet.apply_unconfirmed_txs([(tx, 1)]);
+
+        // The change can't pay the fee, the added input raises it and only the rest of its value
+        // goes to the fee.
+        let report = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["bump_fee", "--txid", &txid, "--fee_rate", "94"],
+        )
+        .unwrap();
+        let sources = &report["fee_sources"];
+        assert_eq!(report["self_payments"], 40_000);
+        assert_eq!(sources["change"], 0);
+        assert_eq!(report["added_inputs"].as_array().unwrap().len(), 1);
+        let added_inputs = sources["added_inputs"].as_u64().unwrap();
+        assert!(added_inputs > 0 && added_inputs < 20_000);
+        assert_eq!(report["extra_fee"].as_u64().unwrap(), added_inputs);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_backup_payload_birthday() {
+        use crate::backup::wallet_backup_payload;
+        use crate::utils::{BIRTHDAY_FILE, store_state_file};
+
+        // A restored wallet that didn't sync yet keeps the birthday of its backup.
+        let (mut wallet, fixture) = test_wallet();
+        store_state_file(&fixture.datadir.path().join(BIRTHDAY_FILE), &120).unwrap();
+        let payload = wallet_backup_payload(&wallet, fixture.datadir.path()).unwrap();
+        assert_eq!(payload["birthday"], 120);
+
+        receive(&mut wallet, 1, 10_000, Some(150));
+        receive(&mut wallet, 2, 10_000, Some(130));
+        receive(&mut wallet, 3, 10_000, None);
+        let payload = wallet_backup_payload(&wallet, fixture.datadir.path()).unwrap();
+        assert_eq!(payload["birthday"], 130);
+        assert_eq!(payload["state_files"], serde_json::json!({}));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_record_replacement() {
+        use crate::utils::{load_replacements, record_replacement};
+        use bdk_wallet::bitcoin::{Amount, FeeRate, ScriptBuf, Transaction, TxIn};
+        use bdk_wallet::{SignOptions, Wallet};
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+        let wallet_dir = fixture.datadir.path();
+
+        let recipient = ScriptBuf::new_p2wsh(&ScriptBuf::new().wscript_hash());
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(recipient, Amount::from_sat(40_000))
+            .fee_rate(FeeRate::from_sat_per_vb_unchecked(1));
+        let mut psbt = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let original = psbt.extract_tx().unwrap();
+        wallet.apply_unconfirmed_txs([(original.clone(), 1)]);
+        let bump = |wallet: &mut Wallet, txid, sat_per_vb| -> Transaction {
+            let mut builder = wallet.build_fee_bump(txid).unwrap();
+            builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(sat_per_vb));
+            let mut psbt = builder.finish().unwrap();
+            assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+            psbt.extract_tx().unwrap()
+        };
+
+        // A transaction spending no wallet output isn't recorded.
+        record_replacement(
+            &wallet,
+            wallet_dir,
+            &Transaction {
+                input: vec![],
+                ..original.clone()
+            },
+        )
+        .unwrap();
+        assert!(load_replacements(wallet_dir).unwrap().is_empty());
+
+        let first = bump(&mut wallet, original.compute_txid(), 5);
+        record_replacement(&wallet, wallet_dir, &first).unwrap();
+        wallet.apply_unconfirmed_txs([(first.clone(), 2)]);
+        // The second bump replaces the first one, even though both spend the original inputs.
+        let second = bump(&mut wallet, first.compute_txid(), 10);
+        record_replacement(&wallet, wallet_dir, &second).unwrap();
+        assert_eq!(
+            load_replacements(wallet_dir).unwrap(),
+            [
+                (original.compute_txid(), first.compute_txid()),
+                (first.compute_txid(), second.compute_txid()),
+            ]
+            .into()
         );
-        assert!(result.is_err());
 
-        // Test empty policy
-        let result =
-            handle_compile_subcommand(Network::Testnet, "".to_string(), "tr".to_string(), false);
-        assert!(result.is_err());
+        // Recording it again when it is broadcast changes nothing.
+        record_replacement(&wallet, wallet_dir, &second).unwrap();
+        // Another bump of the first one is appended to the chain, after the second one.
+        let third = bump(&mut wallet, first.compute_txid(), 20);
+        record_replacement(&wallet, wallet_dir, &third).unwrap();
+        // A signature changing the txid, the signed transaction takes the place of the unsigned one.
+        let signed_third = Transaction {
+            input: vec![TxIn {
+                script_sig: ScriptBuf::from_hex("51").unwrap(),
+                ..third.input[0].clone()
+            }],
+            ..third.clone()
+        };
+        record_replacement(&wallet, wallet_dir, &signed_third).unwrap();
+        assert_eq!(
+            load_replacements(wallet_dir).unwrap(),
+            [
+                (original.compute_txid(), first.compute_txid()),
+                (first.compute_txid(), second.compute_txid()),
+                (second.compute_txid(), signed_third.compute_txid()),
+            ]
+            .into()
+        );
+    }
 
-        // Test malformed policy with unmatched parentheses
-        let result = handle_compile_subcommand(
-            Network::Testnet,
-            "pk(A".to_string(),
-            "tr".to_string(),
-            false,
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_bump_fee_target() {
+        use crate::utils::{FEE_ESTIMATES_FILE, estimated_fee_rate, store_state_file, unix_now};
+        use bdk_wallet::SignOptions;
+        use bdk_wallet::bitcoin::{Amount, FeeRate, ScriptBuf};
+
+        let (mut wallet, fixture) = test_wallet();
+        receive(&mut wallet, 1, 50_000, Some(1));
+
+        let recipient = ScriptBuf::new_p2wsh(&ScriptBuf::new().wscript_hash());
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(recipient, Amount::from_sat(40_000))
+            .fee_rate(FeeRate::from_sat_per_vb_unchecked(2));
+        let mut psbt = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let tx = psbt.extract_tx().unwrap();
+        let txid = tx.compute_txid().to_string();
+        wallet.apply_unconfirmed_txs([(tx, 1)]);
+
+        store_state_file(
+            &fixture.datadir.path().join(FEE_ESTIMATES_FILE),
+            &serde_json::json!({
+                "timestamp": unix_now(),
+                "estimates": {"1": 20.0, "6": 5.0, "24": 1.0},
+            }),
+        )
+        .unwrap();
+        let mut bump_fee = |target: &str| {
+            run_offline_wallet_subcommand(
+                &mut wallet,
+                &fixture,
+                &["bump_fee", "--txid", &txid, "--target", target],
+            )
+        };
+
+        let report = bump_fee("6").unwrap();
+        assert_eq!(report["target"], 6);
+        assert_eq!(report["fee_rate"], 5);
+        // The estimate of the highest cached target within 3 blocks is the 1 block one.
+        assert_eq!(bump_fee("3").unwrap()["fee_rate"], 20);
+
+        let error = bump_fee("30").unwrap_err();
+        assert!(error.to_string().contains("not above the 2.00 sat/vbyte"));
+
+        // The estimates fetched from the blockchain server take the place of the cached ones.
+        let fresh = std::collections::BTreeMap::from([(6, 8.2)]);
+        let estimate = estimated_fee_rate(fixture.datadir.path(), 6, Some(&fresh)).unwrap();
+        assert_eq!(estimate, 9.0);
+        assert!(estimated_fee_rate(fixture.datadir.path(), 3, Some(&fresh)).is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_tx_immature_coinbase() {
+        use bdk_wallet::KeychainKind;
+        use bdk_wallet::bitcoin::TxIn;
+
+        let (mut wallet, fixture) = test_wallet();
+        let coinbase = apply_payment(&mut wallet, TxIn::default(), 50_000, Some(1));
+        let recipient = format!(
+            "{}:10000",
+            wallet.peek_address(KeychainKind::External, 10).address
         );
-        assert!(result.is_err());
+        let coinbase_utxo = coinbase.to_string();
 
-        // Test policy with unknown function
-        let result = handle_compile_subcommand(
-            Network::Testnet,
-            "unknown_func(A)".to_string(),
-            "tr".to_string(),
-            false,
+        let error = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &recipient],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("immature coinbase outputs"));
+
+        let error = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &recipient, "--utxos", &coinbase_utxo],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("is an immature coinbase output"));
+
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &recipient, "--allow_immature"],
         );
-        assert!(result.is_err());
+        assert!(result.is_ok());
+
+        // The coinbase output can be spent once it has 100 confirmations.
+        receive(&mut wallet, 1, 1_000, Some(99));
+        assert!(
+            run_offline_wallet_subcommand(
+                &mut wallet,
+                &fixture,
+                &["create_tx", "--to", &recipient]
+            )
+            .is_err()
+        );
+        receive(&mut wallet, 2, 1_000, Some(100));
+        let result = run_offline_wallet_subcommand(
+            &mut wallet,
+            &fixture,
+            &["create_tx", "--to", &recipient],
+        );
+        assert!(result.is_ok());
     }
 }
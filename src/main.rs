@@ -10,6 +10,7 @@
 #![doc(html_logo_url = "https://github.com/bitcoindevkit/bdk/raw/master/static/bdk.png")]
 #![warn(missing_docs)]
 
+mod backup;
 mod commands;
 mod error;
 mod handlers;
@@ -29,12 +30,31 @@ use log::{debug, error, warn};
 
 use crate::commands::CliOpts;
 use crate::handlers::*;
-use clap::Parser;
+use crate::utils::{
+    envelope_output, init_logging, parse_network, set_quiet, strip_ansi, subcommand_path, use_color,
+};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches};
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-    let cli_opts: CliOpts = CliOpts::parse();
+    let matches = CliOpts::command().get_matches();
+    let mut cli_opts = CliOpts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    set_quiet(cli_opts.quiet);
+    init_logging(cli_opts.log_format, cli_opts.color);
+    // `NETWORK` was the environment variable of `--network` before `BDK_NETWORK`.
+    if matches.value_source("network") == Some(ValueSource::DefaultValue) {
+        if let Ok(network) = std::env::var("NETWORK") {
+            warn!("The NETWORK environment variable is deprecated, use BDK_NETWORK instead.");
+            cli_opts.network = parse_network(&network).unwrap_or_else(|e| {
+                error!("NETWORK: {e}");
+                std::process::exit(1);
+            });
+        }
+    }
+    let envelope = cli_opts.envelope.then(|| subcommand_path(&matches));
+    // The envelope is JSON, which is never colored.
+    let color = envelope.is_none() && use_color(cli_opts.color, &std::io::stdout());
 
     let network = &cli_opts.network;
     debug!("network: {network:?}");
@@ -44,8 +64,15 @@ async fn main() {
         )
     }
 
+    let network = *network;
     match handle_command(cli_opts).await {
-        Ok(result) => println!("{result}"),
+        Ok(result) => {
+            let result = if color { result } else { strip_ansi(&result) };
+            match envelope {
+                Some(command) => println!("{}", envelope_output(&result, network, &command)),
+                None => println!("{result}"),
+            }
+        }
         Err(e) => {
             error!("{e}");
             std::process::exit(1);
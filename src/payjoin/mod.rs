@@ -628,7 +628,7 @@ impl<'a> PayjoinManager<'a> {
             "Waiting for {wait_time_for_sync} seconds before syncing the blockchain and checking if the transaction has been broadcast..."
         );
         tokio::time::sleep(poll_internal).await;
-        sync_wallet(blockchain_client, self.wallet).await?;
+        sync_wallet(blockchain_client, self.wallet, None).await?;
 
         let check_result = receiver
             .check_payment(
@@ -1,12 +1,60 @@
 use crate::error::BDKCliError;
 use bdk_wallet::WalletPersister;
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
 
 // Types of Persistence backends supported by bdk-cli
 pub(crate) enum Persister {
     #[cfg(feature = "sqlite")]
-    Connection(bdk_wallet::rusqlite::Connection),
+    Connection(bdk_wallet::rusqlite::Connection, WalletLock),
     #[cfg(feature = "redb")]
-    RedbStore(bdk_redb::Store),
+    RedbStore(bdk_redb::Store, WalletLock),
+}
+
+/// Advisory lock on the lock file of a wallet, held until the persister is dropped.
+///
+/// Writers take an exclusive lock, so another process can't open the wallet at the same time,
+/// while read-only processes share the lock and can't persist any change.
+pub(crate) struct WalletLock {
+    _file: File,
+    read_only: bool,
+}
+
+impl WalletLock {
+    pub(crate) fn acquire(wallet_dir: &Path, read_only: bool) -> Result<Self, BDKCliError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(wallet_dir.join("wallet.lock"))
+            .map_err(|e| BDKCliError::Generic(format!("Cannot open the wallet lock file: {e}")))?;
+        // Called through the trait, `File` has inherent lock methods since Rust 1.89.
+        let locked = if read_only {
+            FileExt::try_lock_shared(&file)
+        } else {
+            FileExt::try_lock_exclusive(&file)
+        };
+        match locked {
+            Ok(()) => Ok(Self {
+                _file: file,
+                read_only,
+            }),
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => Err(
+                BDKCliError::Generic("The wallet is locked by another process.".to_string()),
+            ),
+            Err(e) => Err(BDKCliError::Generic(format!("Cannot lock the wallet: {e}"))),
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), BDKCliError> {
+        if self.read_only {
+            return Err(BDKCliError::Generic(
+                "The wallet was opened with --read-only, its changes can't be saved.".to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl WalletPersister for Persister {
@@ -15,11 +63,11 @@ impl WalletPersister for Persister {
     fn initialize(persister: &mut Self) -> Result<bdk_wallet::ChangeSet, Self::Error> {
         match persister {
             #[cfg(feature = "sqlite")]
-            Persister::Connection(connection) => {
+            Persister::Connection(connection, _) => {
                 WalletPersister::initialize(connection).map_err(BDKCliError::from)
             }
             #[cfg(feature = "redb")]
-            Persister::RedbStore(store) => {
+            Persister::RedbStore(store, _) => {
                 WalletPersister::initialize(store).map_err(BDKCliError::from)
             }
         }
@@ -28,13 +76,57 @@ impl WalletPersister for Persister {
     fn persist(persister: &mut Self, changeset: &bdk_wallet::ChangeSet) -> Result<(), Self::Error> {
         match persister {
             #[cfg(feature = "sqlite")]
-            Persister::Connection(connection) => {
+            Persister::Connection(connection, lock) => {
+                lock.check_writable()?;
                 WalletPersister::persist(connection, changeset).map_err(BDKCliError::from)
             }
             #[cfg(feature = "redb")]
-            Persister::RedbStore(store) => {
+            Persister::RedbStore(store, lock) => {
+                lock.check_writable()?;
                 WalletPersister::persist(store, changeset).map_err(BDKCliError::from)
             }
         }
     }
 }
+
+impl Persister {
+    /// Removes the chain data and the transactions of the wallet, keeping its descriptors and the
+    /// revealed address indices. Returns the number of removed transactions and blocks.
+    pub(crate) fn reset_chain_data(&mut self) -> Result<(usize, usize), BDKCliError> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Persister::Connection(connection, lock) => {
+                use bdk_wallet::chain::{ConfirmationBlockTime, local_chain, tx_graph};
+
+                type TxGraphChangeSet = tx_graph::ChangeSet<ConfirmationBlockTime>;
+                lock.check_writable()?;
+                let db_tx = connection.transaction()?;
+                for table in [
+                    TxGraphChangeSet::ANCHORS_TABLE_NAME,
+                    TxGraphChangeSet::TXOUTS_TABLE_NAME,
+                ] {
+                    db_tx.execute(&format!("DELETE FROM {table}"), [])?;
+                }
+                let txs = db_tx.execute(
+                    &format!("DELETE FROM {}", TxGraphChangeSet::TXS_TABLE_NAME),
+                    [],
+                )?;
+                // The genesis block is kept, the wallet can't be loaded without it.
+                let blocks = db_tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE block_height > 0",
+                        local_chain::ChangeSet::BLOCKS_TABLE_NAME
+                    ),
+                    [],
+                )?;
+                db_tx.commit()?;
+                Ok((txs, blocks))
+            }
+            #[cfg(feature = "redb")]
+            Persister::RedbStore(..) => Err(BDKCliError::Generic(
+                "Resetting the wallet state is only supported with the sqlite database."
+                    .to_string(),
+            )),
+        }
+    }
+}
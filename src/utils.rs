@@ -11,28 +11,47 @@
 //! This module includes all the utility tools used by the App.
 use crate::error::BDKCliError as Error;
 use std::{
+    any::TypeId,
+    collections::{BTreeMap, BTreeSet},
     fmt::Display,
+    io::{IsTerminal, Write},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
 };
 
-use crate::commands::WalletOpts;
+use crate::commands::{
+    AddressEncoding, ColorMode, Denom, LogFormat, TxBound, TxFeeRate, TxSort, WalletOpts,
+};
 #[cfg(feature = "cbf")]
 use bdk_kyoto::{
     BuilderExt, Info, LightClient, Receiver, ScanType::Sync, UnboundedReceiver, Warning,
     builder::Builder,
 };
 use bdk_wallet::{
-    KeychainKind,
-    bitcoin::bip32::{DerivationPath, Xpub},
-    keys::DescriptorPublicKey,
+    KeychainKind, LocalOutput, TxOrdering, Update, WeightedUtxo,
+    bitcoin::{
+        FeeRate, PrivateKey,
+        bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource, Xpub},
+        hashes::{hash160, sha256},
+        secp256k1::{Parity, PublicKey, rand::RngCore},
+    },
+    chain::{ChainPosition, ConfirmationBlockTime, DescriptorExt},
+    coin_selection::{
+        BranchAndBoundCoinSelection, CoinSelectionAlgorithm, CoinSelectionResult,
+        DefaultCoinSelectionAlgorithm, Excess, InsufficientFunds,
+    },
+    keys::{DescriptorPublicKey, DescriptorSecretKey},
     miniscript::{
-        Descriptor, Miniscript, Terminal,
-        descriptor::{DescriptorXKey, Wildcard},
+        Descriptor, ForEachKey, Miniscript, ScriptContext, Terminal,
+        descriptor::{DescriptorType, DescriptorXKey, KeyMap, ShInner, Wildcard, WshInner},
+        plan::Assets,
     },
+    signer::{SignerId, SignersContainer},
     template::DescriptorTemplate,
 };
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use cli_table::{Cell, CellStruct, Style, Table};
 
 #[cfg(any(
@@ -42,6 +61,8 @@ use cli_table::{Cell, CellStruct, Style, Table};
     feature = "cbf"
 ))]
 use crate::commands::ClientType;
+#[cfg(feature = "rpc")]
+use bdk_bitcoind_rpc::bitcoincore_rpc::RpcApi;
 
 use bdk_wallet::Wallet;
 #[cfg(any(feature = "sqlite", feature = "redb"))]
@@ -49,12 +70,116 @@ use bdk_wallet::{PersistedWallet, WalletPersister};
 
 use bdk_wallet::bip39::{Language, Mnemonic};
 use bdk_wallet::bitcoin::{
-    Address, Network, OutPoint, ScriptBuf, bip32::Xpriv, secp256k1::Secp256k1,
+    Address, Amount, Denomination, Network, NetworkKind, OutPoint, Psbt, Script, ScriptBuf,
+    Transaction, TxIn, TxOut, Txid, Weight, Witness,
+    bip32::Xpriv,
+    hashes::Hash,
+    hex::{DisplayHex, FromHex},
+    psbt,
+    secp256k1::{All, Secp256k1},
+};
+use bdk_wallet::descriptor::{
+    ExtractPolicy, Segwitv0,
+    policy::{BuildSatisfaction, Satisfaction},
 };
-use bdk_wallet::descriptor::Segwitv0;
 use bdk_wallet::keys::{GeneratableKey, GeneratedKey, bip39::WordCount};
+use bdk_wallet::serde::{Serialize, de::DeserializeOwned};
 use serde_json::{Value, json};
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Delay before the first retry of esplora-client, which it doubles after each retry.
+#[cfg(feature = "esplora")]
+const ESPLORA_CLIENT_BACKOFF_MILLIS: u64 = 256;
+
+#[cfg(feature = "esplora")]
+tokio::task_local! {
+    /// Delay before the first retry of the running Esplora requests, set by
+    /// [`with_esplora_backoff`].
+    static ESPLORA_BACKOFF_MILLIS: u64;
+}
+
+/// Sleeper of the Esplora client between retries, scaling the delays of esplora-client to the
+/// first delay of the requests, set with [`with_esplora_backoff`].
+///
+/// esplora-client calls it without any state, so the delay is scoped to the task running the
+/// requests.
+#[cfg(feature = "esplora")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EsploraBackoff;
+
+#[cfg(feature = "esplora")]
+impl bdk_esplora::esplora_client::Sleeper for EsploraBackoff {
+    type Sleep = tokio::time::Sleep;
+
+    fn sleep(delay: std::time::Duration) -> Self::Sleep {
+        let first_delay = ESPLORA_BACKOFF_MILLIS
+            .try_with(|first_delay| *first_delay)
+            .unwrap_or(ESPLORA_CLIENT_BACKOFF_MILLIS);
+        tokio::time::sleep(std::time::Duration::from_millis(
+            (delay.as_millis() as u64).saturating_mul(first_delay) / ESPLORA_CLIENT_BACKOFF_MILLIS,
+        ))
+    }
+}
+
+/// Run Esplora `requests` retrying after `backoff_ms` milliseconds first, from
+/// `--esplora-backoff-ms`.
+#[cfg(feature = "esplora")]
+pub(crate) async fn with_esplora_backoff<F: std::future::Future>(
+    backoff_ms: u64,
+    requests: F,
+) -> F::Output {
+    ESPLORA_BACKOFF_MILLIS.scope(backoff_ms, requests).await
+}
+
+/// Convert an Esplora error, explaining a rate limit the retries didn't get past.
+#[cfg(feature = "esplora")]
+pub(crate) fn esplora_error(error: bdk_esplora::esplora_client::Error) -> Error {
+    match error {
+        bdk_esplora::esplora_client::Error::HttpResponse { status: 429, .. } => Error::Generic(
+            "The Esplora server is still rate limiting the requests (HTTP 429) after the retries, try again later or raise --esplora-max-retries or --esplora-backoff-ms.".to_string(),
+        ),
+        error => Error::Esplora(error),
+    }
+}
+
+/// Name of the state file tracking derivation indexes of `--to_descriptor` recipients.
+pub(crate) const DESCRIPTOR_INDEX_FILE: &str = "descriptor_index.json";
+
+/// Name of the state file tracking the last change address index given out by `new_change_address`.
+pub(crate) const CHANGE_INDEX_FILE: &str = "change_index.json";
+
+/// Name of the state file holding the UTXOs frozen with `freeze_utxo`.
+pub(crate) const FROZEN_UTXOS_FILE: &str = "frozen_utxos.json";
+
+/// Name of the state file mapping each transaction bumped with `bump_fee` to its replacement.
+pub(crate) const REPLACEMENTS_FILE: &str = "replacements.json";
+
+/// Name of the state file caching the last fee estimates fetched by `fee_estimates`.
+pub(crate) const FEE_ESTIMATES_FILE: &str = "fee_estimates.json";
+
+/// Name of the state file holding the accounting annotations set with `annotate`.
+pub(crate) const ANNOTATIONS_FILE: &str = "annotations.json";
+
+/// Name of the state file holding the birthday of a wallet restored from a backup.
+pub(crate) const BIRTHDAY_FILE: &str = "birthday.json";
+
+/// Version of the format written by `export_graph`.
+pub(crate) const GRAPH_EXPORT_VERSION: u32 = 1;
+
+/// Confirmation targets, in blocks, of the cached fee estimates.
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "rpc"))]
+pub(crate) const FEE_ESTIMATE_TARGETS: [u16; 5] = [1, 3, 6, 12, 24];
+
+/// Confirmation target, in blocks, of `--fee_rate auto`.
+pub(crate) const AUTO_FEE_TARGET: u16 = 6;
+
+/// Age, in seconds, after which the cached fee estimates are reported as stale.
+const FEE_ESTIMATES_STALE_AFTER: u64 = 60 * 60;
+
+/// The maximum number of accounts `key derive-accounts` derives at a time.
+const MAX_DERIVED_ACCOUNTS: u32 = 100;
+
 /// Parse the recipient (Address,Amount) argument from cli input.
 pub(crate) fn parse_recipient(s: &str) -> Result<(ScriptBuf, u64), String> {
     let parts: Vec<_> = s.split(':').collect();
@@ -69,6 +194,37 @@ pub(crate) fn parse_recipient(s: &str) -> Result<(ScriptBuf, u64), String> {
     Ok((addr.script_pubkey(), val))
 }
 
+/// Parse the descriptor recipient (Descriptor:Amount) argument from cli input.
+pub(crate) fn parse_descriptor_recipient(s: &str) -> Result<(String, u64), String> {
+    let (descriptor, amount) = s
+        .rsplit_once(':')
+        .ok_or_else(|| "Invalid format".to_string())?;
+    let val = u64::from_str(amount).map_err(|e| e.to_string())?;
+
+    Ok((descriptor.to_string(), val))
+}
+
+/// Parse the script recipient (ScriptHex:Amount) argument from cli input.
+pub(crate) fn parse_script_recipient(s: &str) -> Result<(ScriptBuf, u64), String> {
+    let (script, amount) = s
+        .rsplit_once(':')
+        .ok_or_else(|| "Invalid format".to_string())?;
+    let script = ScriptBuf::from_hex(script).map_err(|e| format!("Invalid script hex: {e}"))?;
+    let val = u64::from_str(amount).map_err(|e| e.to_string())?;
+
+    Ok((script, val))
+}
+
+/// Whether a script pubkey is one of the output types relayed by default by Bitcoin Core.
+pub(crate) fn is_standard_script(script: &Script) -> bool {
+    script.is_p2pk()
+        || script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_witness_program()
+        || script.is_multisig()
+        || script.is_op_return()
+}
+
 #[cfg(any(feature = "electrum", feature = "esplora", feature = "rpc"))]
 /// Parse the proxy (Socket:Port) argument from the cli input.
 pub(crate) fn parse_proxy_auth(s: &str) -> Result<(String, String), Error> {
@@ -88,6 +244,150 @@ pub(crate) fn parse_outpoint(s: &str) -> Result<OutPoint, Error> {
     Ok(OutPoint::from_str(s)?)
 }
 
+/// Parse an account index, or an inclusive range of account indexes like `0-3`.
+pub(crate) fn parse_account_range(s: &str) -> Result<RangeInclusive<u32>, String> {
+    let invalid = || format!("Invalid account index or range: {s}, expected eg. 2 or 0-3");
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let start = u32::from_str(start.trim()).map_err(|_| invalid())?;
+    let end = u32::from_str(end.trim()).map_err(|_| invalid())?;
+    if start > end || ChildNumber::from_hardened_idx(end).is_err() {
+        return Err(invalid());
+    }
+    if end - start >= MAX_DERIVED_ACCOUNTS {
+        return Err(format!(
+            "At most {MAX_DERIVED_ACCOUNTS} accounts can be derived at a time."
+        ));
+    }
+    Ok(start..=end)
+}
+
+/// Parse a fee rate in sat/vbyte, or `auto`.
+pub(crate) fn parse_fee_rate(s: &str) -> Result<TxFeeRate, String> {
+    if s == "auto" {
+        return Ok(TxFeeRate::Auto);
+    }
+    f32::from_str(s)
+        .map(TxFeeRate::SatPerVb)
+        .map_err(|_| format!("Invalid fee rate {s}, expected sat/vbyte or auto"))
+}
+
+/// Parse a network name, accepting the short aliases of each network.
+pub(crate) fn parse_network(s: &str) -> Result<Network, String> {
+    match s.to_lowercase().as_str() {
+        "bitcoin" | "main" | "mainnet" => Ok(Network::Bitcoin),
+        "testnet" | "test" | "testnet3" => Ok(Network::Testnet),
+        "testnet4" => Ok(Network::Testnet4),
+        "signet" | "sig" => Ok(Network::Signet),
+        "regtest" | "reg" => Ok(Network::Regtest),
+        _ => Err(format!(
+            "Unknown network {s}, valid values are bitcoin (main, mainnet), testnet (test, testnet3), testnet4, signet (sig) and regtest (reg)"
+        )),
+    }
+}
+
+/// Elements chain, and its name, whose addresses are used on a network.
+#[cfg(feature = "elements")]
+pub(crate) fn elements_address_params(
+    network: Network,
+) -> (
+    &'static str,
+    &'static elements_miniscript::elements::AddressParams,
+) {
+    use elements_miniscript::elements::AddressParams;
+    match network {
+        Network::Bitcoin => ("liquid", &AddressParams::LIQUID),
+        Network::Regtest => ("elementsregtest", &AddressParams::ELEMENTS),
+        _ => ("liquidtestnet", &AddressParams::LIQUID_TESTNET),
+    }
+}
+
+/// Parse a hex encoded signet challenge script.
+pub(crate) fn parse_signet_challenge(s: &str) -> Result<ScriptBuf, Error> {
+    let challenge = ScriptBuf::from_hex(s)?;
+    if challenge.is_empty() {
+        return Err(Error::Generic("The signet challenge is empty.".to_string()));
+    }
+    Ok(challenge)
+}
+
+/// Parse the hex encoded entropy of `--test-seed`, padded with zeros to 32 bytes.
+pub(crate) fn parse_test_seed(s: &str) -> Result<[u8; 32], Error> {
+    let bytes = Vec::<u8>::from_hex(s)?;
+    if bytes.is_empty() || bytes.len() > 32 {
+        return Err(Error::Generic(format!(
+            "The test seed must have 1 to 32 bytes, not {}.",
+            bytes.len()
+        )));
+    }
+    let mut entropy = [0; 32];
+    entropy[..bytes.len()].copy_from_slice(&bytes);
+    Ok(entropy)
+}
+
+/// Generate a mnemonic from the `--test-seed` entropy when given, or from random entropy.
+pub(crate) fn generate_mnemonic<Ctx: ScriptContext>(
+    word_count: WordCount,
+    test_seed: Option<[u8; 32]>,
+) -> Result<GeneratedKey<Mnemonic, Ctx>, Option<bdk_wallet::bip39::Error>> {
+    match test_seed {
+        Some(entropy) => Mnemonic::generate_with_entropy((word_count, Language::English), entropy),
+        None => Mnemonic::generate((word_count, Language::English)),
+    }
+}
+
+/// Parse a fiat amount, a non-negative decimal number such as `12.50`, keeping it as given.
+pub(crate) fn parse_fiat_amount(s: &str) -> Result<String, String> {
+    let (units, cents) = s.split_once('.').unwrap_or((s, "0"));
+    let is_digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    if !is_digits(units) || !is_digits(cents) {
+        return Err(format!("Invalid fiat amount: {s}"));
+    }
+    Ok(s.to_string())
+}
+
+/// Parse a currency code such as `USD`, in upper case.
+pub(crate) fn parse_currency(s: &str) -> Result<String, String> {
+    if !(3..=5).contains(&s.len()) || !s.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("Invalid currency code: {s}"));
+    }
+    Ok(s.to_ascii_uppercase())
+}
+
+/// Parse a transaction range bound, either a block height or a `YYYY-MM-DD` UTC date.
+pub(crate) fn parse_tx_bound(s: &str) -> Result<TxBound, String> {
+    if let Ok(height) = u32::from_str(s) {
+        return Ok(TxBound::Height(height));
+    }
+    let invalid = || format!("Invalid height or YYYY-MM-DD date: {s}");
+    let parts: Vec<_> = s.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(invalid());
+    };
+    let year = i64::from_str(year).map_err(|_| invalid())?;
+    let month = u32::from_str(month).map_err(|_| invalid())?;
+    let day = u32::from_str(day).map_err(|_| invalid())?;
+    let is_leap_year = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year => 29,
+        2 => 28,
+        _ => return Err(invalid()),
+    };
+    if year < 1970 || !(1..=days_in_month).contains(&day) {
+        return Err(invalid());
+    }
+
+    // Days since the unix epoch, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Ok(TxBound::Date(days as u64 * 86_400))
+}
+
 /// Parse an address string into `Address<NetworkChecked>`.
 pub(crate) fn parse_address(address_str: &str) -> Result<Address, Error> {
     let unchecked_address = Address::from_str(address_str)?;
@@ -136,111 +436,2423 @@ pub(crate) fn prepare_wallet_db_dir(
     Ok(dir)
 }
 
-#[cfg(any(
-    feature = "electrum",
-    feature = "esplora",
-    feature = "rpc",
-    feature = "cbf",
-))]
-pub(crate) enum BlockchainClient {
-    #[cfg(feature = "electrum")]
-    Electrum {
-        client: Box<bdk_electrum::BdkElectrumClient<bdk_electrum::electrum_client::Client>>,
-        batch_size: usize,
-    },
-    #[cfg(feature = "esplora")]
-    Esplora {
-        client: Box<bdk_esplora::esplora_client::AsyncClient>,
-        parallel_requests: usize,
-    },
-    #[cfg(feature = "rpc")]
-    RpcClient {
-        client: Box<bdk_bitcoind_rpc::bitcoincore_rpc::Client>,
-    },
+/// Build transient signers for the wallet keychains from an xprv provided at runtime.
+///
+/// The xprv can either be the root key of a descriptor key origin or the descriptor key itself.
+/// Errors if it doesn't match any key of the wallet descriptors.
+pub(crate) fn xprv_signers(wallet: &Wallet, xprv: &Xpriv) -> Result<Vec<SignersContainer>, Error> {
+    let secp = wallet.secp_ctx();
+    let fingerprint = xprv.fingerprint(secp);
+    let mut signers = Vec::new();
+    for (_, descriptor) in wallet.keychains() {
+        let mut keys = Vec::new();
+        descriptor.for_each_key(|key| {
+            keys.push(key);
+            true
+        });
+        let mut keymap = KeyMap::new();
+        for key in keys {
+            let DescriptorPublicKey::XPub(xpub) = key else {
+                continue;
+            };
+            let xkey = match &xpub.origin {
+                Some((origin_fingerprint, origin_path)) if *origin_fingerprint == fingerprint => {
+                    xprv.derive_priv(secp, origin_path)?
+                }
+                _ => *xprv,
+            };
+            if Xpub::from_priv(secp, &xkey) != xpub.xkey {
+                continue;
+            }
+            let secret = DescriptorSecretKey::XPrv(DescriptorXKey {
+                origin: xpub.origin.clone(),
+                xkey,
+                derivation_path: xpub.derivation_path.clone(),
+                wildcard: xpub.wildcard,
+            });
+            keymap.insert(key.clone(), secret);
+        }
+        if !keymap.is_empty() {
+            signers.push(SignersContainer::build(keymap, descriptor, secp));
+        }
+    }
+    if signers.is_empty() {
+        return Err(Error::Generic(format!(
+            "The xprv with fingerprint {fingerprint} doesn't match any key origin of the wallet descriptors."
+        )));
+    }
+    Ok(signers)
+}
 
-    #[cfg(feature = "cbf")]
-    KyotoClient { client: Box<LightClient> },
+/// Derive the private keys held by the wallet for a keychain at the given derivation index.
+///
+/// Returns an empty list for watch-only keychains.
+pub(crate) fn derive_private_keys(
+    wallet: &Wallet,
+    keychain: KeychainKind,
+    index: u32,
+) -> Result<Vec<PrivateKey>, Error> {
+    let secp = wallet.secp_ctx();
+    wallet
+        .get_signers(keychain)
+        .as_key_map(secp)
+        .values()
+        .map(|secret| derive_private_key(secp, secret, index))
+        .collect()
+}
+
+/// Derive a descriptor private key at the given derivation index.
+fn derive_private_key(
+    secp: &Secp256k1<All>,
+    secret: &DescriptorSecretKey,
+    index: u32,
+) -> Result<PrivateKey, Error> {
+    match secret {
+        DescriptorSecretKey::Single(single) => Ok(single.key),
+        DescriptorSecretKey::XPrv(xprv) => {
+            let path = match xprv.wildcard {
+                Wildcard::None => xprv.derivation_path.clone(),
+                Wildcard::Unhardened => xprv
+                    .derivation_path
+                    .extend([ChildNumber::from_normal_idx(index)?]),
+                Wildcard::Hardened => xprv
+                    .derivation_path
+                    .extend([ChildNumber::from_hardened_idx(index)?]),
+            };
+            Ok(xprv.xkey.derive_priv(secp, &path)?.to_priv())
+        }
+        DescriptorSecretKey::MultiXPrv(_) => Err(Error::Generic(
+            "Multipath private keys are not supported.".to_string(),
+        )),
+    }
+}
+
+/// Derive the script pubkey, the address and, for private descriptors, the WIF private keys of
+/// the first `count` indexes of a descriptor.
+pub(crate) fn derivation_test_vectors(
+    descriptor: &str,
+    count: u32,
+    network: Network,
+) -> Result<Vec<Value>, Error> {
+    let secp = Secp256k1::new();
+    let (descriptor, keymap) =
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())?;
+    if descriptor.is_multipath() {
+        return Err(Error::Generic(
+            "Multipath descriptors are not supported, use a single descriptor.".to_string(),
+        ));
+    }
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        keys.push(key.clone());
+        true
+    });
+
+    (0..count)
+        .map(|index| {
+            let derived = descriptor
+                .at_derivation_index(index)
+                .map_err(|e| Error::Generic(e.to_string()))?;
+            let mut vector = json!({
+                "index": index,
+                "script_pubkey": derived.script_pubkey().to_hex_string(),
+                "address": derived.address(network).ok().map(|address| address.to_string()),
+            });
+            if !keymap.is_empty() {
+                let wif = keys
+                    .iter()
+                    .filter_map(|key| keymap.get(key))
+                    .map(|secret| Ok(derive_private_key(&secp, secret, index)?.to_wif()))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                vector["wif"] = json!(wif);
+            }
+            Ok(vector)
+        })
+        .collect()
+}
+
+/// Electrum scripthash of a script pubkey: its SHA256, hex encoded in reversed byte order.
+pub(crate) fn electrum_scripthash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hash.to_lower_hex_string()
+}
+
+/// Derive the script pubkey of a descriptor at an index.
+pub(crate) fn derive_script_pubkey(descriptor: &str, index: u32) -> Result<ScriptBuf, Error> {
+    let secp = Secp256k1::new();
+    let (descriptor, _) =
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())?;
+    if descriptor.is_multipath() {
+        return Err(Error::Generic(
+            "Multipath descriptors are not supported, use a single descriptor.".to_string(),
+        ));
+    }
+    Ok(descriptor
+        .at_derivation_index(index)
+        .map_err(|e| Error::Generic(e.to_string()))?
+        .script_pubkey())
+}
+
+/// Sort key of a wallet transaction, unconfirmed transactions sort after the confirmed ones.
+pub(crate) fn tx_sort_key(
+    chain_position: &ChainPosition<ConfirmationBlockTime>,
+    sort: TxSort,
+) -> (bool, u64) {
+    match chain_position {
+        ChainPosition::Confirmed { anchor, .. } => match sort {
+            TxSort::Height => (false, anchor.block_id.height as u64),
+            TxSort::Time => (false, anchor.confirmation_time),
+        },
+        ChainPosition::Unconfirmed { last_seen, .. } => match sort {
+            TxSort::Height => (true, 0),
+            TxSort::Time => (true, last_seen.unwrap_or(0)),
+        },
+    }
+}
+
+/// Whether a wallet transaction is within the given bounds, both inclusive.
+///
+/// Dates are compared with the confirmation time, and an until date includes the whole day.
+/// Unconfirmed transactions are only within bounds without an upper bound.
+pub(crate) fn tx_in_range(
+    chain_position: &ChainPosition<ConfirmationBlockTime>,
+    since: Option<TxBound>,
+    until: Option<TxBound>,
+) -> bool {
+    let ChainPosition::Confirmed { anchor, .. } = chain_position else {
+        return until.is_none();
+    };
+    let height = anchor.block_id.height;
+    let time = anchor.confirmation_time;
+    let after_since = match since {
+        Some(TxBound::Height(since)) => height >= since,
+        Some(TxBound::Date(since)) => time >= since,
+        None => true,
+    };
+    let before_until = match until {
+        Some(TxBound::Height(until)) => height <= until,
+        Some(TxBound::Date(until)) => time < until + 86_400,
+        None => true,
+    };
+    after_since && before_until
+}
+
+/// Format rows as CSV with the given header.
+///
+/// Fields containing commas, quotes or line breaks are quoted as described in RFC 4180, with
+/// their quotes doubled.
+pub(crate) fn to_csv(header: &[&str], rows: &[Vec<String>]) -> String {
+    let record = |fields: &mut dyn Iterator<Item = &str>| {
+        fields
+            .map(|field| {
+                if field.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let mut csv = record(&mut header.iter().copied());
+    for row in rows {
+        csv.push('\n');
+        csv.push_str(&record(&mut row.iter().map(String::as_str)));
+    }
+    csv
+}
+
+/// Format an amount of sats in a denomination. The conversion uses integer math, so it never rounds.
+pub(crate) fn format_amount(sats: u64, denom: Denom) -> String {
+    let denomination = match denom {
+        Denom::Sat => return sats.to_string(),
+        Denom::Bit => Denomination::Bit,
+        Denom::Mbtc => Denomination::MilliBitcoin,
+        Denom::Btc => Denomination::Bitcoin,
+    };
+    Amount::from_sat(sats).to_string_in(denomination)
+}
+
+/// An amount of sats as a JSON value: a number of sats, or a decimal string in the other
+/// denominations so that JSON parsers don't round it to a float.
+pub(crate) fn amount_value(sats: u64, denom: Denom) -> Value {
+    match denom {
+        Denom::Sat => json!(sats),
+        _ => json!(format_amount(sats, denom)),
+    }
 }
 
+/// Order transactions so that parents come before the children spending their outputs.
 #[cfg(any(
     feature = "electrum",
     feature = "esplora",
     feature = "rpc",
-    feature = "cbf",
+    feature = "cbf"
 ))]
-/// Create a new blockchain from the wallet configuration options.
-pub(crate) fn new_blockchain_client(
-    wallet_opts: &WalletOpts,
-    _wallet: &Wallet,
-    _datadir: PathBuf,
-) -> Result<BlockchainClient, Error> {
-    #[cfg(any(feature = "electrum", feature = "esplora", feature = "rpc"))]
-    let url = wallet_opts.url.as_str();
-    let client = match wallet_opts.client_type {
-        #[cfg(feature = "electrum")]
-        ClientType::Electrum => {
-            let client = bdk_electrum::electrum_client::Client::new(url)
-                .map(bdk_electrum::BdkElectrumClient::new)?;
-            BlockchainClient::Electrum {
-                client: Box::new(client),
-                batch_size: wallet_opts.batch_size,
+pub(crate) fn parents_first(mut txs: Vec<Transaction>) -> Vec<Transaction> {
+    let mut ordered = Vec::with_capacity(txs.len());
+    while !txs.is_empty() {
+        let pending: std::collections::HashSet<_> =
+            txs.iter().map(|tx| tx.compute_txid()).collect();
+        let (ready, blocked): (Vec<_>, Vec<_>) = txs.into_iter().partition(|tx| {
+            !tx.input
+                .iter()
+                .any(|txin| pending.contains(&txin.previous_output.txid))
+        });
+        if ready.is_empty() {
+            ordered.extend(blocked);
+            break;
+        }
+        ordered.extend(ready);
+        txs = blocked;
+    }
+    ordered
+}
+
+/// Number of confirmations a coinbase output needs before it can be spent.
+pub(crate) const COINBASE_MATURITY: u32 = 100;
+
+/// List the unspent coinbase outputs that can't be spent in the next block yet.
+pub(crate) fn immature_coinbase_utxos(wallet: &Wallet) -> Vec<LocalOutput> {
+    let tip = wallet.latest_checkpoint().height();
+    wallet
+        .list_unspent()
+        .filter(|utxo| {
+            wallet
+                .get_tx(utxo.outpoint.txid)
+                .is_some_and(|tx| tx.tx_node.tx.is_coinbase())
+        })
+        .filter(|utxo| match &utxo.chain_position {
+            ChainPosition::Confirmed { anchor, .. } => {
+                tip + 1 < anchor.block_id.height + COINBASE_MATURITY
             }
+            ChainPosition::Unconfirmed { .. } => true,
+        })
+        .collect()
+}
+
+/// Verify that a fee bump paid the original recipients the same amounts, and report where the
+/// extra fee came from.
+///
+/// Like the wallet, only the outputs of the change keychain are taken as change, the outputs
+/// paying the wallet's other addresses are self-payments that must be kept. The output to the
+/// `--shrink` address, if any, is allowed to change. The added inputs can raise the change, so
+/// only the part of their value going to the fee is reported.
+pub(crate) fn fee_bump_report(
+    wallet: &Wallet,
+    original_tx: &Transaction,
+    psbt: &Psbt,
+    shrink_script: Option<&ScriptBuf>,
+    denom: Denom,
+) -> Result<Value, Error> {
+    let replacement_tx = &psbt.unsigned_tx;
+    let change_keychain = if wallet
+        .keychains()
+        .any(|(keychain, _)| keychain == KeychainKind::Internal)
+    {
+        KeychainKind::Internal
+    } else {
+        KeychainKind::External
+    };
+    let is_change = |txout: &TxOut| {
+        matches!(
+            wallet.derivation_of_spk(txout.script_pubkey.clone()),
+            Some((keychain, _)) if keychain == change_keychain
+        )
+    };
+    let is_shrunk =
+        |txout: &TxOut| Some(&txout.script_pubkey) == shrink_script && !is_change(txout);
+
+    let mut unmatched: Vec<&TxOut> = replacement_tx.output.iter().collect();
+    let mut self_payments = Amount::ZERO;
+    for recipient in original_tx
+        .output
+        .iter()
+        .filter(|txout| !is_change(txout) && !is_shrunk(txout))
+    {
+        let position = unmatched
+            .iter()
+            .position(|txout| *txout == recipient)
+            .ok_or_else(|| {
+                let recipient_address =
+                    Address::from_script(&recipient.script_pubkey, wallet.network())
+                        .map(|address| address.to_string())
+                        .unwrap_or_else(|_| recipient.script_pubkey.to_hex_string());
+                Error::Generic(format!(
+                    "The fee bump changed the {} output to {recipient_address}.",
+                    recipient.value
+                ))
+            })?;
+        unmatched.remove(position);
+        if wallet.is_mine(recipient.script_pubkey.clone()) {
+            self_payments += recipient.value;
         }
-        #[cfg(feature = "esplora")]
-        ClientType::Esplora => {
-            let client = bdk_esplora::esplora_client::Builder::new(url).build_async()?;
-            BlockchainClient::Esplora {
-                client: Box::new(client),
-                parallel_requests: wallet_opts.parallel_requests,
+    }
+
+    let output_value = |tx: &Transaction, filter: &dyn Fn(&TxOut) -> bool| -> Amount {
+        tx.output
+            .iter()
+            .filter(|txout| filter(txout))
+            .map(|txout| txout.value)
+            .sum()
+    };
+
+    let mut added_inputs = Vec::new();
+    let mut added_value = Amount::ZERO;
+    for (txin, psbt_input) in replacement_tx.input.iter().zip(&psbt.inputs) {
+        if original_tx
+            .input
+            .iter()
+            .any(|original| original.previous_output == txin.previous_output)
+        {
+            continue;
+        }
+        let value = psbt_input
+            .witness_utxo
+            .as_ref()
+            .map(|txout| txout.value)
+            .or_else(|| {
+                psbt_input.non_witness_utxo.as_ref().and_then(|tx| {
+                    tx.output
+                        .get(txin.previous_output.vout as usize)
+                        .map(|txout| txout.value)
+                })
+            })
+            .unwrap_or(Amount::ZERO);
+        added_value += value;
+        added_inputs.push(txin.previous_output.to_string());
+    }
+
+    let original_change = output_value(original_tx, &is_change);
+    let replacement_change = output_value(replacement_tx, &is_change);
+    let change_raise = replacement_change
+        .checked_sub(original_change)
+        .unwrap_or(Amount::ZERO);
+    let change_spent = original_change
+        .checked_sub(replacement_change)
+        .unwrap_or(Amount::ZERO);
+    let added_inputs_spent = added_value
+        .checked_sub(change_raise)
+        .unwrap_or(Amount::ZERO);
+    let shrink_spent = output_value(original_tx, &is_shrunk)
+        .checked_sub(output_value(replacement_tx, &is_shrunk))
+        .unwrap_or(Amount::ZERO);
+
+    let original_fee = wallet
+        .calculate_fee(original_tx)
+        .map_err(|e| Error::Generic(e.to_string()))?;
+    let fee = psbt.fee()?;
+
+    Ok(json!({
+        "original_fee": amount_value(original_fee.to_sat(), denom),
+        "fee": amount_value(fee.to_sat(), denom),
+        "extra_fee": amount_value(fee.to_sat().saturating_sub(original_fee.to_sat()), denom),
+        "fee_sources": {
+            "change": amount_value(change_spent.to_sat(), denom),
+            "added_inputs": amount_value(added_inputs_spent.to_sat(), denom),
+            "shrink_address": amount_value(shrink_spent.to_sat(), denom),
+        },
+        "self_payments": amount_value(self_payments.to_sat(), denom),
+        "added_inputs": added_inputs,
+    }))
+}
+
+/// Script pubkey of the output spent by a PSBT input, taken from its UTXO fields.
+fn spent_script_pubkey(txin: &TxIn, psbt_input: &psbt::Input) -> Option<ScriptBuf> {
+    psbt_input
+        .witness_utxo
+        .as_ref()
+        .map(|txout| txout.script_pubkey.clone())
+        .or_else(|| {
+            psbt_input.non_witness_utxo.as_ref().and_then(|tx| {
+                tx.output
+                    .get(txin.previous_output.vout as usize)
+                    .map(|txout| txout.script_pubkey.clone())
+            })
+        })
+}
+
+/// Report, for each input of a PSBT, how many of the signatures required by the wallet's spending
+/// policy it already has, which cosigners signed it and the key origins the wallet signs with.
+///
+/// Inputs that don't belong to the wallet are listed with `"is_mine": false` and no counts.
+pub(crate) fn psbt_signing_status(wallet: &Wallet, psbt: &Psbt) -> Result<Value, Error> {
+    let signer_ids = wallet_signer_ids(wallet);
+    let mut inputs = Vec::new();
+    let mut is_complete = true;
+    for (index, (txin, psbt_input)) in psbt.unsigned_tx.input.iter().zip(&psbt.inputs).enumerate() {
+        let outpoint = txin.previous_output;
+        let is_finalized =
+            psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some();
+        let Some((keychain, _)) = spent_script_pubkey(txin, psbt_input)
+            .and_then(|script_pubkey| wallet.derivation_of_spk(script_pubkey))
+        else {
+            is_complete &= is_finalized;
+            inputs.push(json!({
+                "input": index,
+                "outpoint": outpoint.to_string(),
+                "is_mine": false,
+                "is_finalized": is_finalized,
+            }));
+            continue;
+        };
+
+        let descriptor = wallet.public_descriptor(keychain);
+        let mut cosigners = BTreeSet::new();
+        descriptor.for_each_key(|key| {
+            cosigners.insert(key.master_fingerprint());
+            true
+        });
+
+        let mut signed = BTreeSet::new();
+        for pubkey in psbt_input.partial_sigs.keys() {
+            if let Some((fingerprint, _)) = psbt_input.bip32_derivation.get(&pubkey.inner) {
+                signed.insert(*fingerprint);
+            }
+        }
+        let tap_signers = psbt_input
+            .tap_script_sigs
+            .keys()
+            .map(|(xonly, _)| xonly)
+            .chain(
+                psbt_input
+                    .tap_key_sig
+                    .and(psbt_input.tap_internal_key.as_ref()),
+            );
+        for xonly in tap_signers {
+            if let Some((_, (fingerprint, _))) = psbt_input.tap_key_origins.get(xonly) {
+                signed.insert(*fingerprint);
+            }
+        }
+
+        // Only this input is analyzed, BDK otherwise requires a signature on every input.
+        let mut input_psbt = psbt.clone();
+        input_psbt.inputs = vec![psbt_input.clone()];
+        let policy = descriptor
+            .extract_policy(
+                &wallet.get_signers(keychain),
+                BuildSatisfaction::Psbt(&input_psbt),
+                wallet.secp_ctx(),
+            )?
+            .ok_or_else(|| Error::Generic("The descriptor has no spending policy.".to_string()))?;
+        let (signatures, required) = match &policy.satisfaction {
+            Satisfaction::Partial { m, items, .. }
+            | Satisfaction::PartialComplete { m, items, .. } => (items.len(), *m),
+            Satisfaction::Complete { .. } => (1, 1),
+            Satisfaction::None => (0, 1),
+        };
+        let remaining = if is_finalized {
+            0
+        } else {
+            required.saturating_sub(signatures)
+        };
+        is_complete &= remaining == 0;
+
+        inputs.push(json!({
+            "input": index,
+            "outpoint": outpoint.to_string(),
+            "is_mine": true,
+            "is_finalized": is_finalized,
+            "signatures": signatures,
+            "required": required,
+            "remaining": remaining,
+            "signing_keys": signing_key_origins(&signer_ids, psbt_input),
+            "signed_by": signed.iter().map(|fp| fp.to_string()).collect::<Vec<_>>(),
+            "not_signed_by": cosigners
+                .difference(&signed)
+                .map(|fp| fp.to_string())
+                .collect::<Vec<_>>(),
+        }));
+    }
+
+    Ok(json!({
+        "inputs": inputs,
+        "is_complete": is_complete,
+    }))
+}
+
+/// Identifiers of the signers of both wallet keychains.
+pub(crate) fn wallet_signer_ids(wallet: &Wallet) -> Vec<SignerId> {
+    wallet
+        .keychains()
+        .flat_map(|(keychain, _)| {
+            wallet
+                .get_signers(keychain)
+                .ids()
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// List the key origins of a PSBT input the given signers sign with, taken from its BIP32
+/// derivation fields.
+pub(crate) fn signing_key_origins(signer_ids: &[SignerId], psbt_input: &psbt::Input) -> Vec<Value> {
+    let signs = |fingerprint: &Fingerprint, pubkey: &PublicKey| {
+        signer_ids.contains(&SignerId::Fingerprint(*fingerprint))
+            || signer_ids.contains(&SignerId::PkHash(hash160::Hash::hash(&pubkey.serialize())))
+    };
+    let origin = |pubkey: String, (fingerprint, path): &KeySource| {
+        json!({
+            "fingerprint": fingerprint.to_string(),
+            "path": format!("m/{path}"),
+            "pubkey": pubkey,
+        })
+    };
+
+    let ecdsa_keys = psbt_input
+        .bip32_derivation
+        .iter()
+        .filter(|(pubkey, (fingerprint, _))| signs(fingerprint, pubkey))
+        .map(|(pubkey, source)| origin(pubkey.to_string(), source));
+    let schnorr_keys = psbt_input
+        .tap_key_origins
+        .iter()
+        .filter(|(xonly, (_, (fingerprint, _)))| {
+            signs(fingerprint, &xonly.public_key(Parity::Even))
+        })
+        .map(|(xonly, (_, source))| origin(xonly.to_string(), source));
+    ecdsa_keys.chain(schnorr_keys).collect()
+}
+
+/// Count the signatures of the PSBT inputs, finalized inputs count as one.
+pub(crate) fn psbt_signature_count(psbt: &Psbt) -> usize {
+    psbt.inputs
+        .iter()
+        .map(|input| {
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                1
+            } else {
+                input.partial_sigs.len()
+                    + input.tap_script_sigs.len()
+                    + usize::from(input.tap_key_sig.is_some())
             }
+        })
+        .sum()
+}
+
+/// Explain why the signers added no signature to a PSBT whose inputs have no key origin matching
+/// them, comparing the fingerprints the PSBT expects with the ones of the signers.
+pub(crate) fn fingerprint_mismatch_error(signer_ids: &[SignerId], psbt: &Psbt) -> Error {
+    let psbt_fingerprints: BTreeSet<_> = psbt
+        .inputs
+        .iter()
+        .flat_map(|input| {
+            let ecdsa = input
+                .bip32_derivation
+                .values()
+                .map(|(fingerprint, _)| *fingerprint);
+            let schnorr = input
+                .tap_key_origins
+                .values()
+                .map(|(_, (fingerprint, _))| *fingerprint);
+            ecdsa.chain(schnorr).collect::<Vec<_>>()
+        })
+        .map(|fingerprint| fingerprint.to_string())
+        .collect();
+    let wallet_fingerprints: BTreeSet<_> = signer_ids
+        .iter()
+        .filter_map(|id| match id {
+            SignerId::Fingerprint(fingerprint) => Some(fingerprint.to_string()),
+            _ => None,
+        })
+        .collect();
+    let list = |fingerprints: &BTreeSet<String>| {
+        fingerprints.iter().cloned().collect::<Vec<_>>().join(", ")
+    };
+
+    let expected = match psbt_fingerprints.len() {
+        0 => "The PSBT has no key origin for its inputs".to_string(),
+        1 => format!("The PSBT expects fingerprint {}", list(&psbt_fingerprints)),
+        _ => format!("The PSBT expects fingerprints {}", list(&psbt_fingerprints)),
+    };
+    let available = match (wallet_fingerprints.len(), signer_ids.is_empty()) {
+        (_, true) => "the wallet has no private keys".to_string(),
+        (0, false) => "the wallet only has single private keys without fingerprint".to_string(),
+        _ => format!("the wallet has {}", list(&wallet_fingerprints)),
+    };
+    Error::Generic(format!("No signature was added. {expected}, {available}."))
+}
+
+/// The encoding of the addresses of a descriptor, or `None` for a bare descriptor, which has no
+/// address.
+pub(crate) fn descriptor_address_encoding(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+) -> Option<AddressEncoding> {
+    match descriptor.desc_type() {
+        DescriptorType::Bare => None,
+        DescriptorType::Pkh => Some(AddressEncoding::Legacy),
+        DescriptorType::Sh
+        | DescriptorType::ShSortedMulti
+        | DescriptorType::ShWpkh
+        | DescriptorType::ShWsh
+        | DescriptorType::ShWshSortedMulti => Some(AddressEncoding::P2sh),
+        DescriptorType::Wpkh | DescriptorType::Wsh | DescriptorType::WshSortedMulti => {
+            Some(AddressEncoding::Bech32)
+        }
+        DescriptorType::Tr => Some(AddressEncoding::Bech32m),
+    }
+}
+
+/// Compute the fee and the fee rate, in sat/vbyte, a PSBT will pay once all its inputs are signed.
+///
+/// The satisfaction weight of the inputs that are neither finalized nor owned by the wallet is
+/// unknown and not counted, which can only overestimate the fee rate.
+pub(crate) fn psbt_fee_rate(wallet: &Wallet, psbt: &Psbt) -> Result<(Amount, f64), Error> {
+    let fee = psbt.fee()?;
+    let weight = psbt_signed_weight(wallet, psbt)?;
+    Ok((fee, fee.to_sat() as f64 / (weight.to_wu() as f64 / 4.0)))
+}
+
+/// Estimate the weight of the transaction of a PSBT once all its inputs are signed, with the same
+/// caveat as [`psbt_fee_rate`] for the inputs the wallet can't satisfy.
+pub(crate) fn psbt_signed_weight(wallet: &Wallet, psbt: &Psbt) -> Result<Weight, Error> {
+    let mut weight = psbt.unsigned_tx.weight();
+    for (txin, psbt_input) in psbt.unsigned_tx.input.iter().zip(&psbt.inputs) {
+        if psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some() {
+            let script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+            weight += Weight::from_non_witness_data_size(script_sig.len() as u64);
+            weight += Weight::from_witness_data_size(
+                psbt_input
+                    .final_script_witness
+                    .as_ref()
+                    .map_or(0, |witness| witness.size() as u64),
+            );
+            continue;
+        }
+        if let Some((keychain, _)) = spent_script_pubkey(txin, psbt_input)
+            .and_then(|script_pubkey| wallet.derivation_of_spk(script_pubkey))
+        {
+            weight += wallet.public_descriptor(keychain).max_weight_to_satisfy()?;
         }
+    }
+    Ok(weight)
+}
+
+/// How much the fee of a PSBT exceeds the fee its signed transaction needs at `fee_rate`, the
+/// default rate when not given, such as the excess of a selection of inputs left to the miners
+/// instead of making change.
+pub(crate) fn psbt_fee_excess(
+    wallet: &Wallet,
+    psbt: &Psbt,
+    fee_rate: Option<FeeRate>,
+) -> Result<Amount, Error> {
+    let needed = fee_rate
+        .unwrap_or(FeeRate::BROADCAST_MIN)
+        .fee_wu(psbt_signed_weight(wallet, psbt)?)
+        .unwrap_or(Amount::MAX_MONEY);
+    Ok(psbt.fee()?.checked_sub(needed).unwrap_or_default())
+}
+
+/// Estimate, for each input of an unsigned PSBT, the worst-case weight of its satisfaction and
+/// the weight of the cheapest spending path the transaction can take, with the vsize and fee rate
+/// of the signed transaction in both cases.
+///
+/// The planned path only uses the wallet keys and the timelocks the transaction sets, such as the
+/// ones picked by a policy path. When the wallet keys alone can't satisfy the input, it is planned
+/// as if every key of the descriptor signed. Inputs the wallet can't derive are left out of both
+/// totals, like in [`psbt_signed_weight`].
+pub(crate) fn satisfaction_estimate(wallet: &Wallet, psbt: &Psbt) -> Result<Value, Error> {
+    let tx = &psbt.unsigned_tx;
+    let mut inputs = Vec::new();
+    let mut max_weight = tx.weight();
+    let mut planned_weight = tx.weight();
+    for (txin, psbt_input) in tx.input.iter().zip(&psbt.inputs) {
+        let Some((keychain, index)) = spent_script_pubkey(txin, psbt_input)
+            .and_then(|script_pubkey| wallet.derivation_of_spk(script_pubkey))
+        else {
+            inputs.push(json!({
+                "outpoint": txin.previous_output.to_string(),
+                "max_weight": null,
+                "planned_weight": null,
+            }));
+            continue;
+        };
+        let descriptor = wallet.public_descriptor(keychain);
+        let definite = descriptor
+            .at_derivation_index(index)
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        let max = definite.max_weight_to_satisfy()?;
+
+        let timelocks = || {
+            let assets = Assets::new().after(tx.lock_time);
+            match txin.sequence.to_relative_lock_time() {
+                Some(older) => assets.older(older),
+                None => assets,
+            }
+        };
+        let mut all_keys = Vec::new();
+        descriptor.for_each_key(|key| {
+            all_keys.push(key.clone());
+            true
+        });
+        let wallet_keys = wallet.get_signers(keychain).as_key_map(wallet.secp_ctx());
+        let planned = definite
+            .clone()
+            .plan(&timelocks().add(wallet_keys))
+            .ok()
+            .map(|plan| (plan, "wallet_keys"))
+            .or_else(|| {
+                definite
+                    .plan(&timelocks().add(all_keys))
+                    .ok()
+                    .map(|plan| (plan, "all_keys"))
+            })
+            // Like the worst case, leave out the scriptSig length and witness item count an empty
+            // input already weighs.
+            .map(|(plan, keys)| {
+                let empty = 4 + plan.witness_size().min(1);
+                let weight = plan.satisfaction_weight().saturating_sub(empty);
+                (Weight::from_wu_usize(weight), keys)
+            });
+
+        max_weight += max;
+        planned_weight += planned.map_or(max, |(weight, _)| weight);
+        inputs.push(json!({
+            "outpoint": txin.previous_output.to_string(),
+            "keychain": keychain_name(keychain),
+            "index": index,
+            "max_weight": max.to_wu(),
+            "planned_weight": planned.map(|(weight, _)| weight.to_wu()),
+            "planned_with": planned.map(|(_, keys)| keys),
+        }));
+    }
+
+    let fee = psbt.fee()?.to_sat() as f64;
+    let fee_rate = |weight: Weight| fee / (weight.to_wu() as f64 / 4.0);
+    Ok(json!({
+        "inputs": inputs,
+        "vsize": {
+            "max": max_weight.to_vbytes_ceil(),
+            "planned": planned_weight.to_vbytes_ceil(),
+        },
+        "fee_rate": {
+            "max": fee_rate(max_weight),
+            "planned": fee_rate(planned_weight),
+        },
+    }))
+}
+
+/// Whether each input of a PSBT spends an output of the wallet.
+///
+/// Inputs without UTXO fields can't be attributed and are reported as foreign.
+pub(crate) fn owned_inputs(wallet: &Wallet, psbt: &Psbt) -> Vec<bool> {
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .map(|(txin, psbt_input)| {
+            spent_script_pubkey(txin, psbt_input)
+                .is_some_and(|script_pubkey| wallet.is_mine(script_pubkey))
+        })
+        .collect()
+}
+
+/// Combine PSBTs of the same transaction into one.
+///
+/// The PSBTs are merged in the order of their serialization, so the result only depends on the set
+/// of PSBTs and not on the order they are given in. Identical signatures are kept once, while two
+/// different signatures of the same key for the same input are reported as an error.
+pub(crate) fn combine_psbts(mut psbts: Vec<Psbt>) -> Result<Psbt, Error> {
+    psbts.sort_by_cached_key(|psbt| psbt.serialize());
+    psbts.dedup();
+    let mut psbts = psbts.into_iter();
+    let mut combined = psbts
+        .next()
+        .ok_or_else(|| Error::Generic("Invalid PSBT input".to_string()))?;
+    for psbt in psbts {
+        if psbt.unsigned_tx != combined.unsigned_tx {
+            return Err(Error::Generic(format!(
+                "The PSBTs are for different transactions, {} and {}.",
+                combined.unsigned_tx.compute_txid(),
+                psbt.unsigned_tx.compute_txid()
+            )));
+        }
+        for (index, (input, other)) in combined.inputs.iter().zip(&psbt.inputs).enumerate() {
+            let conflict = input
+                .partial_sigs
+                .iter()
+                .find(|(key, sig)| other.partial_sigs.get(key).is_some_and(|o| o != *sig))
+                .map(|(key, _)| key.to_string())
+                .or_else(|| {
+                    input
+                        .tap_script_sigs
+                        .iter()
+                        .find(|(key, sig)| {
+                            other.tap_script_sigs.get(key).is_some_and(|o| o != *sig)
+                        })
+                        .map(|((key, leaf_hash), _)| format!("{key} in the leaf {leaf_hash}"))
+                })
+                .or_else(|| match (&input.tap_key_sig, &other.tap_key_sig) {
+                    (Some(sig), Some(o)) if sig != o => Some("the taproot key path".to_string()),
+                    _ => None,
+                });
+            if let Some(key) = conflict {
+                return Err(Error::Generic(format!(
+                    "The PSBTs have different signatures of {key} for the input {index}."
+                )));
+            }
+        }
+        combined.combine(psbt)?;
+    }
+    Ok(combined)
+}
+
+/// Whether `script` belongs to the wallet and was already seen in a transaction.
+pub(crate) fn is_used_script(wallet: &Wallet, script: &ScriptBuf) -> bool {
+    wallet
+        .derivation_of_spk(script.clone())
+        .is_some_and(|(keychain, index)| wallet.spk_index().is_used(keychain, index))
+}
+
+/// Whether `output` is the change of a transaction paying `recipients`: it pays `change_script`
+/// if one was set, otherwise a wallet script that isn't a recipient. Single descriptor wallets
+/// send their change to the external keychain, so the keychain can't tell it apart.
+pub(crate) fn is_change_output(
+    wallet: &Wallet,
+    change_script: Option<&ScriptBuf>,
+    recipients: &[(ScriptBuf, u64)],
+    output: &TxOut,
+) -> bool {
+    match change_script {
+        Some(change_script) => output.script_pubkey == *change_script,
+        None => {
+            wallet.is_mine(output.script_pubkey.clone())
+                && !recipients
+                    .iter()
+                    .any(|(script, _)| *script == output.script_pubkey)
+        }
+    }
+}
+
+/// Transaction ordering sorting the inputs and outputs as described in BIP69.
+///
+/// Inputs are sorted by previous txid, in its usual reversed byte order, then by output index.
+/// Outputs are sorted by amount, then by script pubkey.
+pub(crate) fn bip69_ordering() -> TxOrdering {
+    TxOrdering::Custom {
+        input_sort: Arc::new(|a, b| {
+            let key = |txin: &TxIn| {
+                let mut txid = txin.previous_output.txid.to_byte_array();
+                txid.reverse();
+                (txid, txin.previous_output.vout)
+            };
+            key(a).cmp(&key(b))
+        }),
+        output_sort: Arc::new(|a, b| {
+            (a.value, a.script_pubkey.as_bytes()).cmp(&(b.value, b.script_pubkey.as_bytes()))
+        }),
+    }
+}
+
+/// Coin selection of `create_tx`: BDK's default one, or only the changeless solutions of branch
+/// and bound when `no_change` is set.
+///
+/// A changeless solution spends inputs worth the amount to send plus the fee, give or take the
+/// cost of a change output, the excess going to the fee. When there is none the selection fails
+/// with [`InsufficientFunds`], reporting more available than needed.
+///
+/// With `min_change`, a change output below it is left out and its amount goes to the fee, as
+/// BDK does for change below the dust limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TxCoinSelection {
+    pub(crate) no_change: bool,
+    pub(crate) min_change: Option<Amount>,
+}
+
+/// Branch and bound fallback failing instead of selecting a solution with change.
+#[derive(Debug, Clone, Copy)]
+struct NoChangeFallback;
+
+impl CoinSelectionAlgorithm for NoChangeFallback {
+    fn coin_select<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        _fee_rate: FeeRate,
+        target_amount: Amount,
+        _drain_script: &Script,
+        _rand: &mut R,
+    ) -> Result<CoinSelectionResult, InsufficientFunds> {
+        Err(InsufficientFunds {
+            needed: target_amount,
+            available: required_utxos
+                .iter()
+                .chain(&optional_utxos)
+                .map(|utxo| utxo.utxo.txout().value)
+                .sum(),
+        })
+    }
+}
+
+impl CoinSelectionAlgorithm for TxCoinSelection {
+    fn coin_select<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: Amount,
+        drain_script: &Script,
+        rand: &mut R,
+    ) -> Result<CoinSelectionResult, InsufficientFunds> {
+        if !self.no_change {
+            let mut result = DefaultCoinSelectionAlgorithm::default().coin_select(
+                required_utxos,
+                optional_utxos,
+                fee_rate,
+                target_amount,
+                drain_script,
+                rand,
+            )?;
+            match (result.excess, self.min_change) {
+                (Excess::Change { amount, fee }, Some(min_change)) if amount < min_change => {
+                    result.excess = Excess::NoChange {
+                        dust_threshold: min_change,
+                        remaining_amount: amount + fee,
+                        change_fee: fee,
+                    };
+                }
+                (excess, _) => result.excess = excess,
+            }
+            return Ok(result);
+        }
+        let size_of_change = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: drain_script.to_owned(),
+        }
+        .size() as u64;
+        let available = required_utxos
+            .iter()
+            .chain(&optional_utxos)
+            .map(|utxo| utxo.utxo.txout().value)
+            .sum();
+        let result = BranchAndBoundCoinSelection::new(size_of_change, NoChangeFallback)
+            .coin_select(
+                required_utxos,
+                optional_utxos,
+                fee_rate,
+                target_amount,
+                drain_script,
+                rand,
+            )?;
+        // The UTXOs that must be spent can be worth enough to need change on their own.
+        match result.excess {
+            Excess::NoChange { .. } => Ok(result),
+            Excess::Change { .. } => Err(InsufficientFunds {
+                needed: target_amount,
+                available,
+            }),
+        }
+    }
+}
+
+/// The `create_tx` options a draft transaction is rebuilt with by `--subtract_fee_from` and
+/// `--split_change`.
+#[derive(Clone)]
+pub(crate) struct RebuildParams<'a> {
+    pub(crate) coin_selection: TxCoinSelection,
+    /// Height coin selection treats coinbase outputs as mature at, with `--allow_immature`.
+    pub(crate) current_height: Option<u32>,
+    pub(crate) change_script: Option<&'a ScriptBuf>,
+    pub(crate) recipients: &'a [(ScriptBuf, u64)],
+    pub(crate) offline_signer: bool,
+    pub(crate) fee_rate: Option<FeeRate>,
+    pub(crate) policies: &'a [(BTreeMap<String, Vec<usize>>, KeychainKind)],
+    pub(crate) ordering: TxOrdering,
+}
+
+/// Get the index of the change output of a draft transaction, skipping the `matched` outputs.
+pub(crate) fn draft_change_vout(
+    wallet: &Wallet,
+    params: &RebuildParams,
+    tx: &Transaction,
+    matched: &[usize],
+) -> Option<usize> {
+    tx.output.iter().enumerate().position(|(vout, output)| {
+        !matched.contains(&vout)
+            && is_change_output(wallet, params.change_script, params.recipients, output)
+    })
+}
+
+/// Rebuild the draft `psbt` of `create_tx` paying `outputs`, with any change going to
+/// `drain_to`.
+///
+/// The inputs of the draft are spent again with its locktime and sequence, so that only the
+/// outputs differ.
+pub(crate) fn rebuild_tx(
+    wallet: &mut Wallet,
+    params: &RebuildParams,
+    psbt: &Psbt,
+    outputs: Vec<(ScriptBuf, Amount)>,
+    drain_to: Option<&ScriptBuf>,
+) -> Result<Psbt, Error> {
+    let inputs: Vec<OutPoint> = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect();
+    let mut tx_builder = wallet.build_tx().coin_selection(params.coin_selection);
+    if let Some(height) = params.current_height {
+        tx_builder.current_height(height);
+    }
+    tx_builder
+        .add_utxos(&inputs)
+        .map_err(|e| Error::Generic(e.to_string()))?
+        .manually_selected_only()
+        .set_recipients(outputs)
+        .nlocktime(psbt.unsigned_tx.lock_time)
+        .set_exact_sequence(psbt.unsigned_tx.input[0].sequence)
+        .ordering(params.ordering.clone());
+    if let Some(drain_to) = drain_to {
+        tx_builder.drain_to(drain_to.clone());
+    }
+    if params.offline_signer {
+        tx_builder.include_output_redeem_witness_script();
+    }
+    if let Some(fee_rate) = params.fee_rate {
+        tx_builder.fee_rate(fee_rate);
+    }
+    for (policy, keychain) in params.policies {
+        tx_builder.policy_path(policy.clone(), *keychain);
+    }
+    Ok(tx_builder.finish()?)
+}
+
+/// Upper bounds (exclusive, in sats) of the UTXO value histogram buckets.
+const UTXO_VALUE_BUCKETS: [u64; 5] = [1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// Upper bounds (inclusive) of the UTXO confirmation depth histogram buckets.
+const UTXO_DEPTH_BUCKETS: [u32; 4] = [5, 99, 999, 9_999];
+
+/// Compute a statistical summary of a set of UTXOs.
+///
+/// The summary includes the value distribution and two histograms, one by value and one by
+/// confirmation depth relative to `tip_height`.
+pub(crate) fn utxo_stats(utxos: &[LocalOutput], tip_height: u32) -> Value {
+    let mut values: Vec<u64> = utxos.iter().map(|u| u.txout.value.to_sat()).collect();
+    values.sort_unstable();
+    let total: u64 = values.iter().sum();
+    let median = match values.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((values[n / 2 - 1] + values[n / 2]) / 2),
+        n => Some(values[n / 2]),
+    };
+
+    let mut histogram: Vec<(String, usize, u64)> = UTXO_VALUE_BUCKETS
+        .iter()
+        .scan(0u64, |lower, upper| {
+            let bucket = format!("{lower}-{}", upper - 1);
+            *lower = *upper;
+            Some((bucket, 0, 0))
+        })
+        .collect();
+    histogram.push((format!("{}+", UTXO_VALUE_BUCKETS[4]), 0, 0));
+    for value in &values {
+        let i = UTXO_VALUE_BUCKETS
+            .iter()
+            .position(|upper| value < upper)
+            .unwrap_or(UTXO_VALUE_BUCKETS.len());
+        histogram[i].1 += 1;
+        histogram[i].2 += value;
+    }
+
+    let mut depth_histogram: Vec<(String, usize, u64)> = vec![("unconfirmed".to_string(), 0, 0)];
+    depth_histogram.extend(UTXO_DEPTH_BUCKETS.iter().scan(1u32, |lower, upper| {
+        let bucket = format!("{lower}-{upper}");
+        *lower = upper + 1;
+        Some((bucket, 0, 0))
+    }));
+    depth_histogram.push((format!("{}+", UTXO_DEPTH_BUCKETS[3] + 1), 0, 0));
+    for utxo in utxos {
+        let i = match &utxo.chain_position {
+            ChainPosition::Confirmed { anchor, .. } => {
+                let depth = tip_height.saturating_sub(anchor.block_id.height) + 1;
+                1 + UTXO_DEPTH_BUCKETS
+                    .iter()
+                    .position(|upper| depth <= *upper)
+                    .unwrap_or(UTXO_DEPTH_BUCKETS.len())
+            }
+            ChainPosition::Unconfirmed { .. } => 0,
+        };
+        depth_histogram[i].1 += 1;
+        depth_histogram[i].2 += utxo.txout.value.to_sat();
+    }
+
+    let to_json = |buckets: Vec<(String, usize, u64)>| -> Vec<Value> {
+        buckets
+            .into_iter()
+            .map(|(bucket, count, total)| json!({"bucket": bucket, "count": count, "total": total}))
+            .collect()
+    };
+
+    json!({
+        "count": values.len(),
+        "total": total,
+        "min": values.first(),
+        "max": values.last(),
+        "median": median,
+        "histogram": to_json(histogram),
+        "depth_histogram": to_json(depth_histogram),
+    })
+}
+
+/// Load the UTXOs frozen with `freeze_utxo` from the wallet data directory.
+pub(crate) fn load_frozen_utxos(wallet_dir: &Path) -> Result<BTreeSet<OutPoint>, Error> {
+    let frozen: BTreeSet<String> = load_state_file(&wallet_dir.join(FROZEN_UTXOS_FILE))?;
+    frozen
+        .iter()
+        .map(|utxo| Ok(OutPoint::from_str(utxo)?))
+        .collect()
+}
+
+/// Load the accounting annotations set with `annotate` from the wallet data directory.
+pub(crate) fn load_annotations(wallet_dir: &Path) -> Result<BTreeMap<Txid, Value>, Error> {
+    let annotations: BTreeMap<String, Value> = load_state_file(&wallet_dir.join(ANNOTATIONS_FILE))?;
+    annotations
+        .into_iter()
+        .map(|(txid, annotation)| Ok((Txid::from_str(&txid)?, annotation)))
+        .collect()
+}
+
+/// Store the accounting annotations set with `annotate` in the wallet data directory.
+pub(crate) fn store_annotations(
+    wallet_dir: &Path,
+    annotations: &BTreeMap<Txid, Value>,
+) -> Result<(), Error> {
+    let annotations: BTreeMap<String, &Value> = annotations
+        .iter()
+        .map(|(txid, annotation)| (txid.to_string(), annotation))
+        .collect();
+    store_state_file(&wallet_dir.join(ANNOTATIONS_FILE), &annotations)
+}
+
+/// Export the transaction graph and the block checkpoints of the wallet, in the changeset format
+/// of BDK, for `import_graph`.
+pub(crate) fn wallet_graph_export(wallet: &Wallet) -> Value {
+    json!({
+        "version": GRAPH_EXPORT_VERSION,
+        "network": wallet.network(),
+        "external_descriptor": wallet.public_descriptor(KeychainKind::External).to_string(),
+        "local_chain": wallet.local_chain().initial_changeset(),
+        "tx_graph": wallet.tx_graph().initial_changeset(),
+    })
+}
+
+/// The wallet update applying a transaction graph exported by `export_graph`.
+///
+/// The graph must be exported from a wallet with the same external descriptor and network.
+pub(crate) fn graph_import_update(wallet: &Wallet, mut export: Value) -> Result<Update, Error> {
+    use bdk_wallet::chain::{local_chain, tx_graph};
+
+    if export["version"] != GRAPH_EXPORT_VERSION {
+        return Err(Error::Generic(format!(
+            "Unsupported graph export version {}, expected {GRAPH_EXPORT_VERSION}.",
+            export["version"]
+        )));
+    }
+    if export["network"] != json!(wallet.network()) {
+        return Err(Error::Generic(format!(
+            "The graph was exported from a {} wallet, not {}.",
+            export["network"],
+            wallet.network()
+        )));
+    }
+    if export["external_descriptor"] != wallet.public_descriptor(KeychainKind::External).to_string()
+    {
+        return Err(Error::Generic(
+            "The graph was exported from a wallet with another descriptor.".to_string(),
+        ));
+    }
+    let local_chain: local_chain::ChangeSet = serde_json::from_value(export["local_chain"].take())?;
+    let tx_graph: tx_graph::ChangeSet<ConfirmationBlockTime> =
+        serde_json::from_value(export["tx_graph"].take())?;
+
+    Ok(changeset_update(local_chain, tx_graph))
+}
+
+/// The wallet update applying the chain and transaction graph of a changeset.
+fn changeset_update(
+    local_chain: bdk_wallet::chain::local_chain::ChangeSet,
+    tx_graph: bdk_wallet::chain::tx_graph::ChangeSet<ConfirmationBlockTime>,
+) -> Update {
+    use bdk_wallet::chain::{BlockId, CheckPoint};
+
+    let blocks = local_chain
+        .blocks
+        .into_iter()
+        .filter_map(|(height, hash)| hash.map(|hash| BlockId { height, hash }));
+    let mut update = Update {
+        chain: CheckPoint::from_block_ids(blocks).ok(),
+        ..Default::default()
+    };
+    update.tx_update.txs = tx_graph.txs.into_iter().collect();
+    update.tx_update.txouts = tx_graph.txouts;
+    update.tx_update.anchors = tx_graph.anchors;
+    // The graph keeps the earliest and the latest of the times a transaction was seen.
+    update.tx_update.seen_ats = tx_graph
+        .first_seen
+        .into_iter()
+        .chain(tx_graph.last_seen)
+        .collect();
+    update.tx_update.evicted_ats = tx_graph.last_evicted.into_iter().collect();
+    update
+}
+
+/// Name of a keychain in the JSON outputs.
+pub(crate) fn keychain_name(keychain: KeychainKind) -> &'static str {
+    match keychain {
+        KeychainKind::External => "external",
+        KeychainKind::Internal => "internal",
+    }
+}
+
+/// Check which of the addresses, one per line, belong to the wallet, deriving its addresses up
+/// to `gap` past the last revealed index of each keychain. Nothing is revealed.
+///
+/// Empty lines and lines starting with `#` are skipped, invalid addresses are reported with an
+/// `error`.
+pub(crate) fn check_addresses(
+    wallet: &Wallet,
+    addresses: &str,
+    gap: u32,
+) -> Result<Vec<(String, Value)>, Error> {
+    let mut derived = BTreeMap::new();
+    for (keychain, descriptor) in wallet.keychains() {
+        let end = wallet
+            .derivation_index(keychain)
+            .map_or(0, |index| index.saturating_add(1))
+            .saturating_add(gap);
+        for index in 0..end {
+            let script = descriptor
+                .at_derivation_index(index)
+                .map_err(|e| Error::Generic(e.to_string()))?
+                .script_pubkey();
+            derived.insert(script, (keychain, index));
+        }
+    }
+
+    Ok(addresses
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let address = Address::from_str(line)
+                .map_err(|e| e.to_string())
+                .and_then(|address| {
+                    address
+                        .require_network(wallet.network())
+                        .map_err(|e| e.to_string())
+                });
+            let result = match address {
+                Ok(address) => match derived.get(&address.script_pubkey()) {
+                    Some((keychain, index)) => json!({
+                        "is_mine": true,
+                        "keychain": keychain_name(*keychain),
+                        "index": index,
+                    }),
+                    None => json!({"is_mine": false}),
+                },
+                Err(error) => json!({"is_mine": false, "error": error}),
+            };
+            (line.to_string(), result)
+        })
+        .collect())
+}
+
+/// Merge a serialized BDK wallet changeset into the wallet: its chain, transaction graph and
+/// revealed address indexes, reporting what was added.
+///
+/// The network and the descriptors the changeset has must be the wallet's.
+pub(crate) fn import_changeset(
+    wallet: &mut Wallet,
+    changeset: bdk_wallet::ChangeSet,
+) -> Result<Value, Error> {
+    if let Some(network) = changeset
+        .network
+        .filter(|network| *network != wallet.network())
+    {
+        return Err(Error::Generic(format!(
+            "The changeset is for a {network} wallet, not {}.",
+            wallet.network()
+        )));
+    }
+    let keychains: Vec<(KeychainKind, _)> = wallet
+        .keychains()
+        .map(|(keychain, descriptor)| (keychain, descriptor.clone()))
+        .collect();
+    for (keychain, descriptor) in [
+        (KeychainKind::External, &changeset.descriptor),
+        (KeychainKind::Internal, &changeset.change_descriptor),
+    ] {
+        if let Some(descriptor) = descriptor {
+            if !keychains.iter().any(|(kind, wallet_descriptor)| {
+                *kind == keychain && wallet_descriptor == descriptor
+            }) {
+                return Err(Error::Generic(format!(
+                    "The {} descriptor of the changeset, {descriptor}, isn't the wallet's.",
+                    keychain_name(keychain)
+                )));
+            }
+        }
+    }
+
+    let known_txs = wallet.tx_graph().full_txs().count();
+    let known_blocks = wallet.local_chain().iter_checkpoints().count();
+    wallet.apply_update(changeset_update(changeset.local_chain, changeset.tx_graph))?;
+
+    let mut revealed = serde_json::Map::new();
+    for (keychain, descriptor) in keychains {
+        let Some(index) = changeset
+            .indexer
+            .last_revealed
+            .get(&descriptor.descriptor_id())
+        else {
+            continue;
+        };
+        let before = wallet.derivation_index(keychain);
+        if before.map_or(true, |before| before < *index) {
+            let _ = wallet.reveal_addresses_to(keychain, *index);
+        }
+        revealed.insert(
+            keychain_name(keychain).to_string(),
+            json!({"before": before, "after": wallet.derivation_index(keychain)}),
+        );
+    }
+
+    Ok(json!({
+        "transactions_added": wallet.tx_graph().full_txs().count() - known_txs,
+        "blocks_added": wallet.local_chain().iter_checkpoints().count().saturating_sub(known_blocks),
+        "tip": wallet.latest_checkpoint().height(),
+        "revealed_indexes": revealed,
+    }))
+}
+
+/// Load the transactions replaced by `bump_fee` and their replacements from the wallet data directory.
+pub(crate) fn load_replacements(wallet_dir: &Path) -> Result<BTreeMap<Txid, Txid>, Error> {
+    let replacements: BTreeMap<String, String> =
+        load_state_file(&wallet_dir.join(REPLACEMENTS_FILE))?;
+    replacements
+        .iter()
+        .map(|(original, replacement)| {
+            Ok((Txid::from_str(original)?, Txid::from_str(replacement)?))
+        })
+        .collect()
+}
+
+/// Store the replaced wallet transactions and their replacements in the wallet data directory.
+pub(crate) fn store_replacements(
+    wallet_dir: &Path,
+    replacements: &BTreeMap<Txid, Txid>,
+) -> Result<(), Error> {
+    let replacements: BTreeMap<String, String> = replacements
+        .iter()
+        .map(|(original, replacement)| (original.to_string(), replacement.to_string()))
+        .collect();
+    store_state_file(&wallet_dir.join(REPLACEMENTS_FILE), &replacements)
+}
+
+/// Record `tx` as the replacement of the wallet transactions spending the same outputs, when
+/// `bump_fee` creates it and again when it is broadcast.
+///
+/// `tx` is appended to the chain of each transaction it replaces. A fee bump recorded before it
+/// was signed has the txid of its unsigned transaction, that its broadcast version takes over
+/// when the signatures change the txid.
+pub(crate) fn record_replacement(
+    wallet: &Wallet,
+    wallet_dir: &Path,
+    tx: &Transaction,
+) -> Result<(), Error> {
+    let mut replacements = load_replacements(wallet_dir)?;
+    let txid = tx.compute_txid();
+    let mut unsigned_tx = tx.clone();
+    for input in &mut unsigned_tx.input {
+        input.script_sig = ScriptBuf::new();
+        input.witness = Witness::new();
+    }
+    let unsigned_txid = unsigned_tx.compute_txid();
+    let originals: BTreeSet<Txid> = wallet
+        .tx_graph()
+        .direct_conflicts(tx)
+        .map(|(_, original)| original)
+        .collect();
+
+    let mut recorded = false;
+    for original in originals {
+        let chain = replacement_chain(&replacements, original);
+        if chain.contains(&txid) {
+            continue;
+        }
+        match chain.as_slice() {
+            [.., replaced, last] if *last == unsigned_txid => {
+                replacements.insert(*replaced, txid);
+            }
+            [.., last] => {
+                replacements.insert(*last, txid);
+            }
+            [] => unreachable!("chain is not empty"),
+        }
+        recorded = true;
+    }
+    if !recorded {
+        return Ok(());
+    }
+    store_replacements(wallet_dir, &replacements)
+}
+
+/// The chain of replacements `txid` belongs to, from the first transaction to the last replacement.
+pub(crate) fn replacement_chain(replacements: &BTreeMap<Txid, Txid>, txid: Txid) -> Vec<Txid> {
+    let mut first = txid;
+    let mut seen = BTreeSet::from([txid]);
+    while let Some((original, _)) = replacements
+        .iter()
+        .find(|(_, replacement)| **replacement == first)
+    {
+        if !seen.insert(*original) {
+            break;
+        }
+        first = *original;
+    }
+
+    let mut chain = vec![first];
+    let mut seen = BTreeSet::from([first]);
+    while let Some(replacement) = replacements.get(chain.last().expect("chain is not empty")) {
+        if !seen.insert(*replacement) {
+            break;
+        }
+        chain.push(*replacement);
+    }
+    chain
+}
+
+/// The BIP125 replaceability of the wallet transaction `txid`.
+///
+/// A transaction signals replaceability when one of its inputs has a sequence below
+/// `0xfffffffe`, and inherits it from any unconfirmed ancestor signaling it. Parents the wallet
+/// doesn't know are listed in `unknown_parents`, their signaling can't be checked.
+///
+/// `replaceable` is `"inherited"` for an unconfirmed transaction only replaceable through an
+/// ancestor, replacing it also needs the ancestor to be replaced.
+pub(crate) fn rbf_status(wallet: &Wallet, txid: Txid) -> Result<Value, Error> {
+    let status = |txid: Txid| match wallet.get_tx(txid).map(|tx| tx.chain_position) {
+        Some(ChainPosition::Confirmed { .. }) => "confirmed",
+        Some(ChainPosition::Unconfirmed { .. }) => "unconfirmed",
+        None => "replaced",
+    };
+    let tx = wallet
+        .tx_graph()
+        .get_tx(txid)
+        .ok_or_else(|| Error::Generic(format!("Transaction {txid} isn't in the wallet.")))?;
+    let tx_status = status(txid);
+
+    let mut ancestors = Vec::new();
+    let mut unknown_parents = BTreeSet::new();
+    if tx_status == "unconfirmed" {
+        let mut seen = BTreeSet::from([txid]);
+        let mut queue: Vec<Arc<Transaction>> = vec![tx.clone()];
+        while let Some(child) = queue.pop() {
+            for input in &child.input {
+                let parent_txid = input.previous_output.txid;
+                if !seen.insert(parent_txid) {
+                    continue;
+                }
+                match wallet.tx_graph().get_tx(parent_txid) {
+                    Some(parent) => {
+                        if status(parent_txid) == "unconfirmed" {
+                            ancestors.push(json!({
+                                "txid": parent_txid,
+                                "signals_rbf": parent.is_explicitly_rbf(),
+                            }));
+                            queue.push(parent);
+                        }
+                    }
+                    None => {
+                        unknown_parents.insert(parent_txid);
+                    }
+                }
+            }
+        }
+    }
+
+    let signals_rbf = tx.is_explicitly_rbf();
+    let inherits_rbf = ancestors
+        .iter()
+        .any(|ancestor| ancestor["signals_rbf"] == true);
+    let replaceable = match (tx_status, signals_rbf, inherits_rbf) {
+        ("unconfirmed", true, _) => json!(true),
+        ("unconfirmed", false, true) => json!("inherited"),
+        _ => json!(false),
+    };
+    Ok(json!({
+        "txid": txid,
+        "status": tx_status,
+        "signals_rbf": signals_rbf,
+        "inherits_rbf": inherits_rbf,
+        "replaceable": replaceable,
+        "ancestors": ancestors,
+        "unknown_parents": unknown_parents,
+    }))
+}
+
+/// Store the frozen UTXOs in the wallet data directory.
+pub(crate) fn store_frozen_utxos(
+    wallet_dir: &Path,
+    frozen: &BTreeSet<OutPoint>,
+) -> Result<(), Error> {
+    let frozen: BTreeSet<String> = frozen.iter().map(OutPoint::to_string).collect();
+    store_state_file(&wallet_dir.join(FROZEN_UTXOS_FILE), &frozen)
+}
+
+/// Current unix timestamp, in seconds.
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Store fee estimates, in sat/vbyte by confirmation target, in the wallet data directory, unless
+/// it's a dry run.
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "rpc",
+    feature = "cbf"
+))]
+pub(crate) fn store_fee_estimates(
+    wallet_dir: &Path,
+    estimates: &BTreeMap<u16, f64>,
+    dry_run: bool,
+) -> Result<Value, Error> {
+    let cache = json!({
+        "timestamp": unix_now(),
+        "estimates": estimates,
+    });
+    if !dry_run {
+        store_state_file(&wallet_dir.join(FEE_ESTIMATES_FILE), &cache)?;
+    }
+    Ok(cache)
+}
+
+/// Highest confirmation target, in blocks, `fee_estimates --when` estimates.
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "rpc",
+    feature = "cbf"
+))]
+pub(crate) const MAX_CONFIRMATION_TARGET: u16 = 1008;
+
+/// Get the number of blocks a transaction paying `fee_rate`, in sat/vbyte, is expected to take
+/// to confirm from a mempool fee histogram.
+///
+/// The histogram lists `(fee_rate, vsize)` pairs, the vsize of the mempool transactions paying
+/// at least `fee_rate` and less than the previous pair. Each block is filled with 1 MvB of the
+/// transactions paying more than `fee_rate`. Returns `None` below the minimum relay fee rate or
+/// beyond [`MAX_CONFIRMATION_TARGET`] blocks.
+#[cfg(any(feature = "electrum", feature = "esplora"))]
+pub(crate) fn histogram_confirmation_target(
+    histogram: &[(f64, f64)],
+    fee_rate: f64,
+) -> Option<u16> {
+    if fee_rate < 1.0 {
+        return None;
+    }
+    let vsize_ahead: f64 = histogram
+        .iter()
+        .filter(|(bin_fee_rate, _)| *bin_fee_rate > fee_rate)
+        .map(|(_, vsize)| vsize)
+        .sum();
+    let blocks = (vsize_ahead / 1_000_000.0).floor() as u64 + 1;
+    u16::try_from(blocks)
+        .ok()
+        .filter(|blocks| *blocks <= MAX_CONFIRMATION_TARGET)
+}
+
+/// Get the fee rate, in sat/vbyte, to confirm within `target` blocks.
+///
+/// The fresh `estimates` fetched from the blockchain server are used when given, the estimates
+/// cached by `fee_estimates` otherwise. The estimate of the highest target within `target`
+/// blocks is used, and a warning is logged when the cache is older than
+/// [`FEE_ESTIMATES_STALE_AFTER`].
+pub(crate) fn estimated_fee_rate(
+    wallet_dir: &Path,
+    target: u16,
+    estimates: Option<&BTreeMap<u16, f64>>,
+) -> Result<f32, Error> {
+    let within_target = |estimates: &BTreeMap<u16, f64>| {
+        estimates
+            .range(..=target)
+            .next_back()
+            .map(|(_, rate)| *rate)
+    };
+    let estimate = match estimates {
+        Some(estimates) => within_target(estimates).ok_or_else(|| {
+            Error::Generic(format!(
+                "The blockchain server has no fee estimate for a target within {target} blocks."
+            ))
+        })?,
+        None => {
+            let cache: Value = load_state_file(&wallet_dir.join(FEE_ESTIMATES_FILE))?;
+            let cached: BTreeMap<u16, f64> = cache["estimates"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(target, rate)| Some((u16::from_str(target).ok()?, rate.as_f64()?)))
+                .collect();
+            let estimate = within_target(&cached).ok_or_else(|| {
+                Error::Generic(format!(
+                    "No cached fee estimate for a target within {target} blocks, run fee_estimates with a blockchain server first."
+                ))
+            })?;
+
+            let age = unix_now().saturating_sub(cache["timestamp"].as_u64().unwrap_or_default());
+            if age > FEE_ESTIMATES_STALE_AFTER {
+                log::warn!(
+                    "Using fee estimates fetched {} minutes ago, they may be stale. Run fee_estimates to refresh them.",
+                    age / 60
+                );
+            }
+            estimate
+        }
+    };
+    Ok(estimate.ceil().max(1.0) as f32)
+}
+
+/// Load the birthday of a wallet restored from a backup, the height its transactions start at.
+pub(crate) fn load_birthday(wallet_dir: &Path) -> Result<Option<u32>, Error> {
+    load_state_file(&wallet_dir.join(BIRTHDAY_FILE))
+}
+
+/// Read a passphrase from the terminal without echoing it, asking for it twice when `confirm`
+/// is set, or a line of the standard input when it isn't a terminal.
+pub(crate) fn read_passphrase(prompt: &str, confirm: bool) -> Result<String, Error> {
+    let passphrase = if std::io::stdin().is_terminal() {
+        let passphrase =
+            rpassword::prompt_password(prompt).map_err(|e| Error::Generic(e.to_string()))?;
+        if confirm
+            && rpassword::prompt_password("Repeat the passphrase: ")
+                .map_err(|e| Error::Generic(e.to_string()))?
+                != passphrase
+        {
+            return Err(Error::Generic("The passphrases don't match.".to_string()));
+        }
+        passphrase
+    } else {
+        let mut line = String::new();
+        std::io::stdin()
+            .read_line(&mut line)
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if passphrase.is_empty() {
+        return Err(Error::Generic("The passphrase can't be empty.".to_string()));
+    }
+    Ok(passphrase)
+}
+
+/// Load a JSON encoded bdk-cli state file, or the default state if the file doesn't exist yet.
+pub(crate) fn load_state_file<T: DeserializeOwned + Default>(path: &Path) -> Result<T, Error> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| Error::Generic(e.to_string()))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Store a bdk-cli state file as JSON.
+pub(crate) fn store_state_file<T: Serialize>(path: &Path, state: &T) -> Result<(), Error> {
+    let content = serde_json::to_string_pretty(state)?;
+    std::fs::write(path, content).map_err(|e| Error::Generic(e.to_string()))
+}
+
+/// Derive the next address of a descriptor which is not managed by the loaded wallet.
+///
+/// `indexes` maps the public descriptor to its next derivation index. The index is advanced
+/// for ranged descriptors, so the caller should store `indexes` once the address is used.
+pub(crate) fn next_descriptor_address(
+    descriptor: &str,
+    indexes: &mut BTreeMap<String, u32>,
+    network: Network,
+) -> Result<(Address, u32), Error> {
+    let secp = Secp256k1::new();
+    let (descriptor, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)?;
+    if descriptor.is_multipath() {
+        return Err(Error::Generic(
+            "Multipath descriptors are not supported, use a single descriptor.".to_string(),
+        ));
+    }
+    let key = descriptor.to_string();
+    let index = indexes.get(&key).copied().unwrap_or(0);
+    let address = descriptor
+        .at_derivation_index(index)
+        .map_err(|e| Error::Generic(e.to_string()))?
+        .address(network)?;
+    if descriptor.has_wildcard() {
+        indexes.insert(key, index + 1);
+    }
+
+    Ok((address, index))
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "rpc",
+    feature = "cbf",
+))]
+pub(crate) enum BlockchainClient {
+    #[cfg(feature = "electrum")]
+    Electrum {
+        client: Box<bdk_electrum::BdkElectrumClient<bdk_electrum::electrum_client::Client>>,
+        batch_size: usize,
+    },
+    /// An Electrum server reached through a local Unix socket, from a `unix://<path>` url.
+    #[cfg(all(feature = "electrum", unix))]
+    ElectrumUnix {
+        client: Box<
+            bdk_electrum::BdkElectrumClient<
+                bdk_electrum::electrum_client::raw_client::RawClient<
+                    std::os::unix::net::UnixStream,
+                >,
+            >,
+        >,
+        batch_size: usize,
+    },
+    #[cfg(feature = "esplora")]
+    Esplora {
+        client: Box<bdk_esplora::esplora_client::AsyncClient<EsploraBackoff>>,
+        parallel_requests: usize,
+        /// Delay before the first retry, in milliseconds.
+        backoff_ms: u64,
+    },
+    #[cfg(feature = "rpc")]
+    RpcClient {
+        client: Box<bdk_bitcoind_rpc::bitcoincore_rpc::Client>,
+    },
+
+    #[cfg(feature = "cbf")]
+    KyotoClient { client: Box<LightClient> },
+}
+
+/// The URL of the endpoint of the Bitcoin Core wallet `name`, with the name percent-encoded.
+#[cfg(feature = "rpc")]
+fn rpc_wallet_url(url: &str, name: &str) -> String {
+    let name: String = name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect();
+    format!("{}/wallet/{name}", url.trim_end_matches('/'))
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "rpc",
+    feature = "cbf",
+))]
+/// Create a new blockchain from the wallet configuration options.
+pub(crate) fn new_blockchain_client(
+    wallet_opts: &WalletOpts,
+    _wallet: &Wallet,
+    _datadir: PathBuf,
+    _timeout: Option<u64>,
+) -> Result<BlockchainClient, Error> {
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "rpc"))]
+    let url = wallet_opts.url.as_str();
+    let client = match wallet_opts.client_type {
+        #[cfg(feature = "electrum")]
+        ClientType::Electrum if url.starts_with(ELECTRUM_UNIX_SCHEME) => {
+            new_electrum_unix_client(&url[ELECTRUM_UNIX_SCHEME.len()..], wallet_opts, _timeout)?
+        }
+        #[cfg(feature = "electrum")]
+        ClientType::Electrum => {
+            // The Electrum client waits at most 255 seconds for each response, the command still
+            // fails after the whole `--timeout`.
+            let config = bdk_electrum::electrum_client::ConfigBuilder::new()
+                .timeout(_timeout.map(|secs| u8::try_from(secs).unwrap_or(u8::MAX)))
+                .build();
+            let mut client =
+                bdk_electrum::electrum_client::Client::from_config(url, config.clone())?;
+            announce_electrum_client(&client, &wallet_opts.user_agent)?;
+            if wallet_opts.electrum_discover {
+                match discover_electrum_server(&client, &wallet_opts.user_agent, _timeout)? {
+                    Some(peer_url) => {
+                        log::info!("Using the Electrum server {peer_url}.");
+                        client =
+                            bdk_electrum::electrum_client::Client::from_config(&peer_url, config)?;
+                        announce_electrum_client(&client, &wallet_opts.user_agent)?;
+                    }
+                    None => {
+                        log::warn!("No peer of the Electrum server responded, using {url}.")
+                    }
+                }
+            }
+            let client = bdk_electrum::BdkElectrumClient::new(client);
+            BlockchainClient::Electrum {
+                client: Box::new(client),
+                batch_size: wallet_opts.batch_size,
+            }
+        }
+        #[cfg(feature = "esplora")]
+        ClientType::Esplora => {
+            let mut builder = bdk_esplora::esplora_client::Builder::new(url)
+                .header("User-Agent", &wallet_opts.user_agent)
+                .max_retries(wallet_opts.esplora_max_retries);
+            if let Some(timeout) = _timeout {
+                builder = builder.timeout(timeout);
+            }
+            let client = builder.build_async_with_sleeper::<EsploraBackoff>()?;
+            BlockchainClient::Esplora {
+                client: Box::new(client),
+                parallel_requests: wallet_opts.parallel_requests,
+                backoff_ms: wallet_opts.esplora_backoff_ms,
+            }
+        }
+
+        #[cfg(feature = "rpc")]
+        ClientType::Rpc => {
+            let auth = match &wallet_opts.cookie {
+                Some(cookie) => bdk_bitcoind_rpc::bitcoincore_rpc::Auth::CookieFile(cookie.into()),
+                None => bdk_bitcoind_rpc::bitcoincore_rpc::Auth::UserPass(
+                    wallet_opts.basic_auth.0.clone(),
+                    wallet_opts.basic_auth.1.clone(),
+                ),
+            };
+            let new_client = |url: &str| -> Result<_, Error> {
+                Ok(match _timeout {
+                    Some(timeout) => {
+                        use bdk_bitcoind_rpc::bitcoincore_rpc::jsonrpc;
+                        let (user, pass) = auth
+                            .clone()
+                            .get_user_pass()
+                            .map_err(|e| Error::Generic(e.to_string()))?;
+                        let mut builder = jsonrpc::simple_http::SimpleHttpTransport::builder()
+                            .url(url)
+                            .map_err(|e| Error::Generic(e.to_string()))?
+                            .timeout(std::time::Duration::from_secs(timeout));
+                        if let Some(user) = user {
+                            builder = builder.auth(user, pass);
+                        }
+                        bdk_bitcoind_rpc::bitcoincore_rpc::Client::from_jsonrpc(
+                            jsonrpc::Client::with_transport(builder.build()),
+                        )
+                    }
+                    None => bdk_bitcoind_rpc::bitcoincore_rpc::Client::new(url, auth.clone())
+                        .map_err(|e| Error::Generic(e.to_string()))?,
+                })
+            };
+            let mut client = new_client(url)?;
+            if let Some(rpc_wallet) = &wallet_opts.rpc_wallet {
+                let loaded = client
+                    .list_wallets()
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                if !loaded.contains(rpc_wallet) {
+                    return Err(Error::Generic(format!(
+                        "The Bitcoin Core node has no loaded wallet named '{rpc_wallet}', the loaded wallets are: {}.",
+                        loaded
+                            .iter()
+                            .map(|name| format!("'{name}'"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )));
+                }
+                // The node serves its chain calls on the wallet endpoint as well.
+                client = new_client(&rpc_wallet_url(url, rpc_wallet))?;
+            }
+            BlockchainClient::RpcClient {
+                client: Box::new(client),
+            }
+        }
+
+        #[cfg(feature = "cbf")]
+        ClientType::Cbf => {
+            let scan_type = Sync;
+            let builder = Builder::new(_wallet.network());
+
+            let client = builder
+                .required_peers(wallet_opts.compactfilter_opts.conn_count)
+                .data_dir(&_datadir)
+                .build_with_wallet(_wallet, scan_type)?;
+
+            BlockchainClient::KyotoClient {
+                client: Box::new(client),
+            }
+        }
+    };
+    Ok(client)
+}
+
+/// Url scheme of Electrum servers listening on a local Unix socket, as in `unix:///run/electrs.sock`.
+#[cfg(feature = "electrum")]
+const ELECTRUM_UNIX_SCHEME: &str = "unix://";
+
+/// Electrum protocol version negotiated when announcing the client to the server.
+#[cfg(feature = "electrum")]
+const ELECTRUM_PROTOCOL_VERSION: &str = "1.4";
+
+#[cfg(feature = "electrum")]
+/// Announces the client name to the Electrum server, so it shows up in the server logs.
+fn announce_electrum_client(
+    client: &impl bdk_electrum::electrum_client::ElectrumApi,
+    user_agent: &str,
+) -> Result<(), Error> {
+    use bdk_electrum::electrum_client::Param;
+
+    client.raw_call(
+        "server.version",
+        [
+            Param::String(user_agent.to_string()),
+            Param::String(ELECTRUM_PROTOCOL_VERSION.to_string()),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Seconds to wait for each discovered Electrum server when no `--timeout` is set.
+#[cfg(feature = "electrum")]
+const ELECTRUM_DISCOVERY_TIMEOUT: u8 = 5;
+
+#[cfg(feature = "electrum")]
+/// Urls of the peers announced by an Electrum server in reply to `server.peers.subscribe`.
+///
+/// Each peer is `[ip, hostname, features]`, where the features `s<port>` and `t<port>` advertise
+/// its SSL and TCP ports, SSL is preferred. Onion peers are skipped as there is no proxy to reach
+/// them.
+fn electrum_peer_urls(peers: &Value) -> Vec<String> {
+    peers
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|peer| {
+            let host = peer
+                .get(1)
+                .and_then(Value::as_str)
+                .filter(|host| !host.is_empty())
+                .or_else(|| peer.get(0).and_then(Value::as_str))?;
+            if host.ends_with(".onion") {
+                return None;
+            }
+            let features: Vec<&str> = peer
+                .get(2)?
+                .as_array()?
+                .iter()
+                .filter_map(Value::as_str)
+                .collect();
+            let port = |prefix: char, default: &str| {
+                features
+                    .iter()
+                    .find_map(|feature| feature.strip_prefix(prefix))
+                    .map(|port| if port.is_empty() { default } else { port }.to_string())
+            };
+            match (port('s', "50002"), port('t', "50001")) {
+                (Some(port), _) => Some(format!("ssl://{host}:{port}")),
+                (None, Some(port)) => Some(format!("tcp://{host}:{port}")),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "electrum")]
+/// Asks the seed Electrum server for its peers and returns the url of the first one that responds.
+///
+/// Each peer is probed with a short timeout, unless a longer `--timeout` is set.
+fn discover_electrum_server(
+    seed: &bdk_electrum::electrum_client::Client,
+    user_agent: &str,
+    timeout: Option<u64>,
+) -> Result<Option<String>, Error> {
+    use bdk_electrum::electrum_client::ElectrumApi;
+
+    let peers = seed.raw_call("server.peers.subscribe", [])?;
+    let config = bdk_electrum::electrum_client::ConfigBuilder::new()
+        .timeout(Some(timeout.map_or(ELECTRUM_DISCOVERY_TIMEOUT, |secs| {
+            u8::try_from(secs).unwrap_or(u8::MAX)
+        })))
+        .build();
+    for url in electrum_peer_urls(&peers) {
+        let probe = bdk_electrum::electrum_client::Client::from_config(&url, config.clone())
+            .map_err(Error::from)
+            .and_then(|client| announce_electrum_client(&client, user_agent));
+        match probe {
+            Ok(()) => return Ok(Some(url)),
+            Err(e) => log::info!("Skipping the Electrum server {url}: {e}"),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(all(feature = "electrum", unix))]
+/// Connects to an Electrum server listening on the Unix socket at `path`.
+fn new_electrum_unix_client(
+    path: &str,
+    wallet_opts: &WalletOpts,
+    timeout: Option<u64>,
+) -> Result<BlockchainClient, Error> {
+    let path = Path::new(path);
+    if !path.exists() {
+        return Err(Error::Generic(format!(
+            "The Electrum socket {} doesn't exist.",
+            path.display()
+        )));
+    }
+    let stream = std::os::unix::net::UnixStream::connect(path).map_err(|e| {
+        Error::Generic(format!(
+            "Can't connect to the Electrum socket {}: {e}",
+            path.display()
+        ))
+    })?;
+    let timeout = timeout.map(std::time::Duration::from_secs);
+    stream
+        .set_read_timeout(timeout)
+        .and_then(|()| stream.set_write_timeout(timeout))
+        .map_err(|e| Error::Generic(e.to_string()))?;
+    let client = bdk_electrum::electrum_client::raw_client::RawClient::from(stream);
+    announce_electrum_client(&client, &wallet_opts.user_agent)?;
+    Ok(BlockchainClient::ElectrumUnix {
+        client: Box::new(bdk_electrum::BdkElectrumClient::new(client)),
+        batch_size: wallet_opts.batch_size,
+    })
+}
+
+#[cfg(all(feature = "electrum", not(unix)))]
+/// Unix sockets aren't available on this platform.
+fn new_electrum_unix_client(
+    _path: &str,
+    _wallet_opts: &WalletOpts,
+    _timeout: Option<u64>,
+) -> Result<BlockchainClient, Error> {
+    Err(Error::Generic(
+        "Electrum servers on a Unix socket are only supported on Unix platforms.".to_string(),
+    ))
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "rpc",
+    feature = "cbf",
+))]
+/// Runs a network operation, failing if it doesn't complete within `timeout` seconds.
+pub(crate) async fn with_timeout<T>(
+    timeout: Option<u64>,
+    operation: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match timeout {
+        Some(secs) => tokio::time::timeout(std::time::Duration::from_secs(secs), operation)
+            .await
+            .map_err(|_| {
+                Error::Generic(format!("The operation timed out after {secs} seconds."))
+            })?,
+        None => operation.await,
+    }
+}
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "rpc",
+    feature = "cbf",
+))]
+/// Check the blockchain client can be used on the custom signet of the given challenge.
+///
+/// All signets share the default signet genesis block, only the P2P network magic differs, so
+/// Electrum and Esplora servers can't be told apart and are refused unless `unverified` is set,
+/// while a bitcoind node is asked for its challenge.
+pub(crate) fn check_signet_challenge(
+    client: &BlockchainClient,
+    challenge: Option<&Script>,
+    unverified: bool,
+) -> Result<(), Error> {
+    #[cfg_attr(not(feature = "rpc"), allow(unused_variables))]
+    let Some(challenge) = challenge else {
+        return Ok(());
+    };
+    #[cfg_attr(
+        not(any(feature = "electrum", feature = "esplora")),
+        allow(unused_variables)
+    )]
+    let check_unverified = |server: &str| {
+        if unverified {
+            Ok(())
+        } else {
+            Err(Error::Generic(format!(
+                "Can't verify the signet challenge of the {server} server, use --unverified-signet to use it anyway."
+            )))
+        }
+    };
+    match client {
+        #[cfg(feature = "electrum")]
+        BlockchainClient::Electrum { .. } => check_unverified("Electrum"),
+        #[cfg(all(feature = "electrum", unix))]
+        BlockchainClient::ElectrumUnix { .. } => check_unverified("Electrum"),
+        #[cfg(feature = "esplora")]
+        BlockchainClient::Esplora { .. } => check_unverified("Esplora"),
+        #[cfg(feature = "rpc")]
+        BlockchainClient::RpcClient { client } => {
+            let info: Value = client.call("getblockchaininfo", &[])?;
+            match info.get("signet_challenge").and_then(Value::as_str) {
+                Some(node_challenge) if node_challenge == challenge.to_hex_string() => Ok(()),
+                Some(node_challenge) => Err(Error::Generic(format!(
+                    "The node runs a signet with challenge {node_challenge}, expected {}.",
+                    challenge.to_hex_string()
+                ))),
+                None => Err(Error::Generic(
+                    "The node doesn't report a signet challenge, is it running on signet?"
+                        .to_string(),
+                )),
+            }
+        }
+        #[cfg(feature = "cbf")]
+        BlockchainClient::KyotoClient { .. } => Err(Error::Generic(
+            "Compact block filters don't support custom signets, the P2P network magic can't be configured.".to_string(),
+        )),
+    }
+}
+
+/// Get the external and internal descriptors from the wallet configuration options, expanding the
+/// descriptor template if one was given.
+fn wallet_descriptors(
+    network: Network,
+    wallet_opts: &WalletOpts,
+) -> Result<(Option<String>, Option<String>), Error> {
+    match (&wallet_opts.template, &wallet_opts.xpub) {
+        (Some(template), _) => {
+            let (ext_descriptor, int_descriptor) = expand_descriptor_template(template, network)?;
+            Ok((Some(ext_descriptor), Some(int_descriptor)))
+        }
+        (None, Some(xpub)) => {
+            let (ext_descriptor, int_descriptor) =
+                xpub_descriptors(xpub, &wallet_opts.xpub_type, network)?;
+            Ok((Some(ext_descriptor), Some(int_descriptor)))
+        }
+        (None, None) => Ok((
+            wallet_opts.ext_descriptor.clone(),
+            wallet_opts.int_descriptor.clone(),
+        )),
+    }
+}
+
+/// Expand a descriptor template like `bip84(<key>)` into its external and internal descriptors.
+///
+/// The key can be an xprv or an xpub, private descriptors are returned for the former.
+pub(crate) fn expand_descriptor_template(
+    template: &str,
+    network: Network,
+) -> Result<(String, String), Error> {
+    let (name, key) = template
+        .trim()
+        .strip_suffix(')')
+        .and_then(|template| template.split_once('('))
+        .ok_or_else(|| Error::Generic(format!("Invalid descriptor template: {template}")))?;
+    let desc_type = match name.to_lowercase().as_str() {
+        "bip44" => "pkh",
+        "bip49" => "sh",
+        "bip84" => "wpkh",
+        "bip86" => "tr",
+        _ => {
+            return Err(Error::Generic(format!(
+                "Unsupported descriptor template: {name}, expected one of bip44, bip49, bip84 or bip86."
+            )));
+        }
+    };
+
+    let result = generate_descriptors(desc_type, key.trim(), network, None)?;
+    let descriptors = result
+        .get("private_descriptors")
+        .unwrap_or(&result["public_descriptors"]);
+    let descriptor = |keychain: &str| {
+        descriptors[keychain]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| Error::Generic(format!("Missing {keychain} descriptor.")))
+    };
+    Ok((descriptor("external")?, descriptor("internal")?))
+}
+
+/// Build the watch-only external and internal descriptors of an account xpub.
+///
+/// The key origin can be given as `[fingerprint/path]xpub`, otherwise the first BIP44/49/84/86
+/// account path of the descriptor type and the fingerprint of the xpub itself are used.
+pub(crate) fn xpub_descriptors(
+    key: &str,
+    desc_type: &str,
+    network: Network,
+) -> Result<(String, String), Error> {
+    let key = DescriptorPublicKey::from_str(key.trim())?;
+    let xkey = match key {
+        DescriptorPublicKey::XPub(xkey)
+            if xkey.derivation_path.is_empty() && xkey.wildcard == Wildcard::None =>
+        {
+            xkey
+        }
+        _ => {
+            return Err(Error::Generic(
+                "Expected an account xpub without derivation steps, like [fingerprint/84h/0h/0h]xpub."
+                    .to_string(),
+            ));
+        }
+    };
+    let (fingerprint, derivation_path) = match xkey.origin {
+        Some(origin) => origin,
+        None => (
+            xkey.xkey.fingerprint(),
+            bip_account_path(desc_type, network, 0)?,
+        ),
+    };
+
+    let result =
+        public_descriptors_with_origin(desc_type, xkey.xkey, fingerprint, &derivation_path)?;
+    let descriptor = |keychain: &str| {
+        result["public_descriptors"][keychain]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| Error::Generic(format!("Missing {keychain} descriptor.")))
+    };
+    Ok((descriptor("external")?, descriptor("internal")?))
+}
+
+/// Check that the internal descriptor of a new wallet uses the same keys as the external one, on
+/// another derivation branch, to catch descriptors pasted twice or taken from different wallets.
+///
+/// Warns about a mismatch, or fails with `--strict`.
+fn check_descriptor_pair(
+    ext_descriptor: &str,
+    int_descriptor: &str,
+    wallet_opts: &WalletOpts,
+) -> Result<(), Error> {
+    let secp = Secp256k1::new();
+    let (ext, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, ext_descriptor)?;
+    let (int, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, int_descriptor)?;
+    let keys = |descriptor: &Descriptor<DescriptorPublicKey>| {
+        let mut keys = Vec::new();
+        descriptor.for_each_key(|key| {
+            keys.push(key.clone());
+            true
+        });
+        keys
+    };
+    let (ext_keys, int_keys) = (keys(&ext), keys(&int));
+
+    let mismatch = if ext.desc_type() != int.desc_type() {
+        Some(format!(
+            "The external descriptor is {:?} but the internal descriptor is {:?}.",
+            ext.desc_type(),
+            int.desc_type()
+        ))
+    } else if ext_keys.len() != int_keys.len() {
+        Some(
+            "The external and internal descriptors don't have the same number of keys.".to_string(),
+        )
+    } else {
+        ext_keys
+            .iter()
+            .zip(&int_keys)
+            .find_map(|(ext_key, int_key)| match (ext_key, int_key) {
+                (DescriptorPublicKey::XPub(ext_xkey), DescriptorPublicKey::XPub(int_xkey))
+                    if ext_xkey.xkey == int_xkey.xkey && ext_xkey.origin == int_xkey.origin =>
+                {
+                    (ext_xkey.derivation_path == int_xkey.derivation_path).then(|| {
+                        format!("Key {ext_key} derives the same addresses in both keychains.")
+                    })
+                }
+                (DescriptorPublicKey::MultiXPub(_), _) | (_, DescriptorPublicKey::MultiXPub(_)) => {
+                    None
+                }
+                (DescriptorPublicKey::Single(_), _) if ext_key == int_key => None,
+                _ => Some(format!(
+                    "Key {int_key} of the internal descriptor doesn't match key {ext_key} of the external descriptor."
+                )),
+            })
+    };
+
+    match mismatch {
+        Some(mismatch) if wallet_opts.strict => Err(Error::Generic(mismatch)),
+        Some(mismatch) => {
+            log::warn!("{mismatch} Check the descriptors before funding the wallet.");
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// A wallet loaded from its database or, with `--descriptor-override`, a transient wallet with the
+/// given descriptors and the chain data of the stored wallet, whose changes are never saved.
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+pub(crate) enum LoadedWallet<P> {
+    Persisted(PersistedWallet<P>),
+    Transient(Wallet),
+}
 
-        #[cfg(feature = "rpc")]
-        ClientType::Rpc => {
-            let auth = match &wallet_opts.cookie {
-                Some(cookie) => bdk_bitcoind_rpc::bitcoincore_rpc::Auth::CookieFile(cookie.into()),
-                None => bdk_bitcoind_rpc::bitcoincore_rpc::Auth::UserPass(
-                    wallet_opts.basic_auth.0.clone(),
-                    wallet_opts.basic_auth.1.clone(),
-                ),
-            };
-            let client = bdk_bitcoind_rpc::bitcoincore_rpc::Client::new(url, auth)
-                .map_err(|e| Error::Generic(e.to_string()))?;
-            BlockchainClient::RpcClient {
-                client: Box::new(client),
-            }
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+impl<P: WalletPersister> LoadedWallet<P>
+where
+    Error: From<P::Error>,
+{
+    /// Persist the staged changes of the wallet, which a transient wallet refuses to.
+    pub(crate) fn persist(&mut self, persister: &mut P) -> Result<bool, Error> {
+        match self {
+            LoadedWallet::Persisted(wallet) => Ok(wallet.persist(persister)?),
+            LoadedWallet::Transient(_) => Err(Error::Generic(
+                "The wallet descriptors are overridden with --descriptor-override, its changes can't be saved.".to_string(),
+            )),
         }
+    }
+}
 
-        #[cfg(feature = "cbf")]
-        ClientType::Cbf => {
-            let scan_type = Sync;
-            let builder = Builder::new(_wallet.network());
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+impl<P> std::ops::Deref for LoadedWallet<P> {
+    type Target = Wallet;
 
-            let client = builder
-                .required_peers(wallet_opts.compactfilter_opts.conn_count)
-                .data_dir(&_datadir)
-                .build_with_wallet(_wallet, scan_type)?;
+    fn deref(&self) -> &Wallet {
+        match self {
+            LoadedWallet::Persisted(wallet) => wallet,
+            LoadedWallet::Transient(wallet) => wallet,
+        }
+    }
+}
 
-            BlockchainClient::KyotoClient {
-                client: Box::new(client),
-            }
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+impl<P> std::ops::DerefMut for LoadedWallet<P> {
+    fn deref_mut(&mut self) -> &mut Wallet {
+        match self {
+            LoadedWallet::Persisted(wallet) => wallet,
+            LoadedWallet::Transient(wallet) => wallet,
         }
-    };
-    Ok(client)
+    }
 }
 
 #[cfg(any(feature = "sqlite", feature = "redb"))]
 /// Create a new persisted wallet from given wallet configuration options.
+///
+/// With `--descriptor-override`, the stored wallet only provides the chain data, see
+/// [`transient_wallet`].
 pub(crate) fn new_persisted_wallet<P: WalletPersister>(
     network: Network,
     persister: &mut P,
     wallet_opts: &WalletOpts,
-) -> Result<PersistedWallet<P>, Error>
+) -> Result<LoadedWallet<P>, Error>
 where
     P::Error: std::fmt::Display,
 {
-    let ext_descriptor = wallet_opts.ext_descriptor.clone();
-    let int_descriptor = wallet_opts.int_descriptor.clone();
+    let (ext_descriptor, int_descriptor) = wallet_descriptors(network, wallet_opts)?;
+    if wallet_opts.descriptor_override {
+        let ext_descriptor = ext_descriptor.ok_or_else(|| {
+            Error::Generic("--descriptor-override needs an external descriptor.".to_string())
+        })?;
+        let stored =
+            WalletPersister::initialize(persister).map_err(|e| Error::Generic(e.to_string()))?;
+        return transient_wallet(network, stored, ext_descriptor, int_descriptor, wallet_opts)
+            .map(LoadedWallet::Transient);
+    }
 
     let mut wallet_load_params = Wallet::load();
     if ext_descriptor.is_some() {
@@ -264,6 +2876,7 @@ where
         Some(wallet) => wallet,
         None => match (ext_descriptor, int_descriptor) {
             (Some(ext_descriptor), Some(int_descriptor)) => {
+                check_descriptor_pair(&ext_descriptor, &int_descriptor, wallet_opts)?;
                 let wallet = Wallet::create(ext_descriptor, int_descriptor)
                     .network(network)
                     .create_wallet(persister)
@@ -283,17 +2896,71 @@ where
         }?,
     };
 
+    Ok(LoadedWallet::Persisted(wallet))
+}
+
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+/// Create a non-persisted wallet with the given descriptors, holding the blocks and transactions
+/// of a stored wallet and revealing its addresses up to the stored indexes of each keychain.
+fn transient_wallet(
+    network: Network,
+    stored: bdk_wallet::ChangeSet,
+    ext_descriptor: String,
+    int_descriptor: Option<String>,
+    wallet_opts: &WalletOpts,
+) -> Result<Wallet, Error> {
+    match stored.network {
+        None => {
+            return Err(Error::Generic(
+                "There is no stored wallet to override the descriptors of.".to_string(),
+            ));
+        }
+        Some(stored_network) if stored_network != network => {
+            return Err(Error::Generic(format!(
+                "The stored wallet is on {stored_network}, not on {network}."
+            )));
+        }
+        Some(_) => {}
+    }
+    let mut wallet = match int_descriptor {
+        Some(int_descriptor) => {
+            check_descriptor_pair(&ext_descriptor, &int_descriptor, wallet_opts)?;
+            Wallet::create(ext_descriptor, int_descriptor)
+                .network(network)
+                .create_wallet_no_persist()?
+        }
+        None => Wallet::create_single(ext_descriptor)
+            .network(network)
+            .create_wallet_no_persist()?,
+    };
+    wallet.apply_update(changeset_update(stored.local_chain, stored.tx_graph))?;
+    for (keychain, descriptor) in [
+        (KeychainKind::External, stored.descriptor),
+        (KeychainKind::Internal, stored.change_descriptor),
+    ] {
+        if let Some(index) = descriptor.and_then(|descriptor| {
+            stored
+                .indexer
+                .last_revealed
+                .get(&descriptor.descriptor_id())
+        }) {
+            let _ = wallet.reveal_addresses_to(keychain, *index);
+        }
+    }
+    log::warn!(
+        "The descriptors of the stored wallet are overridden for this transient session, nothing is saved or broadcast."
+    );
     Ok(wallet)
 }
 
 #[cfg(not(any(feature = "sqlite", feature = "redb")))]
 /// Create a new non-persisted wallet from given wallet configuration options.
 pub(crate) fn new_wallet(network: Network, wallet_opts: &WalletOpts) -> Result<Wallet, Error> {
-    let ext_descriptor = wallet_opts.ext_descriptor.clone();
-    let int_descriptor = wallet_opts.int_descriptor.clone();
+    let (ext_descriptor, int_descriptor) = wallet_descriptors(network, wallet_opts)?;
 
     match (ext_descriptor, int_descriptor) {
         (Some(ext_descriptor), Some(int_descriptor)) => {
+            check_descriptor_pair(&ext_descriptor, &int_descriptor, wallet_opts)?;
             let wallet = Wallet::create(ext_descriptor, int_descriptor)
                 .network(network)
                 .create_wallet_no_persist()?;
@@ -343,10 +3010,6 @@ pub async fn sync_kyoto_client(wallet: &mut Wallet, client: Box<LightClient>) ->
         node,
     } = *client;
 
-    let subscriber = tracing_subscriber::FmtSubscriber::new();
-    tracing::subscriber::set_global_default(subscriber)
-        .map_err(|e| Error::Generic(format!("SetGlobalDefault error: {e}")))?;
-
     tokio::task::spawn(async move { node.run().await });
     tokio::task::spawn(async move { trace_logger(info_subscriber, warning_subscriber).await });
 
@@ -378,6 +3041,290 @@ pub async fn sync_kyoto_client(wallet: &mut Wallet, client: Box<LightClient>) ->
     Ok(())
 }
 
+/// Describe a transaction as JSON, like Bitcoin Core's `decoderawtransaction`.
+pub(crate) fn decode_transaction(tx: &Transaction, network: Network) -> Value {
+    let inputs: Vec<Value> = tx
+        .input
+        .iter()
+        .map(|input| {
+            json!({
+                "prevout": input.previous_output.to_string(),
+                "sequence": input.sequence.0,
+                "script_sig": input.script_sig.to_hex_string(),
+                "witness": input
+                    .witness
+                    .iter()
+                    .map(|element| element.to_lower_hex_string())
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    let outputs: Vec<Value> = tx
+        .output
+        .iter()
+        .map(|output| {
+            json!({
+                "value": output.value.to_sat(),
+                "script_pubkey": output.script_pubkey.to_hex_string(),
+                "address": Address::from_script(&output.script_pubkey, network)
+                    .ok()
+                    .map(|address| address.to_string()),
+            })
+        })
+        .collect();
+
+    json!({
+        "txid": tx.compute_txid().to_string(),
+        "wtxid": tx.compute_wtxid().to_string(),
+        "version": tx.version.0,
+        "locktime": tx.lock_time.to_consensus_u32(),
+        "size": tx.total_size(),
+        "vsize": tx.vsize(),
+        "weight": tx.weight().to_wu(),
+        "inputs": inputs,
+        "outputs": outputs,
+    })
+}
+
+/// Cargo features the binary was built with.
+pub(crate) fn enabled_features() -> Vec<&'static str> {
+    [
+        ("electrum", cfg!(feature = "electrum")),
+        ("esplora", cfg!(feature = "esplora")),
+        ("rpc", cfg!(feature = "rpc")),
+        ("cbf", cfg!(feature = "cbf")),
+        ("sqlite", cfg!(feature = "sqlite")),
+        ("redb", cfg!(feature = "redb")),
+        ("repl", cfg!(feature = "repl")),
+        ("compiler", cfg!(feature = "compiler")),
+        ("elements", cfg!(feature = "elements")),
+        ("hwi", cfg!(feature = "hwi")),
+        ("verify", cfg!(feature = "verify")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
+/// Name of the subcommand that was run, with its parent subcommands, e.g. `wallet balance`.
+pub(crate) fn subcommand_path(matches: &ArgMatches) -> String {
+    let mut path = Vec::new();
+    let mut matches = matches;
+    while let Some((name, sub_matches)) = matches.subcommand() {
+        path.push(name);
+        matches = sub_matches;
+    }
+    path.join(" ")
+}
+
+/// Whether `--quiet` is set, to leave out the progress messages.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Sets `--quiet`, read back with [`is_quiet`].
+pub(crate) fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether `--quiet` leaves out the logs and the progress messages of the scans and syncs.
+pub(crate) fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Initialize the `log` logger and the `tracing` subscriber used by the CBF client with the given format.
+///
+/// Both write to stderr, so that log records never mix with the command output on stdout.
+///
+/// Warnings are logged unless `RUST_LOG` says otherwise, they are meant to be seen. With
+/// `--quiet`, only errors are logged, whatever `RUST_LOG` is set to.
+pub(crate) fn init_logging(format: LogFormat, color: ColorMode) {
+    let quiet = is_quiet();
+    let mut builder = if quiet {
+        let mut builder = env_logger::Builder::new();
+        builder.filter_level(log::LevelFilter::Error);
+        builder
+    } else {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+    };
+    let max_level = if quiet {
+        tracing::Level::ERROR
+    } else {
+        tracing::Level::INFO
+    };
+    match format {
+        LogFormat::Text => {
+            // The logs go to stderr, which can be a terminal while stdout is piped.
+            let color = use_color(color, &std::io::stderr());
+            let write_style = if color {
+                env_logger::WriteStyle::Always
+            } else {
+                env_logger::WriteStyle::Never
+            };
+            builder.write_style(write_style).init();
+            let _ = tracing::subscriber::set_global_default(
+                tracing_subscriber::fmt()
+                    .with_writer(std::io::stderr)
+                    .with_ansi(color)
+                    .with_max_level(max_level)
+                    .finish(),
+            );
+        }
+        LogFormat::Json => {
+            builder
+                .format(|buf, record| {
+                    let log = json!({
+                        "timestamp": buf.timestamp().to_string(),
+                        "level": record.level().to_string(),
+                        "target": record.target(),
+                        "message": record.args().to_string(),
+                    });
+                    writeln!(buf, "{log}")
+                })
+                .init();
+            let _ = tracing::subscriber::set_global_default(
+                tracing_subscriber::fmt()
+                    .json()
+                    .with_writer(std::io::stderr)
+                    .with_max_level(max_level)
+                    .finish(),
+            );
+        }
+    }
+}
+
+/// Whether the output written to `stream` is colored. With `auto`, it is only colored on a
+/// terminal and when `NO_COLOR` isn't set to a non-empty value, see <https://no-color.org>.
+pub(crate) fn use_color(mode: ColorMode, stream: &impl IsTerminal) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            stream.is_terminal()
+                && std::env::var_os("NO_COLOR").map_or(true, |no_color| no_color.is_empty())
+        }
+    }
+}
+
+/// Remove the ANSI escape sequences the `--pretty` tables are styled with, to print them plain.
+pub(crate) fn strip_ansi(output: &str) -> String {
+    let mut plain = String::with_capacity(output.len());
+    let mut chars = output.chars();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            plain.push(c);
+            continue;
+        }
+        // Skip a control sequence up to its final byte, `ESC [ ... m` for the styles.
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    plain
+}
+
+/// Mark the output of a wallet command run with `--dry-run`. JSON objects get a `"dry_run": true`
+/// field, other outputs are kept as they are and the note is logged.
+pub(crate) fn mark_if_dry_run(output: String, dry_run: bool) -> String {
+    if !dry_run {
+        return output;
+    }
+    match serde_json::from_str::<Value>(&output) {
+        Ok(Value::Object(mut object)) => {
+            object.insert("dry_run".to_string(), json!(true));
+            serde_json::to_string_pretty(&object).unwrap_or(output)
+        }
+        _ => {
+            log::warn!("Dry run, nothing was saved or broadcast.");
+            output
+        }
+    }
+}
+
+/// Wrap the output of a command with the network, the command and the current unix timestamp.
+///
+/// Outputs that aren't JSON are wrapped as a string.
+pub(crate) fn envelope_output(output: &str, network: Network, command: &str) -> String {
+    let result =
+        serde_json::from_str::<Value>(output).unwrap_or_else(|_| Value::String(output.to_string()));
+    let envelope = json!({
+        "result": result,
+        "network": network.to_string(),
+        "command": command,
+        "timestamp": unix_now(),
+    });
+    serde_json::to_string_pretty(&envelope).unwrap_or_else(|_| output.to_string())
+}
+
+/// Describe a clap command, its arguments and subcommands as JSON.
+pub(crate) fn command_schema(command: &Command) -> Value {
+    let args: Vec<Value> = command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .map(|arg| {
+            json!({
+                "id": arg.get_id().as_str(),
+                "long": arg.get_long(),
+                "short": arg.get_short(),
+                "help": arg.get_help().map(|help| help.to_string()),
+                "type": arg_value_type(arg),
+                "positional": arg.is_positional(),
+                "required": arg.is_required_set(),
+                "multiple": matches!(arg.get_action(), ArgAction::Append),
+                "global": arg.is_global_set(),
+                "env": arg.get_env().map(|env| env.to_string_lossy()),
+                "default_values": arg
+                    .get_default_values()
+                    .iter()
+                    .map(|value| value.to_string_lossy())
+                    .collect::<Vec<_>>(),
+                "possible_values": arg
+                    .get_possible_values()
+                    .iter()
+                    .map(|value| value.get_name())
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    let subcommands: Vec<Value> = command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(command_schema)
+        .collect();
+
+    json!({
+        "name": command.get_name(),
+        "about": command.get_about().map(|about| about.to_string()),
+        "args": args,
+        "subcommands": subcommands,
+    })
+}
+
+/// Map the type parsed by an argument value parser to a JSON schema type name.
+fn arg_value_type(arg: &Arg) -> &'static str {
+    let type_id = arg.get_value_parser().type_id();
+    if type_id == TypeId::of::<bool>() {
+        "boolean"
+    } else if [
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+    ]
+    .iter()
+    .any(|id| type_id == *id)
+    {
+        "integer"
+    } else if type_id == TypeId::of::<f32>() || type_id == TypeId::of::<f64>() {
+        "number"
+    } else {
+        "string"
+    }
+}
+
 pub(crate) fn shorten(displayable: impl Display, start: u8, end: u8) -> String {
     let displayable = displayable.to_string();
     let start_str: &str = &displayable[0..start as usize];
@@ -390,26 +3337,161 @@ pub fn is_mnemonic(s: &str) -> bool {
     (12..=24).contains(&word_count) && s.chars().all(|c| c.is_alphanumeric() || c.is_whitespace())
 }
 
-pub fn generate_descriptors(desc_type: &str, key: &str, network: Network) -> Result<Value, Error> {
+pub fn generate_descriptors(
+    desc_type: &str,
+    key: &str,
+    network: Network,
+    account_path: Option<&DerivationPath>,
+) -> Result<Value, Error> {
     let is_private = key.starts_with("xprv") || key.starts_with("tprv");
 
-    if is_private {
-        generate_private_descriptors(desc_type, key, network)
-    } else {
-        let purpose = match desc_type.to_lowercase().as_str() {
-            "pkh" => 44u32,
-            "sh" => 49u32,
-            "wpkh" | "wsh" => 84u32,
-            "tr" => 86u32,
-            _ => 84u32,
-        };
-        let coin_type = match network {
-            Network::Bitcoin => 0u32,
-            _ => 1u32,
-        };
-        let derivation_path = DerivationPath::from_str(&format!("m/{purpose}h/{coin_type}h/0h"))?;
-        generate_public_descriptors(desc_type, key, &derivation_path)
+    match (is_private, account_path) {
+        (true, None) => generate_private_descriptors(desc_type, key, network),
+        (true, Some(account_path)) => {
+            check_account_path(account_path)?;
+            generate_account_descriptors(desc_type, key, account_path)
+        }
+        (false, _) => {
+            let derivation_path = resolve_account_path(desc_type, network, account_path)?;
+            generate_public_descriptors(desc_type, key, &derivation_path)
+        }
+    }
+}
+
+/// The account path given by the user, or the BIP44/49/84/86 path of the first account.
+fn resolve_account_path(
+    desc_type: &str,
+    network: Network,
+    account_path: Option<&DerivationPath>,
+) -> Result<DerivationPath, Error> {
+    match account_path {
+        Some(account_path) => {
+            check_account_path(account_path)?;
+            Ok(account_path.clone())
+        }
+        None => bip_account_path(desc_type, network, 0),
+    }
+}
+
+/// Check that an account path is hardened down to the account level, so the account xpub
+/// doesn't reveal the keys of the other accounts.
+fn check_account_path(account_path: &DerivationPath) -> Result<(), Error> {
+    if account_path.is_empty() || account_path.into_iter().any(|child| child.is_normal()) {
+        return Err(Error::Generic(format!(
+            "The account path m/{account_path} must only have hardened steps, like m/84h/1h/5h."
+        )));
     }
+    Ok(())
+}
+
+/// The descriptor type of a BIP44/49/84/86 standard.
+pub(crate) fn bip_descriptor_type(bip: &str) -> &'static str {
+    match bip {
+        "bip44" => "pkh",
+        "bip49" => "sh",
+        "bip86" => "tr",
+        _ => "wpkh",
+    }
+}
+
+/// Parse an English mnemonic, describing every unknown word and a wrong word count or checksum.
+pub(crate) fn parse_checked_mnemonic(phrase: &str) -> Result<Mnemonic, Error> {
+    let words: Vec<&str> = phrase.split_whitespace().collect();
+    let mut problems = Vec::new();
+    if ![12, 15, 18, 21, 24].contains(&words.len()) {
+        problems.push(format!(
+            "it has {} words instead of 12, 15, 18, 21 or 24",
+            words.len()
+        ));
+    }
+    let unknown: Vec<String> = words
+        .iter()
+        .enumerate()
+        .filter(|(_, word)| Language::English.find_word(&word.to_lowercase()).is_none())
+        .map(|(index, word)| format!("'{word}' (word {})", index + 1))
+        .collect();
+    if !unknown.is_empty() {
+        problems.push(format!(
+            "{} not in the BIP39 English word list",
+            match unknown.len() {
+                1 => format!("{} is", unknown[0]),
+                _ => format!("{} are", unknown.join(", ")),
+            }
+        ));
+    }
+    if problems.is_empty() {
+        if let Err(bdk_wallet::bip39::Error::InvalidChecksum) =
+            Mnemonic::parse_in(Language::English, words.join(" ").to_lowercase())
+        {
+            problems
+                .push("its checksum is invalid, a word is miswritten or out of order".to_string());
+        }
+    }
+    if !problems.is_empty() {
+        return Err(Error::Generic(format!(
+            "The mnemonic is invalid: {}.",
+            problems.join("; ")
+        )));
+    }
+    Ok(Mnemonic::parse_in(
+        Language::English,
+        words.join(" ").to_lowercase(),
+    )?)
+}
+
+/// The BIP44/49/84/86 path of an account for a descriptor type.
+fn bip_account_path(
+    desc_type: &str,
+    network: Network,
+    account: u32,
+) -> Result<DerivationPath, Error> {
+    let purpose = match desc_type.to_lowercase().as_str() {
+        "pkh" => 44u32,
+        "sh" => 49u32,
+        "wpkh" | "wsh" => 84u32,
+        "tr" => 86u32,
+        _ => 84u32,
+    };
+    let coin_type = match network {
+        Network::Bitcoin => 0u32,
+        _ => 1u32,
+    };
+    Ok(DerivationPath::from_str(&format!(
+        "m/{purpose}h/{coin_type}h/{account}h"
+    ))?)
+}
+
+/// Generate watch-only descriptors from the first account xpub of the connected hardware wallet.
+#[cfg(feature = "hwi")]
+pub(crate) fn generate_hwi_descriptors(
+    desc_type: &str,
+    network: Network,
+    account_path: Option<&DerivationPath>,
+) -> Result<Value, Error> {
+    use hwi::HWIClient;
+
+    let mut devices = HWIClient::enumerate()?;
+    let device = match devices.len() {
+        0 => {
+            return Err(Error::Generic(
+                "No hardware wallet device found.".to_string(),
+            ));
+        }
+        1 => devices.remove(0)?,
+        n => {
+            return Err(Error::Generic(format!(
+                "Found {n} hardware wallet devices, connect only the one to use."
+            )));
+        }
+    };
+    let client = HWIClient::get_client(&device, false, network.into())?;
+    let derivation_path = resolve_account_path(desc_type, network, account_path)?;
+    let xpub = client.get_xpub(&derivation_path, false)?.xpub;
+
+    let mut descriptors =
+        public_descriptors_with_origin(desc_type, xpub, device.fingerprint, &derivation_path)?;
+    descriptors["device"] = json!(device.model);
+    Ok(descriptors)
 }
 
 /// Generate descriptors from private key using BIP templates
@@ -466,6 +3548,68 @@ fn generate_private_descriptors(
     }))
 }
 
+/// Generate descriptors from the account of a private key (xprv/tprv) at the given path.
+fn generate_account_descriptors(
+    desc_type: &str,
+    key: &str,
+    account_path: &DerivationPath,
+) -> Result<Value, Error> {
+    let secp = Secp256k1::new();
+    let xprv: Xpriv = key.parse()?;
+    let fingerprint = xprv.fingerprint(&secp);
+    let account_xprv = xprv.derive_priv(&secp, account_path)?;
+
+    let build_descriptor = |branch: &str| -> Result<(String, String), Error> {
+        let secret_key = DescriptorSecretKey::XPrv(DescriptorXKey {
+            origin: Some((fingerprint, account_path.clone())),
+            xkey: account_xprv,
+            derivation_path: DerivationPath::from_str(branch)?,
+            wildcard: Wildcard::Unhardened,
+        });
+        let public_key = secret_key.to_public(&secp)?;
+        let descriptor = build_public_descriptor(desc_type, public_key.clone())?;
+        let keymap = KeyMap::from([(public_key, secret_key)]);
+        Ok((
+            descriptor.to_string(),
+            descriptor.to_string_with_secret(&keymap),
+        ))
+    };
+
+    let (external_pub, external_priv) = build_descriptor("0")?;
+    let (internal_pub, internal_priv) = build_descriptor("1")?;
+
+    Ok(json!({
+        "public_descriptors": {
+            "external": external_pub,
+            "internal": internal_pub
+        },
+        "private_descriptors": {
+            "external": external_priv,
+            "internal": internal_priv
+        },
+        "fingerprint": fingerprint.to_string()
+    }))
+}
+
+/// Generate the descriptors of a range of BIP44/49/84/86 accounts of a master private key.
+pub(crate) fn generate_bip_accounts_descriptors(
+    desc_type: &str,
+    xprv: &Xpriv,
+    network: Network,
+    accounts: RangeInclusive<u32>,
+) -> Result<Vec<Value>, Error> {
+    accounts
+        .map(|account| {
+            let account_path = bip_account_path(desc_type, network, account)?;
+            let mut descriptors =
+                generate_account_descriptors(desc_type, &xprv.to_string(), &account_path)?;
+            descriptors["account"] = json!(account);
+            descriptors["account_path"] = json!(format!("m/{account_path}"));
+            Ok(descriptors)
+        })
+        .collect()
+}
+
 /// Generate descriptors from public key (xpub/tpub)
 pub fn generate_public_descriptors(
     desc_type: &str,
@@ -473,8 +3617,16 @@ pub fn generate_public_descriptors(
     derivation_path: &DerivationPath,
 ) -> Result<Value, Error> {
     let xpub: Xpub = key.parse()?;
-    let fingerprint = xpub.fingerprint();
+    public_descriptors_with_origin(desc_type, xpub, xpub.fingerprint(), derivation_path)
+}
 
+/// Build the external and internal public descriptors of an account xpub with the given origin.
+fn public_descriptors_with_origin(
+    desc_type: &str,
+    xpub: Xpub,
+    fingerprint: Fingerprint,
+    derivation_path: &DerivationPath,
+) -> Result<Value, Error> {
     let build_descriptor = |branch: &str| -> Result<String, Error> {
         let branch_path = DerivationPath::from_str(branch)?;
         let desc_xpub = DescriptorXKey {
@@ -522,18 +3674,166 @@ pub fn build_public_descriptor(
     }
 }
 
+/// Describe a descriptor: its script type, miniscripts, lifted spending policy and maximum
+/// satisfaction weight.
+pub(crate) fn inspect_descriptor(descriptor: &str) -> Result<Value, Error> {
+    use bdk_wallet::miniscript::policy::Liftable;
+
+    let secp = Secp256k1::new();
+    let (descriptor, keymap) =
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())?;
+
+    let miniscripts = match &descriptor {
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Ms(ms) => vec![miniscript_properties(ms, None)],
+            ShInner::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::Ms(ms) => vec![miniscript_properties(ms, None)],
+                WshInner::SortedMulti(_) => vec![],
+            },
+            _ => vec![],
+        },
+        Descriptor::Wsh(wsh) => match wsh.as_inner() {
+            WshInner::Ms(ms) => vec![miniscript_properties(ms, None)],
+            WshInner::SortedMulti(_) => vec![],
+        },
+        Descriptor::Tr(tr) => tr
+            .iter_scripts()
+            .map(|(depth, ms)| miniscript_properties(ms, Some(depth)))
+            .collect(),
+        _ => vec![],
+    };
+
+    Ok(json!({
+        "descriptor": descriptor.to_string(),
+        "script_type": format!("{:?}", descriptor.desc_type()),
+        "is_taproot": matches!(descriptor, Descriptor::Tr(_)),
+        "is_ranged": descriptor.has_wildcard(),
+        "is_multipath": descriptor.is_multipath(),
+        "has_private_keys": !keymap.is_empty(),
+        "miniscripts": miniscripts,
+        "policy": descriptor.lift()?.to_string(),
+        "max_satisfaction_weight": descriptor.max_weight_to_satisfy().ok().map(|weight| weight.to_wu()),
+    }))
+}
+
+/// Describe the properties of a miniscript, `depth` is the depth of taproot leaves.
+fn miniscript_properties<Ctx: ScriptContext>(
+    ms: &Miniscript<DescriptorPublicKey, Ctx>,
+    depth: Option<u8>,
+) -> Value {
+    let mut properties = json!({
+        "miniscript": ms.to_string(),
+        "script_size": ms.script_size(),
+        "requires_sig": ms.requires_sig(),
+        "is_non_malleable": ms.is_non_malleable(),
+        "has_mixed_timelocks": ms.has_mixed_timelocks(),
+        "within_resource_limits": ms.within_resource_limits(),
+        "sanity_check": ms.sanity_check().err().map(|e| e.to_string()).unwrap_or("ok".to_string()),
+    });
+    if let Some(depth) = depth {
+        properties["depth"] = json!(depth);
+    }
+    properties
+}
+
+/// Parse a descriptor and re-emit it in its canonical public form, with the keys of
+/// `sortedmulti` sorted.
+pub(crate) fn normalize_descriptor(
+    descriptor: &str,
+) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+    let secp = Secp256k1::new();
+    let (descriptor, _) =
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())?;
+
+    let sorted = |pks: &[DescriptorPublicKey]| {
+        let mut pks = pks.to_vec();
+        pks.sort_by_key(|pk| pk.to_string());
+        pks
+    };
+    let normalized = match &descriptor {
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::SortedMulti(multi) => {
+                Descriptor::new_sh_sortedmulti(multi.k(), sorted(multi.pks()))?
+            }
+            ShInner::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(multi) => {
+                    Descriptor::new_sh_wsh_sortedmulti(multi.k(), sorted(multi.pks()))?
+                }
+                WshInner::Ms(_) => descriptor,
+            },
+            _ => descriptor,
+        },
+        Descriptor::Wsh(wsh) => match wsh.as_inner() {
+            WshInner::SortedMulti(multi) => {
+                Descriptor::new_wsh_sortedmulti(multi.k(), sorted(multi.pks()))?
+            }
+            WshInner::Ms(_) => descriptor,
+        },
+        _ => descriptor,
+    };
+    Ok(normalized)
+}
+
+/// Describe a descriptor like Bitcoin Core's `getdescriptorinfo`: its public form, the checksum of
+/// the descriptor as given, its canonical form and whether it is ranged, multipath or private.
+pub(crate) fn descriptor_info(descriptor: &str) -> Result<Value, Error> {
+    let secp = Secp256k1::new();
+    let (parsed, keymap) =
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())?;
+
+    Ok(json!({
+        "descriptor": parsed.to_string(),
+        "checksum": bdk_wallet::descriptor::calc_checksum(descriptor.trim())?,
+        "canonical": normalize_descriptor(descriptor)?.to_string(),
+        "has_wildcard": parsed.has_wildcard(),
+        "is_multipath": parsed.is_multipath(),
+        "has_private_keys": !keymap.is_empty(),
+    }))
+}
+
+/// Build a `wsh` vault descriptor spendable by the primary key, or by the recovery key once the
+/// output is `blocks` blocks deep.
+pub(crate) fn vault_descriptor(
+    primary: &str,
+    recovery: &str,
+    blocks: u16,
+    network: Network,
+) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+    let secp = Secp256k1::new();
+    let (descriptor, keymap) = Descriptor::parse_descriptor(
+        &secp,
+        &format!("wsh(or_d(pk({primary}),and_v(v:pk({recovery}),older({blocks}))))"),
+    )?;
+    descriptor.sanity_check()?;
+
+    let network_kind = NetworkKind::from(network);
+    let same_network = descriptor.for_each_key(|key| match key {
+        DescriptorPublicKey::XPub(xpub) => xpub.xkey.network == network_kind,
+        DescriptorPublicKey::MultiXPub(xpub) => xpub.xkey.network == network_kind,
+        DescriptorPublicKey::Single(_) => true,
+    });
+    if !same_network {
+        return Err(Error::Generic(format!(
+            "The vault keys must belong to the {network} network."
+        )));
+    }
+    Ok((descriptor, keymap))
+}
+
 /// Generate new mnemonic and descriptors
 pub fn generate_descriptor_with_mnemonic(
     network: Network,
     desc_type: &str,
+    account_path: Option<&DerivationPath>,
+    test_seed: Option<[u8; 32]>,
 ) -> Result<serde_json::Value, Error> {
     let mnemonic: GeneratedKey<Mnemonic, Segwitv0> =
-        Mnemonic::generate((WordCount::Words12, Language::English)).map_err(Error::BIP39Error)?;
+        generate_mnemonic(WordCount::Words12, test_seed).map_err(Error::BIP39Error)?;
 
     let seed = mnemonic.to_seed("");
     let xprv = Xpriv::new_master(network, &seed)?;
 
-    let mut result = generate_descriptors(desc_type, &xprv.to_string(), network)?;
+    let mut result = generate_descriptors(desc_type, &xprv.to_string(), network, account_path)?;
     result["mnemonic"] = json!(mnemonic.to_string());
     Ok(result)
 }
@@ -543,16 +3843,81 @@ pub fn generate_descriptor_from_mnemonic(
     mnemonic_str: &str,
     network: Network,
     desc_type: &str,
+    account_path: Option<&DerivationPath>,
 ) -> Result<serde_json::Value, Error> {
     let mnemonic = Mnemonic::parse_in(Language::English, mnemonic_str)?;
     let seed = mnemonic.to_seed("");
     let xprv = Xpriv::new_master(network, &seed)?;
 
-    let mut result = generate_descriptors(desc_type, &xprv.to_string(), network)?;
+    let mut result = generate_descriptors(desc_type, &xprv.to_string(), network, account_path)?;
     result["mnemonic"] = json!(mnemonic_str);
     Ok(result)
 }
 
+/// Pair the receive descriptor of `key` with the change descriptor of `change_key`, each key
+/// given as a mnemonic, xprv or xpub and keeping its own origin, for wallets using a
+/// different key per keychain.
+pub(crate) fn generate_paired_descriptors(
+    desc_type: &str,
+    key: &str,
+    change_key: &str,
+    network: Network,
+    account_path: Option<&DerivationPath>,
+) -> Result<Value, Error> {
+    let network_kind = NetworkKind::from(network);
+    let same_network = [key, change_key].iter().all(|key| {
+        if is_mnemonic(key) {
+            return true;
+        }
+        match Xpriv::from_str(key) {
+            Ok(xprv) => xprv.network == network_kind,
+            Err(_) => Xpub::from_str(key).map_or(true, |xpub| xpub.network == network_kind),
+        }
+    });
+    if !same_network {
+        return Err(Error::Generic(format!(
+            "The receive and change keys must belong to the {network} network."
+        )));
+    }
+
+    let descriptors = |key: &str| {
+        if is_mnemonic(key) {
+            generate_descriptor_from_mnemonic(key, network, desc_type, account_path)
+        } else {
+            generate_descriptors(desc_type, key, network, account_path)
+        }
+    };
+    let receive = descriptors(key)?;
+    let change = descriptors(change_key)?;
+    if receive["public_descriptors"]["internal"] == change["public_descriptors"]["internal"] {
+        return Err(Error::Generic(
+            "The change key is the receive key, leave out --change_key to derive both descriptors from it.".to_string(),
+        ));
+    }
+    let private = receive.get("private_descriptors").is_some();
+    if private != change.get("private_descriptors").is_some() {
+        return Err(Error::Generic(
+            "The receive and change keys must both be private or both be public.".to_string(),
+        ));
+    }
+
+    let mut result = json!({
+        "public_descriptors": {
+            "external": receive["public_descriptors"]["external"],
+            "internal": change["public_descriptors"]["internal"]
+        },
+        "fingerprint": receive["fingerprint"],
+        "change_fingerprint": change["fingerprint"],
+    });
+    if private {
+        result["private_descriptors"] = json!({
+            "external": receive["private_descriptors"]["external"],
+            "internal": change["private_descriptors"]["internal"]
+        });
+    }
+    Ok(result)
+}
+
 pub fn format_descriptor_output(result: &Value, pretty: bool) -> Result<String, Error> {
     if !pretty {
         return Ok(serde_json::to_string_pretty(result)?);
@@ -574,6 +3939,20 @@ pub fn format_descriptor_output(result: &Value, pretty: bool) -> Result<String,
         ]);
     }
 
+    if let Some(finger_print) = result.get("change_fingerprint") {
+        rows.push(vec![
+            "Change Fingerprint".cell().bold(true),
+            finger_print.as_str().unwrap_or("N/A").cell(),
+        ]);
+    }
+
+    if let Some(device) = result.get("device") {
+        rows.push(vec![
+            "Device".cell().bold(true),
+            device.as_str().unwrap_or("N/A").cell(),
+        ]);
+    }
+
     if let Some(network) = result.get("network") {
         rows.push(vec![
             "Network".cell().bold(true),
@@ -44,3 +44,716 @@ fn test_pretty_flag_after_subcommand() {
 
     assert!(output.status.success());
 }
+
+#[test]
+fn test_color_flag() {
+    let run = |args: &str| {
+        let output = Command::new("cargo")
+            .args(format!("run -- {args}").split_whitespace())
+            .env_remove("NO_COLOR")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // The output is piped, so `auto` leaves the tables plain.
+    assert!(!run("--pretty version").contains('\u{1b}'));
+    assert!(run("--pretty --color always version").contains('\u{1b}'));
+    let json = run("--color always version");
+    assert!(!json.contains('\u{1b}'));
+    assert!(serde_json::from_str::<serde_json::Value>(&json).is_ok());
+}
+
+#[test]
+fn test_quiet_flag() {
+    let run = |args: &str| {
+        let output = Command::new("cargo")
+            .args(format!("run -- {args}").split_whitespace())
+            .env("RUST_LOG", "warn")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    // Mainnet commands warn that the software is experimental.
+    assert!(run("-n bitcoin key generate").contains("experimental software"));
+    assert!(!run("--quiet -n bitcoin key generate").contains("experimental software"));
+}
+
+#[test]
+fn test_schema_lists_subcommands() {
+    let output = Command::new("cargo")
+        .args("run -- schema".split_whitespace())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let schema: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let subcommands = schema["subcommands"].as_array().unwrap();
+    assert!(subcommands.iter().any(|cmd| cmd["name"] == "wallet"));
+    assert!(subcommands.iter().any(|cmd| cmd["name"] == "key"));
+}
+
+#[test]
+fn test_network_aliases() {
+    let output = Command::new("cargo")
+        .args("run -- --network reg key generate".split_whitespace())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args("run -- --network regnet key generate".split_whitespace())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("regtest (reg)"));
+}
+
+#[test]
+fn test_deprecated_network_env() {
+    let output = Command::new("cargo")
+        .args("run -- key generate".split_whitespace())
+        .env_remove("BDK_NETWORK")
+        .env("NETWORK", "regtest")
+        // Warnings are logged by default.
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("tprv"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("use BDK_NETWORK instead"));
+}
+
+#[test]
+fn test_normalize_sorts_multisig_keys() {
+    let key_a = "tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*";
+    let key_b = "tpubD6NzVbkrYhZ4WaWSyoBvQwbpLkojyoTZPRsgXELWz3Popb3qkjcJyJUGLnL4qHHoQvao8ESaAstxYSnhyswJ76uZPStJRJCTKvosUCJZL5B/0/*";
+
+    let normalize = |descriptor: String| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "normalize", "--descriptor", &descriptor])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let ab = normalize(format!("wsh(sortedmulti(1,{key_a},{key_b}))"));
+    let ba = normalize(format!("wsh(sortedmulti(1,{key_b},{key_a}))"));
+
+    assert_eq!(ab, ba);
+    assert!(ab["descriptor"].as_str().unwrap().contains('#'));
+}
+
+#[test]
+fn test_envelope_flag() {
+    let output = Command::new("cargo")
+        .args("run -- --network regtest --envelope key generate".split_whitespace())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let envelope: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(envelope["command"], "key generate");
+    assert_eq!(envelope["network"], "regtest");
+    assert!(envelope["timestamp"].is_u64());
+    assert!(envelope["result"]["mnemonic"].is_string());
+}
+
+#[test]
+fn test_version_subcommand() {
+    let output = Command::new("cargo")
+        .args("run -- version".split_whitespace())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let version: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(
+        version["target"]
+            .as_str()
+            .is_some_and(|target| !target.is_empty())
+    );
+    let features = version["features"].as_array().unwrap();
+    assert!(features.iter().any(|feature| feature == "sqlite"));
+}
+
+#[test]
+fn test_log_format_json() {
+    let output = Command::new("cargo")
+        .args("run -q -- -n bitcoin --log-format json key generate".split_whitespace())
+        .env("RUST_LOG", "warn")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let record: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(record["level"], "WARN");
+    assert_eq!(record["target"], "bdk_cli");
+    assert!(record["message"].as_str().unwrap().contains("experimental"));
+}
+
+#[test]
+fn test_test_vectors_bip84() {
+    // BIP84 test vector of the "abandon ... about" mnemonic.
+    let xprv = "xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu";
+    let descriptor = format!("wpkh({xprv}/84h/0h/0h/0/*)");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-n", "bitcoin", "test_vectors", "--count", "2"])
+        .args(["--descriptor", &descriptor])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let vectors: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(vectors.as_array().unwrap().len(), 2);
+    assert_eq!(
+        vectors[0]["address"],
+        "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+    );
+    assert_eq!(
+        vectors[0]["script_pubkey"],
+        "0014c0cebcd6c3d3ca8c75dc5ec62ebe55330ef910e2"
+    );
+    assert_eq!(
+        vectors[0]["wif"][0],
+        "KyZpNDKnfs94vbrwhJneDi77V6jF64PWPF8x5cdJb8ifgg2DUc9d"
+    );
+    assert_eq!(vectors[1]["index"], 1);
+}
+
+#[test]
+fn test_key_generate_test_seed() {
+    let generate = |network: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "-n", network, "--test-seed"])
+            .args([
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "key",
+                "generate",
+                "-e",
+                "12",
+            ])
+            .output()
+            .unwrap()
+    };
+
+    let output = generate("regtest");
+    assert!(output.status.success());
+    let key: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // BIP39 test vector of the 0x7f entropy.
+    assert_eq!(
+        key["mnemonic"],
+        "legal winner thank year wave sausage worth useful legal winner thank yellow"
+    );
+    assert_eq!(key["fingerprint"], "b8688df1");
+
+    let output = generate("bitcoin");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("mainnet"));
+}
+
+#[test]
+fn test_key_verify_mnemonic() {
+    let verify = |mnemonic: &str, address: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "-n", "regtest", "key", "verify-mnemonic"])
+            .args(["-m", mnemonic, "--address", address])
+            .output()
+            .unwrap()
+    };
+    let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    let address = "bcrt1q6rz28mcfaxtmd6v789l9rrlrusdprr9pz3cppk";
+
+    let output = verify(mnemonic, address);
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["fingerprint"], "73c5da0a");
+    assert_eq!(result["address_matches"], true);
+
+    let output = verify(&mnemonic.replace("about", "abandon"), address);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("checksum is invalid"));
+
+    let output = verify(&mnemonic.replacen("abandon", "abandn", 1), address);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'abandn' (word 1)"));
+}
+
+#[test]
+fn test_descriptor_change_key() {
+    let receive = "tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+    let change = "tpubDDA3frU31t8hSUKpX7UmhvJuDGG1r7UNzx85fQ49Xa15ax6Yby2sLQYCwCDWgdXQeb4iK8EZGKwRHDdKZTgvpHNi5WeUQn5ZBpEFSnM8FkE";
+    let descriptor = |network: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "-n", network, "descriptor", "-t", "wpkh"])
+            .args([receive, "--change_key", change])
+            .output()
+            .unwrap()
+    };
+
+    let output = descriptor("regtest");
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let external = result["public_descriptors"]["external"].as_str().unwrap();
+    let internal = result["public_descriptors"]["internal"].as_str().unwrap();
+    assert!(external.contains(&format!("{receive}/0/*")));
+    assert!(internal.contains(&format!("{change}/1/*")));
+
+    let output = descriptor("bitcoin");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must belong to the bitcoin network"));
+}
+
+#[test]
+fn test_scripthash() {
+    // Example of the Electrum protocol documentation.
+    let address = "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa";
+    let output = Command::new("cargo")
+        .args(["run", "--", "-n", "bitcoin", "scripthash", address])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let scripthashes: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        scripthashes[address],
+        "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+    );
+}
+
+#[test]
+fn test_verify_address_bip84() {
+    let xprv = "xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu";
+    let descriptor = format!("wpkh({xprv}/84h/0h/0h/0/*)");
+    let verify = |index: &str| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-n", "bitcoin", "verify_address"])
+            .args(["--descriptor", &descriptor, "--index", index])
+            .args(["--expect", "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    assert_eq!(verify("0")["matches"], true);
+    let result = verify("1");
+    assert_eq!(result["matches"], false);
+    assert_ne!(result["derived"], result["expected"]);
+}
+
+#[test]
+fn test_get_descriptor_info_checksum() {
+    let descriptor = "wpkh(tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N/1/2/*)";
+    let info = |descriptor: &str| {
+        let output = Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "get_descriptor_info",
+                "--descriptor",
+                descriptor,
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let result = info(descriptor);
+    assert_eq!(result["checksum"], "tqz0nc62");
+    assert_eq!(result["has_private_keys"], true);
+    assert_eq!(result["has_wildcard"], true);
+    assert_eq!(result["is_multipath"], false);
+    assert!(!result["descriptor"].as_str().unwrap().contains("tprv"));
+    assert_eq!(info(&format!("{descriptor}#tqz0nc62")), result);
+}
+
+#[test]
+fn test_inspect_descriptor_miniscript() {
+    let key = "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+    let descriptor = format!("wsh(and_v(v:pk({key}),older(144)))");
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "inspect_descriptor",
+            "--descriptor",
+            &descriptor,
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let inspection: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(inspection["script_type"], "Wsh");
+    assert_eq!(inspection["is_taproot"], false);
+    assert_eq!(inspection["policy"], format!("and(pk({key}),older(144))"));
+    let miniscript = &inspection["miniscripts"][0];
+    assert_eq!(
+        miniscript["miniscript"],
+        format!("and_v(v:pk({key}),older(144))")
+    );
+    assert_eq!(miniscript["requires_sig"], true);
+    assert!(inspection["max_satisfaction_weight"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn test_descriptor_account_path() {
+    let xprv = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let descriptors = |account_path: Option<&str>| {
+        let mut command = Command::new("cargo");
+        command.args([
+            "run",
+            "--",
+            "-n",
+            "regtest",
+            "descriptor",
+            "-t",
+            "wpkh",
+            xprv,
+        ]);
+        if let Some(account_path) = account_path {
+            command.args(["--account_path", account_path]);
+        }
+        command.output().unwrap()
+    };
+
+    // The first account gives the same public descriptors as the BIP84 template.
+    let default: serde_json::Value = serde_json::from_slice(&descriptors(None).stdout).unwrap();
+    let first: serde_json::Value =
+        serde_json::from_slice(&descriptors(Some("m/84h/1h/0h")).stdout).unwrap();
+    assert_eq!(first["public_descriptors"], default["public_descriptors"]);
+
+    let fifth: serde_json::Value =
+        serde_json::from_slice(&descriptors(Some("m/84h/1h/5h")).stdout).unwrap();
+    let external = fifth["public_descriptors"]["external"].as_str().unwrap();
+    assert!(external.starts_with("wpkh([73c5da0a/84'/1'/5']tpub"));
+    assert!(
+        fifth["private_descriptors"]["external"]
+            .as_str()
+            .unwrap()
+            .starts_with("wpkh([73c5da0a/84'/1'/5']tprv")
+    );
+
+    let unhardened = descriptors(Some("m/84h/1h/5"));
+    assert!(!unhardened.status.success());
+    assert!(String::from_utf8_lossy(&unhardened.stderr).contains("hardened"));
+}
+
+#[test]
+fn test_key_derive_accounts() {
+    let xprv = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-n",
+            "regtest",
+            "key",
+            "derive-accounts",
+            "--xprv",
+            xprv,
+            "--accounts",
+            "0-2",
+        ])
+        .output()
+        .unwrap();
+    let accounts: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let accounts = accounts.as_array().unwrap();
+    assert_eq!(accounts.len(), 3);
+
+    // Each account gives the same descriptors as `descriptor` with its account path.
+    let descriptor = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-n",
+            "regtest",
+            "descriptor",
+            "-t",
+            "wpkh",
+            xprv,
+            "--account_path",
+            "m/84h/1h/2h",
+        ])
+        .output()
+        .unwrap();
+    let descriptor: serde_json::Value = serde_json::from_slice(&descriptor.stdout).unwrap();
+    assert_eq!(accounts[2]["account"], 2);
+    assert_eq!(accounts[2]["account_path"], "m/84'/1'/2'");
+    assert_eq!(
+        accounts[2]["private_descriptors"],
+        descriptor["private_descriptors"]
+    );
+}
+
+#[test]
+fn test_xpub_watch_only_wallet() {
+    let xpub = "[73c5da0a/84h/1h/0h]tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+    let datadir = std::env::temp_dir().join(format!("bdk-cli-xpub-{}", std::process::id()));
+    let output = Command::new("cargo")
+        .args(["run", "--", "-n", "regtest", "--datadir"])
+        .arg(&datadir)
+        .args(["wallet", "-w", "watch", "-d", "sqlite", "--xpub", xpub])
+        .args(["new_address", "--raw"])
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&datadir);
+
+    assert!(output.status.success());
+    // First BIP84 address of the "abandon ... about" mnemonic on regtest.
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "bcrt1q6rz28mcfaxtmd6v789l9rrlrusdprr9pz3cppk"
+    );
+}
+
+#[test]
+fn test_strict_rejects_mismatched_descriptors() {
+    let xprv_a = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let xprv_b = "tprv8ZgxMBicQKsPd1EzCPZcQSPhsotX5HvRDCivA7ASNQFmjWuTsW3WWEwUNKFAZrnD9qpz55rtyLdphqkwRZUqNWYXwSEzd6P4pYvXGByRim3";
+    let datadir = std::env::temp_dir().join(format!("bdk-cli-strict-{}", std::process::id()));
+    let output = Command::new("cargo")
+        .args(["run", "--", "-n", "regtest", "--datadir"])
+        .arg(&datadir)
+        .args(["wallet", "-w", "strict", "-d", "sqlite", "--strict"])
+        .args(["-e", &format!("wpkh({xprv_a}/84'/1'/0'/0/*)")])
+        .args(["-i", &format!("wpkh({xprv_b}/84'/1'/0'/1/*)")])
+        .arg("balance")
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&datadir);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("doesn't match key"));
+}
+
+#[test]
+fn test_dry_run_does_not_persist() {
+    let xprv = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let datadir = std::env::temp_dir().join(format!("bdk-cli-dry-run-{}", std::process::id()));
+    let new_address = |dry_run: bool| {
+        let mut command = Command::new("cargo");
+        command
+            .args(["run", "--", "-n", "regtest", "--datadir"])
+            .arg(&datadir)
+            .args(["wallet", "-w", "dry_run", "-d", "sqlite"])
+            .args(["-e", &format!("wpkh({xprv}/84'/1'/0'/0/*)")])
+            .args(["-i", &format!("wpkh({xprv}/84'/1'/0'/1/*)")])
+            .arg("new_address");
+        if dry_run {
+            command.arg("--dry-run");
+        }
+        let output = command.output().unwrap();
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let preview = new_address(true);
+    let revealed = new_address(false);
+    let _ = std::fs::remove_dir_all(&datadir);
+
+    assert_eq!(preview["dry_run"], true);
+    assert_eq!(preview["address"], revealed["address"]);
+    assert!(revealed.get("dry_run").is_none());
+}
+
+#[test]
+fn test_read_only_does_not_persist() {
+    let xprv = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let datadir = std::env::temp_dir().join(format!("bdk-cli-read-only-{}", std::process::id()));
+    let new_address = |read_only: bool| {
+        let mut command = Command::new("cargo");
+        command
+            .args(["run", "--", "-n", "regtest", "--datadir"])
+            .arg(&datadir)
+            .args(["wallet", "-w", "read_only", "-d", "sqlite"])
+            .args(["-e", &format!("wpkh({xprv}/84'/1'/0'/0/*)")])
+            .args(["-i", &format!("wpkh({xprv}/84'/1'/0'/1/*)")]);
+        if read_only {
+            command.arg("--read-only");
+        }
+        command.arg("new_address").output().unwrap()
+    };
+
+    // The database isn't created by a read-only process.
+    let missing = new_address(true);
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("can't be created with --read-only"));
+
+    new_address(false);
+    let read_only: serde_json::Value = serde_json::from_slice(&new_address(true).stdout).unwrap();
+    let revealed: serde_json::Value = serde_json::from_slice(&new_address(false).stdout).unwrap();
+    let _ = std::fs::remove_dir_all(&datadir);
+
+    assert_eq!(read_only["dry_run"], true);
+    assert_eq!(read_only["address"], revealed["address"]);
+}
+
+#[test]
+fn test_descriptor_override_is_transient() {
+    let xprv = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let other = "tprv8ZgxMBicQKsPdmJDTZGgBJBD9GSh9T19Y5X3Yph6QxF1LkWqkBDqqw7S8zrEB3KK8MV5eEv5TsyqbMM937JryFRftXC6rDoXtVohzzQovoo";
+    let datadir = std::env::temp_dir().join(format!("bdk-cli-override-{}", std::process::id()));
+    let new_address = |key: &str, descriptor_override: bool| {
+        let mut command = Command::new("cargo");
+        command
+            .args(["run", "--", "-n", "regtest", "--datadir"])
+            .arg(&datadir)
+            .args(["wallet", "-w", "override", "-d", "sqlite"])
+            .args(["-e", &format!("wpkh({key}/84'/1'/0'/0/*)")])
+            .args(["-i", &format!("wpkh({key}/84'/1'/0'/1/*)")]);
+        if descriptor_override {
+            command.arg("--descriptor-override");
+        }
+        let output = command.args(["-v", "new_address"]).output().unwrap();
+        assert!(output.status.success());
+        (
+            serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    new_address(xprv, false);
+    // The stored indexes carry over to the overriding descriptors.
+    let (overridden, warning) = new_address(other, true);
+    let (stored, _) = new_address(xprv, false);
+    let _ = std::fs::remove_dir_all(&datadir);
+
+    assert!(warning.contains("transient session"));
+    assert_eq!(overridden["dry_run"], true);
+    assert_eq!(overridden["index"], 1);
+    assert_eq!(stored["index"], 1);
+    assert_ne!(overridden["address"], stored["address"]);
+}
+
+#[test]
+fn test_total_balance_per_network() {
+    let xprv = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let datadir =
+        std::env::temp_dir().join(format!("bdk-cli-total-balance-{}", std::process::id()));
+    let create_wallet = |network: &str, wallet: &str| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-n", network, "--datadir"])
+            .arg(&datadir)
+            .args(["wallet", "-w", wallet, "-d", "sqlite"])
+            .args(["-e", &format!("wpkh({xprv}/84'/1'/0'/0/*)")])
+            .args(["-i", &format!("wpkh({xprv}/84'/1'/0'/1/*)")])
+            .arg("balance")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+    };
+    create_wallet("regtest", "first");
+    create_wallet("regtest", "second");
+    create_wallet("testnet", "third");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--datadir"])
+        .arg(&datadir)
+        .arg("total_balance")
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&datadir);
+    assert!(output.status.success());
+    let result = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+
+    let wallets = |network: &str| -> Vec<String> {
+        result["networks"][network]["wallets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|wallet| wallet["wallet"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(wallets("regtest"), ["first", "second"]);
+    assert_eq!(wallets("testnet"), ["third"]);
+    assert_eq!(result["networks"]["regtest"]["total"]["total"], 0);
+    assert_eq!(result["errors"], serde_json::json!([]));
+}
+
+#[test]
+fn test_backup_restore_state_files() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let xprv = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    let datadir = std::env::temp_dir().join(format!("bdk-cli-backup-{}", std::process::id()));
+    let backup_file = datadir.join("backup.json");
+    // The passphrase is read from the standard input when it isn't a terminal.
+    let run = |args: &[&str], passphrase: &str| {
+        let mut child = Command::new("cargo")
+            .args(["run", "--", "-n", "regtest", "--datadir"])
+            .arg(&datadir)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        writeln!(child.stdin.take().unwrap(), "{passphrase}").unwrap();
+        child.wait_with_output().unwrap()
+    };
+    let external = format!("wpkh({xprv}/84'/1'/0'/0/*)");
+    let internal = format!("wpkh({xprv}/84'/1'/0'/1/*)");
+    let wallet = |subcommand: &[&str]| {
+        let args = [
+            &[
+                "wallet", "-w", "original", "-d", "sqlite", "-e", &external, "-i", &internal,
+            ],
+            subcommand,
+        ]
+        .concat();
+        run(&args, "secret")
+    };
+
+    assert!(wallet(&["new_change_address"]).status.success());
+    let backup_arg = backup_file.to_str().unwrap();
+    assert!(wallet(&["backup", "--out", backup_arg]).status.success());
+    let existing = wallet(&["backup", "--out", backup_arg]);
+    assert!(String::from_utf8_lossy(&existing.stderr).contains("use --force to overwrite it"));
+    assert!(
+        wallet(&["backup", "--out", backup_arg, "--force"])
+            .status
+            .success()
+    );
+
+    let restore = [
+        "restore", "-w", "restored", "-d", "sqlite", "--file", backup_arg,
+    ];
+    let wrong = run(&restore, "wrong");
+    assert!(String::from_utf8_lossy(&wrong.stderr).contains("Wrong passphrase"));
+    let restored = run(&restore, "secret");
+    let change_index = datadir.join("restored").join("change_index.json");
+    let restored_change_index = std::fs::read_to_string(&change_index).unwrap();
+    let original_change_index =
+        std::fs::read_to_string(datadir.join("original").join("change_index.json")).unwrap();
+    let _ = std::fs::remove_dir_all(&datadir);
+
+    assert!(restored.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&restored.stdout).unwrap();
+    assert_eq!(
+        result["state_files"],
+        serde_json::json!(["change_index.json"])
+    );
+    assert_eq!(restored_change_index, original_change_index);
+}